@@ -0,0 +1,342 @@
+pub mod blip;
+pub mod dmc;
+pub mod envelope;
+pub mod frame_counter;
+pub mod length_counter;
+pub mod mixer;
+pub mod noise;
+pub mod pulse;
+pub mod triangle;
+
+use crate::apu::dmc::Dmc;
+use crate::apu::frame_counter::{FrameCounter, FrameEvent};
+use crate::apu::mixer::Mixer;
+use crate::apu::noise::Noise;
+use crate::apu::pulse::Pulse;
+use crate::apu::triangle::Triangle;
+use crate::region::Region;
+use crate::state::{Serialize, Serializer};
+
+/// APU reference: https://www.nesdev.org/wiki/APU
+///
+/// The audio processing unit, mapped to $4000-$4017. It is clocked off the CPU's cycle count.
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    pub mixer: Mixer,
+    /// Level of the cartridge's expansion audio, mixed into the output as is.
+    expansion_output: f32,
+    /// Number of CPU cycles the APU has been clocked for.
+    cycle: u64,
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            mixer: Mixer::new(),
+            expansion_output: 0.0,
+            cycle: 0,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+        self.mixer.set_clock_rate(region.get_cpu_clock_rate());
+    }
+
+    /// Sets the level of the cartridge's expansion audio, which stays until it gets set again.
+    pub fn set_expansion_output(&mut self, amplitude: f32) {
+        self.expansion_output = amplitude;
+    }
+
+    /// Sets the rate of the generated audio, usually 44100 or 48000 Hz.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mixer.set_sample_rate(sample_rate);
+    }
+
+    /// Number of audio samples ready to be read by [Apu::read_samples].
+    pub fn get_samples_available(&self) -> usize {
+        self.mixer.get_samples_available()
+    }
+
+    /// Drains the generated audio into `out`, returning the number of samples read. The
+    /// frontend is expected to regularly drain the samples, as only about a second is buffered.
+    pub fn read_samples(&mut self, out: &mut [i16]) -> usize {
+        self.mixer.read_samples(out)
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr - 0x4000, value),
+            0x4004..=0x4007 => self.pulse2.write_register(addr - 0x4004, value),
+            0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, value),
+            0x400C..=0x400F => self.noise.write_register(addr - 0x400C, value),
+            0x4010..=0x4013 => self.dmc.write_register(addr - 0x4010, value),
+            0x4015 => {
+                self.pulse1.length_counter.set_enabled(value & 1 == 1);
+                self.pulse2
+                    .length_counter
+                    .set_enabled((value >> 1) & 1 == 1);
+                self.triangle
+                    .length_counter
+                    .set_enabled((value >> 2) & 1 == 1);
+                self.noise.length_counter.set_enabled((value >> 3) & 1 == 1);
+                self.dmc.set_enabled((value >> 4) & 1 == 1);
+            }
+            0x4017 => self.frame_counter.write(value, self.cycle),
+            _ => {}
+        }
+    }
+
+    /// Reads $4015, which acknowledges the frame interrupt. Bit layout of the returned value: <pre>
+    /// 1 << 7 => DMC interrupt
+    /// 1 << 6 => frame interrupt
+    /// 1 << 4 => DMC sample has bytes remaining
+    /// 1 << 3 => noise length counter is active
+    /// 1 << 2 => triangle length counter is active
+    /// 1 << 1 => pulse 2 length counter is active
+    /// 1      => pulse 1 length counter is active
+    /// </pre>
+    pub fn read_status(&self) -> u8 {
+        let mut out: u8 = 0;
+        if self.pulse1.length_counter.is_active() {
+            out |= 1;
+        }
+        if self.pulse2.length_counter.is_active() {
+            out |= 1 << 1;
+        }
+        if self.triangle.length_counter.is_active() {
+            out |= 1 << 2;
+        }
+        if self.noise.length_counter.is_active() {
+            out |= 1 << 3;
+        }
+        if self.dmc.is_active() {
+            out |= 1 << 4;
+        }
+        if self.frame_counter.get_irq() {
+            out |= 1 << 6;
+        }
+        if self.dmc.get_irq() {
+            out |= 1 << 7;
+        }
+        self.frame_counter.acknowledge_irq();
+        out
+    }
+
+    /// Whether the APU is pulling the CPU's IRQ line low.
+    pub fn get_irq(&self) -> bool {
+        self.frame_counter.get_irq() || self.dmc.get_irq()
+    }
+
+    /// Catches the APU up to the given CPU cycle. Stops early when the DMC needs the CPU to fetch
+    /// a sample byte, as the fetch stalls the CPU.
+    pub fn run_until(&mut self, cpu_cycle: u64) {
+        while self.cycle < cpu_cycle && self.dmc.get_dma_address().is_none() {
+            self.clock();
+        }
+        self.mixer.end_time(self.cycle);
+    }
+
+    /// Advances the APU by a single CPU cycle.
+    fn clock(&mut self) {
+        if self.cycle % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        let event: FrameEvent = self.frame_counter.clock();
+        if event.quarter_frame {
+            self.clock_quarter_frame();
+        }
+        if event.half_frame {
+            self.clock_half_frame();
+        }
+
+        let amplitude: f32 = self.mixer.mix(
+            self.pulse1.get_output(),
+            self.pulse2.get_output(),
+            self.triangle.get_output(),
+            self.noise.get_output(),
+            self.dmc.get_output(),
+        ) + self.expansion_output;
+        self.mixer.set_amplitude(self.cycle, amplitude);
+        self.cycle += 1;
+    }
+
+    /// Clocks the envelopes and the triangle's linear counter.
+    pub fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    /// Clocks the length counters and sweep units.
+    pub fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Moves the APU to a given cycle without clocking it, to match a CPU that starts late.
+    #[cfg(test)]
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+        self.mixer.set_time(cycle);
+    }
+}
+
+impl Serialize for Apu {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.pulse1);
+        s.value(&mut self.pulse2);
+        s.value(&mut self.triangle);
+        s.value(&mut self.noise);
+        s.value(&mut self.dmc);
+        s.value(&mut self.frame_counter);
+        s.value(&mut self.expansion_output);
+        s.value(&mut self.cycle);
+        if s.is_loading() {
+            self.mixer.set_time(self.cycle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let mut apu: Apu = Apu::new();
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_register(0x4015, 0b11);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b11);
+
+        apu.write_register(0x4015, 0b10);
+        assert_eq!(apu.read_status(), 0b10);
+
+        apu.write_register(0x4015, 0b1_1100);
+        apu.write_register(0x400B, 0b0000_1000);
+        apu.write_register(0x400F, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b1_1100);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu: Apu = Apu::new();
+        apu.run_until(29830);
+        assert!(apu.get_irq());
+        assert_eq!(apu.read_status(), 1 << 6);
+        assert!(!apu.get_irq());
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_length_counter_clocking() {
+        let mut apu: Apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        // length index 3 loads a length of 2, which runs out after two half frames
+        apu.write_register(0x4003, 0b0001_1000);
+        apu.run_until(14913);
+        assert_eq!(apu.pulse1.length_counter.get_value(), 1);
+        apu.run_until(29829);
+        assert_eq!(apu.read_status() & 1, 0);
+    }
+
+    #[test]
+    fn test_dmc_dma_stops_clocking() {
+        let mut apu: Apu = Apu::new();
+        apu.write_register(0x4015, 0b1_0000);
+        apu.run_until(10);
+        assert_eq!(apu.get_cycle(), 0);
+
+        apu.dmc.complete_dma(0);
+        apu.run_until(10);
+        assert_eq!(apu.get_cycle(), 10);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut apu: Apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b0101_1111);
+        apu.write_register(0x4002, 0x08);
+        apu.write_register(0x4003, 0b0000_1000);
+
+        // 25% duty: the output goes high once the sequencer reaches its second step
+        assert_eq!(apu.pulse1.get_output(), 0);
+        apu.run_until(2);
+        assert_eq!(apu.get_cycle(), 2);
+        assert_eq!(apu.pulse1.get_output(), 15);
+    }
+
+    #[test]
+    fn test_audio_output() {
+        let mut apu: Apu = Apu::new();
+        apu.set_sample_rate(48000);
+        apu.run_until(29830);
+        let mut out: Vec<i16> = vec![0; 2000];
+        let silent: usize = apu.read_samples(&mut out);
+        assert!(silent > 700);
+        // the triangle's idle output is a constant offset, which the high-pass filters remove
+        assert!(out[silent - 1].abs() < 100);
+
+        // a constant volume square wave at roughly 440 Hz
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.run_until(29830 * 2);
+        let count: usize = apu.read_samples(&mut out);
+        assert_eq!(apu.get_samples_available(), 0);
+        assert!(count > 700);
+        assert!(out[..count].iter().any(|sample| *sample > 1000));
+        assert!(out[..count].iter().any(|sample| *sample < -1000));
+    }
+
+    #[test]
+    fn test_expansion_output() {
+        let mut apu: Apu = Apu::new();
+        apu.run_until(29830);
+        let mut out: Vec<i16> = vec![0; 2000];
+        apu.read_samples(&mut out);
+
+        apu.set_expansion_output(0.5);
+        apu.run_until(29830 * 2);
+        assert!(apu.read_samples(&mut out) > 700);
+        // the step shows up right away, before the high-pass filters remove it again
+        assert!(out[..100].iter().any(|sample| *sample > 10000));
+    }
+}
@@ -0,0 +1,214 @@
+use std::f64::consts::PI;
+
+/// Number of sub-sample positions the step kernel is computed for.
+const PHASES: usize = 64;
+/// Number of output samples a single step is spread across.
+const KERNEL_WIDTH: usize = 16;
+const HALF_WIDTH: usize = KERNEL_WIDTH / 2;
+/// Cutoff of the step's low-pass filter, relative to the output sample rate.
+const CUTOFF: f64 = 0.45;
+
+/// Resamples a signal from a high clock rate down to an output sample rate without aliasing.
+///
+/// Instead of sampling the signal, every change of amplitude gets added as a band-limited step,
+/// spread across the surrounding output samples. Summing up these deltas yields the output.
+pub struct BlipBuffer {
+    samples_per_clock: f64,
+    /// Deltas of the samples which were not read yet, starting at `read_position`.
+    deltas: Vec<f32>,
+    read_position: u64,
+    /// Clock time up to which the signal is known.
+    end_clock: u64,
+    integrator: f32,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> BlipBuffer {
+        BlipBuffer {
+            samples_per_clock: sample_rate as f64 / clock_rate,
+            deltas: Vec::new(),
+            read_position: 0,
+            end_clock: 0,
+            integrator: 0.0,
+            kernel: create_kernel(),
+        }
+    }
+
+    /// Changes the rates. Samples which were not read yet get dropped.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: u32) {
+        self.samples_per_clock = sample_rate as f64 / clock_rate;
+        self.deltas.clear();
+        self.read_position = (self.end_clock as f64 * self.samples_per_clock) as u64;
+    }
+
+    /// Moves the timeline to the given clock time, which may lie in the past. Samples which were
+    /// not read yet get dropped.
+    pub fn set_time(&mut self, clock: u64) {
+        self.deltas.clear();
+        self.end_clock = clock;
+        self.read_position = (clock as f64 * self.samples_per_clock) as u64;
+    }
+
+    /// Adds a change in amplitude at the given clock time. Times have to be added in order.
+    pub fn add_delta(&mut self, clock: u64, delta: f32) {
+        let position: f64 = clock as f64 * self.samples_per_clock;
+        let whole: u64 = position as u64;
+        let phase: usize = ((position - whole as f64) * PHASES as f64) as usize;
+
+        // the kernel starts HALF_WIDTH - 1 samples before the step
+        let start: i64 = whole as i64 + 1 - HALF_WIDTH as i64;
+        let end: usize = (start + KERNEL_WIDTH as i64 - self.read_position as i64).max(0) as usize;
+        if self.deltas.len() < end {
+            self.deltas.resize(end, 0.0);
+        }
+        for (index, weight) in self.kernel[phase].iter().enumerate() {
+            let offset: i64 = start + index as i64 - self.read_position as i64;
+            if offset < 0 {
+                // the sample was already read, so only keep the step's height intact
+                self.integrator += delta * weight;
+            } else {
+                self.deltas[offset as usize] += delta * weight;
+            }
+        }
+    }
+
+    /// Marks the signal as known up to the given clock time, making the samples before it
+    /// available for reading.
+    pub fn end_time(&mut self, clock: u64) {
+        self.end_clock = clock;
+    }
+
+    /// Number of samples which are final and can be read.
+    pub fn get_samples_available(&self) -> usize {
+        let end: u64 = (self.end_clock as f64 * self.samples_per_clock) as u64;
+        // steps added from now on can still affect the last HALF_WIDTH samples
+        end.saturating_sub(HALF_WIDTH as u64)
+            .saturating_sub(self.read_position) as usize
+    }
+
+    /// Reads as many samples as available and fit into `out`, returning how many were read.
+    pub fn read_samples(&mut self, out: &mut [f32]) -> usize {
+        let count: usize = self.get_samples_available().min(out.len());
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+        for (sample, delta) in out.iter_mut().zip(self.deltas.drain(..count)) {
+            self.integrator += delta;
+            *sample = self.integrator;
+        }
+        self.read_position += count as u64;
+        count
+    }
+
+    /// Drops up to `count` of the oldest available samples.
+    pub fn skip_samples(&mut self, count: usize) {
+        let count: usize = self.get_samples_available().min(count);
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+        self.integrator += self.deltas.drain(..count).sum::<f32>();
+        self.read_position += count as u64;
+    }
+}
+
+/// Computes the band-limited step for every phase. Each entry contains the difference between
+/// consecutive samples of the step, so that summing them up reproduces the step.
+fn create_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    // integral of a windowed sinc, sampled every 1 / PHASES samples across the kernel's width
+    const SUB_STEPS: usize = 16;
+    let resolution: usize = KERNEL_WIDTH * PHASES;
+    let dt: f64 = 1.0 / (PHASES * SUB_STEPS) as f64;
+    let mut integral: Vec<f64> = vec![0.0; resolution + 1];
+    let mut sum: f64 = 0.0;
+    for j in 0..resolution {
+        for sub in 0..SUB_STEPS {
+            let t: f64 = -(HALF_WIDTH as f64) + (j * SUB_STEPS + sub) as f64 * dt + dt / 2.0;
+            sum += windowed_sinc(t) * dt;
+        }
+        integral[j + 1] = sum;
+    }
+
+    let mut out: Vec<[f32; KERNEL_WIDTH]> = vec![[0.0; KERNEL_WIDTH]; PHASES];
+    for (phase, weights) in out.iter_mut().enumerate() {
+        for (k, weight) in weights.iter_mut().enumerate() {
+            let high: usize = (k + 1) * PHASES - phase;
+            let low: usize = (k * PHASES).saturating_sub(phase);
+            *weight = ((integral[high] - integral[low]) / sum) as f32;
+        }
+        // make sure a step always ends up at exactly its full height
+        let total: f32 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight /= total;
+        }
+    }
+    out
+}
+
+/// A low-pass filter's impulse response, shaped by a Blackman window.
+fn windowed_sinc(t: f64) -> f64 {
+    let x: f64 = 2.0 * CUTOFF * t;
+    let sinc: f64 = if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    };
+    let n: f64 = (t + HALF_WIDTH as f64) / KERNEL_WIDTH as f64;
+    let window: f64 = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+    2.0 * CUTOFF * sinc * window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        let mut blip: BlipBuffer = BlipBuffer::new(1000.0, 100);
+        blip.add_delta(105, 1.0);
+        blip.end_time(1000);
+        assert_eq!(blip.get_samples_available(), 100 - HALF_WIDTH);
+
+        let mut out: Vec<f32> = vec![0.0; 100];
+        assert_eq!(blip.read_samples(&mut out), 100 - HALF_WIDTH);
+        assert!(out[0].abs() < 0.01);
+        assert!((out[40] - 1.0).abs() < 0.01);
+        // the step is smoothed around its position
+        assert!(out[10] > 0.05 && out[10] < 0.5);
+        assert!(out[11] > 0.5 && out[11] < 0.95);
+    }
+
+    #[test]
+    fn test_anti_aliasing() {
+        // a square wave above the Nyquist frequency must not alias into the audible range
+        let mut blip: BlipBuffer = BlipBuffer::new(1_789_773.0, 44100);
+        for clock in (0..100_000).step_by(20) {
+            let delta: f32 = if clock % 40 == 0 { 1.0 } else { -1.0 };
+            blip.add_delta(clock, delta);
+        }
+        blip.end_time(100_000);
+
+        let mut out: Vec<f32> = vec![0.0; 4096];
+        let count: usize = blip.read_samples(&mut out);
+        let samples: &[f32] = &out[HALF_WIDTH..count];
+        let mean: f32 = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!((mean - 0.5).abs() < 0.05);
+        assert!(samples.iter().all(|sample| (sample - mean).abs() < 0.1));
+    }
+
+    #[test]
+    fn test_read_in_parts() {
+        let mut blip: BlipBuffer = BlipBuffer::new(1000.0, 100);
+        blip.add_delta(0, 0.5);
+        blip.end_time(500);
+        let mut out: Vec<f32> = vec![0.0; 30];
+        assert_eq!(blip.read_samples(&mut out), 30);
+        assert_eq!(blip.read_samples(&mut out), 50 - HALF_WIDTH - 30);
+
+        blip.add_delta(600, 0.5);
+        blip.end_time(2000);
+        let mut rest: Vec<f32> = vec![0.0; 200];
+        assert_eq!(blip.read_samples(&mut rest), 200 - 50);
+        assert!((rest[100] - 1.0).abs() < 0.01);
+    }
+}
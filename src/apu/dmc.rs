@@ -0,0 +1,288 @@
+use crate::region::Region;
+use crate::state::{Serialize, Serializer, StateError};
+
+/// DMC reference: https://www.nesdev.org/wiki/APU_DMC
+///
+/// The delta modulation channel plays 1-bit delta encoded samples straight out of CPU memory.
+/// It can't access memory by itself, so the CPU has to service its DMA requests (see
+/// [Dmc::get_dma_address] and [Dmc::complete_dma]).
+pub struct Dmc {
+    irq_enabled: bool,
+    irq_flag: bool,
+    loop_flag: bool,
+    periods: [u16; 16],
+    /// Index of the period in `periods`, kept to look it up again when the region changes.
+    period_index: u8,
+    timer_period: u16,
+    timer: u16,
+
+    /// 7-bit output level.
+    output_level: u8,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+}
+
+impl Default for Dmc {
+    fn default() -> Dmc {
+        Dmc::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Dmc {
+        let periods: [u16; 16] = Region::Ntsc.get_dmc_periods();
+        Dmc {
+            irq_enabled: false,
+            irq_flag: false,
+            loop_flag: false,
+            periods,
+            period_index: 0,
+            timer_period: periods[0],
+            timer: 0,
+            output_level: 0,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = region.get_dmc_periods();
+        self.timer_period = self.periods[self.period_index as usize];
+    }
+
+    /// Handles writes to $4010-$4013, with `register` being the offset from $4010.
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = (value >> 7) & 1 == 1;
+                self.loop_flag = (value >> 6) & 1 == 1;
+                self.period_index = value & 0x0F;
+                self.timer_period = self.periods[self.period_index as usize];
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            1 => self.output_level = value & 0x7F,
+            2 => self.sample_address = 0xC000 | ((value as u16) << 6),
+            3 => self.sample_length = ((value as u16) << 4) | 1,
+            _ => panic!("Invalid DMC register: {}", register),
+        }
+    }
+
+    /// Handles the DMC bit of $4015 writes. Enabling the channel restarts the sample if it
+    /// already finished. Writes to $4015 always acknowledge the DMC's interrupt.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Whether the sample still has bytes left to fetch.
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// Returns the address the DMC wants to read from, if its sample buffer ran empty.
+    pub fn get_dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Fills the sample buffer with the byte read from [Dmc::get_dma_address].
+    pub fn complete_dma(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // the address wraps around to $8000 rather than $0000
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// Clocked on every CPU cycle, as the rate table is in CPU cycles.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Returns the current output level, between 0 and 127.
+    pub fn get_output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Serialize for Dmc {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_flag);
+        s.value(&mut self.loop_flag);
+        s.value(&mut self.period_index);
+        if self.period_index > 0x0F {
+            s.fail(StateError::Mismatch("period index"));
+            self.period_index = 0;
+        }
+        self.timer_period = self.periods[self.period_index as usize];
+        s.value(&mut self.timer);
+        s.value(&mut self.output_level);
+        s.value(&mut self.shift_register);
+        s.value(&mut self.bits_remaining);
+        s.value(&mut self.silence);
+        s.value(&mut self.sample_address);
+        s.value(&mut self.sample_length);
+        s.value(&mut self.current_address);
+        s.value(&mut self.bytes_remaining);
+        s.value(&mut self.sample_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dma() {
+        let mut dmc: Dmc = Dmc::new();
+        dmc.write_register(2, 0xFF);
+        dmc.write_register(3, 0x00);
+        assert_eq!(dmc.get_dma_address(), None);
+
+        dmc.set_enabled(true);
+        assert!(dmc.is_active());
+        assert_eq!(dmc.get_dma_address(), Some(0xFFC0));
+        dmc.complete_dma(0xAA);
+        assert!(!dmc.is_active());
+        assert_eq!(dmc.get_dma_address(), None);
+        assert!(!dmc.get_irq());
+    }
+
+    #[test]
+    fn test_address_wrap() {
+        let mut dmc: Dmc = Dmc::new();
+        dmc.write_register(2, 0xFF);
+        dmc.write_register(3, 0x04);
+        dmc.set_enabled(true);
+        for _ in 0..64 {
+            dmc.complete_dma(0);
+            dmc.sample_buffer = None;
+        }
+        assert_eq!(dmc.get_dma_address(), Some(0x8000));
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut dmc: Dmc = Dmc::new();
+        dmc.write_register(0, 0x80);
+        dmc.write_register(3, 0x00);
+        dmc.set_enabled(true);
+        dmc.complete_dma(0);
+        assert!(dmc.get_irq());
+
+        // writing $4015 acknowledges the interrupt
+        dmc.set_enabled(true);
+        assert!(!dmc.get_irq());
+
+        // looping samples never raise an interrupt
+        dmc.write_register(0, 0xC0);
+        dmc.sample_buffer = None;
+        dmc.complete_dma(0);
+        assert!(!dmc.get_irq());
+        assert!(dmc.is_active());
+    }
+
+    #[test]
+    fn test_output() {
+        let mut dmc: Dmc = Dmc::new();
+        dmc.write_register(0, 0x0F);
+        dmc.write_register(1, 0x40);
+        dmc.write_register(3, 0x00);
+        dmc.set_enabled(true);
+        dmc.complete_dma(0b0000_0101);
+
+        // the first output cycle is silent and loads the buffered sample
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.get_output(), 0x40);
+
+        let mut outputs: Vec<u8> = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..54 {
+                dmc.clock_timer();
+            }
+            outputs.push(dmc.get_output());
+        }
+        assert_eq!(outputs, vec![0x42, 0x40, 0x42, 0x40]);
+    }
+
+    #[test]
+    fn test_periods() {
+        let mut dmc: Dmc = Dmc::new();
+        dmc.write_register(0, 0x0F);
+        assert_eq!(dmc.timer_period, 54);
+        dmc.set_region(Region::Pal);
+        assert_eq!(dmc.timer_period, 50);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::state::{Serialize, Serializer};
+
+/// Envelope reference: https://www.nesdev.org/wiki/APU_Envelope
+///
+/// Generates either a constant volume or a decreasing saw envelope, which optionally loops.
+pub struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    /// Doubles as the constant volume and the divider's period.
+    volume: u8,
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope::new()
+    }
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            start: false,
+            loop_flag: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay_level: 0,
+        }
+    }
+
+    /// Bit layout of the written value: <pre>
+    /// 1 << 5 => loop (shared with the length counter halt flag)
+    /// 1 << 4 => constant volume
+    /// 0..=3  => volume / envelope period
+    /// </pre>
+    pub fn write_control(&mut self, value: u8) {
+        self.loop_flag = (value >> 5) & 1 == 1;
+        self.constant_volume = (value >> 4) & 1 == 1;
+        self.volume = value & 0x0F;
+    }
+
+    /// Restarts the envelope on the next quarter frame clock.
+    pub fn restart(&mut self) {
+        self.start = true
+    }
+
+    /// Clocked by the frame counter on every quarter frame.
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.loop_flag {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+impl Serialize for Envelope {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.start);
+        s.value(&mut self.loop_flag);
+        s.value(&mut self.constant_volume);
+        s.value(&mut self.volume);
+        s.value(&mut self.divider);
+        s.value(&mut self.decay_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_volume() {
+        let mut envelope: Envelope = Envelope::new();
+        envelope.write_control(0b0001_1010);
+        envelope.restart();
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 10);
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut envelope: Envelope = Envelope::new();
+        envelope.write_control(0b0000_0001);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 15);
+
+        // with a period of 1, the decay level drops every second clock
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 15);
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 14);
+
+        for _ in 0..28 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.get_volume(), 0);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 0);
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut envelope: Envelope = Envelope::new();
+        envelope.write_control(0b0010_0000);
+        envelope.restart();
+        for _ in 0..16 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.get_volume(), 0);
+        envelope.clock();
+        assert_eq!(envelope.get_volume(), 15);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::region::Region;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// Frame counter reference: https://www.nesdev.org/wiki/APU_Frame_Counter
+///
+/// Which of the APU's units got clocked during a CPU cycle.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct FrameEvent {
+    /// Clocks the envelopes and the triangle's linear counter.
+    pub quarter_frame: bool,
+    /// Clocks the length counters and sweep units.
+    pub half_frame: bool,
+}
+
+/// The frame sequencer at $4017, which drives the APU's low frequency units and raises the frame
+/// interrupt.
+pub struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    /// Reading $4015 acknowledges the interrupt, hence the interior mutability.
+    irq_flag: Cell<bool>,
+    /// Number of CPU cycles since the sequence started.
+    cycle: u32,
+    steps: [u32; 5],
+    /// A $4017 write only resets the sequence 3 or 4 CPU cycles later. Holds the written value
+    /// and the remaining delay.
+    pending_write: Option<(u8, u8)>,
+}
+
+impl Default for FrameCounter {
+    fn default() -> FrameCounter {
+        FrameCounter::new()
+    }
+}
+
+impl FrameCounter {
+    pub fn new() -> FrameCounter {
+        FrameCounter {
+            five_step: false,
+            irq_inhibit: false,
+            irq_flag: Cell::new(false),
+            cycle: 0,
+            steps: Region::Ntsc.get_frame_counter_steps(),
+            pending_write: None,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.steps = region.get_frame_counter_steps();
+    }
+
+    /// Handles writes to $4017. Bit layout of the written value: <pre>
+    /// 1 << 7 => 5-step mode
+    /// 1 << 6 => interrupt inhibit
+    /// </pre>
+    /// The sequence restarts 3 CPU cycles after the write if it happened on an even cycle, and 4
+    /// cycles after it otherwise.
+    pub fn write(&mut self, value: u8, cpu_cycle: u64) {
+        self.irq_inhibit = (value >> 6) & 1 == 1;
+        if self.irq_inhibit {
+            self.irq_flag.set(false);
+        }
+        let delay: u8 = if cpu_cycle.is_multiple_of(2) { 3 } else { 4 };
+        self.pending_write = Some((value, delay));
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.irq_flag.get()
+    }
+
+    /// Clears the interrupt flag, as done by reading $4015.
+    pub fn acknowledge_irq(&self) {
+        self.irq_flag.set(false)
+    }
+
+    fn raise_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.irq_flag.set(true);
+        }
+    }
+
+    /// Advances the sequencer by a single CPU cycle.
+    pub fn clock(&mut self) -> FrameEvent {
+        let mut out: FrameEvent = FrameEvent::default();
+
+        if let Some((value, delay)) = self.pending_write {
+            if delay <= 1 {
+                self.pending_write = None;
+                self.five_step = (value >> 7) & 1 == 1;
+                self.cycle = 0;
+                // entering 5-step mode immediately clocks all units
+                if self.five_step {
+                    out.quarter_frame = true;
+                    out.half_frame = true;
+                }
+                return out;
+            }
+            self.pending_write = Some((value, delay - 1));
+        }
+
+        self.cycle += 1;
+        let steps: [u32; 5] = self.steps;
+        if self.cycle == steps[0] || self.cycle == steps[2] {
+            out.quarter_frame = true;
+        } else if self.cycle == steps[1] {
+            out.quarter_frame = true;
+            out.half_frame = true;
+        } else if self.five_step {
+            if self.cycle == steps[4] {
+                out.quarter_frame = true;
+                out.half_frame = true;
+            } else if self.cycle == steps[4] + 1 {
+                self.cycle = 0;
+            }
+        } else if self.cycle == steps[3] - 1 {
+            self.raise_irq();
+        } else if self.cycle == steps[3] {
+            out.quarter_frame = true;
+            out.half_frame = true;
+            self.raise_irq();
+        } else if self.cycle == steps[3] + 1 {
+            self.raise_irq();
+            self.cycle = 0;
+        }
+        out
+    }
+}
+
+impl Serialize for FrameCounter {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.five_step);
+        s.value(&mut self.irq_inhibit);
+        s.value(&mut self.irq_flag);
+        s.value(&mut self.cycle);
+        s.value(&mut self.pending_write);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clocks the frame counter for a number of cycles and returns the cycles at which the
+    /// quarter and half frame clocks happened.
+    fn collect_events(counter: &mut FrameCounter, cycles: u32) -> (Vec<u32>, Vec<u32>) {
+        let mut quarters: Vec<u32> = Vec::new();
+        let mut halves: Vec<u32> = Vec::new();
+        for cycle in 1..=cycles {
+            let event: FrameEvent = counter.clock();
+            if event.quarter_frame {
+                quarters.push(cycle);
+            }
+            if event.half_frame {
+                halves.push(cycle);
+            }
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step() {
+        let mut counter: FrameCounter = FrameCounter::new();
+        let (quarters, halves) = collect_events(&mut counter, 29830 + 7457);
+        assert_eq!(quarters, vec![7457, 14913, 22371, 29829, 29830 + 7457]);
+        assert_eq!(halves, vec![14913, 29829]);
+        assert!(counter.get_irq());
+
+        counter.acknowledge_irq();
+        assert!(!counter.get_irq());
+    }
+
+    #[test]
+    fn test_irq_timing() {
+        let mut counter: FrameCounter = FrameCounter::new();
+        collect_events(&mut counter, 29827);
+        assert!(!counter.get_irq());
+        counter.clock();
+        assert!(counter.get_irq());
+
+        // the flag gets set again on the following two cycles
+        counter.acknowledge_irq();
+        counter.clock();
+        assert!(counter.get_irq());
+        counter.acknowledge_irq();
+        counter.clock();
+        assert!(counter.get_irq());
+        counter.acknowledge_irq();
+        counter.clock();
+        assert!(!counter.get_irq());
+    }
+
+    #[test]
+    fn test_five_step() {
+        let mut counter: FrameCounter = FrameCounter::new();
+        counter.write(0x80, 0);
+        // the write takes effect after 3 cycles and immediately clocks everything
+        let (quarters, halves) = collect_events(&mut counter, 3 + 37282);
+        assert_eq!(quarters, vec![3, 3 + 7457, 3 + 14913, 3 + 22371, 3 + 37281]);
+        assert_eq!(halves, vec![3, 3 + 14913, 3 + 37281]);
+        assert!(!counter.get_irq());
+    }
+
+    #[test]
+    fn test_write_jitter() {
+        let mut even: FrameCounter = FrameCounter::new();
+        even.write(0x80, 10);
+        let (quarters, _) = collect_events(&mut even, 4);
+        assert_eq!(quarters, vec![3]);
+
+        let mut odd: FrameCounter = FrameCounter::new();
+        odd.write(0x80, 11);
+        let (quarters, _) = collect_events(&mut odd, 4);
+        assert_eq!(quarters, vec![4]);
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut counter: FrameCounter = FrameCounter::new();
+        collect_events(&mut counter, 29829);
+        assert!(counter.get_irq());
+
+        counter.write(0x40, 0);
+        assert!(!counter.get_irq());
+        collect_events(&mut counter, 29830);
+        assert!(!counter.get_irq());
+    }
+}
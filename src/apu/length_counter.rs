@@ -0,0 +1,111 @@
+use crate::state::{Serialize, Serializer};
+
+/// Length counter reference: https://www.nesdev.org/wiki/APU_Length_Counter
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel once the note's duration has run out.
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    value: u8,
+}
+
+impl Default for LengthCounter {
+    fn default() -> LengthCounter {
+        LengthCounter::new()
+    }
+}
+
+impl LengthCounter {
+    pub fn new() -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            value: 0,
+        }
+    }
+
+    /// Loads the counter from the 5-bit index written to the channel's last register. Does
+    /// nothing while the channel is disabled through $4015.
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    /// Disabling a channel through $4015 immediately silences it.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt
+    }
+
+    /// Clocked by the frame counter on every half frame.
+    pub fn clock(&mut self) {
+        if self.value > 0 && !self.halt {
+            self.value -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.value > 0
+    }
+
+    pub fn get_value(&self) -> u8 {
+        self.value
+    }
+}
+
+impl Serialize for LengthCounter {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.enabled);
+        s.value(&mut self.halt);
+        s.value(&mut self.value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let mut counter: LengthCounter = LengthCounter::new();
+        counter.load(1);
+        assert!(!counter.is_active());
+
+        counter.set_enabled(true);
+        counter.load(1);
+        assert_eq!(counter.get_value(), 254);
+
+        counter.set_enabled(false);
+        assert!(!counter.is_active());
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut counter: LengthCounter = LengthCounter::new();
+        counter.set_enabled(true);
+        counter.load(3);
+        counter.clock();
+        assert_eq!(counter.get_value(), 1);
+
+        counter.set_halt(true);
+        counter.clock();
+        assert_eq!(counter.get_value(), 1);
+
+        counter.set_halt(false);
+        counter.clock();
+        counter.clock();
+        assert_eq!(counter.get_value(), 0);
+    }
+}
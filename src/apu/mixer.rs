@@ -0,0 +1,223 @@
+use crate::apu::blip::BlipBuffer;
+use crate::region::Region;
+
+/// Sample rate used until the frontend picks one.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+/// Samples the frontend didn't drain get dropped once more than this many seconds are buffered.
+const MAX_BUFFERED_SECONDS: u32 = 1;
+
+/// Mixer reference: https://www.nesdev.org/wiki/APU_Mixer
+///
+/// Combines the channel outputs the way the console's resistor network does, then resamples the
+/// result to the host's sample rate and filters it like the console's output stage.
+pub struct Mixer {
+    /// Indexed by the sum of both pulse outputs.
+    pulse_table: [f32; 31],
+    /// Indexed by 3 * triangle + 2 * noise + DMC.
+    tnd_table: [f32; 203],
+    clock_rate: f64,
+    sample_rate: u32,
+    blip: BlipBuffer,
+    last_amplitude: f32,
+    filters: [Filter; 3],
+    buffer: Vec<f32>,
+}
+
+impl Default for Mixer {
+    fn default() -> Mixer {
+        Mixer::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        let mut pulse_table: [f32; 31] = [0.0; 31];
+        for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table: [f32; 203] = [0.0; 203];
+        for (n, value) in tnd_table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+
+        let clock_rate: f64 = Region::Ntsc.get_cpu_clock_rate();
+        Mixer {
+            pulse_table,
+            tnd_table,
+            clock_rate,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            blip: BlipBuffer::new(clock_rate, DEFAULT_SAMPLE_RATE),
+            last_amplitude: 0.0,
+            filters: create_filters(DEFAULT_SAMPLE_RATE),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Sets the rate the mixer gets clocked at. Samples which were not read yet get dropped.
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.blip.set_rates(self.clock_rate, self.sample_rate);
+    }
+
+    /// Sets the output sample rate, usually 44100 or 48000 Hz. Samples which were not read yet
+    /// get dropped.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.blip.set_rates(self.clock_rate, self.sample_rate);
+        self.filters = create_filters(sample_rate);
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Combines the channel outputs into a single amplitude between 0 and 1.
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse: usize = (pulse1 + pulse2) as usize;
+        let tnd: usize = 3 * triangle as usize + 2 * noise as usize + dmc as usize;
+        self.pulse_table[pulse] + self.tnd_table[tnd]
+    }
+
+    /// Sets the amplitude of the signal from the given cycle onwards.
+    pub fn set_amplitude(&mut self, cycle: u64, amplitude: f32) {
+        if amplitude != self.last_amplitude {
+            self.blip.add_delta(cycle, amplitude - self.last_amplitude);
+            self.last_amplitude = amplitude;
+        }
+    }
+
+    /// Restarts the signal at the given cycle, like after loading a save state. Samples which were
+    /// not read yet get dropped.
+    pub fn set_time(&mut self, cycle: u64) {
+        self.blip.set_time(cycle);
+    }
+
+    /// Marks the signal as complete up to the given cycle.
+    pub fn end_time(&mut self, cycle: u64) {
+        self.blip.end_time(cycle);
+        let max: usize = (self.sample_rate * MAX_BUFFERED_SECONDS) as usize;
+        let available: usize = self.blip.get_samples_available();
+        if available > max {
+            self.blip.skip_samples(available - max);
+        }
+    }
+
+    pub fn get_samples_available(&self) -> usize {
+        self.blip.get_samples_available()
+    }
+
+    /// Drains as many samples as available and fit into `out`, returning how many were read.
+    pub fn read_samples(&mut self, out: &mut [i16]) -> usize {
+        self.buffer.resize(out.len(), 0.0);
+        let count: usize = self.blip.read_samples(&mut self.buffer);
+        for (sample, value) in out.iter_mut().zip(&self.buffer[..count]) {
+            let mut value: f32 = *value;
+            for filter in self.filters.iter_mut() {
+                value = filter.process(value);
+            }
+            *sample = (value * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+        count
+    }
+}
+
+/// The console's output stage: two high-pass filters at 90 Hz and 440 Hz, followed by a low-pass
+/// filter at 14 kHz.
+fn create_filters(sample_rate: u32) -> [Filter; 3] {
+    [
+        Filter::high_pass(90.0, sample_rate),
+        Filter::high_pass(440.0, sample_rate),
+        Filter::low_pass(14000.0, sample_rate),
+    ]
+}
+
+/// A first-order RC filter.
+struct Filter {
+    high_pass: bool,
+    alpha: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl Filter {
+    fn high_pass(cutoff: f32, sample_rate: u32) -> Filter {
+        let rc: f32 = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt: f32 = 1.0 / sample_rate as f32;
+        Filter {
+            high_pass: true,
+            alpha: rc / (rc + dt),
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    fn low_pass(cutoff: f32, sample_rate: u32) -> Filter {
+        let rc: f32 = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt: f32 = 1.0 / sample_rate as f32;
+        Filter {
+            high_pass: false,
+            alpha: dt / (rc + dt),
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output: f32 = if self.high_pass {
+            self.alpha * (self.last_output + input - self.last_input)
+        } else {
+            self.last_output + self.alpha * (input - self.last_output)
+        };
+        self.last_input = input;
+        self.last_output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        let mixer: Mixer = Mixer::new();
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+        // full volume on every channel peaks at roughly 1
+        let max: f32 = mixer.mix(15, 15, 15, 15, 127);
+        assert!((max - 1.0).abs() < 0.01);
+        // the mixing is nonlinear: two pulses are quieter than twice a single one
+        assert!(mixer.mix(15, 15, 0, 0, 0) < 2.0 * mixer.mix(15, 0, 0, 0, 0));
+        assert!((mixer.mix(15, 15, 0, 0, 0) - 0.2585).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_dc_gets_removed() {
+        let mut mixer: Mixer = Mixer::new();
+        mixer.set_amplitude(0, 0.5);
+        let cycles: u64 = mixer.clock_rate as u64;
+        mixer.end_time(cycles);
+
+        let mut out: Vec<i16> = vec![0; 48000];
+        let count: usize = mixer.read_samples(&mut out);
+        assert!(count > 44000);
+        // the step shows up right away and decays due to the high-pass filters
+        assert!(out[..100].iter().any(|sample| *sample > 10000));
+        assert!(out[count - 1].abs() < 100);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut mixer: Mixer = Mixer::new();
+        mixer.set_sample_rate(48000);
+        mixer.end_time(mixer.clock_rate as u64);
+        let available: usize = mixer.get_samples_available();
+        assert!(available > 47900 && available <= 48000);
+    }
+
+    #[test]
+    fn test_overflow_drops_samples() {
+        let mut mixer: Mixer = Mixer::new();
+        mixer.end_time(mixer.clock_rate as u64 * 5);
+        assert_eq!(mixer.get_samples_available(), DEFAULT_SAMPLE_RATE as usize);
+    }
+}
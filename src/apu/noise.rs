@@ -0,0 +1,167 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::region::Region;
+use crate::state::{Serialize, Serializer, StateError};
+
+/// Noise reference: https://www.nesdev.org/wiki/APU_Noise
+pub struct Noise {
+    /// 15-bit linear feedback shift register.
+    shift_register: u16,
+    /// Whether the feedback is taken from bit 6 instead of bit 1, producing short loops.
+    mode: bool,
+    periods: [u16; 16],
+    /// Index of the period in `periods`, kept to look it up again when the region changes.
+    period_index: u8,
+    timer_period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        let periods: [u16; 16] = Region::Ntsc.get_noise_periods();
+        Noise {
+            shift_register: 1,
+            mode: false,
+            periods,
+            period_index: 0,
+            timer_period: periods[0],
+            timer: 0,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.periods = region.get_noise_periods();
+        self.timer_period = self.periods[self.period_index as usize];
+    }
+
+    /// Handles writes to $400C-$400F, with `register` being the offset from $400C.
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.length_counter.set_halt((value >> 5) & 1 == 1);
+                self.envelope.write_control(value);
+            }
+            1 => {}
+            2 => {
+                self.mode = (value >> 7) & 1 == 1;
+                self.period_index = value & 0x0F;
+                self.timer_period = self.periods[self.period_index as usize];
+            }
+            3 => {
+                self.length_counter.load(value >> 3);
+                self.envelope.restart();
+            }
+            _ => panic!("Invalid noise register: {}", register),
+        }
+    }
+
+    /// Clocked on every CPU cycle, as the period table is in CPU cycles.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap: u16 = if self.mode { 6 } else { 1 };
+            let feedback: u16 = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// Returns the current volume, between 0 and 15.
+    pub fn get_output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length_counter.is_active() {
+            0
+        } else {
+            self.envelope.get_volume()
+        }
+    }
+}
+
+impl Serialize for Noise {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.shift_register);
+        s.value(&mut self.mode);
+        s.value(&mut self.period_index);
+        if self.period_index > 0x0F {
+            s.fail(StateError::Mismatch("period index"));
+            self.period_index = 0;
+        }
+        self.timer_period = self.periods[self.period_index as usize];
+        s.value(&mut self.timer);
+        s.value(&mut self.envelope);
+        s.value(&mut self.length_counter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the number of timer clocks until the shift register repeats itself.
+    fn get_sequence_length(noise: &mut Noise) -> usize {
+        let start: u16 = noise.shift_register;
+        let mut out: usize = 0;
+        loop {
+            noise.timer = 0;
+            noise.clock_timer();
+            out += 1;
+            if noise.shift_register == start {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequence_lengths() {
+        let mut noise: Noise = Noise::new();
+        assert_eq!(get_sequence_length(&mut noise), 32767);
+
+        noise.write_register(2, 0x80);
+        assert_eq!(get_sequence_length(&mut noise), 93);
+    }
+
+    #[test]
+    fn test_periods() {
+        let mut noise: Noise = Noise::new();
+        noise.write_register(2, 0x0F);
+        assert_eq!(noise.timer_period, 4068);
+
+        noise.set_region(Region::Pal);
+        assert_eq!(noise.timer_period, 3778);
+        noise.write_register(2, 0x00);
+        noise.write_register(2, 0x0F);
+        assert_eq!(noise.timer_period, 3778);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut noise: Noise = Noise::new();
+        noise.length_counter.set_enabled(true);
+        noise.write_register(0, 0b0001_0111);
+        noise.write_register(3, 0b0000_1000);
+
+        // bit 0 of the shift register being set mutes the channel
+        assert_eq!(noise.get_output(), 0);
+        noise.clock_timer();
+        assert_eq!(noise.shift_register, 1 << 14);
+        assert_eq!(noise.get_output(), 7);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::state::{Serialize, Serializer};
+
+/// Pulse reference: https://www.nesdev.org/wiki/APU_Pulse
+///
+/// Output of the sequencer for each of the four duty cycles.
+#[rustfmt::skip]
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Sweep reference: https://www.nesdev.org/wiki/APU_Sweep
+///
+/// Periodically adjusts the pulse channel's period up or down.
+pub struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+    /// The first pulse channel negates using ones' complement, the second using two's complement.
+    ones_complement: bool,
+}
+
+impl Sweep {
+    pub fn new(ones_complement: bool) -> Sweep {
+        Sweep {
+            enabled: false,
+            period: 0,
+            negate: false,
+            shift: 0,
+            divider: 0,
+            reload: false,
+            ones_complement,
+        }
+    }
+
+    /// Bit layout of the written value: <pre>
+    /// 1 << 7 => enabled
+    /// 4..=6  => divider period
+    /// 1 << 3 => negate
+    /// 0..=2  => shift count
+    /// </pre>
+    pub fn write(&mut self, value: u8) {
+        self.enabled = (value >> 7) & 1 == 1;
+        self.period = (value >> 4) & 0x07;
+        self.negate = (value >> 3) & 1 == 1;
+        self.shift = value & 0x07;
+        self.reload = true;
+    }
+
+    /// The period the channel would get changed to. This is calculated continuously, even while
+    /// the sweep unit is disabled, and mutes the channel once it overflows.
+    pub fn get_target_period(&self, timer_period: u16) -> u16 {
+        let change: u16 = timer_period >> self.shift;
+        if self.negate {
+            let change: u16 = if self.ones_complement {
+                change + 1
+            } else {
+                change
+            };
+            timer_period.saturating_sub(change)
+        } else {
+            timer_period + change
+        }
+    }
+
+    pub fn is_muting(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.get_target_period(timer_period) > 0x7FF
+    }
+
+    /// Clocked by the frame counter on every half frame. Returns the new timer period.
+    pub fn clock(&mut self, timer_period: u16) -> u16 {
+        let mut out: u16 = timer_period;
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.is_muting(timer_period) {
+            out = self.get_target_period(timer_period);
+        }
+
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+        out
+    }
+}
+
+pub struct Pulse {
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+    pub sweep: Sweep,
+    /// Expansion audio pulse channels, such as the MMC5's, come without a sweep unit.
+    has_sweep: bool,
+}
+
+impl Pulse {
+    /// `ones_complement` selects the sweep negation of the first pulse channel.
+    pub fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            sweep: Sweep::new(ones_complement),
+            has_sweep: true,
+        }
+    }
+
+    /// Creates a channel without a sweep unit, which ignores writes to the second register and
+    /// never gets muted by it.
+    pub fn new_without_sweep() -> Pulse {
+        let mut out: Pulse = Pulse::new(false);
+        out.has_sweep = false;
+        out
+    }
+
+    /// Handles writes to the channel's four registers, with `register` being the offset from the
+    /// first one ($4000 or $4004).
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.length_counter.set_halt((value >> 5) & 1 == 1);
+                self.envelope.write_control(value);
+            }
+            1 if self.has_sweep => self.sweep.write(value),
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length_counter.load(value >> 3);
+                self.sequence_step = 0;
+                self.envelope.restart();
+            }
+            _ => panic!("Invalid pulse register: {}", register),
+        }
+    }
+
+    /// Clocked on every APU cycle, which is every second CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        if self.has_sweep {
+            self.timer_period = self.sweep.clock(self.timer_period);
+        }
+    }
+
+    pub fn get_timer_period(&self) -> u16 {
+        self.timer_period
+    }
+
+    /// Returns the current volume, between 0 and 15.
+    pub fn get_output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+            || !self.length_counter.is_active()
+            || (self.has_sweep && self.sweep.is_muting(self.timer_period))
+        {
+            0
+        } else {
+            self.envelope.get_volume()
+        }
+    }
+}
+
+impl Serialize for Sweep {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.enabled);
+        s.value(&mut self.period);
+        s.value(&mut self.negate);
+        s.value(&mut self.shift);
+        s.value(&mut self.divider);
+        s.value(&mut self.reload);
+    }
+}
+
+impl Serialize for Pulse {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.duty);
+        s.value(&mut self.sequence_step);
+        s.value(&mut self.timer_period);
+        s.value(&mut self.timer);
+        s.value(&mut self.envelope);
+        s.value(&mut self.length_counter);
+        s.value(&mut self.sweep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_pulse(ones_complement: bool) -> Pulse {
+        let mut pulse: Pulse = Pulse::new(ones_complement);
+        pulse.length_counter.set_enabled(true);
+        // 50% duty, constant volume 8
+        pulse.write_register(0, 0b1001_1000);
+        pulse.write_register(2, 0x10);
+        pulse.write_register(3, 0b0000_1000);
+        pulse
+    }
+
+    #[test]
+    fn test_sequencer() {
+        let mut pulse: Pulse = create_pulse(false);
+        let mut outputs: Vec<u8> = Vec::new();
+        for _ in 0..8 {
+            outputs.push(pulse.get_output());
+            for _ in 0..=0x10 {
+                pulse.clock_timer();
+            }
+        }
+        assert_eq!(outputs, vec![0, 8, 8, 8, 8, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_muting() {
+        let mut pulse: Pulse = create_pulse(false);
+        pulse.write_register(2, 0x07);
+        pulse.write_register(3, 0b0000_1000);
+        for _ in 0..8 {
+            assert_eq!(pulse.get_output(), 0);
+            pulse.clock_timer();
+        }
+
+        // without the length counter, the channel is silent as well
+        let mut pulse: Pulse = create_pulse(false);
+        pulse.length_counter.set_enabled(false);
+        pulse.clock_timer();
+        assert_eq!(pulse.get_output(), 0);
+    }
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut ones: Sweep = Sweep::new(true);
+        let mut twos: Sweep = Sweep::new(false);
+        ones.write(0b1000_1001);
+        twos.write(0b1000_1001);
+        assert_eq!(ones.get_target_period(0x100), 0x7F);
+        assert_eq!(twos.get_target_period(0x100), 0x80);
+
+        // the divider starts at 0, so the first clock already updates the period
+        assert_eq!(ones.clock(0x100), 0x7F);
+        assert_eq!(twos.clock(0x100), 0x80);
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut pulse: Pulse = create_pulse(true);
+        pulse.write_register(1, 0b1000_0001);
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0b0000_1110);
+        // the target period of $600 + $300 overflows, which mutes the channel
+        assert!(pulse.sweep.is_muting(pulse.get_timer_period()));
+        pulse.clock_half_frame();
+        assert_eq!(pulse.get_timer_period(), 0x600);
+    }
+
+    #[test]
+    fn test_without_sweep() {
+        let mut pulse: Pulse = Pulse::new_without_sweep();
+        pulse.length_counter.set_enabled(true);
+        pulse.write_register(0, 0b1001_1000);
+        pulse.write_register(1, 0b1000_0001);
+        pulse.write_register(2, 0x04);
+        pulse.write_register(3, 0b0000_1000);
+        // periods below 8 don't mute the channel
+        for _ in 0..5 * 4 {
+            pulse.clock_timer();
+        }
+        assert_eq!(pulse.get_output(), 8);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.get_timer_period(), 0x04);
+    }
+}
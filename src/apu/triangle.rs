@@ -0,0 +1,157 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::state::{Serialize, Serializer};
+
+/// Triangle reference: https://www.nesdev.org/wiki/APU_Triangle
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+pub struct Triangle {
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    /// Doubles as the length counter halt flag.
+    control: bool,
+    linear_counter: u8,
+    linear_counter_reload: u8,
+    linear_counter_reload_flag: bool,
+    pub length_counter: LengthCounter,
+}
+
+impl Default for Triangle {
+    fn default() -> Triangle {
+        Triangle::new()
+    }
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle {
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            control: false,
+            linear_counter: 0,
+            linear_counter_reload: 0,
+            linear_counter_reload_flag: false,
+            length_counter: LengthCounter::new(),
+        }
+    }
+
+    /// Handles writes to $4008-$400B, with `register` being the offset from $4008.
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = (value >> 7) & 1 == 1;
+                self.length_counter.set_halt(self.control);
+                self.linear_counter_reload = value & 0x7F;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length_counter.load(value >> 3);
+                self.linear_counter_reload_flag = true;
+            }
+            _ => panic!("Invalid triangle register: {}", register),
+        }
+    }
+
+    /// Unlike the other channels, the triangle's timer is clocked on every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload_flag {
+            self.linear_counter = self.linear_counter_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_counter_reload_flag = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    /// Returns the current step of the sequence, between 0 and 15. Silencing the channel only
+    /// halts the sequencer, so the output keeps its last value.
+    pub fn get_output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl Serialize for Triangle {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.sequence_step);
+        s.value(&mut self.timer_period);
+        s.value(&mut self.timer);
+        s.value(&mut self.control);
+        s.value(&mut self.linear_counter);
+        s.value(&mut self.linear_counter_reload);
+        s.value(&mut self.linear_counter_reload_flag);
+        s.value(&mut self.length_counter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequencer() {
+        let mut triangle: Triangle = Triangle::new();
+        triangle.length_counter.set_enabled(true);
+        triangle.write_register(0, 0x10);
+        triangle.write_register(2, 0x02);
+        triangle.write_register(3, 0b0000_1000);
+
+        // the sequencer does not move until the linear counter got reloaded
+        triangle.clock_timer();
+        assert_eq!(triangle.get_output(), 15);
+
+        triangle.clock_quarter_frame();
+        let mut outputs: Vec<u8> = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..3 {
+                triangle.clock_timer();
+            }
+            outputs.push(triangle.get_output());
+        }
+        assert_eq!(outputs, vec![14, 13, 12, 11]);
+    }
+
+    #[test]
+    fn test_linear_counter() {
+        let mut triangle: Triangle = Triangle::new();
+        triangle.length_counter.set_enabled(true);
+        triangle.write_register(0, 0x02);
+        triangle.write_register(3, 0b0000_1000);
+
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 2);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 0);
+
+        // with the control flag set, the counter keeps getting reloaded
+        triangle.write_register(0, 0x82);
+        triangle.write_register(3, 0b0000_1000);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 2);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::mapper::Mapper;
+use std::path::{Path, PathBuf};
+
+/// Number of frames between checks for changed save data, about 5 seconds.
+pub const FLUSH_INTERVAL: u64 = 300;
+
+/// Keeps a cartridge's battery-backed memory, as returned by [Mapper::get_save_data], in a
+/// `.sav` file. The file holds the raw bytes of the memory, like in other emulators, and only
+/// gets written when they changed since the last flush.
+pub struct SaveFile {
+    path: PathBuf,
+    /// The contents of the file.
+    saved: Vec<u8>,
+}
+
+impl SaveFile {
+    /// The save file of a ROM, which is next to it with the `.sav` extension.
+    pub fn get_path(rom: &Path) -> PathBuf {
+        rom.with_extension("sav")
+    }
+
+    /// Loads the save file into the cartridge, if there is one. Files of the wrong size get
+    /// truncated or padded with zeros. Returns `None` for cartridges without battery-backed
+    /// memory.
+    pub fn open(path: PathBuf, mapper: &mut dyn Mapper) -> std::io::Result<Option<SaveFile>> {
+        let Some(data) = mapper.get_save_data_mut() else {
+            return Ok(None);
+        };
+        let mut saved: Vec<u8> = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => data.to_vec(),
+            Err(e) => return Err(e),
+        };
+        saved.resize(data.len(), 0);
+        data.copy_from_slice(&saved);
+        Ok(Some(SaveFile { path, saved }))
+    }
+
+    /// Writes the cartridge's memory to the file if it changed, returning whether it did. The
+    /// bytes go to a temporary file first, so the save survives being interrupted.
+    pub fn flush(&mut self, mapper: &dyn Mapper) -> std::io::Result<bool> {
+        let Some(data) = mapper.get_save_data() else {
+            return Ok(false);
+        };
+        let exists: bool = self.path.exists();
+        if exists && data == self.saved.as_slice() {
+            return Ok(false);
+        }
+        // nothing worth creating a file for yet
+        if !exists && data.iter().all(|byte| *byte == 0) {
+            return Ok(false);
+        }
+        let temp: PathBuf = self.path.with_extension("sav.tmp");
+        std::fs::write(&temp, data)?;
+        std::fs::rename(&temp, &self.path)?;
+        self.saved = data.to_vec();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::nrom::Nrom;
+    use crate::mapper::tests::create_rom;
+    use crate::rom::Rom;
+
+    fn create_mapper(battery: bool) -> Nrom {
+        let mut rom: Rom = create_rom(0, 1, 1);
+        rom.header.battery = battery;
+        rom.header.prg_ram_size = 0x2000;
+        Nrom::new(rom)
+    }
+
+    fn create_dir(name: &str) -> PathBuf {
+        let dir: PathBuf = std::env::temp_dir().join(format!("nes-emulator-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            SaveFile::get_path(Path::new("roms/Zelda.nes")),
+            PathBuf::from("roms/Zelda.sav")
+        );
+    }
+
+    #[test]
+    fn test_no_battery() {
+        let dir: PathBuf = create_dir("no-battery");
+        let mut mapper: Nrom = create_mapper(false);
+        assert!(
+            SaveFile::open(dir.join("game.sav"), &mut mapper)
+                .unwrap()
+                .is_none()
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_flush_and_load() {
+        let dir: PathBuf = create_dir("flush");
+        let path: PathBuf = dir.join("game.sav");
+        let mut mapper: Nrom = create_mapper(true);
+        let mut save: SaveFile = SaveFile::open(path.clone(), &mut mapper).unwrap().unwrap();
+        // blank memory doesn't create a file
+        assert!(!save.flush(&mapper).unwrap());
+        assert!(!path.exists());
+
+        mapper.cpu_write(0x6010, 0x42);
+        assert!(save.flush(&mapper).unwrap());
+        assert!(!save.flush(&mapper).unwrap());
+        let bytes: Vec<u8> = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 0x2000);
+        assert_eq!(bytes[0x10], 0x42);
+
+        let mut mapper: Nrom = create_mapper(true);
+        SaveFile::open(path.clone(), &mut mapper).unwrap().unwrap();
+        assert_eq!(mapper.cpu_read(0x6010), 0x42);
+
+        // short files get padded
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+        let mut mapper: Nrom = create_mapper(true);
+        SaveFile::open(path, &mut mapper).unwrap().unwrap();
+        assert_eq!(mapper.cpu_read(0x6002), 3);
+        assert_eq!(mapper.cpu_read(0x6003), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,15 @@
+use crate::apu::Apu;
+use crate::input::InputDevice;
+use crate::input::controller::Controller;
+use crate::mapper::{Mapper, Scanline};
+use crate::parser::Parser;
+use crate::region::Region;
+use crate::rom::{Rom, RomError};
+use crate::state::{Serializer, StateError, StateReader, StateWriter};
+use std::cell::Cell;
+
 pub struct Cpu {
-    memory: [u8; 0xFFFF],
+    memory: [u8; 0x10000],
     program_counter: u16,
     /// Initially starts at 255. Each push decreases this value by one, each pop increases it.
     stack_pointer: u8,
@@ -17,9 +27,33 @@ pub struct Cpu {
     /// </pre>
     processor_status: u8,
 
-    cycle: u32,
+    cycle: u64,
+    /// Number of frames completed by [Cpu::run_frame].
+    frame: u64,
 
     change_interrupt_disable_flag: i8,
+
+    region: Region,
+
+    apu: Apu,
+
+    /// The devices plugged into controller ports 1 and 2, read through $4016 and $4017.
+    ports: [Option<Box<dyn InputDevice>>; 2],
+    /// The device plugged into the Famicom expansion port, read through both.
+    expansion: Option<Box<dyn InputDevice>>,
+    /// The port read from by the instruction being executed, if any. See [Cpu::step].
+    input_read: Cell<Option<usize>>,
+
+    /// The inserted cartridge. Without one, the whole address space is backed by `memory`.
+    mapper: Option<Box<dyn Mapper>>,
+    /// Number of CPU cycles the mapper has been clocked for.
+    mapper_cycle: u64,
+    /// Number of scanlines the mapper has been notified about.
+    scanline: u64,
+    /// Set by an op code the CPU doesn't know, after which it stops until the next reset.
+    jammed: bool,
+
+    parser: Parser,
 }
 
 /// Instruction reference: https://www.nesdev.org/wiki/Instruction_reference
@@ -44,31 +78,39 @@ impl Instruction {
     }
 }
 
+const DOTS_PER_SCANLINE: u64 = 341;
+
 const BRANCHING_OP_CODES: [u8; 14] = [
     0x90, 0xB0, 0xF0, 0x30, 0xD0, 0x10, 0x00, 0x50, 0x70, 0x4C, 0x6C, 0x20, 0x40, 0x60,
 ];
 
-fn increment_if_crossed_absolute(base: u32, addr: u16, inc: u8) -> u32 {
-    if ((addr + inc as u16) & 0xFF00) == (addr & 0xFF00) {
+fn increment_if_crossed_absolute(base: u64, addr: u16, inc: u8) -> u64 {
+    if (addr.wrapping_add(inc as u16) & 0xFF00) == (addr & 0xFF00) {
         base
     } else {
         base + 1
     }
 }
 
-fn increment_if_crossed_indirect_indexed(base: u32, addr: u8, cpu: &Cpu) -> u32 {
+fn increment_if_crossed_indirect_indexed(base: u64, addr: u8, cpu: &Cpu) -> u64 {
     let indirect_indexed: u16 = cpu.get_addr_indirect_indexed_index(addr) as u16;
-    if ((indirect_indexed - cpu.index_y as u16) & 0xFF00) == (indirect_indexed & 0xFF00) {
+    if (indirect_indexed.wrapping_sub(cpu.index_y as u16) & 0xFF00) == (indirect_indexed & 0xFF00) {
         base
     } else {
         base + 1
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Cpu {
         Cpu {
-            memory: [0; 65535],
+            memory: [0; 0x10000],
             program_counter: 0,
             stack_pointer: 0xFF,
             accumulator: 0,
@@ -76,11 +118,384 @@ impl Cpu {
             index_y: 0,
             processor_status: 0,
             cycle: 0,
+            frame: 0,
             change_interrupt_disable_flag: -1,
+            region: Region::Ntsc,
+            apu: Apu::new(),
+            ports: [
+                Some(Box::new(Controller::new())),
+                Some(Box::new(Controller::new())),
+            ],
+            expansion: None,
+            input_read: Cell::new(None),
+            mapper: None,
+            mapper_cycle: 0,
+            scanline: 0,
+            jammed: false,
+            parser: Parser::new(),
+        }
+    }
+
+    pub fn get_region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.apu.set_region(region);
+    }
+
+    /// Number of master clock ticks elapsed since power-on.
+    pub fn get_master_clock(&self) -> u64 {
+        self.cycle * self.region.get_cpu_divider() as u64
+    }
+
+    /// Number of PPU dots elapsed since power-on. On PAL, the PPU runs 3.2 dots per CPU cycle.
+    pub fn get_ppu_dots(&self) -> u64 {
+        self.get_master_clock() / self.region.get_ppu_divider() as u64
+    }
+
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn get_frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Inserts the cartridge the ROM describes, failing if its mapper isn't supported.
+    /// Inserts the cartridge and switches to the region its header asks for. Call
+    /// [`Cpu::set_region`] afterwards to force another one.
+    pub fn load_rom(&mut self, rom: Rom) -> Result<(), RomError> {
+        self.set_region(Region::detect(&rom.header, None));
+        self.insert_cartridge(crate::mapper::create(rom)?);
+        Ok(())
+    }
+
+    pub fn insert_cartridge(&mut self, mapper: Box<dyn Mapper>) {
+        self.mapper = Some(mapper);
+        self.mapper_cycle = self.cycle;
+        self.scanline = self.get_ppu_dots() / DOTS_PER_SCANLINE;
+    }
+
+    pub fn get_mapper(&self) -> Option<&dyn Mapper> {
+        self.mapper.as_deref()
+    }
+
+    pub fn get_mapper_mut(&mut self) -> Option<&mut (dyn Mapper + 'static)> {
+        self.mapper.as_deref_mut()
+    }
+
+    /// Runs until the end of the current frame. Frames don't span a whole number of CPU cycles,
+    /// so the boundaries are derived from the frame count to keep them from drifting.
+    pub fn run_frame(&mut self) {
+        self.frame += 1;
+        let end: u64 = (self.frame as f64 * self.region.get_cpu_cycles_per_frame()).floor() as u64;
+        while self.cycle < end {
+            self.step();
+        }
+        self.sync_mapper();
+        self.sync_apu();
+    }
+
+    pub fn get_apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn get_apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Plugs a device into the given controller port, 0 for $4016 and 1 for $4017. Both start
+    /// out with a standard controller.
+    pub fn set_port(&mut self, port: usize, device: Option<Box<dyn InputDevice>>) {
+        self.ports[port] = device;
+    }
+
+    /// Used to set the device's input state, usually once per frame.
+    pub fn get_port_mut(&mut self, port: usize) -> Option<&mut (dyn InputDevice + 'static)> {
+        self.ports[port].as_deref_mut()
+    }
+
+    pub fn set_expansion(&mut self, device: Option<Box<dyn InputDevice>>) {
+        self.expansion = device;
+    }
+
+    pub fn get_expansion_mut(&mut self) -> Option<&mut (dyn InputDevice + 'static)> {
+        self.expansion.as_deref_mut()
+    }
+
+    /// Snapshots the whole machine: the CPU, its memory, the APU, the cartridge's registers and
+    /// RAM, and the input devices. The cartridge's ROM isn't included, so the state only loads
+    /// back with the same cartridge inserted.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut writer: StateWriter = StateWriter::new();
+        writer.add_section(*b"CPU ", |s| self.serialize_registers(s));
+        writer.add_section(*b"RAM ", |s| s.bytes(&mut self.memory));
+        writer.add_section(*b"APU ", |s| s.value(&mut self.apu));
+        if let Some(mapper) = self.mapper.as_mut() {
+            writer.add_section(*b"MAPR", |s| mapper.serialize(s));
+        }
+        writer.add_section(*b"INPT", |s| self.serialize_input(s));
+        writer.into_bytes()
+    }
+
+    /// Restores a state made by [Cpu::save_state], which has to come from a machine of the same
+    /// region. On failure, the machine is left untouched.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let reader: StateReader = StateReader::new(bytes)?;
+        if reader.has_section(*b"MAPR") != self.mapper.is_some() {
+            return Err(StateError::Mismatch("cartridge"));
+        }
+
+        let backup: Vec<u8> = self.save_state();
+        let result: Result<(), StateError> = self.load_sections(&reader);
+        if result.is_err() {
+            let reader: StateReader = StateReader::new(&backup)?;
+            self.load_sections(&reader)?;
+        }
+        result
+    }
+
+    fn load_sections(&mut self, reader: &StateReader) -> Result<(), StateError> {
+        reader.load_section(*b"CPU ", |s| self.serialize_registers(s))?;
+        reader.load_section(*b"RAM ", |s| s.bytes(&mut self.memory))?;
+        reader.load_section(*b"APU ", |s| s.value(&mut self.apu))?;
+        if let Some(mapper) = self.mapper.as_mut() {
+            reader.load_section(*b"MAPR", |s| mapper.serialize(s))?;
+        }
+        reader.load_section(*b"INPT", |s| self.serialize_input(s))
+    }
+
+    fn serialize_registers(&mut self, s: &mut Serializer) {
+        s.value(&mut self.program_counter);
+        s.value(&mut self.stack_pointer);
+        s.value(&mut self.accumulator);
+        s.value(&mut self.index_x);
+        s.value(&mut self.index_y);
+        s.value(&mut self.processor_status);
+        s.value(&mut self.cycle);
+        s.value(&mut self.frame);
+        s.value(&mut self.change_interrupt_disable_flag);
+        s.value(&mut self.mapper_cycle);
+        s.value(&mut self.scanline);
+        s.value(&mut self.jammed);
+        let mut region: Region = self.region;
+        s.value(&mut region);
+        if region != self.region {
+            s.fail(StateError::Mismatch("region"));
+        }
+    }
+
+    /// The devices get loaded into whatever is plugged in, which has to match what was plugged
+    /// in when saving.
+    fn serialize_input(&mut self, s: &mut Serializer) {
+        for device in self.ports.iter_mut().chain([&mut self.expansion]) {
+            let mut plugged_in: bool = device.is_some();
+            s.value(&mut plugged_in);
+            if plugged_in != device.is_some() {
+                s.fail(StateError::Mismatch("input devices"));
+            }
+            if let Some(device) = device {
+                device.serialize(s);
+            }
+        }
+    }
+
+    /// Reads a byte from the CPU's address space, dispatching to the memory mapped registers.
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.apu.read_status(),
+            0x4016 | 0x4017 => self.read_input(addr as usize - 0x4016),
+            0x4020..=0xFFFF if self.mapper.is_some() => {
+                self.mapper.as_ref().unwrap().cpu_read(addr)
+            }
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    /// Writes a byte into the CPU's address space, dispatching to the memory mapped registers.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2000..=0x3FFF => {
+                if let Some(mapper) = self.mapper.as_mut() {
+                    mapper.notify_ppu_register_write(addr, value);
+                }
+                self.memory[addr as usize] = value;
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                self.sync_apu();
+                self.apu.write_register(addr, value);
+            }
+            0x4016 => {
+                let devices = self.ports.iter_mut().chain([&mut self.expansion]);
+                for device in devices.flatten() {
+                    device.write(value);
+                }
+                self.memory[addr as usize] = value;
+            }
+            0x4020..=0xFFFF if self.mapper.is_some() => {
+                self.sync_mapper();
+                self.mapper.as_mut().unwrap().cpu_write(addr, value);
+            }
+            _ => self.memory[addr as usize] = value,
+        }
+    }
+
+    /// Only bits 0-4 are driven by the input devices, the upper bits are open bus and keep the
+    /// high byte of the address, $40.
+    fn read_input(&self, port: usize) -> u8 {
+        self.input_read.set(Some(port));
+        let dots: u64 = self.get_ppu_dots();
+        let scanline: u64 = dots / DOTS_PER_SCANLINE % self.region.get_scanlines_per_frame() as u64;
+        let dot: u64 = dots % DOTS_PER_SCANLINE;
+
+        let devices = [&self.ports[port], &self.expansion];
+        let value: u8 = devices.into_iter().flatten().fold(0, |value, device| {
+            device.notify_beam(scanline as u16, dot as u16);
+            value | device.read(port)
+        });
+        0x40 | value & 0x1F
+    }
+
+    /// Catches the APU up to the current cycle, servicing the DMC's sample fetches on the way.
+    /// Every fetch stalls the CPU for 4 cycles. The cartridge's expansion audio gets mixed in at
+    /// its current level.
+    fn sync_apu(&mut self) {
+        if let Some(mapper) = &self.mapper {
+            self.apu.set_expansion_output(mapper.get_audio_output());
+        }
+        loop {
+            self.apu.run_until(self.cycle);
+            match self.apu.dmc.get_dma_address() {
+                Some(addr) => {
+                    let value: u8 = self.read(addr);
+                    self.apu.dmc.complete_dma(value);
+                    self.cycle += 4;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Catches the cartridge up to the current cycle. There is no PPU to report its scanlines,
+    /// so they are derived from the PPU's dot count instead, as if rendering was always enabled.
+    fn sync_mapper(&mut self) {
+        let scanlines: u64 = self.get_ppu_dots() / DOTS_PER_SCANLINE;
+        let scanlines_per_frame: u64 = self.region.get_scanlines_per_frame() as u64;
+        let Some(mapper) = self.mapper.as_mut() else {
+            return;
+        };
+
+        while self.mapper_cycle < self.cycle {
+            mapper.clock_cpu();
+            self.mapper_cycle += 1;
+        }
+        while self.scanline < scanlines {
+            let line: u64 = self.scanline % scanlines_per_frame;
+            if line < 240 {
+                mapper.notify_scanline(Scanline::Visible(line as u8));
+            } else if line == 240 {
+                mapper.notify_scanline(Scanline::PostRender);
+            } else if line == scanlines_per_frame - 1 {
+                mapper.notify_scanline(Scanline::PreRender);
+            }
+            self.scanline += 1;
+        }
+    }
+
+    /// Whether any device is pulling the IRQ line low.
+    pub fn get_irq_line(&self) -> bool {
+        self.apu.get_irq() || self.mapper.as_ref().is_some_and(|mapper| mapper.get_irq())
+    }
+
+    /// Jumps to the reset vector at $FFFC, with interrupts disabled.
+    pub fn reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.set_flag_interrupt(true);
+        self.program_counter = u16::from_be_bytes([self.read(0xFFFD), self.read(0xFFFC)]);
+        self.cycle += 7;
+        self.jammed = false;
+    }
+
+    /// Whether the CPU hit an op code it doesn't know. Jammed CPUs ignore interrupts and only let
+    /// time pass, until reset.
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    /// Pushes the program counter and processor status, then jumps to the address stored at
+    /// `vector`. Unlike with BRK, the pushed break flag is cleared.
+    fn interrupt(&mut self, vector: u16) {
+        let bytes: [u8; 2] = self.program_counter.to_be_bytes();
+        self.push(bytes[0]);
+        self.push(bytes[1]);
+        self.push(self.get_processor_status() & !(1 << 4));
+        self.set_flag_interrupt(true);
+
+        self.program_counter = u16::from_be_bytes([self.read(vector + 1), self.read(vector)]);
+        self.cycle += 7;
+    }
+
+    /// Services a pending IRQ, or fetches and executes the instruction at the program counter.
+    pub fn step(&mut self) {
+        self.sync_mapper();
+        self.sync_apu();
+        if self.jammed {
+            self.cycle += 1;
+            return;
+        }
+        if self.get_irq_line() && !self.get_flag_interrupt() {
+            self.interrupt(0xFFFE);
+            return;
+        }
+
+        let Some(inst) = self.fetch_instruction() else {
+            self.jammed = true;
+            self.cycle += 1;
+            return;
+        };
+        self.input_read.set(None);
+        self.execute_instruction(&inst);
+        if let Some(port) = self.input_read.take() {
+            self.apply_dma_read_conflict(port);
+        }
+    }
+
+    /// DMA reference: https://www.nesdev.org/wiki/DMA#DMC_DMA_during_register_reads
+    ///
+    /// When a DMC sample fetch halts the CPU on the cycle it reads $4016 or $4017, the CPU keeps
+    /// repeating the read while halted, clocking the input devices once more and making them drop
+    /// a bit. Instructions execute at once here, so the extra clock is applied after
+    /// the CPU's read instead of before it: the dropped button is the one following it.
+    fn apply_dma_read_conflict(&mut self, port: usize) {
+        self.apu.run_until(self.cycle);
+        // the read happens on the instruction's last cycle
+        if self.apu.dmc.get_dma_address().is_some() && self.apu.get_cycle() == self.cycle - 1 {
+            self.read_input(port);
         }
     }
 
+    /// Decodes the instruction at the program counter. Its arguments are stored high byte first.
+    /// Returns `None` for unknown op codes.
+    fn fetch_instruction(&self) -> Option<Instruction> {
+        let op_code: u8 = self.read(self.program_counter);
+        let size: u8 = self.parser.get_instruction_length(op_code)?;
+
+        let arguments: [u8; 2] = match size {
+            1 => [0, 0],
+            2 => [self.read(self.program_counter.wrapping_add(1)), 0],
+            _ => [
+                self.read(self.program_counter.wrapping_add(2)),
+                self.read(self.program_counter.wrapping_add(1)),
+            ],
+        };
+        Some(Instruction::new(op_code, arguments, size))
+    }
+
     pub fn execute_instruction(&mut self, inst: &Instruction) {
+        self.sync_apu();
+
         if self.change_interrupt_disable_flag != -1 {
             self.set_flag_interrupt(self.change_interrupt_disable_flag != 0);
             self.change_interrupt_disable_flag = -1;
@@ -104,7 +519,7 @@ impl Cpu {
 
                 0x00 => {
                     // TODO implement the brk hardware bug
-                    let val: u16 = self.program_counter + 2;
+                    let val: u16 = self.program_counter.wrapping_add(2);
                     let bytes: [u8; 2] = val.to_be_bytes();
                     self.push(bytes[0]);
                     self.push(bytes[1]);
@@ -113,28 +528,28 @@ impl Cpu {
                     self.set_flag_interrupt(true);
 
                     self.cycle += 7;
-                    0xFFFE
+                    u16::from_be_bytes([self.read(0xFFFF), self.read(0xFFFE)])
                 }
 
                 0x4C => {
                     self.cycle += 3;
-                    self.get_addr_absolute(inst.get_absolute_addr()) as u16
+                    inst.get_absolute_addr()
                 }
                 0x6C => {
                     self.cycle += 5;
                     // TODO: implement JMP hardware bug
-                    let addr: usize = inst.get_absolute_addr() as usize;
-                    u16::from_be_bytes([self.memory[addr + 1], self.memory[addr]])
+                    let addr: u16 = inst.get_absolute_addr();
+                    u16::from_be_bytes([self.read(addr.wrapping_add(1)), self.read(addr)])
                 }
 
                 0x20 => {
                     // jsr
-                    let val: u16 = self.program_counter + 2;
+                    let val: u16 = self.program_counter.wrapping_add(2);
                     let bytes: [u8; 2] = val.to_be_bytes();
                     self.push(bytes[0]);
                     self.push(bytes[1]);
                     self.cycle += 6;
-                    self.get_addr_absolute(inst.get_absolute_addr()) as u16
+                    inst.get_absolute_addr()
                 }
 
                 0x40 => {
@@ -150,7 +565,7 @@ impl Cpu {
                     let low: u8 = self.pop();
                     let high: u8 = self.pop();
                     self.cycle += 6;
-                    u16::from_be_bytes([high, low]) + 1
+                    u16::from_be_bytes([high, low]).wrapping_add(1)
                 }
 
                 _ => panic!(
@@ -269,18 +684,21 @@ impl Cpu {
             0xC6 => self.execute_dec(self.get_addr_zero_index(inst.arguments[0]) as u16, 5),
             0xD6 => self.execute_dec(self.get_addr_zero_x_index(inst.arguments[0]) as u16, 6),
             0xCE => self.execute_dec(inst.get_absolute_addr(), 6),
-            0xDE => self.execute_dec(inst.get_absolute_addr() + self.index_x as u16, 7),
+            0xDE => self.execute_dec(
+                inst.get_absolute_addr().wrapping_add(self.index_x as u16),
+                7,
+            ),
 
             0xCA => {
                 // dex
-                self.index_x -= 1;
+                self.index_x = self.index_x.wrapping_sub(1);
                 self.set_flag_zero_by_val(self.index_x);
                 self.set_flag_negative_by_val(self.index_x);
                 self.cycle += 2;
             }
             0x88 => {
                 // dey
-                self.index_y -= 1;
+                self.index_y = self.index_y.wrapping_sub(1);
                 self.set_flag_zero_by_val(self.index_y);
                 self.set_flag_negative_by_val(self.index_y);
                 self.cycle += 2;
@@ -307,18 +725,21 @@ impl Cpu {
             0xE6 => self.execute_inc(self.get_addr_zero_index(inst.arguments[0]) as u16, 5),
             0xF6 => self.execute_inc(self.get_addr_zero_x_index(inst.arguments[0]) as u16, 6),
             0xEE => self.execute_inc(inst.get_absolute_addr(), 6),
-            0xFE => self.execute_inc(inst.get_absolute_addr() + self.index_x as u16, 7),
+            0xFE => self.execute_inc(
+                inst.get_absolute_addr().wrapping_add(self.index_x as u16),
+                7,
+            ),
 
             0xE8 => {
                 // inx
-                self.index_x += 1;
+                self.index_x = self.index_x.wrapping_add(1);
                 self.set_flag_zero_by_val(self.index_x);
                 self.set_flag_negative_by_val(self.index_x);
                 self.cycle += 2;
             }
             0xC8 => {
                 // iny
-                self.index_y += 1;
+                self.index_y = self.index_y.wrapping_add(1);
                 self.set_flag_zero_by_val(self.index_y);
                 self.set_flag_negative_by_val(self.index_y);
                 self.cycle += 2;
@@ -510,12 +931,12 @@ impl Cpu {
             ),
             0x8D => self.execute_st(inst.get_absolute_addr(), self.accumulator, 4),
             0x9D => self.execute_st(
-                inst.get_absolute_addr() + self.index_x as u16,
+                inst.get_absolute_addr().wrapping_add(self.index_x as u16),
                 self.accumulator,
                 5,
             ),
             0x99 => self.execute_st(
-                inst.get_absolute_addr() + self.index_y as u16,
+                inst.get_absolute_addr().wrapping_add(self.index_y as u16),
                 self.accumulator,
                 5,
             ),
@@ -591,10 +1012,10 @@ impl Cpu {
 
             _ => panic!("Unknown op code received: {}", inst.op_code),
         };
-        self.program_counter += inst.size as u16
+        self.program_counter = self.program_counter.wrapping_add(inst.size as u16)
     }
 
-    fn execute_adc(&mut self, memory: u8, cycles: u32) {
+    fn execute_adc(&mut self, memory: u8, cycles: u64) {
         let result: u16 = self.accumulator as u16 + memory as u16 + self.get_flag_carry() as u16;
         self.set_flag_carry_by_val(result);
         self.set_flag_zero_by_val(result as u8);
@@ -606,7 +1027,7 @@ impl Cpu {
         self.cycle += cycles
     }
 
-    fn execute_and(&mut self, memory: u8, cycles: u32) {
+    fn execute_and(&mut self, memory: u8, cycles: u64) {
         let result: u8 = self.accumulator & memory;
         self.set_flag_zero_by_val(result);
         self.set_flag_negative_by_val(result);
@@ -614,7 +1035,7 @@ impl Cpu {
         self.cycle += cycles
     }
 
-    fn execute_asl<R>(&mut self, value: u8, r: R, cycles: u32)
+    fn execute_asl<R>(&mut self, value: u8, r: R, cycles: u64)
     where
         R: Fn(&mut Cpu, u8),
     {
@@ -629,9 +1050,12 @@ impl Cpu {
     fn branch_if_condition(&mut self, value: u8, condition: bool) -> u16 {
         self.cycle += 2;
         if !condition {
-            self.program_counter + 2
+            self.program_counter.wrapping_add(2)
         } else {
-            let new_pc = self.program_counter + 2 + value.cast_signed() as u16;
+            let new_pc = self
+                .program_counter
+                .wrapping_add(2)
+                .wrapping_add_signed(value.cast_signed() as i16);
             self.cycle += if new_pc & 0xFF00 == self.program_counter & 0xFF00 {
                 1
             } else {
@@ -641,7 +1065,7 @@ impl Cpu {
         }
     }
 
-    fn execute_bit(&mut self, value: u8, cycles: u32) {
+    fn execute_bit(&mut self, value: u8, cycles: u64) {
         let result: u8 = self.accumulator & value;
         self.set_flag_zero_by_val(result);
         self.set_flag_overflow_by_val(result);
@@ -650,7 +1074,7 @@ impl Cpu {
         self.cycle += cycles;
     }
 
-    fn execute_cmp(&mut self, value: u8, cycles: u32) {
+    fn execute_cmp(&mut self, value: u8, cycles: u64) {
         self.set_flag_carry(self.accumulator >= value);
         self.set_flag_zero(self.accumulator == value);
         self.set_flag_negative(self.accumulator < value);
@@ -658,7 +1082,7 @@ impl Cpu {
         self.cycle += cycles
     }
 
-    fn execute_cmx(&mut self, value: u8, cycles: u32) {
+    fn execute_cmx(&mut self, value: u8, cycles: u64) {
         self.set_flag_carry(self.index_x >= value);
         self.set_flag_zero(self.index_x == value);
         self.set_flag_negative(self.index_x < value);
@@ -666,7 +1090,7 @@ impl Cpu {
         self.cycle += cycles
     }
 
-    fn execute_cmy(&mut self, value: u8, cycles: u32) {
+    fn execute_cmy(&mut self, value: u8, cycles: u64) {
         self.set_flag_carry(self.index_y >= value);
         self.set_flag_zero(self.index_y == value);
         self.set_flag_negative(self.index_y < value);
@@ -674,51 +1098,51 @@ impl Cpu {
         self.cycle += cycles
     }
 
-    fn execute_dec(&mut self, addr: u16, cycles: u32) {
-        let result: u8 = self.memory[addr as usize] - 1;
-        self.memory[addr as usize] = result;
+    fn execute_dec(&mut self, addr: u16, cycles: u64) {
+        let result: u8 = self.read(addr).wrapping_sub(1);
+        self.write(addr, result);
         self.set_flag_zero_by_val(result);
         self.set_flag_negative_by_val(result);
         self.cycle += cycles;
     }
 
-    fn execute_eor(&mut self, value: u8, cycles: u32) {
+    fn execute_eor(&mut self, value: u8, cycles: u64) {
         self.accumulator ^= value;
         self.set_flag_zero_by_val(self.accumulator);
         self.set_flag_negative_by_val(self.accumulator);
         self.cycle += cycles;
     }
 
-    fn execute_inc(&mut self, addr: u16, cycles: u32) {
-        let result: u8 = self.memory[addr as usize] + 1;
-        self.memory[addr as usize] = result;
+    fn execute_inc(&mut self, addr: u16, cycles: u64) {
+        let result: u8 = self.read(addr).wrapping_add(1);
+        self.write(addr, result);
         self.set_flag_zero_by_val(result);
         self.set_flag_negative_by_val(result);
         self.cycle += cycles;
     }
 
-    fn execute_lda(&mut self, value: u8, cycles: u32) {
+    fn execute_lda(&mut self, value: u8, cycles: u64) {
         self.accumulator = value;
         self.set_flag_zero_by_val(self.accumulator);
         self.set_flag_negative_by_val(self.accumulator);
         self.cycle += cycles;
     }
 
-    fn execute_ldx(&mut self, value: u8, cycles: u32) {
+    fn execute_ldx(&mut self, value: u8, cycles: u64) {
         self.index_x = value;
         self.set_flag_zero_by_val(self.index_x);
         self.set_flag_negative_by_val(self.index_x);
         self.cycle += cycles;
     }
 
-    fn execute_ldy(&mut self, value: u8, cycles: u32) {
+    fn execute_ldy(&mut self, value: u8, cycles: u64) {
         self.index_y = value;
         self.set_flag_zero_by_val(self.index_y);
         self.set_flag_negative_by_val(self.index_y);
         self.cycle += cycles;
     }
 
-    fn execute_lsr<R>(&mut self, value: u8, r: R, cycles: u32)
+    fn execute_lsr<R>(&mut self, value: u8, r: R, cycles: u64)
     where
         R: Fn(&mut Cpu, u8),
     {
@@ -730,14 +1154,14 @@ impl Cpu {
         self.cycle += cycles
     }
 
-    fn execute_ora(&mut self, value: u8, cycles: u32) {
+    fn execute_ora(&mut self, value: u8, cycles: u64) {
         self.accumulator |= value;
         self.set_flag_zero_by_val(self.accumulator);
         self.set_flag_negative_by_val(self.accumulator);
         self.cycle += cycles;
     }
 
-    fn execute_rol<R>(&mut self, value: u8, r: R, cycles: u32)
+    fn execute_rol<R>(&mut self, value: u8, r: R, cycles: u64)
     where
         R: Fn(&mut Cpu, u8),
     {
@@ -749,7 +1173,7 @@ impl Cpu {
         self.cycle += cycles;
     }
 
-    fn execute_ror<R>(&mut self, value: u8, r: R, cycles: u32)
+    fn execute_ror<R>(&mut self, value: u8, r: R, cycles: u64)
     where
         R: Fn(&mut Cpu, u8),
     {
@@ -761,22 +1185,22 @@ impl Cpu {
         self.cycle += cycles;
     }
 
-    fn execute_sbc(&mut self, value: u8, cycles: u32) {
+    fn execute_sbc(&mut self, value: u8, cycles: u64) {
         self.execute_adc(value ^ 0xFF, cycles)
     }
 
-    fn execute_st(&mut self, addr: u16, value: u8, cycles: u32) {
-        self.memory[addr as usize] = value;
+    fn execute_st(&mut self, addr: u16, value: u8, cycles: u64) {
+        self.write(addr, value);
         self.cycle += cycles;
     }
 
     fn push(&mut self, val: u8) {
         self.memory[self.stack_pointer as usize + 0x0100] = val;
-        self.stack_pointer -= 1;
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
     fn pop(&mut self) -> u8 {
-        self.stack_pointer += 1;
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
         self.memory[self.stack_pointer as usize + 0x0100]
     }
 
@@ -842,23 +1266,23 @@ impl Cpu {
         self.memory[self.get_addr_zero_x_index(arg) as usize] = value
     }
     fn get_addr_absolute(&self, arg: u16) -> u8 {
-        self.memory[arg as usize]
+        self.read(arg)
     }
     fn set_addr_absolute(&mut self, arg: u16, value: u8) {
-        self.memory[arg as usize] = value
+        self.write(arg, value)
     }
     fn get_addr_absolute_x(&self, arg: u16) -> u8 {
-        self.memory[(arg + self.index_x as u16) as usize]
+        self.read(arg.wrapping_add(self.index_x as u16))
     }
     fn set_addr_absolute_x(&mut self, arg: u16, value: u8) {
-        self.memory[(arg + self.index_x as u16) as usize] = value
+        self.write(arg.wrapping_add(self.index_x as u16), value)
     }
     fn get_addr_absolute_y(&self, arg: u16) -> u8 {
-        self.memory[(arg + self.index_y as u16) as usize]
+        self.read(arg.wrapping_add(self.index_y as u16))
     }
     /// (Indirect,X)
     fn get_addr_indexed_indirect(&self, arg: u8) -> u8 {
-        self.memory[self.get_addr_indexed_indirect_index(arg)]
+        self.read(self.get_addr_indexed_indirect_index(arg) as u16)
     }
     /// (Indirect,X)
     ///
@@ -866,13 +1290,13 @@ impl Cpu {
     /// (arg + x) points to the low byte, (arg + x + 1) points to the high byte.
     #[rustfmt::skip]
     fn get_addr_indexed_indirect_index(&self, arg: u8) -> usize {
-        (((self.memory[((arg + self.index_x + 1) as usize) & 0xFF] as u16) << 8)
-            | self.memory[((arg + self.index_x) & 0xFF) as usize] as u16
+        (((self.memory[arg.wrapping_add(self.index_x).wrapping_add(1) as usize] as u16) << 8)
+            | self.memory[arg.wrapping_add(self.index_x) as usize] as u16
         ) as usize
     }
     /// (Indirect),Y
     fn get_addr_indirect_indexed(&self, arg: u8) -> u8 {
-        self.memory[self.get_addr_indirect_indexed_index(arg)]
+        self.read(self.get_addr_indirect_indexed_index(arg) as u16)
     }
     /// (Indirect),Y
     ///
@@ -881,9 +1305,9 @@ impl Cpu {
     #[rustfmt::skip]
     fn get_addr_indirect_indexed_index(&self, arg: u8) -> usize {
         (
-            (((self.memory[(arg as usize + 1) & 0xFF] as u16) << 8)
+            (((self.memory[arg.wrapping_add(1) as usize] as u16) << 8)
                 | self.memory[arg as usize] as u16)
-            + self.index_y as u16
+            .wrapping_add(self.index_y as u16)
         ) as usize
     }
     //</editor-fold>
@@ -971,6 +1395,13 @@ impl Cpu {
 mod tests {
 use implicit_fn::implicit_fn;
 use crate::cpu::{Cpu, Instruction};
+use crate::input::controller::Button;
+use crate::input::multitap::{FamicomMultitap, FourScore};
+use crate::input::zapper::Zapper;
+use crate::palette::{FRAME_HEIGHT, FRAME_WIDTH};
+use crate::region::Region;
+use crate::rom::Rom;
+use crate::state::{Serialize, Serializer, StateError};
 
     //<editor-fold desc="Test Utility Methods">
     fn no_init(_: &mut Cpu) {}
@@ -978,7 +1409,7 @@ use crate::cpu::{Cpu, Instruction};
     fn no_test(_: &mut Cpu) {}
 
     fn test_inst<I, T>(
-        init: I, op_code: u8, args: [u8; 2], size: u8, test: T, pc: u16, cycle: u32,
+        init: I, op_code: u8, args: [u8; 2], size: u8, test: T, pc: u16, cycle: u64,
     ) where
         I: Fn(&mut Cpu) -> (),
         T: Fn(&mut Cpu) -> (),
@@ -1075,7 +1506,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_accumulator<M>(
-         op_code: u8, acc: u8, check_value: M, cycles: u32
+         op_code: u8, acc: u8, check_value: M, cycles: u64
     ) where
         M: Fn(&Cpu, u8) -> u8
     {
@@ -1088,7 +1519,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_immediate<M, I>(
-         init: I, op_code: u8, val: u8, check_value: M, cycles: u32
+         init: I, op_code: u8, val: u8, check_value: M, cycles: u64
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1102,7 +1533,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_zero_page<M, I>(
-        init: I, op_code: u8, val: u8, check_value: M, cycles: u32
+        init: I, op_code: u8, val: u8, check_value: M, cycles: u64
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1119,7 +1550,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_zero_page_x<M, I>(
-        init: I, op_code: u8, val: u8, check_value: M, cycles: u32
+        init: I, op_code: u8, val: u8, check_value: M, cycles: u64
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1137,7 +1568,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_zero_page_y<M, I>(
-        init: I, op_code: u8, val: u8, check_value: M, cycles: u32
+        init: I, op_code: u8, val: u8, check_value: M, cycles: u64
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1155,7 +1586,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_absolute<M, I>(
-        init: I, op_code: u8, val: u16, check_value: M, cycles: u32
+        init: I, op_code: u8, val: u16, check_value: M, cycles: u64
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1173,7 +1604,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_absolute_x<M, I>(
-        init: I, op_code: u8, val: u16, check_value: M, cycles: u32, cross_page: bool
+        init: I, op_code: u8, val: u16, check_value: M, cycles: u64, cross_page: bool
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1192,7 +1623,7 @@ use crate::cpu::{Cpu, Instruction};
     }
 
     fn test_absolute_y<M, I>(
-        init: I, op_code: u8, val: u16, check_value: M, cycles: u32, cross_page: bool
+        init: I, op_code: u8, val: u16, check_value: M, cycles: u64, cross_page: bool
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1383,7 +1814,7 @@ use crate::cpu::{Cpu, Instruction};
 
     /// (Indirect,X)
     fn test_indirect_indexed<M, I>(
-        init: I, op_code: u8, val: u8, check_value: M, cycles: u32
+        init: I, op_code: u8, val: u8, check_value: M, cycles: u64
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1405,7 +1836,7 @@ use crate::cpu::{Cpu, Instruction};
 
     /// (Indirect),Y
     fn test_indexed_indirect<M, I>(
-        init: I, op_code: u8, val: u8, check_value: M, cycles: u32, cross_page: bool
+        init: I, op_code: u8, val: u8, check_value: M, cycles: u64, cross_page: bool
     ) where
         I: Fn(&mut Cpu),
         M: Fn(&Cpu, u8) -> u8
@@ -1659,13 +2090,15 @@ use crate::cpu::{Cpu, Instruction};
         cpu.set_flag_negative(true);
         cpu.set_flag_carry(true);
         cpu.program_counter = 0xAB01;
+        cpu.memory[0xFFFE] = 0x34;
+        cpu.memory[0xFFFF] = 0x12;
 
         assert_eq!(cpu.get_flag_interrupt(), false);
 
         cpu.execute_instruction(&Instruction::new(0x00, [0, 0], 1));
 
         assert_eq!(cpu.cycle, 7);
-        assert_eq!(cpu.program_counter, 0xFFFE);
+        assert_eq!(cpu.program_counter, 0x1234);
         assert_eq!(cpu.memory[cpu.stack_pointer as usize + 0x100 + 1], 0b10110001);
         assert_eq!(cpu.memory[cpu.stack_pointer as usize + 0x100 + 2], 0x03);
         assert_eq!(cpu.memory[cpu.stack_pointer as usize + 0x100 + 3], 0xAB);
@@ -2031,7 +2464,7 @@ use crate::cpu::{Cpu, Instruction};
             |cpu| -> () { cpu.memory[0xABCD] = 0x24 },
             0x4C, [0xAB, 0xCD], 3,
             no_test,
-            0x24, 3
+            0xABCD, 3
         );
         test_inst(
             |cpu| -> () {
@@ -2056,7 +2489,7 @@ use crate::cpu::{Cpu, Instruction};
                 assert_eq!(cpu.memory[cpu.stack_pointer as usize + 1 + 0x0100], 0xBB + 2);
                 assert_eq!(cpu.memory[cpu.stack_pointer as usize + 2 + 0x0100], 0xAA);
             },
-            0x24, 6
+            0xABCD, 6
         );
     }
 
@@ -2508,6 +2941,559 @@ use crate::cpu::{Cpu, Instruction};
         );
     }
 
+    #[test]
+    fn test_region_timing() {
+        let mut cpu = Cpu::new();
+        cpu.cycle = 10;
+        assert_eq!(cpu.get_ppu_dots(), 30);
+
+        cpu.set_region(Region::Pal);
+        assert_eq!(cpu.get_region(), Region::Pal);
+        assert_eq!(cpu.get_master_clock(), 160);
+        assert_eq!(cpu.get_ppu_dots(), 32);
+
+        cpu.set_region(Region::Dendy);
+        assert_eq!(cpu.get_ppu_dots(), 30);
+    }
+
+    #[test]
+    fn test_apu_registers() {
+        test_inst(
+            |cpu| -> () {
+                cpu.accumulator = 0b01;
+                cpu.cycle = 20;
+            },
+            0x8D, [0x40, 0x15], 3,
+            |cpu| -> () {
+                assert_eq!(cpu.read(0x4015), 0);
+                assert_eq!(cpu.get_apu().get_cycle(), 20);
+
+                cpu.accumulator = 0b0000_1000;
+                cpu.execute_instruction(&Instruction::new(0x8D, [0x40, 0x03], 3));
+                assert_eq!(cpu.read(0x4015), 0b01);
+                assert_eq!(cpu.memory[0x4003], 0);
+            },
+            3, 24
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0xFFFC] = 0x00;
+        cpu.memory[0xFFFD] = 0x02;
+        // LDA #$42, STA $1234
+        cpu.memory[0x0200..0x0205].copy_from_slice(&[0xA9, 0x42, 0x8D, 0x34, 0x12]);
+
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x0200);
+        assert!(cpu.get_flag_interrupt());
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.memory[0x1234], 0x42);
+        assert_eq!(cpu.program_counter, 0x0205);
+        assert_eq!(cpu.cycle, 7 + 2 + 4);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0xFFFC] = 0x00;
+        cpu.memory[0xFFFD] = 0x02;
+        // BNE to itself, as the zero flag is clear
+        cpu.memory[0x0200..0x0202].copy_from_slice(&[0xD0, 0xFE]);
+        cpu.reset();
+
+        cpu.run_frame();
+        assert_eq!(cpu.get_frame(), 1);
+        assert_eq!(cpu.program_counter, 0x0200);
+        assert!(cpu.cycle >= 29780 && cpu.cycle < 29780 + 3);
+        cpu.run_frame();
+        assert!(cpu.cycle >= 59561 && cpu.cycle < 59561 + 3);
+        assert_eq!(cpu.get_apu().get_cycle(), cpu.cycle);
+    }
+
+    #[test]
+    fn test_jam() {
+        let mut cpu = Cpu::new();
+        cpu.program_counter = 0x0200;
+        cpu.memory[0x0200] = 0x02;
+        cpu.step();
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x0200);
+
+        // interrupts don't wake it up
+        cpu.memory[0xFFFE] = 0x00;
+        cpu.memory[0xFFFF] = 0x80;
+        cpu.cycle = 29830;
+        cpu.step();
+        cpu.step();
+        assert!(cpu.get_irq_line());
+        assert_eq!(cpu.program_counter, 0x0200);
+        assert_eq!(cpu.cycle, 29832);
+
+        cpu.memory[0xFFFC] = 0x00;
+        cpu.memory[0xFFFD] = 0x03;
+        cpu.reset();
+        assert!(!cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x0300);
+    }
+
+    #[test]
+    fn test_register_wrap() {
+        let mut cpu = Cpu::new();
+        cpu.program_counter = 0x0200;
+        cpu.memory[0x0010] = 0xFF;
+        cpu.memory[0x0000] = 0x42;
+        #[rustfmt::skip]
+        let program: [u8; 17] = [
+            0xA2, 0xFF, // LDX #$FF
+            0xE8,       // INX
+            0xCA,       // DEX
+            0xA0, 0x00, // LDY #$00
+            0x88,       // DEY
+            0xC8,       // INY
+            0xE6, 0x10, // INC $10
+            0xC6, 0x11, // DEC $11
+            0xE8,       // INX
+            0xE8,       // INX
+            0xBD, 0xFF, 0xFF, // LDA $FFFF,X
+        ];
+        cpu.memory[0x0200..0x0211].copy_from_slice(&program);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.index_x, 0x00);
+        assert!(cpu.get_flag_zero());
+        cpu.step();
+        assert_eq!(cpu.index_x, 0xFF);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.index_y, 0xFF);
+        assert!(cpu.get_flag_negative());
+        cpu.step();
+        assert_eq!(cpu.index_y, 0x00);
+        cpu.step();
+        assert_eq!(cpu.memory[0x10], 0x00);
+        cpu.step();
+        assert_eq!(cpu.memory[0x11], 0xFF);
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.accumulator, 0x42);
+    }
+
+    #[test]
+    fn test_stack_wrap() {
+        let mut cpu = Cpu::new();
+        cpu.program_counter = 0x0200;
+        cpu.stack_pointer = 0x00;
+        #[rustfmt::skip]
+        let program: [u8; 7] = [
+            0xA9, 0x37, // LDA #$37
+            0x48,       // PHA
+            0xA9, 0x00, // LDA #$00
+            0x68,       // PLA
+            0x60,       // RTS
+        ];
+        cpu.memory[0x0200..0x0207].copy_from_slice(&program);
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.memory[0x0100], 0x37);
+        assert_eq!(cpu.stack_pointer, 0xFF);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.accumulator, 0x37);
+        assert_eq!(cpu.stack_pointer, 0x00);
+
+        // returning to $FFFF + 1, with the address split across both ends of the stack
+        cpu.stack_pointer = 0xFE;
+        cpu.memory[0x01FF] = 0xFF;
+        cpu.memory[0x0100] = 0xFF;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0000);
+        assert_eq!(cpu.stack_pointer, 0x00);
+
+        // JMP ($FFFF) reads its high byte from $0000, which is the opcode itself
+        cpu.memory[0x0000..0x0003].copy_from_slice(&[0x6C, 0xFF, 0xFF]);
+        cpu.memory[0xFFFF] = 0x00;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x6C00);
+    }
+
+    #[test]
+    fn test_program_counter_wrap() {
+        let mut cpu = Cpu::new();
+        cpu.program_counter = 0xFFFF;
+        cpu.memory[0xFFFF] = 0xA9;
+        cpu.memory[0x0000] = 0x42;
+        cpu.step();
+        assert_eq!(cpu.accumulator, 0x42);
+        assert_eq!(cpu.program_counter, 0x0001);
+    }
+
+    #[test]
+    fn test_run_frame_past_u32() {
+        let mut cpu = Cpu::new();
+        cpu.frame = (u32::MAX as f64 / Region::Ntsc.get_cpu_cycles_per_frame()) as u64;
+        cpu.cycle = (cpu.frame as f64 * Region::Ntsc.get_cpu_cycles_per_frame()) as u64;
+        cpu.apu.set_cycle(cpu.cycle);
+        cpu.load_rom(crate::mapper::tests::create_rom(0, 1, 1)).unwrap();
+        cpu.program_counter = 0x0200;
+        cpu.set_flag_interrupt(true);
+        cpu.memory[0x0200..0x0202].copy_from_slice(&[0xD0, 0xFE]);
+
+        cpu.run_frame();
+        cpu.run_frame();
+        assert!(cpu.cycle > u32::MAX as u64);
+        assert_eq!(cpu.mapper_cycle, cpu.cycle);
+        assert_eq!(cpu.get_apu().get_cycle(), cpu.cycle);
+    }
+
+    #[test]
+    fn test_load_rom() {
+        let mut bytes: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend((0..0x4000).map(|i| (i % 251) as u8));
+        let rom: Rom = Rom::from_bytes(&bytes).unwrap();
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom(rom).unwrap();
+        assert_eq!(cpu.read(0x8000), 0);
+        assert_eq!(cpu.read(0x80FB), 0);
+        assert_eq!(cpu.read(0xFFFF), cpu.read(0xBFFF));
+        assert_eq!(cpu.read(0xC001), 1);
+
+        // ROM space goes through the cartridge rather than memory
+        cpu.write(0xC001, 0x42);
+        assert_eq!(cpu.read(0xC001), 1);
+        assert_eq!(cpu.memory[0xC001], 0);
+        cpu.write(0x6000, 0x42);
+        assert_eq!(cpu.read(0x6000), 0x42);
+        assert_eq!(cpu.get_mapper().unwrap().ppu_read(0), 0);
+    }
+
+    #[test]
+    fn test_load_rom_region() {
+        let mut bytes: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0, 0x08, 0, 0, 0, 0, 1, 0, 0, 0];
+        bytes.extend([0; 0x4000]);
+
+        let mut cpu = Cpu::new();
+        cpu.load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(cpu.get_region(), Region::Pal);
+
+        // The user can still override it
+        cpu.set_region(Region::Dendy);
+        assert_eq!(cpu.get_region(), Region::Dendy);
+
+        cpu.load_rom(crate::mapper::tests::create_rom(0, 1, 1)).unwrap();
+        assert_eq!(cpu.get_region(), Region::Ntsc);
+    }
+
+    /// Counts the notifications it receives, and raises an interrupt after 10 scanlines.
+    struct CountingMapper {
+        cycles: u64,
+        scanlines: u32,
+    }
+
+    impl crate::mapper::Mapper for CountingMapper {
+        fn cpu_read(&self, _addr: u16) -> u8 {
+            0xEA
+        }
+        fn cpu_write(&mut self, _addr: u16, _value: u8) {}
+        fn ppu_read(&self, _addr: u16) -> u8 {
+            0
+        }
+        fn ppu_write(&mut self, _addr: u16, _value: u8) {}
+        fn get_mirroring(&self) -> crate::rom::Mirroring {
+            crate::rom::Mirroring::Vertical
+        }
+        fn get_irq(&self) -> bool {
+            self.scanlines >= 10
+        }
+        fn clock_cpu(&mut self) {
+            self.cycles += 1;
+        }
+        fn notify_scanline(&mut self, _scanline: crate::mapper::Scanline) {
+            self.scanlines += 1;
+        }
+    }
+
+    impl Serialize for CountingMapper {
+        fn serialize(&mut self, s: &mut Serializer) {
+            s.value(&mut self.cycles);
+            s.value(&mut self.scanlines);
+        }
+    }
+
+    #[test]
+    fn test_mapper_notifications() {
+        let mut cpu = Cpu::new();
+        cpu.insert_cartridge(Box::new(CountingMapper { cycles: 0, scanlines: 0 }));
+        cpu.program_counter = 0x8000;
+        cpu.set_flag_interrupt(true);
+        while !cpu.get_irq_line() {
+            cpu.step();
+        }
+        // 10 scanlines of 341 dots end after 1136.67 CPU cycles, which the NOP starting at cycle
+        // 1138 is the first to notice
+        assert_eq!(cpu.cycle, 1140);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut cpu = Cpu::new();
+        cpu.program_counter = 0x0200;
+        cpu.memory[0xFFFE] = 0x00;
+        cpu.memory[0xFFFF] = 0x80;
+        cpu.cycle = 29830;
+
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert!(cpu.get_flag_interrupt());
+
+        // reading $4015 acknowledges the interrupt
+        assert_eq!(cpu.read(0x4015), 1 << 6);
+        assert!(!cpu.get_irq_line());
+    }
+
+    #[test]
+    fn test_controllers() {
+        let mut cpu = Cpu::new();
+        let right: u8 = Button::Right.get_mask();
+        cpu.get_port_mut(0).unwrap().set_controller_buttons(0, 0b0000_0101);
+        cpu.get_port_mut(1).unwrap().set_controller_buttons(0, right);
+        cpu.write(0x4016, 1);
+        assert_eq!(cpu.read(0x4016), 0x41);
+        assert_eq!(cpu.read(0x4016), 0x41);
+        cpu.write(0x4016, 0);
+
+        let port1: Vec<u8> = (0..9).map(|_| cpu.read(0x4016)).collect();
+        assert_eq!(port1, vec![0x41, 0x40, 0x41, 0x40, 0x40, 0x40, 0x40, 0x40, 0x41]);
+        let port2: Vec<u8> = (0..9).map(|_| cpu.read(0x4017) & 1).collect();
+        assert_eq!(port2, vec![0, 0, 0, 0, 0, 0, 0, 1, 1]);
+
+        cpu.set_port(1, None);
+        assert_eq!(cpu.read(0x4017), 0x40);
+    }
+
+    #[test]
+    fn test_input_devices() {
+        let mut cpu = Cpu::new();
+        cpu.set_port(0, Some(Box::new(FourScore::new(0))));
+        cpu.set_port(1, Some(Box::new(FourScore::new(1))));
+        cpu.set_expansion(Some(Box::new(FamicomMultitap::new(false))));
+        cpu.get_port_mut(0).unwrap().set_controller_buttons(1, 0x01);
+        cpu.get_port_mut(1).unwrap().set_controller_buttons(0, 0x01);
+        cpu.get_expansion_mut().unwrap().set_controller_buttons(0, 0x02);
+
+        cpu.write(0x4016, 1);
+        cpu.write(0x4016, 0);
+        let port1: Vec<u8> = (0..24).map(|_| cpu.read(0x4016)).collect();
+        let mut expected: Vec<u8> = vec![0x40; 24];
+        expected[1] = 0x42;
+        expected[8] = 0x41;
+        expected[19] = 0x41;
+        for bits in &mut expected[8..] {
+            *bits |= 0x02;
+        }
+        assert_eq!(port1, expected);
+
+        let port2: Vec<u8> = (0..24).map(|_| cpu.read(0x4017)).collect();
+        assert_eq!(port2[0], 0x41);
+        assert_eq!(port2[18], 0x43);
+    }
+
+    #[test]
+    fn test_zapper() {
+        let mut cpu = Cpu::new();
+        let mut frame: Vec<u16> = vec![0x0F; FRAME_WIDTH * FRAME_HEIGHT];
+        frame[60 * FRAME_WIDTH + 128] = 0x30;
+        cpu.set_port(1, Some(Box::new(Zapper::new())));
+        let zapper = cpu.get_port_mut(1).unwrap();
+        zapper.set_frame(&frame);
+        zapper.set_light_gun(Some((128, 60)), true);
+
+        assert_eq!(cpu.read(0x4017), 0x58);
+        // scanline 70, 10 lines after the beam drew the pixel
+        cpu.cycle = 70 * 341 / 3;
+        assert_eq!(cpu.read(0x4017), 0x50);
+        // the next frame
+        cpu.cycle = 262 * 341 / 3;
+        assert_eq!(cpu.read(0x4017), 0x58);
+    }
+
+    /// Strobes the controller, then reads it 9 times with LDA $4016, returning bit 0 of each.
+    fn read_controller_with_lda(cpu: &mut Cpu) -> Vec<u8> {
+        cpu.write(0x4016, 1);
+        cpu.write(0x4016, 0);
+        (0..9)
+            .map(|_| {
+                cpu.program_counter = 0x0200;
+                cpu.step();
+                cpu.accumulator & 1
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_controller_dma_read_conflict() {
+        let mut cpu = Cpu::new();
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0xAD, 0x16, 0x40]);
+        let expected: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 0, 1];
+        for _ in 0..200 {
+            assert_eq!(read_controller_with_lda(&mut cpu), expected);
+        }
+
+        // a looping sample at the highest rate, fetching a byte every 432 cycles
+        cpu.write(0x4010, 0x4F);
+        cpu.write(0x4013, 0x00);
+        cpu.write(0x4015, 0x10);
+        let mut conflicts: usize = 0;
+        for _ in 0..200 {
+            // idle for a cycle, moving the fetches to another cycle of the reads
+            cpu.cycle += 1;
+            let bits: Vec<u8> = read_controller_with_lda(&mut cpu);
+            if bits != expected {
+                // the fetch made the controller drop a button
+                assert_eq!(bits, vec![0, 0, 0, 0, 0, 0, 0, 1, 1]);
+                conflicts += 1;
+            }
+        }
+        assert!(conflicts > 0);
+        assert!(conflicts < 200);
+    }
+
+    #[test]
+    fn test_dmc_irq() {
+        let mut cpu = Cpu::new();
+        cpu.program_counter = 0x0200;
+        cpu.memory[0x0200] = 0xEA;
+        cpu.memory[0xFFFE] = 0x34;
+        cpu.memory[0xFFFF] = 0x12;
+        cpu.memory[0xC000] = 0x55;
+
+        // a 1 byte sample with interrupts enabled
+        cpu.write(0x4010, 0x80);
+        cpu.write(0x4013, 0x00);
+        cpu.write(0x4015, 0x10);
+        assert!(!cpu.get_irq_line());
+
+        // fetching the sample stalls the CPU, and finishing it raises the interrupt
+        cpu.step();
+        assert!(cpu.get_irq_line());
+        assert_eq!(cpu.program_counter, 0x1234);
+        assert_eq!(cpu.cycle, 4 + 7);
+        assert!(cpu.get_flag_interrupt());
+        assert_eq!(cpu.stack_pointer, 0xFC);
+        assert_eq!(cpu.memory[0x01FF], 0x02);
+        assert_eq!(cpu.memory[0x01FE], 0x00);
+        assert_eq!(cpu.memory[0x01FD], 0b0010_0000);
+        assert_eq!(cpu.read(0x4015) >> 7, 1);
+
+        // with interrupts disabled, execution continues normally
+        cpu.memory[0x1234] = 0xEA;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x1235);
+    }
+
+    /// Plays a square wave while incrementing $10 over and over, up to 255 times.
+    fn create_save_state_program() -> Cpu {
+        let mut cpu = Cpu::new();
+        for addr in (0x0200..0x1200).step_by(2) {
+            cpu.memory[addr] = 0xE6;
+            cpu.memory[addr + 1] = 0x10;
+        }
+        cpu.program_counter = 0x0200;
+        cpu.write(0x4015, 0x01);
+        cpu.write(0x4000, 0xBF);
+        cpu.write(0x4002, 0x20);
+        cpu.write(0x4003, 0x08);
+        cpu
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut cpu = create_save_state_program();
+        for _ in 0..120 {
+            cpu.step();
+        }
+        let state: Vec<u8> = cpu.save_state();
+        for _ in 0..120 {
+            cpu.step();
+        }
+        let expected: Vec<u8> = cpu.save_state();
+        assert_eq!(cpu.memory[0x10], 240);
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.memory[0x10], 120);
+        assert_eq!(cpu.cycle, 120 * 5);
+        for _ in 0..120 {
+            cpu.step();
+        }
+        assert_eq!(cpu.save_state(), expected);
+
+        // the state loads into another machine
+        let mut other = Cpu::new();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.program_counter, 0x0200 + 120 * 2);
+        assert_eq!(other.get_apu().read_status() & 1, 1);
+
+        // but not into one of another region
+        let mut other = Cpu::new();
+        other.set_region(Region::Pal);
+        assert!(matches!(
+            other.load_state(&state),
+            Err(StateError::Mismatch("region"))
+        ));
+    }
+
+    #[test]
+    fn test_save_state_cartridge() {
+        let mut bytes: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0];
+        bytes.resize(16 + 0x4000 + 0x2000, 0);
+        let mut cpu = Cpu::new();
+        cpu.load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+
+        cpu.write(0x6000, 0x55);
+        let state: Vec<u8> = cpu.save_state();
+        cpu.write(0x6000, 0xAA);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.read(0x6000), 0x55);
+
+        assert!(matches!(
+            Cpu::new().load_state(&state),
+            Err(StateError::Mismatch("cartridge"))
+        ));
+    }
+
+    #[test]
+    fn test_load_state_failure() {
+        let mut cpu = create_save_state_program();
+        cpu.set_port(0, Some(Box::new(FourScore::new(0))));
+        let state: Vec<u8> = cpu.save_state();
+
+        // failing on the last section rolls back the ones loaded before it
+        cpu.step();
+        cpu.set_port(0, None);
+        assert!(matches!(
+            cpu.load_state(&state),
+            Err(StateError::Mismatch("input devices"))
+        ));
+        assert_eq!(cpu.cycle, 5);
+        assert_eq!(cpu.memory[0x10], 1);
+
+        assert!(matches!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+        assert!(matches!(
+            cpu.load_state(b"not a state"),
+            Err(StateError::InvalidMagic)
+        ));
+    }
+
     #[test]
     fn test_sec() {
         test_set(0x38, Cpu::set_flag_carry, Cpu::get_flag_carry, true);
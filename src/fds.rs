@@ -0,0 +1,160 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Size of a disk side in .fds images, which leave out the gaps and checksums between blocks.
+pub const SIDE_SIZE: usize = 65500;
+/// Size of the Famicom Disk System BIOS, disksys.rom.
+pub const BIOS_SIZE: usize = 0x2000;
+const HEADER_SIZE: usize = 16;
+const MAGIC: [u8; 4] = [b'F', b'D', b'S', 0x1A];
+
+#[derive(Debug)]
+pub enum FdsError {
+    /// The image holds no disk sides, or a size which isn't a whole number of them.
+    InvalidSize(usize),
+    /// The fwNES header announces more sides than the file holds.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// The BIOS isn't 8 KiB large.
+    InvalidBios(usize),
+    Io(std::io::Error),
+}
+
+impl Display for FdsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FdsError::InvalidSize(size) => write!(
+                f,
+                "invalid disk image size: {} bytes is not a multiple of {}",
+                size, SIDE_SIZE
+            ),
+            FdsError::Truncated { expected, actual } => write!(
+                f,
+                "disk image is truncated: expected {} bytes, got {}",
+                expected, actual
+            ),
+            FdsError::InvalidBios(size) => write!(
+                f,
+                "invalid FDS BIOS: expected {} bytes, got {}",
+                BIOS_SIZE, size
+            ),
+            FdsError::Io(err) => write!(f, "failed to read disk image: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FdsError {}
+
+impl From<std::io::Error> for FdsError {
+    fn from(err: std::io::Error) -> FdsError {
+        FdsError::Io(err)
+    }
+}
+
+/// Disk format reference: https://www.nesdev.org/wiki/FDS_disk_format
+///
+/// A Famicom Disk System image in the .fds format, either raw or behind a 16-byte fwNES header.
+/// Each side holds the disk's blocks back to back: <pre>
+/// 1 => disk info, 56 bytes
+/// 2 => file count, 2 bytes
+/// 3 => file header, 16 bytes, with the file size at offsets 13-14
+/// 4 => file data, 1 + file size bytes
+/// </pre>
+/// with a pair of blocks 3 and 4 for every file.
+pub struct DiskImage {
+    pub sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    pub fn from_bytes(bytes: &[u8]) -> Result<DiskImage, FdsError> {
+        let data: &[u8] = if bytes.len() >= HEADER_SIZE && bytes[0..4] == MAGIC {
+            let expected: usize = HEADER_SIZE + bytes[4] as usize * SIDE_SIZE;
+            if bytes.len() < expected {
+                return Err(FdsError::Truncated {
+                    expected,
+                    actual: bytes.len(),
+                });
+            }
+            &bytes[HEADER_SIZE..expected]
+        } else {
+            bytes
+        };
+
+        if data.is_empty() || !data.len().is_multiple_of(SIDE_SIZE) {
+            return Err(FdsError::InvalidSize(data.len()));
+        }
+        Ok(DiskImage {
+            sides: data.chunks(SIDE_SIZE).map(|side| side.to_vec()).collect(),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<DiskImage, FdsError> {
+        DiskImage::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Serializes the image without a header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.sides.concat()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Creates a disk side holding the given files.
+    pub fn create_side(files: &[&[u8]]) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![1];
+        out.extend(b"*NINTENDO-HVC*");
+        out.resize(56, 0);
+        out.extend([2, files.len() as u8]);
+        for (i, file) in files.iter().enumerate() {
+            let mut header: Vec<u8> = vec![3, i as u8, i as u8];
+            header.extend(b"FILENAME");
+            header.extend([0x00, 0x60]);
+            header.extend((file.len() as u16).to_le_bytes());
+            header.push(0);
+            out.extend(header);
+            out.push(4);
+            out.extend(*file);
+        }
+        out.resize(SIDE_SIZE, 0);
+        out
+    }
+
+    #[test]
+    fn test_disk_image() {
+        let side: Vec<u8> = create_side(&[&[1, 2, 3]]);
+        assert_eq!(&side[56..58], &[2, 1]);
+        assert_eq!(side[58 + 13], 3);
+        assert_eq!(&side[58 + 16..58 + 20], &[4, 1, 2, 3]);
+
+        let raw: Vec<u8> = [side.clone(), vec![0; SIDE_SIZE]].concat();
+        let image: DiskImage = DiskImage::from_bytes(&raw).unwrap();
+        assert_eq!(image.sides.len(), 2);
+        assert_eq!(image.sides[0], side);
+        assert_eq!(image.to_bytes(), raw);
+
+        let mut headered: Vec<u8> = MAGIC.to_vec();
+        headered.push(1);
+        headered.resize(HEADER_SIZE, 0);
+        headered.extend(&side);
+        let image: DiskImage = DiskImage::from_bytes(&headered).unwrap();
+        assert_eq!(image.sides, vec![side]);
+
+        assert!(matches!(
+            DiskImage::from_bytes(&headered[..1000]),
+            Err(FdsError::Truncated { .. })
+        ));
+        assert!(matches!(
+            DiskImage::from_bytes(&raw[..1000]),
+            Err(FdsError::InvalidSize(1000))
+        ));
+        assert!(matches!(
+            DiskImage::from_bytes(&[]),
+            Err(FdsError::InvalidSize(0))
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+pub mod controller;
+pub mod multitap;
+pub mod power_pad;
+pub mod vaus;
+pub mod zapper;
+
+use crate::state::Serialize;
+
+/// Input devices reference: https://www.nesdev.org/wiki/Input_devices
+///
+/// A device plugged into one of the two controller ports or the Famicom expansion port. Writes
+/// to $4016 drive the output lines of every device, while reads of $4016 and $4017 pulse the
+/// clock line of port 1 and 2 respectively and sample the data lines: <pre>
+/// 1 << 4 => D4, serial data of the Power Pad and the NES Arkanoid controller
+/// 1 << 3 => D3, serial data of the Power Pad, button of the NES Arkanoid controller
+/// 1 << 1 => D1, controllers on the Famicom expansion port
+/// 1      => D0, standard controllers in the ports
+/// </pre>
+/// The expansion port sees the reads of both addresses.
+pub trait InputDevice: Serialize {
+    /// Handles writes to $4016. Bits 0-2 are the OUT0-OUT2 lines, with OUT0 being the strobe
+    /// of the standard controllers.
+    fn write(&mut self, value: u8);
+
+    /// Handles reads of $4016 for port 0 and of $4017 for port 1, returning the data lines in
+    /// bits 0-4.
+    fn read(&self, port: usize) -> u8;
+
+    /// Sets the buttons of the standard controller at the given index, as laid out in
+    /// [controller::Controller::set_buttons]. Devices with several controllers number them in
+    /// the order they get read.
+    fn set_controller_buttons(&mut self, _index: usize, _buttons: u8) {}
+
+    /// Sets the position of the paddle's knob and whether its button is held.
+    fn set_paddle(&mut self, _position: u8, _button: bool) {}
+
+    /// Sets the 12 buttons of a mat controller. Bit `n - 1` holds button `n`.
+    fn set_mat_buttons(&mut self, _buttons: u16) {}
+
+    /// Sets the pixel a light gun aims at, `None` when pointing away from the screen, and
+    /// whether its trigger is pulled.
+    fn set_light_gun(&mut self, _aim: Option<(u8, u8)>, _trigger: bool) {}
+
+    /// Hands over the frame the PPU is outputting, in the format of [crate::palette::Palette],
+    /// for light guns to look at. They panic unless it's a full frame of
+    /// [crate::palette::FRAME_WIDTH] by [crate::palette::FRAME_HEIGHT] pixels.
+    fn set_frame(&mut self, _frame: &[u16]) {}
+
+    /// Notifies about the scanline and dot the PPU is at, right before each read.
+    fn notify_beam(&self, _scanline: u16, _dot: u16) {}
+}
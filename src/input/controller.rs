@@ -0,0 +1,179 @@
+use crate::input::InputDevice;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// A button of the standard controller, in the order the shift register reports them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// The button's bit in [Controller::set_buttons].
+    pub fn get_mask(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// Standard controller reference: https://www.nesdev.org/wiki/Standard_controller
+///
+/// A 4021 8-bit shift register. While the strobe bit written to $4016 is set, it keeps loading
+/// the button states; once cleared, every read of $4016 or $4017 shifts out the next button.
+/// After all 8 buttons, official controllers keep returning 1.
+pub struct Controller {
+    buttons: u8,
+    strobe: bool,
+    shift: Cell<u8>,
+}
+
+impl Default for Controller {
+    fn default() -> Controller {
+        Controller::new()
+    }
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller {
+            buttons: 0,
+            strobe: false,
+            shift: Cell::new(0),
+        }
+    }
+
+    /// Sets the state of every button at once. Bit layout: <pre>
+    /// 1 << 7 => right
+    /// 1 << 6 => left
+    /// 1 << 5 => down
+    /// 1 << 4 => up
+    /// 1 << 3 => start
+    /// 1 << 2 => select
+    /// 1 << 1 => B
+    /// 1      => A
+    /// </pre>
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift.set(buttons);
+        }
+    }
+
+    pub fn get_buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let buttons: u8 = if pressed {
+            self.buttons | button.get_mask()
+        } else {
+            self.buttons & !button.get_mask()
+        };
+        self.set_buttons(buttons);
+    }
+
+    /// Handles writes to bit 0 of $4016. The buttons get latched when the strobe gets cleared.
+    pub fn write_strobe(&mut self, value: u8) {
+        let strobe: bool = value & 1 == 1;
+        if self.strobe || strobe {
+            self.shift.set(self.buttons);
+        }
+        self.strobe = strobe;
+    }
+
+    /// Returns the next button in bit 0. While the strobe is set, this is always the A button.
+    pub fn read_bit(&self) -> u8 {
+        if self.strobe {
+            return self.buttons & 1;
+        }
+        let shift: u8 = self.shift.get();
+        self.shift.set(shift >> 1 | 0x80);
+        shift & 1
+    }
+}
+
+impl InputDevice for Controller {
+    fn write(&mut self, value: u8) {
+        self.write_strobe(value);
+    }
+
+    fn read(&self, _port: usize) -> u8 {
+        self.read_bit()
+    }
+
+    fn set_controller_buttons(&mut self, index: usize, buttons: u8) {
+        if index == 0 {
+            self.set_buttons(buttons);
+        }
+    }
+}
+
+impl Serialize for Controller {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.buttons);
+        s.value(&mut self.strobe);
+        s.value(&mut self.shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &Controller) -> Vec<u8> {
+        (0..10).map(|_| controller.read_bit()).collect()
+    }
+
+    #[test]
+    fn test_buttons() {
+        let mut controller: Controller = Controller::new();
+        controller.set_button(Button::A, true);
+        controller.set_button(Button::Start, true);
+        controller.set_button(Button::Left, true);
+        controller.set_button(Button::Left, false);
+        controller.set_button(Button::Right, true);
+        assert_eq!(controller.get_buttons(), 0b1000_1001);
+        assert_eq!(Button::Down.get_mask(), 1 << 5);
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut controller: Controller = Controller::new();
+        controller.set_buttons(0b1000_1001);
+        // nothing got latched yet
+        assert_eq!(read_all(&controller), vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+
+        controller.write_strobe(1);
+        controller.write_strobe(0);
+        assert_eq!(read_all(&controller), vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+        // changes after the latch only show up with the next strobe
+        controller.write_strobe(1);
+        controller.write_strobe(0);
+        controller.set_buttons(0b10);
+        assert_eq!(controller.read_bit(), 1);
+        controller.write_strobe(1);
+        controller.write_strobe(0);
+        assert_eq!(controller.read_bit(), 0);
+        assert_eq!(controller.read_bit(), 1);
+    }
+
+    #[test]
+    fn test_strobe_held() {
+        let mut controller: Controller = Controller::new();
+        controller.write_strobe(1);
+        controller.set_buttons(1);
+        assert_eq!(read_all(&controller), vec![1; 10]);
+        controller.set_buttons(0b10);
+        assert_eq!(controller.read_bit(), 0);
+        controller.write_strobe(0);
+        assert_eq!(controller.read_bit(), 0);
+        assert_eq!(controller.read_bit(), 1);
+    }
+}
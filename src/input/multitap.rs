@@ -0,0 +1,264 @@
+use crate::input::InputDevice;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// Signatures following the controllers, read starting with the most significant bit.
+const SIGNATURE_PORT_1: u8 = 0b0001_0000;
+const SIGNATURE_PORT_2: u8 = 0b0010_0000;
+
+/// The shift registers behind a single data line of a multitap, reporting two controllers and
+/// an optional signature byte. All bits after those read as 1.
+struct Chain {
+    buttons: [u8; 2],
+    signature: Option<u8>,
+    strobe: bool,
+    shift: Cell<u32>,
+}
+
+impl Chain {
+    fn new(signature: Option<u8>) -> Chain {
+        Chain {
+            buttons: [0; 2],
+            signature,
+            strobe: false,
+            shift: Cell::new(u32::MAX),
+        }
+    }
+
+    fn latch(&self) {
+        let shift: u32 = match self.signature {
+            Some(signature) => {
+                self.buttons[0] as u32
+                    | (self.buttons[1] as u32) << 8
+                    | (signature.reverse_bits() as u32) << 16
+                    | 0xFF00_0000
+            }
+            None => self.buttons[0] as u32 | 0xFFFF_FF00,
+        };
+        self.shift.set(shift);
+    }
+
+    fn set_buttons(&mut self, index: usize, buttons: u8) {
+        self.buttons[index] = buttons;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    fn write_strobe(&mut self, value: u8) {
+        let strobe: bool = value & 1 == 1;
+        if self.strobe || strobe {
+            self.latch();
+        }
+        self.strobe = strobe;
+    }
+
+    fn read_bit(&self) -> u8 {
+        if self.strobe {
+            return self.buttons[0] & 1;
+        }
+        let shift: u32 = self.shift.get();
+        self.shift.set(shift >> 1 | 0x8000_0000);
+        (shift & 1) as u8
+    }
+}
+
+/// Four Score reference: https://www.nesdev.org/wiki/Four_Score
+///
+/// Half of the NES Four Score, plugged into a single controller port. Each read returns the
+/// next of 24 bits on D0: <pre>
+/// 1-8   => first controller, players 1 or 2
+/// 9-16  => second controller, players 3 or 4
+/// 17-24 => signature, 00010000 on $4016 and 00100000 on $4017
+/// </pre>
+/// A Four Score needs one half in each port.
+pub struct FourScore {
+    chain: Chain,
+}
+
+impl FourScore {
+    /// Creates the half for the given port, 0 for $4016 and 1 for $4017.
+    pub fn new(port: usize) -> FourScore {
+        let signature: u8 = if port == 0 {
+            SIGNATURE_PORT_1
+        } else {
+            SIGNATURE_PORT_2
+        };
+        FourScore {
+            chain: Chain::new(Some(signature)),
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, value: u8) {
+        self.chain.write_strobe(value);
+    }
+
+    fn read(&self, _port: usize) -> u8 {
+        self.chain.read_bit()
+    }
+
+    fn set_controller_buttons(&mut self, index: usize, buttons: u8) {
+        if index < 2 {
+            self.chain.set_buttons(index, buttons);
+        }
+    }
+}
+
+/// Famicom 4 player adapters reference: https://www.nesdev.org/wiki/Four_player_adapters
+///
+/// A multitap on the Famicom expansion port, like the Hori 4 Players Adapter, reporting its
+/// controllers on D1. With 2 controllers, $4016 and $4017 each read one, like the controllers
+/// plugged straight into the expansion port which many games support as players 3 and 4. With
+/// 4 controllers, the reads follow the Four Score protocol with the signatures swapped: <pre>
+/// $4016 => controller 1, controller 3, 00100000
+/// $4017 => controller 2, controller 4, 00010000
+/// </pre>
+pub struct FamicomMultitap {
+    chains: [Chain; 2],
+}
+
+impl FamicomMultitap {
+    pub fn new(four_players: bool) -> FamicomMultitap {
+        let (signature_1, signature_2): (Option<u8>, Option<u8>) = if four_players {
+            (Some(SIGNATURE_PORT_2), Some(SIGNATURE_PORT_1))
+        } else {
+            (None, None)
+        };
+        FamicomMultitap {
+            chains: [Chain::new(signature_1), Chain::new(signature_2)],
+        }
+    }
+}
+
+impl InputDevice for FamicomMultitap {
+    fn write(&mut self, value: u8) {
+        for chain in &mut self.chains {
+            chain.write_strobe(value);
+        }
+    }
+
+    fn read(&self, port: usize) -> u8 {
+        self.chains[port].read_bit() << 1
+    }
+
+    /// Controllers 1 and 3 are read through $4016, 2 and 4 through $4017.
+    fn set_controller_buttons(&mut self, index: usize, buttons: u8) {
+        if index >= 4 {
+            return;
+        }
+        let chain: &mut Chain = &mut self.chains[index % 2];
+        if index < 2 || chain.signature.is_some() {
+            chain.set_buttons(index / 2, buttons);
+        }
+    }
+}
+
+impl Serialize for Chain {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.buttons);
+        s.value(&mut self.strobe);
+        s.value(&mut self.shift);
+    }
+}
+
+impl Serialize for FourScore {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.chain);
+    }
+}
+
+impl Serialize for FamicomMultitap {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.chains);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_bits(device: &dyn InputDevice, port: usize, count: usize) -> Vec<u8> {
+        (0..count).map(|_| device.read(port)).collect()
+    }
+
+    fn to_bits(byte: u8, bit: u8) -> Vec<u8> {
+        (0..8).map(|i| (byte >> i & 1) << bit).collect()
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut port1: FourScore = FourScore::new(0);
+        let mut port2: FourScore = FourScore::new(1);
+        port1.set_controller_buttons(0, 0x81);
+        port1.set_controller_buttons(1, 0x42);
+        port2.set_controller_buttons(0, 0x24);
+        port2.set_controller_buttons(1, 0x18);
+        for device in [&mut port1, &mut port2] {
+            device.write(1);
+            device.write(0);
+        }
+
+        let expected: Vec<u8> = [
+            to_bits(0x81, 0),
+            to_bits(0x42, 0),
+            vec![0, 0, 0, 1, 0, 0, 0, 0],
+            vec![1, 1],
+        ]
+        .concat();
+        assert_eq!(read_bits(&port1, 0, 26), expected);
+
+        let expected: Vec<u8> = [
+            to_bits(0x24, 0),
+            to_bits(0x18, 0),
+            vec![0, 0, 1, 0, 0, 0, 0, 0],
+            vec![1, 1],
+        ]
+        .concat();
+        assert_eq!(read_bits(&port2, 1, 26), expected);
+
+        // while strobed, the first controller's A button is returned
+        port1.write(1);
+        assert_eq!(read_bits(&port1, 0, 3), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn test_famicom_multitap() {
+        let mut multitap: FamicomMultitap = FamicomMultitap::new(false);
+        for i in 0..4 {
+            multitap.set_controller_buttons(i, 0x11 << i);
+        }
+        multitap.write(1);
+        multitap.write(0);
+        let expected: Vec<u8> = [to_bits(0x11, 1), vec![2; 4]].concat();
+        assert_eq!(read_bits(&multitap, 0, 12), expected);
+        let expected: Vec<u8> = [to_bits(0x22, 1), vec![2; 4]].concat();
+        assert_eq!(read_bits(&multitap, 1, 12), expected);
+
+        let mut multitap: FamicomMultitap = FamicomMultitap::new(true);
+        for i in 0..4 {
+            multitap.set_controller_buttons(i, 0x11 << i);
+        }
+        // there is no fifth controller
+        for i in 4..8 {
+            multitap.set_controller_buttons(i, 0xFF);
+        }
+        multitap.write(1);
+        multitap.write(0);
+        let expected: Vec<u8> = [
+            to_bits(0x11, 1),
+            to_bits(0x44, 1),
+            vec![0, 0, 2, 0, 0, 0, 0, 0, 2],
+        ]
+        .concat();
+        assert_eq!(read_bits(&multitap, 0, 25), expected);
+        let expected: Vec<u8> = [
+            to_bits(0x22, 1),
+            to_bits(0x88, 1),
+            vec![0, 0, 0, 2, 0, 0, 0, 0, 2],
+        ]
+        .concat();
+        assert_eq!(read_bits(&multitap, 1, 25), expected);
+    }
+}
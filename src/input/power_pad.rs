@@ -0,0 +1,109 @@
+use crate::input::InputDevice;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// The buttons reported on D3 and D4, in the order they get read.
+const D3_BUTTONS: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_BUTTONS: [u8; 4] = [4, 3, 12, 8];
+
+/// Power Pad reference: https://www.nesdev.org/wiki/Power_Pad
+///
+/// A mat with 12 buttons laid out in 3 rows, usually plugged into port 2: <pre>
+///  1  2  3  4
+///  5  6  7  8
+///  9 10 11 12
+/// </pre>
+/// Like the standard controller, strobing latches the buttons and every read shifts out the
+/// next ones, on two data lines at once. Pressed buttons read as 1, as do all bits after them.
+pub struct PowerPad {
+    buttons: u16,
+    strobe: bool,
+    /// The shift registers behind D3 and D4.
+    shift: Cell<[u8; 2]>,
+}
+
+impl Default for PowerPad {
+    fn default() -> PowerPad {
+        PowerPad::new()
+    }
+}
+
+impl PowerPad {
+    pub fn new() -> PowerPad {
+        PowerPad {
+            buttons: 0,
+            strobe: false,
+            shift: Cell::new([0xFF; 2]),
+        }
+    }
+
+    fn latch(&self) {
+        // the unused bits of D4 already read as 1
+        let pack = |order: &[u8]| -> u8 {
+            let unused: u8 = (0xFF_u16 << order.len()) as u8;
+            order.iter().enumerate().fold(unused, |out, (i, button)| {
+                out | ((self.buttons >> (button - 1) & 1) as u8) << i
+            })
+        };
+        self.shift.set([pack(&D3_BUTTONS), pack(&D4_BUTTONS)]);
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, value: u8) {
+        let strobe: bool = value & 1 == 1;
+        if self.strobe || strobe {
+            self.latch();
+        }
+        self.strobe = strobe;
+    }
+
+    fn read(&self, _port: usize) -> u8 {
+        let shift: [u8; 2] = self.shift.get();
+        if !self.strobe {
+            self.shift.set(shift.map(|bits| bits >> 1 | 0x80));
+        }
+        (shift[0] & 1) << 3 | (shift[1] & 1) << 4
+    }
+
+    fn set_mat_buttons(&mut self, buttons: u16) {
+        self.buttons = buttons & 0x0FFF;
+        if self.strobe {
+            self.latch();
+        }
+    }
+}
+
+impl Serialize for PowerPad {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.buttons);
+        s.value(&mut self.strobe);
+        s.value(&mut self.shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_pad() {
+        let mut pad: PowerPad = PowerPad::new();
+        // buttons 1, 7, 8 and 12
+        pad.set_mat_buttons(1 << 0 | 1 << 6 | 1 << 7 | 1 << 11);
+        pad.write(1);
+        pad.write(0);
+        let bits: Vec<u8> = (0..10).map(|_| pad.read(1)).collect();
+        let d3: Vec<u8> = bits.iter().map(|bits| bits >> 3 & 1).collect();
+        let d4: Vec<u8> = bits.iter().map(|bits| bits >> 4 & 1).collect();
+        assert_eq!(d3, vec![0, 1, 0, 0, 0, 0, 0, 1, 1, 1]);
+        assert_eq!(d4, vec![0, 0, 1, 1, 1, 1, 1, 1, 1, 1]);
+        assert!(bits.iter().all(|bits| bits & !0x18 == 0));
+
+        // while strobed, the first button of each line is returned
+        pad.write(1);
+        pad.set_mat_buttons(1 << 1 | 1 << 3);
+        assert_eq!(pad.read(1), 0x18);
+        assert_eq!(pad.read(1), 0x18);
+    }
+}
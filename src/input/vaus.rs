@@ -0,0 +1,115 @@
+use crate::input::InputDevice;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// Arkanoid controller reference: https://www.nesdev.org/wiki/Arkanoid_controller
+///
+/// The Vaus paddle bundled with Arkanoid. Strobing latches the knob's position, which then gets
+/// shifted out inverted, most significant bit first, with 1s after it. The NES version plugs into
+/// port 2, the Famicom version into the expansion port: <pre>
+/// NES     => $4017 D3 button, $4017 D4 position
+/// Famicom => $4016 D1 button, $4017 D1 position
+/// </pre>
+/// Arkanoid expects positions in about $62-$F2.
+pub struct Vaus {
+    famicom: bool,
+    position: u8,
+    button: bool,
+    strobe: bool,
+    shift: Cell<u8>,
+}
+
+impl Vaus {
+    pub fn new(famicom: bool) -> Vaus {
+        Vaus {
+            famicom,
+            position: 0,
+            button: false,
+            strobe: false,
+            shift: Cell::new(0),
+        }
+    }
+
+    /// Returns the next bit of the position.
+    fn read_position(&self) -> u8 {
+        let shift: u8 = self.shift.get();
+        if !self.strobe {
+            self.shift.set(shift << 1);
+        }
+        !shift >> 7
+    }
+}
+
+impl InputDevice for Vaus {
+    fn write(&mut self, value: u8) {
+        let strobe: bool = value & 1 == 1;
+        if self.strobe || strobe {
+            self.shift.set(self.position);
+        }
+        self.strobe = strobe;
+    }
+
+    fn read(&self, port: usize) -> u8 {
+        let button: u8 = self.button as u8;
+        match (self.famicom, port) {
+            (false, _) => button << 3 | self.read_position() << 4,
+            (true, 0) => button << 1,
+            (true, _) => self.read_position() << 1,
+        }
+    }
+
+    fn set_paddle(&mut self, position: u8, button: bool) {
+        self.position = position;
+        self.button = button;
+        if self.strobe {
+            self.shift.set(position);
+        }
+    }
+}
+
+impl Serialize for Vaus {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.position);
+        s.value(&mut self.button);
+        s.value(&mut self.strobe);
+        s.value(&mut self.shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nes() {
+        let mut vaus: Vaus = Vaus::new(false);
+        vaus.set_paddle(0b1010_0011, true);
+        vaus.write(1);
+        vaus.write(0);
+        vaus.set_paddle(0xFF, false);
+        let bits: Vec<u8> = (0..10).map(|_| vaus.read(1)).collect();
+        let position: Vec<u8> = bits.iter().map(|bits| bits >> 4).collect();
+        assert_eq!(position, vec![0, 1, 0, 1, 1, 1, 0, 0, 1, 1]);
+        assert!(bits.iter().all(|bits| bits & 0x08 == 0));
+
+        vaus.set_paddle(0, true);
+        assert_eq!(vaus.read(1), 0x18);
+    }
+
+    #[test]
+    fn test_famicom() {
+        let mut vaus: Vaus = Vaus::new(true);
+        vaus.set_paddle(0x7F, true);
+        vaus.write(1);
+        assert_eq!(vaus.read(1), 0x02);
+        assert_eq!(vaus.read(1), 0x02);
+        vaus.write(0);
+        assert_eq!(vaus.read(0), 0x02);
+        let position: Vec<u8> = (0..9).map(|_| vaus.read(1)).collect();
+        assert_eq!(position, vec![2, 0, 0, 0, 0, 0, 0, 0, 2]);
+        // reading the button doesn't shift the position
+        assert_eq!(vaus.read(0), 0x02);
+        vaus.set_paddle(0x7F, false);
+        assert_eq!(vaus.read(0), 0);
+    }
+}
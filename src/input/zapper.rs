@@ -0,0 +1,197 @@
+use crate::input::InputDevice;
+use crate::palette::{FRAME_HEIGHT, FRAME_WIDTH, Palette};
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// Average of the red, green and blue components a pixel needs to reach to be seen.
+pub const DEFAULT_THRESHOLD: u8 = 85;
+/// Number of scanlines the photodiode keeps reporting light after the beam passed a pixel.
+pub const PERSISTENCE_SCANLINES: u16 = 20;
+/// Distance in pixels from the aimed coordinates the photodiode picks up light from.
+const RADIUS: i32 = 2;
+
+/// Zapper reference: https://www.nesdev.org/wiki/Zapper
+///
+/// The light gun, usually plugged into port 2. Reads return: <pre>
+/// 1 << 4 => trigger, 1 while pulled
+/// 1 << 3 => light sense, 0 while detecting light
+/// </pre>
+/// The photodiode only sees the spot being aimed at, and only reacts for a short while after
+/// the beam drew a bright enough pixel there. This compares the beam's position, as reported by
+/// [InputDevice::notify_beam], with the pixels of the frame given to [InputDevice::set_frame].
+pub struct Zapper {
+    aim: Option<(u8, u8)>,
+    trigger: bool,
+    threshold: u8,
+    palette: Palette,
+    frame: Vec<u16>,
+    /// The scanline and dot the PPU is at.
+    beam: Cell<(u16, u16)>,
+}
+
+impl Default for Zapper {
+    fn default() -> Zapper {
+        Zapper::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Zapper {
+            aim: None,
+            trigger: false,
+            threshold: DEFAULT_THRESHOLD,
+            palette: Palette::new(),
+            frame: vec![0x0F; FRAME_WIDTH * FRAME_HEIGHT],
+            beam: Cell::new((0, 0)),
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: u8) {
+        self.threshold = threshold;
+    }
+
+    /// The palette used to judge how bright the pixels are.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn is_bright(&self, x: usize, y: usize) -> bool {
+        let rgb: [u8; 3] = self.palette.get_color(self.frame[y * FRAME_WIDTH + x]);
+        let sum: u16 = rgb.iter().map(|component| *component as u16).sum();
+        sum / 3 >= self.threshold as u16
+    }
+
+    /// Whether the beam drew a bright pixel near the aimed spot within the last
+    /// [PERSISTENCE_SCANLINES] scanlines.
+    pub fn is_detecting_light(&self) -> bool {
+        let Some((aim_x, aim_y)) = self.aim else {
+            return false;
+        };
+        let (scanline, dot): (u16, u16) = self.beam.get();
+        for dy in -RADIUS..=RADIUS {
+            for dx in -RADIUS..=RADIUS {
+                let x: i32 = aim_x as i32 + dx;
+                let y: i32 = aim_y as i32 + dy;
+                if x < 0 || y < 0 || x >= FRAME_WIDTH as i32 || y >= FRAME_HEIGHT as i32 {
+                    continue;
+                }
+                // pixel x gets output on dot x + 1
+                let drawn: bool =
+                    scanline as i32 > y || (scanline as i32 == y && dot as i32 > x + 1);
+                let fresh: bool = (scanline as i32) < y + PERSISTENCE_SCANLINES as i32;
+                if drawn && fresh && self.is_bright(x as usize, y as usize) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl InputDevice for Zapper {
+    fn write(&mut self, _value: u8) {}
+
+    fn read(&self, _port: usize) -> u8 {
+        let light: u8 = if self.is_detecting_light() { 0 } else { 1 };
+        (self.trigger as u8) << 4 | light << 3
+    }
+
+    fn set_light_gun(&mut self, aim: Option<(u8, u8)>, trigger: bool) {
+        self.aim = aim.filter(|(_, y)| (*y as usize) < FRAME_HEIGHT);
+        self.trigger = trigger;
+    }
+
+    fn set_frame(&mut self, frame: &[u16]) {
+        assert_eq!(
+            frame.len(),
+            self.frame.len(),
+            "frames have to be {}x{} pixels",
+            FRAME_WIDTH,
+            FRAME_HEIGHT
+        );
+        self.frame.copy_from_slice(frame);
+    }
+
+    fn notify_beam(&self, scanline: u16, dot: u16) {
+        self.beam.set((scanline, dot));
+    }
+}
+
+impl Serialize for Zapper {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.aim);
+        s.value(&mut self.trigger);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A black frame with a white 16x16 square at (100, 50).
+    fn create_frame() -> Vec<u16> {
+        let mut frame: Vec<u16> = vec![0x0F; FRAME_WIDTH * FRAME_HEIGHT];
+        for y in 50..66 {
+            frame[y * FRAME_WIDTH + 100..y * FRAME_WIDTH + 116].fill(0x30);
+        }
+        frame
+    }
+
+    #[test]
+    fn test_trigger() {
+        let mut zapper: Zapper = Zapper::new();
+        assert_eq!(zapper.read(1), 0x08);
+        zapper.set_light_gun(None, true);
+        assert_eq!(zapper.read(1), 0x18);
+    }
+
+    #[test]
+    fn test_light_sense() {
+        let mut zapper: Zapper = Zapper::new();
+        zapper.set_frame(&create_frame());
+        zapper.set_light_gun(Some((108, 58)), false);
+
+        // the beam has yet to reach the square
+        zapper.notify_beam(40, 0);
+        assert_eq!(zapper.read(1), 0x08);
+        // the top of the square is within range
+        zapper.notify_beam(56, 120);
+        assert_eq!(zapper.read(1), 0x00);
+        zapper.notify_beam(70, 0);
+        assert_eq!(zapper.read(1), 0x00);
+        // the light faded away
+        zapper.notify_beam(58 + 2 + PERSISTENCE_SCANLINES, 0);
+        assert_eq!(zapper.read(1), 0x08);
+
+        // aiming at black, or off the screen
+        zapper.notify_beam(70, 0);
+        zapper.set_light_gun(Some((10, 58)), true);
+        assert_eq!(zapper.read(1), 0x18);
+        zapper.set_light_gun(None, true);
+        assert_eq!(zapper.read(1), 0x18);
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut zapper: Zapper = Zapper::new();
+        let mut frame: Vec<u16> = create_frame();
+        // dark gray
+        frame
+            .iter_mut()
+            .filter(|pixel| **pixel == 0x30)
+            .for_each(|pixel| *pixel = 0x00);
+        zapper.set_frame(&frame);
+        zapper.set_light_gun(Some((108, 58)), false);
+        zapper.notify_beam(70, 0);
+        assert_eq!(zapper.read(1), 0x08);
+        zapper.set_threshold(0x20);
+        assert_eq!(zapper.read(1), 0x00);
+    }
+
+    #[test]
+    #[should_panic(expected = "frames have to be 256x240 pixels")]
+    fn test_invalid_frame() {
+        Zapper::new().set_frame(&[0x0F; 256]);
+    }
+}
@@ -0,0 +1,16 @@
+pub mod apu;
+pub mod battery;
+pub mod cpu;
+pub mod fds;
+pub mod input;
+pub mod mapper;
+pub mod movie;
+pub mod nsf;
+pub mod ntsc;
+pub mod palette;
+mod parser;
+pub mod region;
+pub mod rewind;
+pub mod rom;
+pub mod state;
+pub mod wav;
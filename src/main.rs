@@ -1,6 +1,380 @@
-mod cpu;
-mod parser;
+use nes_emulator::battery::{FLUSH_INTERVAL, SaveFile};
+use nes_emulator::cpu::Cpu;
+use nes_emulator::fds::DiskImage;
+use nes_emulator::mapper::fds::Fds;
+use nes_emulator::movie::{Movie, MoviePlayer};
+use nes_emulator::nsf::{Nsf, NsfPlayer};
+use nes_emulator::region::Region;
+use nes_emulator::rom::Rom;
+use nes_emulator::wav::{WavWriter, discard_apu_samples, write_apu_samples};
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: nes-emulator <rom> [options]
+
+Famicom Disk System images (.fds) need the BIOS, which is looked up as disksys.rom next to the
+image unless given with --bios. NSF and NSFe music rips (.nsf, .nsfe) get played back.
+Cartridges with battery-backed memory save it to a .sav file next to the ROM. Movies give the
+input of the frames they cover, from power-on, and stop with an error if the replay desyncs.
+They start with blank battery-backed memory and leave the .sav file untouched.
+
+Options:
+  --bios <file>         Famicom Disk System BIOS
+  --wav <file>          Record the audio output to a 16-bit PCM WAV file
+  --frames <count>      Number of frames to record (default: 600)
+  --start-frame <frame> Frame at which the recording starts (default: 0)
+  --sample-rate <rate>  Sample rate of the recording, in Hz (default: 44100)
+  --region <region>     Force the region: ntsc, pal or dendy
+  --track <number>      NSF track to play, counting from 1 (default: the file's starting track)
+  --movie <file>        Replay an FCEUX movie (.fm2) or BizHawk input log (Input Log.txt)";
+
+/// Options parsed from the command line.
+#[derive(PartialEq, Debug)]
+struct Options {
+    rom: PathBuf,
+    bios: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    frames: u64,
+    start_frame: u64,
+    sample_rate: u32,
+    region: Option<Region>,
+    track: Option<u8>,
+    movie: Option<PathBuf>,
+}
+
+fn parse_region(name: &str) -> Result<Region, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "ntsc" => Ok(Region::Ntsc),
+        "pal" => Ok(Region::Pal),
+        "dendy" => Ok(Region::Dendy),
+        _ => Err(format!("Unknown region: {}", name)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value: String = value.ok_or(format!("Missing value for {}", option))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", option, value))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom: Option<PathBuf> = None;
+    let mut options: Options = Options {
+        rom: PathBuf::new(),
+        bios: None,
+        wav: None,
+        frames: 600,
+        start_frame: 0,
+        sample_rate: 44100,
+        region: None,
+        track: None,
+        movie: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bios" => {
+                let path: String = args.next().ok_or("Missing value for --bios")?;
+                options.bios = Some(PathBuf::from(path));
+            }
+            "--wav" => {
+                let path: String = args.next().ok_or("Missing value for --wav")?;
+                options.wav = Some(PathBuf::from(path));
+            }
+            "--frames" => options.frames = parse_number(&arg, args.next())?,
+            "--start-frame" => options.start_frame = parse_number(&arg, args.next())?,
+            "--sample-rate" => options.sample_rate = parse_number(&arg, args.next())?,
+            "--region" => {
+                let name: String = args.next().ok_or("Missing value for --region")?;
+                options.region = Some(parse_region(&name)?);
+            }
+            "--track" => options.track = Some(parse_number(&arg, args.next())?),
+            "--movie" => {
+                let path: String = args.next().ok_or("Missing value for --movie")?;
+                options.movie = Some(PathBuf::from(path));
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or("Missing ROM path")?;
+    Ok(options)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().is_some_and(|extension| {
+        extensions
+            .iter()
+            .any(|expected| extension.eq_ignore_ascii_case(expected))
+    })
+}
+
+fn print_nsf_info(player: &NsfPlayer) {
+    let nsf: &Nsf = player.get_nsf();
+    println!("{} - {} ({})", nsf.title, nsf.artist, nsf.copyright);
+    let track: usize = player.get_track() as usize;
+    match nsf.track_labels.get(track) {
+        Some(label) => println!("Track {}/{}: {}", track + 1, nsf.track_count, label),
+        None => println!("Track {}/{}", track + 1, nsf.track_count),
+    }
+}
+
+/// Sets up the console for the given file. NSFs get a player which is already running, while
+/// ROMs and disk images get loaded and the console reset. The FDS only exists for NTSC consoles.
+fn load(options: &Options) -> Result<Cpu, String> {
+    if has_extension(&options.rom, &["nsf", "nsfe"]) {
+        let nsf: Nsf = Nsf::from_file(&options.rom).map_err(|e| e.to_string())?;
+        let region: Region = options.region.unwrap_or(nsf.get_region());
+        let mut player: NsfPlayer = NsfPlayer::new(nsf, region);
+        if let Some(track) = options.track {
+            let index: u8 = track.checked_sub(1).ok_or("Tracks count from 1")?;
+            player.select_track(index).map_err(|e| e.to_string())?;
+        }
+        print_nsf_info(&player);
+        return Ok(player.into_cpu());
+    }
+
+    let mut cpu: Cpu = Cpu::new();
+    if has_extension(&options.rom, &["fds"]) {
+        let disk: DiskImage = DiskImage::from_file(&options.rom).map_err(|e| e.to_string())?;
+        let bios_path: PathBuf = match &options.bios {
+            Some(path) => path.clone(),
+            None => options.rom.with_file_name("disksys.rom"),
+        };
+        let bios: Vec<u8> = std::fs::read(&bios_path)
+            .map_err(|e| format!("failed to read FDS BIOS {}: {}", bios_path.display(), e))?;
+        let fds: Fds = Fds::new(bios, disk).map_err(|e| e.to_string())?;
+        cpu.set_region(options.region.unwrap_or(Region::Ntsc));
+        cpu.insert_cartridge(Box::new(fds));
+    } else {
+        let rom: Rom = Rom::from_file(&options.rom).map_err(|e| e.to_string())?;
+        cpu.load_rom(rom).map_err(|e| e.to_string())?;
+        if let Some(region) = options.region {
+            cpu.set_region(region);
+        }
+    }
+    cpu.reset();
+    Ok(cpu)
+}
+
+/// Loads the cartridge's battery-backed memory from its save file, if it has any. Movies are
+/// recorded from blank memory, so they get neither the save file nor the chance to overwrite it.
+fn open_save_file(options: &Options, cpu: &mut Cpu) -> Result<Option<SaveFile>, String> {
+    if options.movie.is_some() {
+        return Ok(None);
+    }
+    let Some(mapper) = cpu.get_mapper_mut() else {
+        return Ok(None);
+    };
+    let path: PathBuf = SaveFile::get_path(&options.rom);
+    SaveFile::open(path.clone(), mapper)
+        .map_err(|e| format!("failed to read save file {}: {}", path.display(), e))
+}
+
+fn flush_save_file(save_file: &mut Option<SaveFile>, cpu: &Cpu) -> Result<(), String> {
+    if let (Some(save_file), Some(mapper)) = (save_file, cpu.get_mapper()) {
+        save_file.flush(mapper).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Runs a number of frames through `run_chunk` in chunks, flushing the save file after each one.
+fn run_frames(
+    cpu: &mut Cpu,
+    frames: u64,
+    save_file: &mut Option<SaveFile>,
+    mut run_chunk: impl FnMut(&mut Cpu, u64) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut remaining: u64 = frames;
+    while remaining > 0 {
+        let chunk: u64 = remaining.min(FLUSH_INTERVAL);
+        run_chunk(cpu, chunk)?;
+        flush_save_file(save_file, cpu)?;
+        remaining -= chunk;
+    }
+    Ok(())
+}
+
+fn start_movie(options: &Options, cpu: &mut Cpu) -> Result<Option<MoviePlayer>, String> {
+    let Some(path) = &options.movie else {
+        return Ok(None);
+    };
+    let movie: Movie = Movie::from_file(path)
+        .map_err(|e| format!("failed to load movie {}: {}", path.display(), e))?;
+    MoviePlayer::start(cpu, movie)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Runs a frame, with the movie's input for as long as it lasts.
+fn run_frame(cpu: &mut Cpu, player: &mut Option<MoviePlayer>) -> Result<(), String> {
+    if let Some(player) = player
+        && player.step(cpu).map_err(|e| e.to_string())?
+    {
+        return Ok(());
+    }
+    cpu.run_frame();
+    Ok(())
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut cpu: Cpu = load(&options)?;
+    cpu.get_apu_mut().set_sample_rate(options.sample_rate);
+    let mut save_file: Option<SaveFile> = open_save_file(&options, &mut cpu)?;
+    let mut player: Option<MoviePlayer> = start_movie(&options, &mut cpu)?;
+
+    run_frames(
+        &mut cpu,
+        options.start_frame,
+        &mut save_file,
+        |cpu, frames| (0..frames).try_for_each(|_| run_frame(cpu, &mut player)),
+    )?;
+
+    if let Some(path) = &options.wav {
+        let mut wav = WavWriter::create(path, options.sample_rate).map_err(|e| e.to_string())?;
+        discard_apu_samples(&mut cpu);
+        run_frames(&mut cpu, options.frames, &mut save_file, |cpu, frames| {
+            (0..frames).try_for_each(|_| {
+                run_frame(cpu, &mut player)?;
+                write_apu_samples(cpu, &mut wav).map_err(|e| e.to_string())
+            })
+        })?;
+        wav.finish().map_err(|e| e.to_string())?;
+    } else {
+        run_frames(&mut cpu, options.frames, &mut save_file, |cpu, frames| {
+            (0..frames).try_for_each(|_| run_frame(cpu, &mut player))
+        })?;
+    }
+
+    if let Some(player) = &player {
+        println!(
+            "Replayed {} of {} movie frames",
+            player.get_position(),
+            player.get_movie().frames.len()
+        );
+    }
+    flush_save_file(&mut save_file, &cpu)
+}
 
 fn main() {
-    println!("Hello, world!");
+    let options: Options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(message) = run(options) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options: Options = parse(&[
+            "game.nes",
+            "--bios",
+            "disksys.rom",
+            "--wav",
+            "out.wav",
+            "--frames",
+            "120",
+            "--start-frame",
+            "30",
+            "--sample-rate",
+            "48000",
+            "--region",
+            "PAL",
+            "--track",
+            "3",
+            "--movie",
+            "run.fm2",
+        ])
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                rom: PathBuf::from("game.nes"),
+                bios: Some(PathBuf::from("disksys.rom")),
+                wav: Some(PathBuf::from("out.wav")),
+                frames: 120,
+                start_frame: 30,
+                sample_rate: 48000,
+                region: Some(Region::Pal),
+                track: Some(3),
+                movie: Some(PathBuf::from("run.fm2")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["game.nes", "--frames"]).is_err());
+        assert!(parse(&["game.nes", "--frames", "many"]).is_err());
+        assert!(parse(&["game.nes", "--region", "secam"]).is_err());
+        assert!(parse(&["game.nes", "other.nes"]).is_err());
+        assert!(parse(&["game.nes", "--fast"]).is_err());
+        assert!(parse(&["game.fds", "--bios"]).is_err());
+        assert!(parse(&["music.nsf", "--track", "300"]).is_err());
+        assert!(parse(&["game.nes", "--movie"]).is_err());
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut cpu: Cpu = Cpu::new();
+        let mut chunks: Vec<u64> = Vec::new();
+        run_frames(&mut cpu, 2 * FLUSH_INTERVAL + 5, &mut None, |_, frames| {
+            chunks.push(frames);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(chunks, vec![FLUSH_INTERVAL, FLUSH_INTERVAL, 5]);
+    }
+
+    #[test]
+    fn test_movie_skips_save_file() {
+        let dir: PathBuf = std::env::temp_dir().join("nes-emulator-movie-save");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // NROM with battery-backed PRG-RAM
+        let mut rom: Vec<u8> = vec![
+            b'N', b'E', b'S', 0x1A, 1, 1, 0b10, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        std::fs::write(dir.join("game.nes"), rom).unwrap();
+        std::fs::write(dir.join("game.sav"), [0x42]).unwrap();
+        let rom: String = dir.join("game.nes").display().to_string();
+
+        let options: Options = parse(&[&rom]).unwrap();
+        let mut cpu: Cpu = load(&options).unwrap();
+        assert!(open_save_file(&options, &mut cpu).unwrap().is_some());
+        assert_eq!(cpu.read(0x6000), 0x42);
+
+        let options: Options = parse(&[&rom, "--movie", "run.fm2"]).unwrap();
+        let mut cpu: Cpu = load(&options).unwrap();
+        assert!(open_save_file(&options, &mut cpu).unwrap().is_none());
+        assert_eq!(cpu.read(0x6000), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_has_extension() {
+        assert!(has_extension(Path::new("game.fds"), &["fds"]));
+        assert!(has_extension(Path::new("dir/GAME.FDS"), &["fds"]));
+        assert!(has_extension(Path::new("music.nsfe"), &["nsf", "nsfe"]));
+        assert!(!has_extension(Path::new("game.nes"), &["fds"]));
+        assert!(!has_extension(Path::new("fds"), &["fds"]));
+    }
 }
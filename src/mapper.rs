@@ -0,0 +1,455 @@
+pub mod bandai;
+pub mod discrete;
+pub mod fds;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod multicart;
+pub mod namco108;
+pub mod namco163;
+pub mod namco175;
+pub mod nina001;
+pub mod nrom;
+pub mod nsf;
+pub mod rambo1;
+pub mod vrc4;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+
+use crate::mapper::bandai::Bandai;
+use crate::mapper::discrete::{Board, Discrete};
+use crate::mapper::fme7::Fme7;
+use crate::mapper::mmc1::Mmc1;
+use crate::mapper::mmc2::Mmc2;
+use crate::mapper::mmc3::Mmc3;
+use crate::mapper::mmc5::Mmc5;
+use crate::mapper::multicart::{Multicart, MulticartBoard};
+use crate::mapper::namco108::Namco108;
+use crate::mapper::namco163::Namco163;
+use crate::mapper::namco175::Namco175;
+use crate::mapper::nina001::Nina001;
+use crate::mapper::nrom::Nrom;
+use crate::mapper::rambo1::Rambo1;
+use crate::mapper::vrc4::Vrc4;
+use crate::mapper::vrc6::Vrc6;
+use crate::mapper::vrc7::Vrc7;
+use crate::rom::{Mirroring, Rom, RomError};
+use crate::state::Serialize;
+
+/// A scanline the PPU started working on, as reported to the mapper.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scanline {
+    /// The scanline before the first visible one, which already fetches tiles.
+    PreRender,
+    /// One of the 240 visible scanlines.
+    Visible(u8),
+    /// The idle scanline after the visible ones, right before the vertical blank.
+    PostRender,
+}
+
+/// Mapper reference: https://www.nesdev.org/wiki/Mapper
+///
+/// The cartridge hardware. It decodes the CPU's accesses to $4020-$FFFF and the PPU's accesses to
+/// the pattern tables at $0000-$1FFF, switching banks of the ROM and RAM chips in and out, and
+/// possibly controlling the nametable mirroring and raising interrupts.
+pub trait Mapper: Serialize {
+    /// Handles CPU reads in $4020-$FFFF. Addresses nothing is mapped to return 0.
+    fn cpu_read(&self, addr: u16) -> u8;
+
+    /// Handles CPU writes in $4020-$FFFF.
+    fn cpu_write(&mut self, addr: u16, value: u8);
+
+    /// Handles PPU reads in $0000-$1FFF.
+    fn ppu_read(&self, addr: u16) -> u8;
+
+    /// Handles PPU writes in $0000-$1FFF, which only have an effect on CHR-RAM.
+    fn ppu_write(&mut self, addr: u16, value: u8);
+
+    fn get_mirroring(&self) -> Mirroring;
+
+    /// Handles PPU reads in $2000-$2FFF. `ciram` is the console's nametable memory, which is 2
+    /// KiB large, or 4 KiB for four-screen cartridges. By default, it gets mirrored according to
+    /// [Mapper::get_mirroring].
+    fn read_nametable(&self, addr: u16, ciram: &[u8]) -> u8 {
+        ciram[get_ciram_index(self.get_mirroring(), addr) % ciram.len()]
+    }
+
+    /// Handles PPU writes in $2000-$2FFF, see [Mapper::read_nametable].
+    fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8]) {
+        let len: usize = ciram.len();
+        ciram[get_ciram_index(self.get_mirroring(), addr) % len] = value;
+    }
+
+    /// Whether the cartridge is pulling the CPU's IRQ line low.
+    fn get_irq(&self) -> bool {
+        false
+    }
+
+    /// Called once per CPU cycle, for mappers with cycle based timers.
+    fn clock_cpu(&mut self) {}
+
+    /// Called at the start of every scanline the PPU renders or idles on, for mappers with
+    /// scanline based timers. Scanlines during the vertical blank aren't reported.
+    fn notify_scanline(&mut self, _scanline: Scanline) {}
+
+    /// Called when the PPU starts or stops fetching sprite patterns, at dots 257 and 321 of
+    /// rendered scanlines, for mappers which bank sprites and background separately.
+    fn notify_sprite_fetch(&mut self, _active: bool) {}
+
+    /// Called on CPU writes to the PPU's registers at $2000-$3FFF, for mappers which snoop on them.
+    fn notify_ppu_register_write(&mut self, _addr: u16, _value: u8) {}
+
+    /// Current output of the cartridge's expansion audio, on the scale of the APU's mixed output.
+    fn get_audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Number of disk sides, for the Famicom Disk System. Cartridges have none.
+    fn get_disk_side_count(&self) -> usize {
+        0
+    }
+
+    /// The disk side in the drive, if any.
+    fn get_disk_side(&self) -> Option<usize> {
+        None
+    }
+
+    /// Inserts a disk side into the drive, or ejects the disk with `None`.
+    fn set_disk_side(&mut self, _side: Option<usize>) {}
+
+    /// The battery-backed memory which outlives the session, such as PRG-RAM or an EEPROM.
+    /// Cartridges without a battery have none.
+    fn get_save_data(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Used to restore the battery-backed memory, like from a `.sav` file.
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+/// Maps a nametable address in $2000-$2FFF to the nametable memory.
+pub fn get_ciram_index(mirroring: Mirroring, addr: u16) -> usize {
+    let table: usize = ((addr >> 10) & 0b11) as usize;
+    let offset: usize = (addr & 0x3FF) as usize;
+    let page: usize = match mirroring {
+        Mirroring::Horizontal => table >> 1,
+        Mirroring::Vertical => table & 1,
+        Mirroring::FourScreen => table,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+    };
+    page * 0x400 + offset
+}
+
+/// An entry of the mapper registry.
+pub struct MapperInfo {
+    /// The iNES/NES 2.0 mapper number.
+    pub number: u16,
+    /// The boards or chips the number stands for.
+    pub name: &'static str,
+    create: fn(Rom) -> Box<dyn Mapper>,
+}
+
+/// Every supported mapper, ordered by number.
+const REGISTRY: &[MapperInfo] = &[
+    MapperInfo {
+        number: 0,
+        name: "NROM",
+        create: |rom| Box::new(Nrom::new(rom)),
+    },
+    MapperInfo {
+        number: 1,
+        name: "MMC1",
+        create: |rom| Box::new(Mmc1::new(rom)),
+    },
+    MapperInfo {
+        number: 2,
+        name: "UxROM",
+        create: |rom| Box::new(Discrete::new(rom, Board::Uxrom)),
+    },
+    MapperInfo {
+        number: 3,
+        name: "CNROM",
+        create: |rom| Box::new(Discrete::new(rom, Board::Cnrom)),
+    },
+    MapperInfo {
+        number: 4,
+        name: "MMC3",
+        create: |rom| Box::new(Mmc3::new(rom)),
+    },
+    MapperInfo {
+        number: 5,
+        name: "MMC5",
+        create: |rom| Box::new(Mmc5::new(rom)),
+    },
+    MapperInfo {
+        number: 7,
+        name: "AxROM",
+        create: |rom| Box::new(Discrete::new(rom, Board::Axrom)),
+    },
+    MapperInfo {
+        number: 9,
+        name: "MMC2",
+        create: |rom| Box::new(Mmc2::new(rom)),
+    },
+    MapperInfo {
+        number: 10,
+        name: "MMC4",
+        create: |rom| Box::new(Mmc2::new(rom)),
+    },
+    MapperInfo {
+        number: 11,
+        name: "Color Dreams",
+        create: |rom| Box::new(Discrete::new(rom, Board::ColorDreams)),
+    },
+    MapperInfo {
+        number: 13,
+        name: "CPROM",
+        create: |rom| Box::new(Discrete::new(rom, Board::Cprom)),
+    },
+    MapperInfo {
+        number: 15,
+        name: "K-1029",
+        create: |rom| Box::new(Multicart::new(rom, MulticartBoard::K1029)),
+    },
+    MapperInfo {
+        number: 16,
+        name: "Bandai FCG/LZ93D50",
+        create: |rom| Box::new(Bandai::new(rom)),
+    },
+    MapperInfo {
+        number: 19,
+        name: "Namco 163",
+        create: |rom| Box::new(Namco163::new(rom)),
+    },
+    MapperInfo {
+        number: 21,
+        name: "VRC4a/VRC4c",
+        create: |rom| Box::new(Vrc4::new(rom)),
+    },
+    MapperInfo {
+        number: 22,
+        name: "VRC2a",
+        create: |rom| Box::new(Vrc4::new(rom)),
+    },
+    MapperInfo {
+        number: 23,
+        name: "VRC2b/VRC4e/VRC4f",
+        create: |rom| Box::new(Vrc4::new(rom)),
+    },
+    MapperInfo {
+        number: 24,
+        name: "VRC6a",
+        create: |rom| Box::new(Vrc6::new(rom)),
+    },
+    MapperInfo {
+        number: 25,
+        name: "VRC2c/VRC4b/VRC4d",
+        create: |rom| Box::new(Vrc4::new(rom)),
+    },
+    MapperInfo {
+        number: 26,
+        name: "VRC6b",
+        create: |rom| Box::new(Vrc6::new(rom)),
+    },
+    MapperInfo {
+        number: 34,
+        name: "BNROM/NINA-001",
+        // BNROM boards have no CHR-ROM or only 8 KiB of it, NINA-001 ones have more
+        create: |rom| match rom.header.submapper {
+            1 => Box::new(Nina001::new(rom)),
+            2 => Box::new(Discrete::new(rom, Board::Bnrom)),
+            _ if rom.chr_rom.len() > 0x2000 => Box::new(Nina001::new(rom)),
+            _ => Box::new(Discrete::new(rom, Board::Bnrom)),
+        },
+    },
+    MapperInfo {
+        number: 58,
+        name: "Study & Game multicart",
+        create: |rom| Box::new(Multicart::new(rom, MulticartBoard::Bmc58)),
+    },
+    MapperInfo {
+        number: 64,
+        name: "RAMBO-1",
+        create: |rom| Box::new(Rambo1::new(rom)),
+    },
+    MapperInfo {
+        number: 66,
+        name: "GxROM",
+        create: |rom| Box::new(Discrete::new(rom, Board::Gxrom)),
+    },
+    MapperInfo {
+        number: 69,
+        name: "FME-7/Sunsoft 5B",
+        create: |rom| Box::new(Fme7::new(rom)),
+    },
+    MapperInfo {
+        number: 71,
+        name: "Camerica",
+        create: |rom| Box::new(Discrete::new(rom, Board::Camerica)),
+    },
+    MapperInfo {
+        number: 79,
+        name: "NINA-03/NINA-06",
+        create: |rom| Box::new(Discrete::new(rom, Board::Nina0306)),
+    },
+    MapperInfo {
+        number: 85,
+        name: "VRC7",
+        create: |rom| Box::new(Vrc7::new(rom)),
+    },
+    MapperInfo {
+        number: 159,
+        name: "Bandai LZ93D50 with 24C01",
+        create: |rom| Box::new(Bandai::new(rom)),
+    },
+    MapperInfo {
+        number: 206,
+        name: "Namco 108",
+        create: |rom| Box::new(Namco108::new(rom)),
+    },
+    MapperInfo {
+        number: 210,
+        name: "Namco 175/340",
+        create: |rom| Box::new(Namco175::new(rom)),
+    },
+    MapperInfo {
+        number: 225,
+        name: "64-in-1 multicart",
+        create: |rom| Box::new(Multicart::new(rom, MulticartBoard::Bmc225)),
+    },
+    MapperInfo {
+        number: 228,
+        name: "Action 52",
+        create: |rom| Box::new(Multicart::new(rom, MulticartBoard::Action52)),
+    },
+];
+
+/// Looks up a mapper number in the registry.
+pub fn get_info(number: u16) -> Option<&'static MapperInfo> {
+    REGISTRY.iter().find(|info| info.number == number)
+}
+
+/// Returns every supported mapper, ordered by number.
+pub fn get_supported() -> &'static [MapperInfo] {
+    REGISTRY
+}
+
+/// Creates the mapper a ROM asks for.
+pub fn create(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+    match get_info(rom.header.mapper) {
+        Some(info) => Ok((info.create)(rom)),
+        None => Err(RomError::UnsupportedMapper(rom.header.mapper)),
+    }
+}
+
+/// Returns the cartridge's CHR memory, and whether it is RAM. Cartridges without CHR-ROM come
+/// with CHR-RAM instead.
+pub fn create_chr(rom: &Rom) -> (Vec<u8>, bool) {
+    if rom.chr_rom.is_empty() {
+        let size: usize = rom.header.chr_ram_size + rom.header.chr_nvram_size;
+        (vec![0; size.max(0x2000)], true)
+    } else {
+        (rom.chr_rom.clone(), false)
+    }
+}
+
+/// Returns the cartridge's PRG-RAM, with the trainer, if any, loaded to $7000.
+pub fn create_prg_ram(rom: &Rom) -> Vec<u8> {
+    let size: usize = rom.header.prg_ram_size + rom.header.prg_nvram_size;
+    let mut out: Vec<u8> = vec![0; size];
+    if let Some(trainer) = &rom.trainer {
+        if out.len() < 0x2000 {
+            out.resize(0x2000, 0);
+        }
+        out[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+    }
+    out
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Creates an iNES ROM with the given number of 16 KiB PRG and 8 KiB CHR banks. Every byte
+    /// holds the index of the KiB it is in, to make checking the bank switching easy.
+    pub fn create_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Rom {
+        let mut bytes: Vec<u8> = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks];
+        bytes.extend([(mapper & 0x0F) << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend((0..prg_banks as usize * 0x4000).map(|i| (i / 0x400) as u8));
+        bytes.extend((0..chr_banks as usize * 0x2000).map(|i| (i / 0x400) as u8));
+        Rom::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_create_chr() {
+        let (chr, ram) = create_chr(&create_rom(0, 1, 0));
+        assert_eq!(chr.len(), 0x2000);
+        assert!(ram);
+
+        let (chr, ram) = create_chr(&create_rom(0, 1, 2));
+        assert_eq!(chr.len(), 0x4000);
+        assert!(!ram);
+    }
+
+    #[test]
+    fn test_create_prg_ram() {
+        let mut rom: Rom = create_rom(0, 1, 1);
+        rom.trainer = Some(vec![0x42; 512]);
+        rom.header.prg_ram_size = 0;
+        let ram: Vec<u8> = create_prg_ram(&rom);
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(ram[0x0FFF], 0);
+        assert_eq!(ram[0x1000], 0x42);
+    }
+
+    #[test]
+    fn test_ciram_index() {
+        assert_eq!(get_ciram_index(Mirroring::Horizontal, 0x2400), 0);
+        assert_eq!(get_ciram_index(Mirroring::Horizontal, 0x2801), 0x401);
+        assert_eq!(get_ciram_index(Mirroring::Vertical, 0x2400), 0x400);
+        assert_eq!(get_ciram_index(Mirroring::Vertical, 0x2C00), 0x400);
+        assert_eq!(get_ciram_index(Mirroring::FourScreen, 0x2C00), 0xC00);
+        assert_eq!(get_ciram_index(Mirroring::SingleScreenUpper, 0x2000), 0x400);
+        // $3000-$3EFF mirrors $2000-$2EFF
+        assert_eq!(get_ciram_index(Mirroring::SingleScreenLower, 0x3C05), 5);
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(
+            get_supported()
+                .windows(2)
+                .all(|pair| pair[0].number < pair[1].number)
+        );
+        assert_eq!(get_info(85).unwrap().name, "VRC7");
+        // every entry can create its mapper
+        for info in get_supported() {
+            let mapper: Box<dyn Mapper> = create(create_rom(info.number as u8, 2, 2)).unwrap();
+            assert_eq!(mapper.cpu_read(0xFFFF), 31, "{}", info.name);
+        }
+    }
+
+    #[test]
+    fn test_nina001_detection() {
+        // NINA-001 boards can be told apart from BNROM ones by their CHR-ROM
+        let mut nina: Box<dyn Mapper> = create(create_rom(34, 2, 2)).unwrap();
+        nina.cpu_write(0x7FFF, 2);
+        assert_eq!(nina.ppu_read(0x1000), 8);
+        let mut bnrom: Box<dyn Mapper> = create(create_rom(34, 2, 1)).unwrap();
+        bnrom.cpu_write(0x7FFF, 2);
+        assert_eq!(bnrom.ppu_read(0x1000), 4);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let mut rom: Rom = create_rom(0, 1, 1);
+        rom.header.mapper = 255;
+        assert!(matches!(create(rom), Err(RomError::UnsupportedMapper(255))));
+    }
+}
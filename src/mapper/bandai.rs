@@ -0,0 +1,324 @@
+pub mod eeprom;
+
+use crate::mapper::bandai::eeprom::{Eeprom, EepromChip};
+use crate::mapper::{Mapper, create_chr};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Bandai FCG board reference: https://www.nesdev.org/wiki/Bandai_FCG_board
+///
+/// Mappers 16 and 159. Switches a 16 KiB PRG bank at $8000-$BFFF, with the last one fixed at
+/// $C000-$FFFF, and 1 KiB CHR banks, and comes with a cycle based interrupt counter. The
+/// registers are mirrored every 16 bytes, in $6000-$7FFF on the FCG-1 and FCG-2 (submapper 4)
+/// and in $8000-$FFFF on the LZ93D50 (submapper 5), with submapper 0 decoding both.
+///
+/// Instead of PRG-RAM, the LZ93D50 boards save to a serial EEPROM, a 24C02 on mapper 16 and a
+/// 24C01 on mapper 159. Reads in $6000-$7FFF return its SDA line in bit 4.
+pub struct Bandai {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// Whether the registers are decoded in $6000-$7FFF and $8000-$FFFF.
+    fcg_registers: bool,
+    lz93d50_registers: bool,
+
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: Mirroring,
+
+    /// Counts down every CPU cycle, raising an interrupt when reaching 0.
+    irq_counter: u16,
+    /// The LZ93D50 loads the counter from the latch when enabling the interrupt, the FCG writes
+    /// the counter directly.
+    irq_latch: u16,
+    irq_enabled: bool,
+    irq_flag: bool,
+
+    eeprom: Option<Eeprom>,
+    /// The last value written to the EEPROM control register: <pre>
+    /// 1 << 7 => SDA released, to read the EEPROM
+    /// 1 << 6 => SDA
+    /// 1 << 5 => SCL
+    /// </pre>
+    eeprom_control: u8,
+}
+
+impl Bandai {
+    pub fn new(rom: Rom) -> Bandai {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let submapper: u8 = rom.header.submapper;
+        let chip: Option<EepromChip> = match rom.header.mapper {
+            159 => Some(EepromChip::X24C01),
+            _ if submapper == 5 || rom.header.battery => Some(EepromChip::X24C02),
+            _ => None,
+        };
+        Bandai {
+            fcg_registers: rom.header.mapper == 16 && submapper != 5,
+            lz93d50_registers: rom.header.mapper == 159 || submapper != 4,
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_enabled: false,
+            irq_flag: false,
+            eeprom: chip.map(Eeprom::new),
+            eeprom_control: 0,
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let bank: usize = if addr < 0xC000 {
+            (self.prg_bank & 0x0F) as usize
+        } else {
+            self.prg_rom.len() / 0x4000 - 1
+        };
+        (bank * 0x4000 + (addr & 0x3FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = self.chr_banks[(addr >> 10) as usize & 0b111] as usize;
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+
+    /// Registers, mirrored every 16 bytes: <pre>
+    /// $0-$7 => CHR banks
+    /// $8    => PRG bank at $8000-$BFFF
+    /// $9    => mirroring
+    /// $A    => 1: interrupt enabled; acknowledges the interrupt
+    /// $B-$C => lower and upper byte of the counter, or the LZ93D50's latch
+    /// $D    => EEPROM control
+    /// </pre>
+    fn write_register(&mut self, addr: u16, value: u8, lz93d50: bool) {
+        match addr & 0x0F {
+            0x0..=0x7 => self.chr_banks[(addr & 0x07) as usize] = value,
+            0x8 => self.prg_bank = value,
+            0x9 => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xA => {
+                self.irq_enabled = value & 1 == 1;
+                self.irq_flag = false;
+                if lz93d50 {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB | 0xC => {
+                let target: &mut u16 = if lz93d50 {
+                    &mut self.irq_latch
+                } else {
+                    &mut self.irq_counter
+                };
+                *target = if addr & 0x0F == 0xB {
+                    (*target & 0xFF00) | value as u16
+                } else {
+                    (*target & 0x00FF) | (value as u16) << 8
+                };
+            }
+            0xD => {
+                self.eeprom_control = value;
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write((value >> 5) & 1 == 1, value & 0xC0 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            // SDA is pulled low by either the EEPROM or the mapper
+            0x6000..=0x7FFF => match &self.eeprom {
+                Some(eeprom) => {
+                    let sda: bool = eeprom.get_output() && self.eeprom_control & 0xC0 != 0;
+                    (sda as u8) << 4
+                }
+                None => 0,
+            },
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.fcg_registers => self.write_register(addr, value, false),
+            0x8000..=0xFFFF if self.lz93d50_registers => self.write_register(addr, value, true),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq_flag = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.eeprom.as_ref().map(|eeprom| eeprom.get_data())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.eeprom.as_mut().map(|eeprom| eeprom.get_data_mut())
+    }
+}
+
+impl Serialize for Bandai {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.prg_bank);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.irq_counter);
+        s.value(&mut self.irq_latch);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_flag);
+        if let Some(eeprom) = &mut self.eeprom {
+            s.value(eeprom);
+        }
+        s.value(&mut self.eeprom_control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::bandai::eeprom::tests::Host;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_banks() {
+        let mut bandai: Bandai = Bandai::new(create_rom(16, 8, 16));
+        for i in 0..8 {
+            bandai.cpu_write(0x8000 + i, 50 + i as u8);
+        }
+        let banks: Vec<u8> = (0..8).map(|i| bandai.ppu_read(i * 0x400)).collect();
+        assert_eq!(banks, vec![50, 51, 52, 53, 54, 55, 56, 57]);
+
+        bandai.cpu_write(0x6008, 3);
+        assert_eq!(bandai.cpu_read(0x8000), 3 * 16);
+        assert_eq!(bandai.cpu_read(0xBC00), 3 * 16 + 15);
+        assert_eq!(bandai.cpu_read(0xC000), 7 * 16);
+
+        bandai.cpu_write(0x8019, 3);
+        assert_eq!(bandai.get_mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_submappers() {
+        let mut rom: Rom = create_rom(16, 8, 16);
+        rom.header.submapper = 4;
+        let mut fcg: Bandai = Bandai::new(rom);
+        fcg.cpu_write(0x8008, 3);
+        assert_eq!(fcg.cpu_read(0x8000), 0);
+        fcg.cpu_write(0x6008, 3);
+        assert_eq!(fcg.cpu_read(0x8000), 3 * 16);
+        assert!(fcg.get_save_data().is_none());
+
+        let mut rom: Rom = create_rom(16, 8, 16);
+        rom.header.submapper = 5;
+        let mut lz93d50: Bandai = Bandai::new(rom);
+        lz93d50.cpu_write(0x6008, 3);
+        assert_eq!(lz93d50.cpu_read(0x8000), 0);
+        assert_eq!(lz93d50.get_save_data().map(|data| data.len()), Some(0x100));
+
+        let bandai: Bandai = Bandai::new(create_rom(159, 8, 16));
+        assert_eq!(bandai.get_save_data().map(|data| data.len()), Some(0x80));
+    }
+
+    #[test]
+    fn test_irq() {
+        // the FCG writes the counter directly
+        let mut rom: Rom = create_rom(16, 8, 16);
+        rom.header.submapper = 4;
+        let mut fcg: Bandai = Bandai::new(rom);
+        fcg.cpu_write(0x600B, 1);
+        fcg.cpu_write(0x600C, 0);
+        fcg.cpu_write(0x600A, 1);
+        fcg.clock_cpu();
+        assert!(!fcg.get_irq());
+        fcg.clock_cpu();
+        assert!(fcg.get_irq());
+        fcg.cpu_write(0x600A, 0);
+        assert!(!fcg.get_irq());
+
+        // the LZ93D50 loads it from the latch when enabling
+        let mut rom: Rom = create_rom(16, 8, 16);
+        rom.header.submapper = 5;
+        let mut lz93d50: Bandai = Bandai::new(rom);
+        lz93d50.cpu_write(0x800B, 2);
+        lz93d50.cpu_write(0x800C, 0);
+        for _ in 0..10 {
+            lz93d50.clock_cpu();
+        }
+        lz93d50.cpu_write(0x800A, 1);
+        for _ in 0..2 {
+            lz93d50.clock_cpu();
+        }
+        assert!(!lz93d50.get_irq());
+        lz93d50.clock_cpu();
+        assert!(lz93d50.get_irq());
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut rom: Rom = create_rom(16, 8, 16);
+        rom.header.submapper = 5;
+        let mut bandai: Bandai = Bandai::new(rom);
+        bandai.get_save_data_mut().unwrap()[0x20] = 0x5A;
+
+        // clocks SCL with SDA released
+        let clock =
+            |bandai: &mut Bandai, scl: bool| bandai.cpu_write(0x800D, (scl as u8) << 5 | 0xC0);
+        let mut host: Host = Host {
+            eeprom: bandai.eeprom.as_mut().unwrap(),
+        };
+        host.start();
+        host.send(0xA0, false);
+        host.send(0x20, false);
+        host.start();
+        host.send(0xA1, false);
+
+        // the first bit of $5A goes out on the next rising edge of SCL
+        clock(&mut bandai, false);
+        clock(&mut bandai, true);
+        assert_eq!(bandai.cpu_read(0x6000), 0x00);
+        clock(&mut bandai, false);
+        clock(&mut bandai, true);
+        assert_eq!(bandai.cpu_read(0x7FF0), 0x10);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::state::{Serialize, Serializer, StateError};
+
+/// The serial EEPROMs found on Bandai boards.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EepromChip {
+    /// 128 bytes. Transfers start with the 7-bit address and a read bit, and bytes are sent
+    /// least significant bit first.
+    X24C01,
+    /// 256 bytes. Transfers start with the device address, the word address gets sent
+    /// separately, and bytes are sent most significant bit first.
+    X24C02,
+}
+
+impl EepromChip {
+    pub fn get_size(self) -> usize {
+        match self {
+            EepromChip::X24C01 => 0x80,
+            EepromChip::X24C02 => 0x100,
+        }
+    }
+}
+
+/// What the EEPROM expects on the next clock.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    Idle,
+    /// Receiving the device address of the 24C02.
+    ChipAddress,
+    /// Receiving the word address.
+    Address,
+    /// Sending a byte.
+    Read,
+    /// Receiving a byte.
+    Write,
+    /// Acknowledging a received byte by pulling SDA low.
+    SendAck,
+    /// Waiting for the host to acknowledge a sent byte, which asks for the next one.
+    WaitAck,
+}
+
+/// 24C0x reference: https://www.nesdev.org/wiki/Bandai_FCG_board#Serial_EEPROM
+///
+/// An I²C EEPROM, bit-banged by the CPU through the SCL clock and SDA data lines. While SCL is
+/// high, SDA going low starts a transfer and SDA going high stops it. Otherwise, bits get sampled
+/// when SCL rises and SDA changes while SCL is low: <pre>
+/// 24C01 => START, address (7 bits), R/W, ACK, data, ACK, ..., STOP
+/// 24C02 => START, $A0 | R/W, ACK, address, ACK, data, ACK, ..., STOP
+/// </pre>
+/// Reading from the 24C02 goes through a write of the address first, then a second START with
+/// the read bit set. The address increments after every byte.
+pub struct Eeprom {
+    chip: EepromChip,
+    data: Vec<u8>,
+    mode: Mode,
+    /// The mode to switch to after the acknowledgement.
+    next_mode: Mode,
+    chip_address: u8,
+    address: u8,
+    /// The byte being sent or received.
+    buffer: u8,
+    /// Number of bits of the byte sent or received so far.
+    bit: u8,
+    scl: bool,
+    sda: bool,
+    /// The level the EEPROM drives SDA to, high when released.
+    output: bool,
+}
+
+impl Eeprom {
+    pub fn new(chip: EepromChip) -> Eeprom {
+        Eeprom {
+            chip,
+            data: vec![0; chip.get_size()],
+            mode: Mode::Idle,
+            next_mode: Mode::Idle,
+            chip_address: 0,
+            address: 0,
+            buffer: 0,
+            bit: 0,
+            scl: false,
+            sda: false,
+            output: true,
+        }
+    }
+
+    pub fn get_chip(&self) -> EepromChip {
+        self.chip
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn get_data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn get_output(&self) -> bool {
+        self.output
+    }
+
+    /// Updates the levels of the SCL and SDA lines.
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda && !sda {
+            self.start();
+        } else if self.scl && scl && !self.sda && sda {
+            self.mode = Mode::Idle;
+            self.output = true;
+        } else if !self.scl && scl {
+            self.clock_rise(sda);
+        } else if self.scl && !scl {
+            self.clock_fall();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn start(&mut self) {
+        self.mode = match self.chip {
+            EepromChip::X24C01 => Mode::Address,
+            EepromChip::X24C02 => Mode::ChipAddress,
+        };
+        self.bit = 0;
+        self.output = true;
+    }
+
+    /// Index of the bit sent or received next, within its byte.
+    fn get_shift(&self) -> u8 {
+        match self.chip {
+            EepromChip::X24C01 => self.bit,
+            EepromChip::X24C02 => 7 - self.bit,
+        }
+    }
+
+    fn receive_bit(&mut self, sda: bool) {
+        if self.bit < 8 {
+            let shift: u8 = self.get_shift();
+            self.buffer = (self.buffer & !(1 << shift)) | (sda as u8) << shift;
+            self.bit += 1;
+        }
+    }
+
+    fn send_bit(&mut self) {
+        if self.bit < 8 {
+            self.output = (self.buffer >> self.get_shift()) & 1 == 1;
+            self.bit += 1;
+        }
+    }
+
+    fn get_index(&self) -> usize {
+        self.address as usize % self.data.len()
+    }
+
+    fn increment_address(&mut self) {
+        self.address = ((self.address as usize + 1) % self.data.len()) as u8;
+    }
+
+    /// Starts sending the byte at the current address.
+    fn prepare_read(&mut self) {
+        self.next_mode = Mode::Read;
+        self.buffer = self.data[self.get_index()];
+    }
+
+    fn clock_rise(&mut self, sda: bool) {
+        match self.mode {
+            Mode::Idle => {}
+            // the 24C01's eighth bit is the R/W bit rather than part of the address
+            Mode::Address if self.chip == EepromChip::X24C01 && self.bit == 7 => {
+                self.address = self.buffer & 0x7F;
+                self.bit = 8;
+                if sda {
+                    self.prepare_read();
+                } else {
+                    self.next_mode = Mode::Write;
+                }
+            }
+            Mode::ChipAddress | Mode::Address | Mode::Write => self.receive_bit(sda),
+            Mode::Read => self.send_bit(),
+            Mode::SendAck => self.output = false,
+            Mode::WaitAck => {
+                if sda {
+                    self.next_mode = Mode::Idle;
+                } else {
+                    self.prepare_read();
+                }
+            }
+        }
+    }
+
+    fn clock_fall(&mut self) {
+        if self.bit < 8 && !matches!(self.mode, Mode::SendAck | Mode::WaitAck) {
+            return;
+        }
+        match self.mode {
+            Mode::Idle => {}
+            Mode::ChipAddress => {
+                self.chip_address = self.buffer;
+                if self.chip_address & 0xF0 == 0xA0 {
+                    if self.chip_address & 1 == 1 {
+                        self.prepare_read();
+                    } else {
+                        self.next_mode = Mode::Address;
+                    }
+                    self.mode = Mode::SendAck;
+                } else {
+                    self.mode = Mode::Idle;
+                }
+                self.output = true;
+            }
+            Mode::Address => {
+                if self.chip == EepromChip::X24C02 {
+                    self.address = self.buffer;
+                    self.next_mode = Mode::Write;
+                }
+                self.mode = Mode::SendAck;
+                self.output = true;
+            }
+            Mode::Read => {
+                self.mode = Mode::WaitAck;
+                self.output = true;
+                self.increment_address();
+            }
+            Mode::Write => {
+                let index: usize = self.get_index();
+                self.data[index] = self.buffer;
+                self.increment_address();
+                self.mode = Mode::SendAck;
+                self.next_mode = Mode::Write;
+                self.output = true;
+            }
+            Mode::SendAck | Mode::WaitAck => {
+                self.mode = self.next_mode;
+                self.bit = 0;
+                self.output = true;
+            }
+        }
+    }
+}
+
+impl Serialize for Mode {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut index: u8 = *self as u8;
+        s.value(&mut index);
+        *self = match index {
+            0 => Mode::Idle,
+            1 => Mode::ChipAddress,
+            2 => Mode::Address,
+            3 => Mode::Read,
+            4 => Mode::Write,
+            5 => Mode::SendAck,
+            6 => Mode::WaitAck,
+            _ => {
+                s.fail(StateError::Mismatch("EEPROM mode"));
+                *self
+            }
+        };
+    }
+}
+
+impl Serialize for Eeprom {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.bytes(&mut self.data);
+        s.value(&mut self.mode);
+        s.value(&mut self.next_mode);
+        s.value(&mut self.chip_address);
+        s.value(&mut self.address);
+        s.value(&mut self.buffer);
+        s.value(&mut self.bit);
+        s.value(&mut self.scl);
+        s.value(&mut self.sda);
+        s.value(&mut self.output);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Drives the EEPROM like a game would, through SCL and SDA.
+    pub struct Host<'a> {
+        pub eeprom: &'a mut Eeprom,
+    }
+
+    impl Host<'_> {
+        pub fn start(&mut self) {
+            self.eeprom.write(false, true);
+            self.eeprom.write(true, true);
+            self.eeprom.write(true, false);
+            self.eeprom.write(false, false);
+        }
+
+        pub fn stop(&mut self) {
+            self.eeprom.write(false, false);
+            self.eeprom.write(true, false);
+            self.eeprom.write(true, true);
+        }
+
+        /// Sends a bit and returns the level of SDA while SCL was high.
+        pub fn clock(&mut self, sda: bool) -> bool {
+            self.eeprom.write(false, sda);
+            self.eeprom.write(true, sda);
+            let out: bool = self.eeprom.get_output() && sda;
+            self.eeprom.write(false, sda);
+            out
+        }
+
+        /// Sends a byte and returns whether it got acknowledged.
+        pub fn send(&mut self, byte: u8, lsb_first: bool) -> bool {
+            for i in 0..8 {
+                let shift: u8 = if lsb_first { i } else { 7 - i };
+                self.clock(byte >> shift & 1 == 1);
+            }
+            !self.clock(true)
+        }
+
+        /// Receives a byte, then acknowledges it to ask for the next one.
+        pub fn receive(&mut self, lsb_first: bool, ack: bool) -> u8 {
+            let mut out: u8 = 0;
+            for i in 0..8 {
+                let shift: u8 = if lsb_first { i } else { 7 - i };
+                out |= (self.clock(true) as u8) << shift;
+            }
+            self.clock(!ack);
+            out
+        }
+    }
+
+    #[test]
+    fn test_24c02() {
+        let mut eeprom: Eeprom = Eeprom::new(EepromChip::X24C02);
+        let mut host: Host = Host {
+            eeprom: &mut eeprom,
+        };
+        host.start();
+        assert!(host.send(0xA0, false));
+        assert!(host.send(0x10, false));
+        assert!(host.send(0x12, false));
+        assert!(host.send(0x34, false));
+        host.stop();
+
+        // sets the address with a write, then reads sequentially
+        host.start();
+        assert!(host.send(0xA0, false));
+        assert!(host.send(0x10, false));
+        host.start();
+        assert!(host.send(0xA1, false));
+        assert_eq!(host.receive(false, true), 0x12);
+        assert_eq!(host.receive(false, false), 0x34);
+        host.stop();
+
+        // other devices are ignored
+        host.start();
+        assert!(!host.send(0x50, false));
+        host.stop();
+        assert_eq!(&eeprom.get_data()[0x10..0x13], &[0x12, 0x34, 0]);
+    }
+
+    #[test]
+    fn test_24c01() {
+        let mut eeprom: Eeprom = Eeprom::new(EepromChip::X24C01);
+        let mut host: Host = Host {
+            eeprom: &mut eeprom,
+        };
+        host.start();
+        // address $05, write
+        assert!(host.send(0x05, true));
+        assert!(host.send(0xC1, true));
+        host.stop();
+
+        host.start();
+        // address $05, read
+        assert!(host.send(0x85, true));
+        assert_eq!(host.receive(true, false), 0xC1);
+        host.stop();
+        assert_eq!(eeprom.get_data()[5], 0xC1);
+        assert_eq!(eeprom.get_data().len(), 0x80);
+    }
+}
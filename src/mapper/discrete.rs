@@ -0,0 +1,299 @@
+use crate::mapper::{Mapper, create_chr};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// The discrete logic boards, which consist of little more than a latch for the bank numbers.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Board {
+    /// UxROM, mapper 2: switches 16 KiB at $8000, the last bank is fixed at $C000.
+    Uxrom,
+    /// CNROM, mapper 3: switches 8 KiB of CHR.
+    Cnrom,
+    /// AxROM, mapper 7: switches 32 KiB of PRG and selects a single screen nametable.
+    Axrom,
+    /// Color Dreams, mapper 11: the low nibble switches 32 KiB of PRG, the high one 8 KiB of CHR.
+    ColorDreams,
+    /// CPROM, mapper 13: switches 4 KiB of its 16 KiB CHR-RAM at $1000.
+    Cprom,
+    /// BNROM, mapper 34: switches 32 KiB of PRG.
+    Bnrom,
+    /// GxROM, mapper 66: bits 4-5 switch 32 KiB of PRG, bits 0-1 8 KiB of CHR.
+    Gxrom,
+    /// Camerica, mapper 71: like UxROM, but the latch is at $C000-$FFFF. Fire Hawk's board
+    /// (submapper 1) selects a single screen nametable through bit 4 of writes to $8000-$9FFF.
+    Camerica,
+    /// NINA-03/06, mapper 79: the latch is at $4100-$5FFF; bit 3 switches 32 KiB of PRG, bits 0-2
+    /// 8 KiB of CHR.
+    Nina0306,
+}
+
+/// Discrete mapper reference: https://www.nesdev.org/wiki/Category:Discrete_logic_mappers
+///
+/// Every write to $8000-$FFFF, or another range on some boards, loads the bank latch. Boards
+/// which don't disconnect the ROM while it is written to suffer from bus conflicts: the ROM drives
+/// the data bus at the same time, so the latch receives the written value ANDed with the ROM's
+/// byte at that address.
+pub struct Discrete {
+    board: Board,
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    /// Whether a Camerica board controls the mirroring.
+    single_screen: bool,
+    latch: u8,
+}
+
+impl Discrete {
+    pub fn new(rom: Rom, board: Board) -> Discrete {
+        let (mut chr, chr_is_ram) = create_chr(&rom);
+        // NES 2.0 submapper 1 marks boards without bus conflicts, submapper 2 boards with them
+        let bus_conflicts: bool = match (board, rom.header.submapper) {
+            (Board::Uxrom | Board::Cnrom | Board::Axrom, 1) => false,
+            (Board::Uxrom | Board::Cnrom | Board::Axrom, 2) => true,
+            // AxROM boards mostly come without them, as the ANROM ones are rare
+            (Board::Axrom, _) => false,
+            // the latch isn't in ROM space
+            (Board::Camerica | Board::Nina0306, _) => false,
+            _ => true,
+        };
+        if board == Board::Cprom && chr_is_ram && chr.len() < 0x4000 {
+            chr.resize(0x4000, 0);
+        }
+        Discrete {
+            board,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.header.mirroring,
+            bus_conflicts,
+            single_screen: board == Board::Camerica && rom.header.submapper == 1,
+            latch: 0,
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let offset: usize = (addr - 0x8000) as usize;
+        let base: usize = match self.board {
+            Board::Uxrom | Board::Camerica => {
+                let bank: usize = if addr >= 0xC000 {
+                    self.prg_rom.len() / 0x4000 - 1
+                } else {
+                    self.latch as usize
+                };
+                return (bank * 0x4000 + (offset & 0x3FFF)) % self.prg_rom.len();
+            }
+            Board::Cnrom | Board::Cprom => 0,
+            Board::Axrom => (self.latch & 0x07) as usize * 0x8000,
+            Board::ColorDreams => (self.latch & 0x03) as usize * 0x8000,
+            Board::Bnrom => self.latch as usize * 0x8000,
+            Board::Gxrom => ((self.latch >> 4) & 0x03) as usize * 0x8000,
+            Board::Nina0306 => ((self.latch >> 3) & 1) as usize * 0x8000,
+        };
+        (base + offset) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = match self.board {
+            Board::Uxrom | Board::Axrom | Board::Bnrom | Board::Camerica => 0,
+            Board::Cprom if addr < 0x1000 => 0,
+            Board::Cprom => {
+                let bank: usize = (self.latch & 0x03) as usize;
+                return (bank * 0x1000 + (addr & 0x0FFF) as usize) % self.chr.len();
+            }
+            Board::Cnrom => self.latch as usize,
+            Board::ColorDreams => (self.latch >> 4) as usize,
+            Board::Gxrom => (self.latch & 0x03) as usize,
+            Board::Nina0306 => (self.latch & 0x07) as usize,
+        };
+        (bank * 0x2000 + addr as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match (self.board, addr) {
+            (Board::Nina0306, 0x4100..=0x5FFF) if addr & 0xE100 == 0x4100 => self.latch = value,
+            (Board::Nina0306, _) => {}
+            (Board::Camerica, 0x8000..=0x9FFF) if self.single_screen => {
+                self.mirroring = if (value >> 4) & 1 == 1 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            (Board::Camerica, 0xC000..=0xFFFF) => self.latch = value,
+            (Board::Camerica, _) => {}
+            (_, 0x8000..=0xFFFF) => {
+                self.latch = if self.bus_conflicts {
+                    value & self.cpu_read(addr)
+                } else {
+                    value
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        match self.board {
+            Board::Axrom if (self.latch >> 4) & 1 == 1 => Mirroring::SingleScreenUpper,
+            Board::Axrom => Mirroring::SingleScreenLower,
+            _ => self.mirroring,
+        }
+    }
+}
+
+impl Serialize for Discrete {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.value(&mut self.mirroring);
+        s.value(&mut self.latch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    /// Creates a board without bus conflicts, so the written values end up in the latch as is.
+    fn create(mapper: u8, prg_banks: u8, chr_banks: u8, board: Board) -> Discrete {
+        let mut out: Discrete = Discrete::new(create_rom(mapper, prg_banks, chr_banks), board);
+        out.bus_conflicts = false;
+        out
+    }
+
+    #[test]
+    fn test_uxrom() {
+        let mut uxrom: Discrete = create(2, 8, 0, Board::Uxrom);
+        assert_eq!(uxrom.cpu_read(0x8000), 0);
+        assert_eq!(uxrom.cpu_read(0xC000), 7 * 16);
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0x8000), 5 * 16);
+        assert_eq!(uxrom.cpu_read(0xFFFF), 7 * 16 + 15);
+
+        uxrom.ppu_write(0x1234, 0x42);
+        assert_eq!(uxrom.ppu_read(0x1234), 0x42);
+    }
+
+    #[test]
+    fn test_cnrom() {
+        let mut cnrom: Discrete = create(3, 2, 4, Board::Cnrom);
+        cnrom.cpu_write(0x8000, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 2 * 8);
+        assert_eq!(cnrom.ppu_read(0x1C00), 2 * 8 + 7);
+        assert_eq!(cnrom.cpu_read(0xC000), 16);
+    }
+
+    #[test]
+    fn test_axrom() {
+        let mut axrom: Discrete = create(7, 8, 0, Board::Axrom);
+        assert_eq!(axrom.get_mirroring(), Mirroring::SingleScreenLower);
+        axrom.cpu_write(0x8000, 0x13);
+        assert_eq!(axrom.cpu_read(0x8000), 3 * 32);
+        assert_eq!(axrom.get_mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_color_dreams() {
+        let mut color_dreams: Discrete = create(11, 4, 4, Board::ColorDreams);
+        color_dreams.cpu_write(0x8000, 0x21);
+        assert_eq!(color_dreams.cpu_read(0x8000), 32);
+        assert_eq!(color_dreams.ppu_read(0x0000), 2 * 8);
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let mut gxrom: Discrete = create(66, 8, 4, Board::Gxrom);
+        gxrom.cpu_write(0x8000, 0x23);
+        assert_eq!(gxrom.cpu_read(0x8000), 2 * 32);
+        assert_eq!(gxrom.ppu_read(0x0000), 3 * 8);
+    }
+
+    #[test]
+    fn test_cprom() {
+        let mut cprom: Discrete = create(13, 2, 0, Board::Cprom);
+        assert_eq!(cprom.chr.len(), 0x4000);
+        cprom.ppu_write(0x1000, 0x42);
+        cprom.cpu_write(0x8000, 3);
+        cprom.ppu_write(0x1000, 0x43);
+        assert_eq!(cprom.ppu_read(0x1000), 0x43);
+        cprom.cpu_write(0x8000, 0);
+        assert_eq!(cprom.ppu_read(0x0000), 0x42);
+        assert_eq!(cprom.ppu_read(0x1000), 0x42);
+    }
+
+    #[test]
+    fn test_bnrom() {
+        let mut bnrom: Discrete = create(34, 8, 0, Board::Bnrom);
+        bnrom.cpu_write(0x8000, 3);
+        assert_eq!(bnrom.cpu_read(0x8000), 3 * 32);
+        assert_eq!(bnrom.cpu_read(0xC000), 3 * 32 + 16);
+    }
+
+    #[test]
+    fn test_camerica() {
+        let mut rom: Rom = create_rom(71, 8, 0);
+        rom.header.submapper = 1;
+        let mut camerica: Discrete = Discrete::new(rom, Board::Camerica);
+        assert_eq!(camerica.get_mirroring(), Mirroring::Horizontal);
+        camerica.cpu_write(0x8000, 5);
+        assert_eq!(camerica.cpu_read(0x8000), 0);
+        camerica.cpu_write(0xC000, 5);
+        assert_eq!(camerica.cpu_read(0x8000), 5 * 16);
+        assert_eq!(camerica.cpu_read(0xC000), 7 * 16);
+
+        assert_eq!(camerica.get_mirroring(), Mirroring::SingleScreenLower);
+        camerica.cpu_write(0x9000, 0x10);
+        assert_eq!(camerica.get_mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_nina0306() {
+        let mut nina: Discrete = create(79, 4, 8, Board::Nina0306);
+        nina.cpu_write(0x8000, 0x0F);
+        assert_eq!(nina.latch, 0);
+        // $4200 doesn't have A8 set
+        nina.cpu_write(0x4200, 0x0F);
+        assert_eq!(nina.latch, 0);
+        nina.cpu_write(0x4100, 0x0D);
+        assert_eq!(nina.cpu_read(0x8000), 32);
+        assert_eq!(nina.ppu_read(0x0000), 5 * 8);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut uxrom: Discrete = Discrete::new(create_rom(2, 8, 0), Board::Uxrom);
+        // $C000 holds 7 * 16 = 0b111_0000 in the test ROM
+        uxrom.cpu_write(0xC000, 0b111_0011);
+        assert_eq!(uxrom.latch, 0b111_0000);
+
+        let mut rom: Rom = create_rom(2, 8, 0);
+        rom.header.submapper = 1;
+        let mut uxrom: Discrete = Discrete::new(rom, Board::Uxrom);
+        uxrom.cpu_write(0xC000, 0b111_0011);
+        assert_eq!(uxrom.latch, 0b111_0011);
+    }
+}
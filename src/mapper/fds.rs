@@ -0,0 +1,643 @@
+pub mod audio;
+
+use crate::fds::{BIOS_SIZE, DiskImage, FdsError, SIDE_SIZE};
+use crate::mapper::Mapper;
+use crate::mapper::fds::audio::FdsAudio;
+use crate::rom::Mirroring;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// Zero bytes before the first block of a side, about 28300 bits.
+const LEADING_GAP_SIZE: usize = 3537;
+/// Zero bytes after every block, about 976 bits.
+const GAP_SIZE: usize = 122;
+/// The drive reads or writes a byte about every 149 CPU cycles, at 96.4 kbit/s.
+const BYTE_CYCLES: u32 = 149;
+/// CPU cycles the drive takes to bring the head back to the start of the disk.
+const REWIND_CYCLES: u32 = 50000;
+/// CPU cycles a disk stays out of the drive when switching sides, about half a second, so the
+/// BIOS notices the disk change.
+const SWAP_CYCLES: u32 = 900_000;
+
+/// Famicom Disk System reference: https://www.nesdev.org/wiki/Family_Computer_Disk_System
+///
+/// The RAM adapter, which plugs into the cartridge slot and provides 32 KiB of PRG-RAM at
+/// $6000-$DFFF, the BIOS at $E000-$FFFF, 8 KiB of CHR-RAM, a timer interrupt, the wavetable
+/// expansion audio, and the interface to the disk drive.
+///
+/// Disk sides get converted to the layout the drive sees, with gaps before every block and a
+/// start mark and two checksum bytes around it. Checksums are never verified, so they are left
+/// as zeroes.
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+    inserted_side: Option<usize>,
+    /// The side to insert once the previous one spent [SWAP_CYCLES] out of the drive.
+    pending_side: Option<usize>,
+    swap_delay: u32,
+    mirroring: Mirroring,
+
+    disk_registers_enabled: bool,
+    sound_registers_enabled: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq_flag: Cell<bool>,
+
+    /// Bit layout of $4025: <pre>
+    /// 1 << 7 => interrupt after every transferred byte
+    /// 1 << 6 => transfer enabled, which skips the gap before a block
+    /// 1 << 4 => transferring the checksum
+    /// 1 << 3 => horizontal rather than vertical mirroring
+    /// 1 << 2 => read rather than write mode
+    /// 1 << 1 => transfer reset, holding the head at the start of the disk
+    /// 1      => motor on
+    /// </pre>
+    motor_on: bool,
+    transfer_reset: bool,
+    read_mode: bool,
+    crc_control: bool,
+    transfer_enabled: bool,
+    disk_irq_enabled: bool,
+    disk_irq_flag: Cell<bool>,
+    transfer_flag: Cell<bool>,
+
+    /// Whether the head reached the end of the disk, and has to be brought back to its start.
+    end_of_head: bool,
+    scanning: bool,
+    position: usize,
+    delay: u32,
+    /// Whether the start mark of the current block was found.
+    gap_ended: bool,
+    read_data: u8,
+    write_data: u8,
+    external_output: u8,
+    pub audio: FdsAudio,
+}
+
+/// Converts a side of an .fds image to the layout the drive sees.
+fn create_raw_side(side: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![0; LEADING_GAP_SIZE];
+    let mut offset: usize = 0;
+    let mut file_size: usize = 0;
+    while offset < side.len() {
+        let size: usize = match side[offset] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            4 => 1 + file_size,
+            _ => break,
+        };
+        if offset + size > side.len() {
+            break;
+        }
+        if side[offset] == 3 {
+            file_size = u16::from_le_bytes([side[offset + 13], side[offset + 14]]) as usize;
+        }
+        out.push(0x80);
+        out.extend_from_slice(&side[offset..offset + size]);
+        out.extend([0, 0]);
+        out.extend([0; GAP_SIZE]);
+        offset += size;
+    }
+    // leaves room for files the BIOS writes after the existing ones
+    out.resize(out.len().max(LEADING_GAP_SIZE + SIDE_SIZE), 0);
+    out
+}
+
+impl Fds {
+    /// Creates the RAM adapter from the 8 KiB BIOS, disksys.rom, with the first disk side
+    /// inserted.
+    pub fn new(bios: Vec<u8>, disk: DiskImage) -> Result<Fds, FdsError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(FdsError::InvalidBios(bios.len()));
+        }
+        Ok(Fds {
+            bios,
+            prg_ram: vec![0; 0x8000],
+            chr_ram: vec![0; 0x2000],
+            sides: disk
+                .sides
+                .iter()
+                .map(|side| create_raw_side(side))
+                .collect(),
+            inserted_side: Some(0),
+            pending_side: None,
+            swap_delay: 0,
+            mirroring: Mirroring::Horizontal,
+            disk_registers_enabled: true,
+            sound_registers_enabled: true,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq_flag: Cell::new(false),
+            motor_on: false,
+            transfer_reset: false,
+            read_mode: true,
+            crc_control: false,
+            transfer_enabled: false,
+            disk_irq_enabled: false,
+            disk_irq_flag: Cell::new(false),
+            transfer_flag: Cell::new(false),
+            end_of_head: true,
+            scanning: false,
+            position: 0,
+            delay: 0,
+            gap_ended: false,
+            read_data: 0,
+            write_data: 0,
+            external_output: 0,
+            audio: FdsAudio::new(),
+        })
+    }
+
+    /// Handles writes to $4020-$4026.
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = value & 1 == 1;
+                self.irq_enabled = (value >> 1) & 1 == 1 && self.disk_registers_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq_flag.set(false);
+                }
+            }
+            0x4023 => {
+                self.disk_registers_enabled = value & 1 == 1;
+                self.sound_registers_enabled = (value >> 1) & 1 == 1;
+                if !self.disk_registers_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq_flag.set(false);
+                    self.disk_irq_flag.set(false);
+                }
+            }
+            0x4024 => {
+                self.write_data = value;
+                self.transfer_flag.set(false);
+                self.disk_irq_flag.set(false);
+            }
+            0x4025 => {
+                self.motor_on = value & 1 == 1;
+                self.transfer_reset = (value >> 1) & 1 == 1;
+                self.read_mode = (value >> 2) & 1 == 1;
+                self.mirroring = if (value >> 3) & 1 == 1 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                };
+                self.crc_control = (value >> 4) & 1 == 1;
+                self.transfer_enabled = (value >> 6) & 1 == 1;
+                self.disk_irq_enabled = (value >> 7) & 1 == 1;
+                self.disk_irq_flag.set(false);
+            }
+            0x4026 => self.external_output = value,
+            _ => {}
+        }
+    }
+
+    /// Handles reads of $4030-$4033. Bit layout of $4030: <pre>
+    /// 1 << 6 => end of the disk reached
+    /// 1 << 4 => checksum error
+    /// 1 << 1 => byte transferred
+    /// 1      => timer interrupt
+    /// </pre>
+    /// Bit layout of $4032: <pre>
+    /// 1 << 2 => write protected
+    /// 1 << 1 => not ready
+    /// 1      => no disk inserted
+    /// </pre>
+    /// $4031 holds the byte last read from the disk, and $4033 the external connector's inputs
+    /// in the lower bits and the battery status in the upper one.
+    fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x4030 => {
+                let out: u8 = self.timer_irq_flag.get() as u8
+                    | (self.transfer_flag.get() as u8) << 1
+                    | (self.end_of_head as u8) << 6;
+                self.timer_irq_flag.set(false);
+                self.transfer_flag.set(false);
+                self.disk_irq_flag.set(false);
+                out
+            }
+            0x4031 => {
+                self.transfer_flag.set(false);
+                self.disk_irq_flag.set(false);
+                self.read_data
+            }
+            0x4032 => {
+                let ejected: bool = self.inserted_side.is_none();
+                ejected as u8 | ((ejected || !self.scanning) as u8) << 1 | (ejected as u8) << 2
+            }
+            // a good battery, and the outputs looping back
+            0x4033 => 0x80 | (self.external_output & 0x7F),
+            _ => 0,
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled || !self.disk_registers_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq_flag.set(true);
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_swap(&mut self) {
+        if self.pending_side.is_some() {
+            self.swap_delay = self.swap_delay.saturating_sub(1);
+            if self.swap_delay == 0 {
+                self.inserted_side = self.pending_side.take();
+            }
+        }
+    }
+
+    /// Moves the head along the disk, transferring a byte whenever it passes one.
+    fn clock_drive(&mut self) {
+        let Some(side) = self.inserted_side else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.transfer_reset && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.end_of_head = false;
+            self.delay = REWIND_CYCLES;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut irq: bool = self.disk_irq_enabled;
+        if self.read_mode {
+            let value: u8 = self.sides[side][self.position];
+            if !self.transfer_enabled {
+                self.gap_ended = false;
+            } else if value != 0 && !self.gap_ended {
+                // the start mark itself doesn't raise an interrupt
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_flag.set(true);
+                self.read_data = value;
+                if irq {
+                    self.disk_irq_flag.set(true);
+                }
+            }
+        } else {
+            let value: u8 = if self.crc_control {
+                0
+            } else {
+                self.transfer_flag.set(true);
+                if irq {
+                    self.disk_irq_flag.set(true);
+                }
+                if self.transfer_enabled {
+                    self.write_data
+                } else {
+                    0
+                }
+            };
+            self.sides[side][self.position] = value;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES - 1;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4030..=0x4033 if self.disk_registers_enabled => self.read_register(addr),
+            0x4040..=0x4097 if self.sound_registers_enabled => self.audio.read_register(addr),
+            0x6000..=0xDFFF => self.prg_ram[addr as usize - 0x6000],
+            0xE000..=0xFFFF => self.bios[addr as usize - 0xE000],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4020..=0x4022 | 0x4024..=0x4026 if self.disk_registers_enabled => {
+                self.write_register(addr, value)
+            }
+            0x4023 => self.write_register(addr, value),
+            0x4040..=0x408A if self.sound_registers_enabled => {
+                self.audio.write_register(addr, value)
+            }
+            0x6000..=0xDFFF => self.prg_ram[addr as usize - 0x6000] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize & 0x1FFF]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr_ram[addr as usize & 0x1FFF] = value;
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.timer_irq_flag.get() || self.disk_irq_flag.get()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.clock_timer();
+        self.clock_swap();
+        self.clock_drive();
+        self.audio.clock();
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.audio.get_output()
+    }
+
+    fn get_disk_side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn get_disk_side(&self) -> Option<usize> {
+        self.inserted_side.or(self.pending_side)
+    }
+
+    /// Switching from one side to another ejects the disk for a while first, as the BIOS only
+    /// looks for a new disk after seeing the drive empty.
+    fn set_disk_side(&mut self, side: Option<usize>) {
+        let side: Option<usize> = side.filter(|&side| side < self.sides.len());
+        if self.inserted_side.is_some() && side.is_some() && self.inserted_side != side {
+            self.pending_side = side;
+            self.swap_delay = SWAP_CYCLES;
+            self.inserted_side = None;
+        } else if self.inserted_side.is_none() && self.pending_side.is_some() && side.is_some() {
+            self.pending_side = side;
+        } else {
+            self.pending_side = None;
+            self.inserted_side = side;
+        }
+    }
+}
+
+impl Serialize for Fds {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.bytes(&mut self.prg_ram);
+        s.bytes(&mut self.chr_ram);
+        s.value(&mut self.sides);
+        s.value(&mut self.inserted_side);
+        s.value(&mut self.pending_side);
+        s.value(&mut self.swap_delay);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.disk_registers_enabled);
+        s.value(&mut self.sound_registers_enabled);
+        s.value(&mut self.irq_reload);
+        s.value(&mut self.irq_counter);
+        s.value(&mut self.irq_repeat);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.timer_irq_flag);
+        s.value(&mut self.motor_on);
+        s.value(&mut self.transfer_reset);
+        s.value(&mut self.read_mode);
+        s.value(&mut self.crc_control);
+        s.value(&mut self.transfer_enabled);
+        s.value(&mut self.disk_irq_enabled);
+        s.value(&mut self.disk_irq_flag);
+        s.value(&mut self.transfer_flag);
+        s.value(&mut self.end_of_head);
+        s.value(&mut self.scanning);
+        s.value(&mut self.position);
+        s.value(&mut self.delay);
+        s.value(&mut self.gap_ended);
+        s.value(&mut self.read_data);
+        s.value(&mut self.write_data);
+        s.value(&mut self.external_output);
+        s.value(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fds::tests::create_side;
+
+    fn create_fds() -> Fds {
+        let disk: DiskImage = DiskImage {
+            sides: vec![create_side(&[&[0x11, 0x22]]), create_side(&[])],
+        };
+        let mut bios: Vec<u8> = vec![0; BIOS_SIZE];
+        bios[0x1FFC] = 0x42;
+        Fds::new(bios, disk).unwrap()
+    }
+
+    /// Clocks the drive until it transfers a byte, returning it.
+    fn read_byte(fds: &mut Fds) -> u8 {
+        for _ in 0..1_000_000 {
+            fds.clock_cpu();
+            if fds.get_irq() {
+                return fds.cpu_read(0x4031);
+            }
+        }
+        panic!("no byte transferred");
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut fds: Fds = create_fds();
+        assert_eq!(fds.cpu_read(0xFFFC), 0x42);
+        fds.cpu_write(0xFFFC, 0);
+        assert_eq!(fds.cpu_read(0xFFFC), 0x42);
+        fds.cpu_write(0x6000, 1);
+        fds.cpu_write(0xDFFF, 2);
+        assert_eq!(fds.cpu_read(0x6000), 1);
+        assert_eq!(fds.cpu_read(0xDFFF), 2);
+        fds.ppu_write(0x1FFF, 3);
+        assert_eq!(fds.ppu_read(0x1FFF), 3);
+
+        fds.cpu_write(0x4025, 0x2E);
+        assert_eq!(fds.get_mirroring(), Mirroring::Horizontal);
+        fds.cpu_write(0x4025, 0x26);
+        assert_eq!(fds.get_mirroring(), Mirroring::Vertical);
+
+        assert!(matches!(
+            Fds::new(vec![0; 0x1000], DiskImage { sides: vec![] }),
+            Err(FdsError::InvalidBios(0x1000))
+        ));
+    }
+
+    #[test]
+    fn test_raw_side() {
+        let raw: Vec<u8> = create_raw_side(&create_side(&[&[0x11, 0x22]]));
+        assert_eq!(raw.len(), LEADING_GAP_SIZE + SIDE_SIZE);
+        assert!(raw[..LEADING_GAP_SIZE].iter().all(|&value| value == 0));
+        assert_eq!(
+            &raw[LEADING_GAP_SIZE..LEADING_GAP_SIZE + 3],
+            &[0x80, 1, b'*']
+        );
+        // disk info, checksum and gap
+        let offset: usize = LEADING_GAP_SIZE + 1 + 56 + 2 + GAP_SIZE;
+        assert_eq!(&raw[offset..offset + 3], &[0x80, 2, 1]);
+        let offset: usize = offset + 1 + 2 + 2 + GAP_SIZE + 1 + 16 + 2 + GAP_SIZE;
+        assert_eq!(&raw[offset..offset + 6], &[0x80, 4, 0x11, 0x22, 0, 0]);
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds: Fds = create_fds();
+        fds.cpu_write(0x4020, 2);
+        fds.cpu_write(0x4021, 0);
+        fds.cpu_write(0x4022, 0b11);
+        for _ in 0..3 {
+            assert!(!fds.get_irq());
+            fds.clock_cpu();
+        }
+        assert!(fds.get_irq());
+        assert_eq!(fds.cpu_read(0x4030) & 1, 1);
+        assert!(!fds.get_irq());
+
+        // repeating
+        for _ in 0..3 {
+            fds.clock_cpu();
+        }
+        assert!(fds.get_irq());
+
+        // disabling the disk registers stops the timer
+        fds.cpu_write(0x4023, 0);
+        assert!(!fds.get_irq());
+        for _ in 0..10 {
+            fds.clock_cpu();
+        }
+        assert!(!fds.get_irq());
+    }
+
+    #[test]
+    fn test_read_disk() {
+        let mut fds: Fds = create_fds();
+        assert_eq!(fds.cpu_read(0x4032) & 0b11, 0b10);
+        // motor on, read mode, transfers with interrupts
+        fds.cpu_write(0x4025, 0xE5);
+        assert_eq!(read_byte(&mut fds), 1);
+        assert_eq!(fds.cpu_read(0x4032) & 0b11, 0);
+        let mut text: Vec<u8> = Vec::new();
+        for _ in 0..14 {
+            text.push(read_byte(&mut fds));
+        }
+        assert_eq!(text, b"*NINTENDO-HVC*");
+
+        // skipping to the file data through the next blocks
+        for _ in 0..41 + 2 {
+            read_byte(&mut fds);
+        }
+        // disabling the transfer for a byte makes the drive look for the next start mark
+        fds.cpu_write(0x4025, 0xA5);
+        for _ in 0..BYTE_CYCLES * 2 {
+            fds.clock_cpu();
+        }
+        fds.cpu_write(0x4025, 0xE5);
+        assert_eq!(read_byte(&mut fds), 2);
+        assert_eq!(read_byte(&mut fds), 1);
+
+        // stopping the motor brings the head back to the start
+        fds.cpu_write(0x4025, 0xE4);
+        fds.clock_cpu();
+        assert_eq!(fds.cpu_read(0x4030) & 0x40, 0x40);
+        fds.cpu_write(0x4025, 0xE5);
+        assert_eq!(read_byte(&mut fds), 1);
+    }
+
+    #[test]
+    fn test_write_disk() {
+        let mut fds: Fds = create_fds();
+        fds.cpu_write(0x4025, 0xE5);
+        read_byte(&mut fds);
+        let position: usize = fds.position;
+
+        // write mode
+        fds.cpu_write(0x4024, 0x99);
+        fds.cpu_write(0x4025, 0xC1);
+        for _ in 0..BYTE_CYCLES {
+            fds.clock_cpu();
+        }
+        assert!(fds.get_irq());
+        assert_eq!(fds.sides[0][position], 0x99);
+    }
+
+    #[test]
+    fn test_disk_sides() {
+        let mut fds: Fds = create_fds();
+        assert_eq!(fds.get_disk_side_count(), 2);
+        assert_eq!(fds.get_disk_side(), Some(0));
+
+        fds.set_disk_side(Some(1));
+        assert_eq!(fds.get_disk_side(), Some(1));
+        // the drive looks empty for a while
+        assert_eq!(fds.cpu_read(0x4032) & 1, 1);
+        for _ in 0..SWAP_CYCLES {
+            fds.clock_cpu();
+        }
+        assert_eq!(fds.cpu_read(0x4032) & 1, 0);
+        assert_eq!(fds.inserted_side, Some(1));
+
+        fds.set_disk_side(None);
+        assert_eq!(fds.get_disk_side(), None);
+        assert_eq!(fds.cpu_read(0x4032), 0b111);
+        fds.set_disk_side(Some(5));
+        assert_eq!(fds.get_disk_side(), None);
+        fds.set_disk_side(Some(0));
+        assert_eq!(fds.inserted_side, Some(0));
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut fds: Fds = create_fds();
+        fds.cpu_write(0x4089, 0x80);
+        fds.cpu_write(0x4040, 63);
+        fds.cpu_write(0x4089, 0);
+        assert_eq!(fds.cpu_read(0x4040), 0x40 | 63);
+        fds.cpu_write(0x4080, 0x80 | 32);
+        fds.clock_cpu();
+        fds.cpu_write(0x4083, 0);
+        fds.clock_cpu();
+        assert!(fds.get_audio_output() > 0.3);
+
+        // with the sound registers disabled
+        fds.cpu_write(0x4023, 0b01);
+        fds.cpu_write(0x4089, 3);
+        assert!(fds.get_audio_output() > 0.3);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::state::{Serialize, Serializer};
+
+/// Output level of the channel at full volume, a bit more than twice that of an APU pulse.
+const OUTPUT_LEVEL: f32 = 0.35;
+/// Change of the modulation counter for each 3-bit modulation table entry, where 4 resets it.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+/// Output multiplier of each master volume setting: 2/2, 2/3, 2/4 and 2/5.
+const MASTER_VOLUMES: [f32; 4] = [1.0, 2.0 / 3.0, 0.5, 0.4];
+
+/// One of the two envelopes, ramping a gain between 0 and 32 or holding a fixed one.
+///
+/// Register bit layout: <pre>
+/// 1 << 7    => disabled, using the speed bits as the gain
+/// 1 << 6    => increase rather than decrease
+/// 0b11_1111 => speed
+/// </pre>
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            speed: 0,
+            gain: 0,
+            increase: false,
+            disabled: true,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.speed = value & 0x3F;
+        self.increase = (value >> 6) & 1 == 1;
+        self.disabled = (value >> 7) & 1 == 1;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    /// Envelopes step every 8 * (speed + 1) * master speed CPU cycles.
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+/// FDS audio reference: https://www.nesdev.org/wiki/FDS_audio
+///
+/// A single wavetable channel playing 64 6-bit samples, whose pitch is bent by a modulation unit
+/// stepping through a table of 32 pitch deltas.
+///
+/// Registers: <pre>
+/// $4040-$407F => wavetable, writable while $4089 bit 7 is set
+/// $4080       => volume envelope
+/// $4082-$4083 => 12-bit pitch, lower byte first; $4083 1 << 7: halt wave, 1 << 6: halt envelopes
+/// $4084       => modulation envelope
+/// $4085       => 7-bit signed modulation counter
+/// $4086-$4087 => 12-bit modulation pitch; $4087 1 << 7: halt modulation
+/// $4088       => appends a 3-bit entry to the modulation table while it's halted
+/// $4089       => 1 << 7: wavetable write mode, 0b11: master volume
+/// $408A       => envelope master speed
+/// </pre>
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    master_volume: usize,
+    pitch: u16,
+    wave_halted: bool,
+    envelopes_halted: bool,
+    /// The top 6 of its 22 bits index the wavetable.
+    wave_accumulator: u32,
+    /// The volume gain is only applied at the start of each wave cycle.
+    output_gain: u8,
+    output: u8,
+    volume: Envelope,
+    master_speed: u8,
+
+    mod_table: [u8; 64],
+    mod_position: usize,
+    mod_counter: i8,
+    mod_pitch: u16,
+    mod_halted: bool,
+    mod_accumulator: u32,
+    modulation: Envelope,
+}
+
+impl Default for FdsAudio {
+    fn default() -> FdsAudio {
+        FdsAudio::new()
+    }
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave: [0; 64],
+            wave_write: false,
+            master_volume: 0,
+            pitch: 0,
+            wave_halted: true,
+            envelopes_halted: false,
+            wave_accumulator: 0,
+            output_gain: 0,
+            output: 0,
+            volume: Envelope::new(),
+            master_speed: 0xE8,
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_pitch: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            modulation: Envelope::new(),
+        }
+    }
+
+    /// Handles writes to $4040-$408A.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[addr as usize - 0x4040] = value & 0x3F,
+            0x4080 => self.volume.write(value, self.master_speed),
+            0x4082 => self.pitch = (self.pitch & 0x0F00) | value as u16,
+            0x4083 => {
+                self.pitch = (self.pitch & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.wave_halted = (value >> 7) & 1 == 1;
+                self.envelopes_halted = (value >> 6) & 1 == 1;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.master_speed);
+                    self.modulation.reset_timer(self.master_speed);
+                }
+            }
+            0x4084 => self.modulation.write(value, self.master_speed),
+            // sign extends the 7-bit value
+            0x4085 => self.mod_counter = ((value << 1) as i8) >> 1,
+            0x4086 => self.mod_pitch = (self.mod_pitch & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_pitch = (self.mod_pitch & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.mod_halted = (value >> 7) & 1 == 1;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            0x4088 if self.mod_halted => {
+                // every entry takes up two steps of the table
+                self.mod_table[self.mod_position] = value & 0b111;
+                self.mod_table[self.mod_position + 1] = value & 0b111;
+                self.mod_position = (self.mod_position + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.wave_write = (value >> 7) & 1 == 1;
+                self.master_volume = (value & 0b11) as usize;
+            }
+            0x408A => self.master_speed = value,
+            _ => {}
+        }
+    }
+
+    /// Handles reads of $4040-$4097. Only the wavetable and the current envelope gains at $4090
+    /// and $4092 are readable, the upper bits being open bus.
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x407F => self.wave[addr as usize - 0x4040] | 0x40,
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.modulation.gain | 0x40,
+            _ => 0x40,
+        }
+    }
+
+    /// The wave's pitch after modulation, computed with the hardware's peculiar rounding.
+    fn get_modulated_pitch(&self) -> u32 {
+        let counter: i32 = self.mod_counter as i32;
+        let mut temp: i32 = counter * self.modulation.gain as i32;
+        let remainder: i32 = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= self.pitch as i32;
+        let remainder: i32 = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (self.pitch as i32 + temp).max(0) as u32
+    }
+
+    fn clock_modulation(&mut self) {
+        if self.mod_halted || self.mod_pitch == 0 {
+            return;
+        }
+        self.mod_accumulator += self.mod_pitch as u32;
+        if self.mod_accumulator >= 0x10000 {
+            self.mod_accumulator -= 0x10000;
+            let step: u8 = self.mod_table[self.mod_position];
+            let counter: i8 = if step == 4 {
+                0
+            } else {
+                self.mod_counter.wrapping_add(MOD_STEPS[step as usize])
+            };
+            // wraps around within 7 bits
+            self.mod_counter = (counter << 1) >> 1;
+            self.mod_position = (self.mod_position + 1) & 0x3F;
+        }
+    }
+
+    /// Advances the channel by a single CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.clock(self.master_speed);
+            self.modulation.clock(self.master_speed);
+        }
+        self.clock_modulation();
+
+        if self.wave_halted {
+            self.output_gain = self.volume.gain.min(32);
+        } else if !self.wave_write {
+            let previous: u32 = self.wave_accumulator >> 16;
+            self.wave_accumulator =
+                (self.wave_accumulator + self.get_modulated_pitch()) & 0x3F_FFFF;
+            let index: u32 = self.wave_accumulator >> 16;
+            if index < previous {
+                self.output_gain = self.volume.gain.min(32);
+            }
+            self.output = self.wave[index as usize];
+        }
+    }
+
+    pub fn get_output(&self) -> f32 {
+        let level: f32 = (self.output as u32 * self.output_gain as u32) as f32 / (63.0 * 32.0);
+        level * MASTER_VOLUMES[self.master_volume] * OUTPUT_LEVEL
+    }
+}
+
+impl Serialize for Envelope {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.speed);
+        s.value(&mut self.gain);
+        s.value(&mut self.increase);
+        s.value(&mut self.disabled);
+        s.value(&mut self.timer);
+    }
+}
+
+impl Serialize for FdsAudio {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.wave);
+        s.value(&mut self.wave_write);
+        s.value(&mut self.master_volume);
+        s.value(&mut self.pitch);
+        s.value(&mut self.wave_halted);
+        s.value(&mut self.envelopes_halted);
+        s.value(&mut self.wave_accumulator);
+        s.value(&mut self.output_gain);
+        s.value(&mut self.output);
+        s.value(&mut self.volume);
+        s.value(&mut self.master_speed);
+        s.value(&mut self.mod_table);
+        s.value(&mut self.mod_position);
+        s.value(&mut self.mod_counter);
+        s.value(&mut self.mod_pitch);
+        s.value(&mut self.mod_halted);
+        s.value(&mut self.mod_accumulator);
+        s.value(&mut self.modulation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_square(audio: &mut FdsAudio) {
+        audio.write_register(0x4089, 0x80);
+        for i in 0..64 {
+            audio.write_register(0x4040 + i, if i < 32 { 63 } else { 0 });
+        }
+        audio.write_register(0x4089, 0);
+    }
+
+    #[test]
+    fn test_wave() {
+        let mut audio: FdsAudio = FdsAudio::new();
+        create_square(&mut audio);
+        assert_eq!(audio.read_register(0x4041), 0x7F);
+        // wavetable writes are ignored outside of write mode
+        audio.write_register(0x4041, 0);
+        assert_eq!(audio.read_register(0x4041), 0x7F);
+
+        audio.write_register(0x4080, 0x80 | 32);
+        // the halted wave picks up the volume right away, rather than at the end of a cycle
+        audio.clock();
+        audio.write_register(0x4082, 0x00);
+        audio.write_register(0x4083, 0x04);
+        // a pitch of $400 steps through the table every 64 cycles
+        let mut outputs: Vec<f32> = Vec::new();
+        for _ in 0..64 * 64 * 2 {
+            audio.clock();
+            outputs.push(audio.get_output());
+        }
+        assert!((outputs[64 * 8] - OUTPUT_LEVEL).abs() < 0.001);
+        assert_eq!(outputs[64 * 40], 0.0);
+        assert!((outputs[64 * 72] - OUTPUT_LEVEL).abs() < 0.001);
+
+        audio.write_register(0x4089, 3);
+        assert!((audio.get_output() - OUTPUT_LEVEL * 0.4).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut audio: FdsAudio = FdsAudio::new();
+        audio.write_register(0x408A, 1);
+        // increasing every 8 cycles
+        audio.write_register(0x4080, 0x40);
+        audio.write_register(0x4083, 0x00);
+        for _ in 0..8 * 10 {
+            audio.clock();
+        }
+        assert_eq!(audio.read_register(0x4090), 0x40 | 10);
+        for _ in 0..8 * 100 {
+            audio.clock();
+        }
+        assert_eq!(audio.read_register(0x4090), 0x40 | 32);
+
+        // halted envelopes
+        audio.write_register(0x4080, 0x80 | 20);
+        audio.write_register(0x4080, 3);
+        audio.write_register(0x4083, 0x40);
+        for _ in 0..1000 {
+            audio.clock();
+        }
+        assert_eq!(audio.read_register(0x4090), 0x40 | 20);
+    }
+
+    #[test]
+    fn test_modulation() {
+        let mut audio: FdsAudio = FdsAudio::new();
+        audio.write_register(0x4087, 0x80);
+        // filling the table wraps around to its start
+        for step in [1, 1, 7, 4].into_iter().chain([0; 28]) {
+            audio.write_register(0x4088, step);
+        }
+        audio.write_register(0x4086, 0x00);
+        audio.write_register(0x4087, 0x08);
+
+        // a modulation pitch of $800 steps through the table every 32 cycles
+        let mut counters: Vec<i8> = Vec::new();
+        for _ in 0..8 {
+            for _ in 0..32 {
+                audio.clock();
+            }
+            counters.push(audio.mod_counter);
+        }
+        assert_eq!(counters, vec![1, 2, 3, 4, 3, 2, 0, 0]);
+
+        audio.write_register(0x4085, 0x7F);
+        assert_eq!(audio.mod_counter, -1);
+    }
+
+    #[test]
+    fn test_modulated_pitch() {
+        let mut audio: FdsAudio = FdsAudio::new();
+        audio.write_register(0x4082, 0x00);
+        audio.write_register(0x4083, 0x01);
+        assert_eq!(audio.get_modulated_pitch(), 0x100);
+
+        audio.write_register(0x4084, 0x80 | 32);
+        // 16 * 32 / 16 = 32, bending the pitch by half
+        audio.write_register(0x4085, 16);
+        assert_eq!(audio.get_modulated_pitch(), 0x180);
+        audio.write_register(0x4085, 0x70);
+        assert_eq!(audio.get_modulated_pitch(), 0x80);
+        // 63 * 63 / 16 = 248, which wraps around to -8
+        audio.write_register(0x4084, 0x80 | 63);
+        audio.write_register(0x4085, 63);
+        assert_eq!(audio.get_modulated_pitch(), 0x100 - 0x100 * 8 / 64);
+    }
+}
@@ -0,0 +1,282 @@
+pub mod audio;
+
+use crate::mapper::fme7::audio::Sunsoft5bAudio;
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Sunsoft FME-7 reference: https://www.nesdev.org/wiki/Sunsoft_FME-7
+///
+/// Mapper 69. Switches 8 KiB PRG banks, including one at $6000-$7FFF which can hold either ROM or
+/// RAM, and 1 KiB CHR banks, and comes with a cycle based interrupt counter. The Sunsoft 5A and
+/// 5B are compatible, the 5B adding expansion audio.
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+
+    /// Selects the register the next write to $A000-$BFFF goes to.
+    command: u8,
+    chr_banks: [u8; 8],
+    /// Bit layout: <pre>
+    /// 1 << 7     => RAM enabled
+    /// 1 << 6     => RAM rather than ROM
+    /// 0b11_1111  => bank
+    /// </pre>
+    prg_bank_6000: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+
+    /// Counts down every CPU cycle, raising an interrupt when wrapping around from 0.
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_flag: bool,
+    pub audio: Sunsoft5bAudio,
+}
+
+impl Fme7 {
+    pub fn new(rom: Rom) -> Fme7 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Fme7 {
+            battery: rom.header.battery,
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_bank_6000: 0,
+            prg_banks: [0, 1, 2],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_flag: false,
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let bank: usize = match addr {
+            0x6000..=0x7FFF => (self.prg_bank_6000 & 0x3F) as usize,
+            0x8000..=0xDFFF => self.prg_banks[((addr - 0x8000) >> 13) as usize] as usize,
+            _ => self.prg_rom.len() / 0x2000 - 1,
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = self.chr_banks[(addr >> 10) as usize & 0b111] as usize;
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+
+    fn get_prg_ram_addr(&self, addr: u16) -> usize {
+        ((self.prg_bank_6000 & 0x3F) as usize * 0x2000 + (addr & 0x1FFF) as usize)
+            % self.prg_ram.len()
+    }
+
+    /// Commands: <pre>
+    /// $0-$7 => CHR banks
+    /// $8    => PRG bank at $6000-$7FFF
+    /// $9-$B => PRG banks at $8000-$DFFF
+    /// $C    => mirroring
+    /// $D    => 1 << 7: counter enabled, 1: interrupt enabled; acknowledges the interrupt
+    /// $E-$F => lower and upper byte of the counter
+    /// </pre>
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = value,
+            0x8 => self.prg_bank_6000 = value,
+            0x9..=0xB => self.prg_banks[self.command as usize - 0x9] = value & 0x3F,
+            0xC => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xD => {
+                self.irq_enabled = value & 1 == 1;
+                self.irq_counter_enabled = (value >> 7) & 1 == 1;
+                self.irq_flag = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        let ram: bool = (self.prg_bank_6000 >> 6) & 1 == 1;
+        let ram_enabled: bool = (self.prg_bank_6000 >> 7) & 1 == 1;
+        match addr {
+            0x6000..=0x7FFF if ram && ram_enabled => self.prg_ram[self.get_prg_ram_addr(addr)],
+            0x6000..=0x7FFF if ram => 0,
+            0x6000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_bank_6000 >> 6 == 0b11 => {
+                let index: usize = self.get_prg_ram_addr(addr);
+                self.prg_ram[index] = value;
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.write_address(value),
+            0xE000..=0xFFFF => self.audio.write_data(value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.audio.get_output()
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Fme7 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.command);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.prg_bank_6000);
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.irq_counter);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_counter_enabled);
+        s.value(&mut self.irq_flag);
+        s.value(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    fn command(fme7: &mut Fme7, command: u8, value: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xA000, value);
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut fme7: Fme7 = Fme7::new(create_rom(69, 8, 16));
+        for i in 0..8 {
+            command(&mut fme7, i, 50 + i);
+        }
+        let banks: Vec<u8> = (0..8).map(|i| fme7.ppu_read(i * 0x400)).collect();
+        assert_eq!(banks, vec![50, 51, 52, 53, 54, 55, 56, 57]);
+
+        command(&mut fme7, 9, 3);
+        command(&mut fme7, 0xA, 4);
+        command(&mut fme7, 0xB, 5);
+        assert_eq!(fme7.cpu_read(0x8000), 3 * 8);
+        assert_eq!(fme7.cpu_read(0xA000), 4 * 8);
+        assert_eq!(fme7.cpu_read(0xC000), 5 * 8);
+        assert_eq!(fme7.cpu_read(0xE000), 15 * 8);
+
+        command(&mut fme7, 0xC, 2);
+        assert_eq!(fme7.get_mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_6000() {
+        let mut fme7: Fme7 = Fme7::new(create_rom(69, 8, 16));
+        // ROM
+        command(&mut fme7, 8, 6);
+        assert_eq!(fme7.cpu_read(0x6000), 6 * 8);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 6 * 8);
+        // disabled RAM
+        command(&mut fme7, 8, 0x40);
+        assert_eq!(fme7.cpu_read(0x6000), 0);
+        // enabled RAM
+        command(&mut fme7, 8, 0xC0);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut fme7: Fme7 = Fme7::new(create_rom(69, 8, 16));
+        command(&mut fme7, 0xE, 1);
+        command(&mut fme7, 0xF, 0);
+        command(&mut fme7, 0xD, 0x81);
+        fme7.clock_cpu();
+        assert!(!fme7.get_irq());
+        fme7.clock_cpu();
+        assert!(fme7.get_irq());
+        command(&mut fme7, 0xD, 0x81);
+        assert!(!fme7.get_irq());
+
+        // with only the counter enabled, it runs without raising interrupts
+        command(&mut fme7, 0xD, 0x80);
+        for _ in 0..0x10000 {
+            fme7.clock_cpu();
+        }
+        assert!(!fme7.get_irq());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut fme7: Fme7 = Fme7::new(create_rom(69, 8, 16));
+        fme7.cpu_write(0xC000, 7);
+        fme7.cpu_write(0xE000, 0xFF);
+        fme7.cpu_write(0xC000, 8);
+        fme7.cpu_write(0xE000, 0x0F);
+        assert!(fme7.get_audio_output() > 0.1);
+    }
+}
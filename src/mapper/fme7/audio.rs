@@ -0,0 +1,293 @@
+use crate::state::{Serialize, Serializer};
+
+/// The tone, noise and envelope generators step once every 16 CPU cycles.
+const CLOCK_DIVIDER: u8 = 16;
+/// Output level of a channel at full volume.
+const CHANNEL_LEVEL: f32 = 0.15;
+
+/// Sunsoft 5B audio reference: https://www.nesdev.org/wiki/Sunsoft_5B_audio
+///
+/// A Yamaha YM2149F, a variant of the General Instrument AY-3-8910, with three square wave
+/// channels which can each mix in a shared noise generator and use a shared envelope rather than
+/// a fixed volume. Volumes are logarithmic, at 3 dB per step.
+///
+/// Registers: <pre>
+/// $00-$05 => tone periods of the channels, 12 bits each, lower byte first
+/// $06     => noise period, 5 bits
+/// $07     => 1 << 3..=5: disables noise per channel, 1 << 0..=2: disables tone per channel
+/// $08-$0A => 1 << 4: use envelope, 0b1111: volume
+/// $0B-$0C => envelope period, lower byte first
+/// $0D     => envelope shape; 1 << 3: continue, 1 << 2: attack, 1 << 1: alternate, 1: hold
+/// </pre>
+pub struct Sunsoft5bAudio {
+    address: u8,
+    registers: [u8; 16],
+    divider: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    /// 17-bit linear feedback shift register.
+    noise_shift: u32,
+    envelope_counter: u16,
+    /// 5-bit level, counting up while attacking and down otherwise.
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+    /// Amplitude of each 5-bit level, 1.5 dB apart.
+    levels: [f32; 32],
+}
+
+impl Default for Sunsoft5bAudio {
+    fn default() -> Sunsoft5bAudio {
+        Sunsoft5bAudio::new()
+    }
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Sunsoft5bAudio {
+        let mut levels: [f32; 32] = [0.0; 32];
+        for (level, value) in levels.iter_mut().enumerate().skip(1) {
+            *value = 10f32.powf(-1.5 * (31 - level) as f32 / 20.0);
+        }
+        Sunsoft5bAudio {
+            address: 0,
+            registers: [0; 16],
+            divider: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true,
+            levels,
+        }
+    }
+
+    /// Handles writes to $C000-$DFFF. Data written while an address above $0F is selected gets
+    /// ignored.
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    /// Handles writes to $E000-$FFFF.
+    pub fn write_data(&mut self, value: u8) {
+        if self.address > 0x0F {
+            return;
+        }
+        self.registers[self.address as usize] = value;
+        if self.address == 0x0D {
+            self.envelope_attack = (value >> 2) & 1 == 1;
+            self.envelope_step = if self.envelope_attack { 0 } else { 31 };
+            self.envelope_holding = false;
+            self.envelope_counter = 0;
+        }
+    }
+
+    fn get_tone_period(&self, channel: usize) -> u16 {
+        let period: u16 = self.registers[channel * 2] as u16
+            | ((self.registers[channel * 2 + 1] & 0x0F) as u16) << 8;
+        period.max(1)
+    }
+
+    /// Advances the chip by a single CPU cycle.
+    pub fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider < CLOCK_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.get_tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        // the noise steps at half the rate of the tones
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[6] & 0x1F).max(1) * 2 {
+            self.noise_counter = 0;
+            let feedback: u32 = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+
+        let envelope_period: u16 =
+            (self.registers[0x0B] as u16 | (self.registers[0x0C] as u16) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= envelope_period {
+            self.envelope_counter = 0;
+            self.clock_envelope();
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        let at_end: bool = if self.envelope_attack {
+            self.envelope_step == 31
+        } else {
+            self.envelope_step == 0
+        };
+        if !at_end {
+            if self.envelope_attack {
+                self.envelope_step += 1;
+            } else {
+                self.envelope_step -= 1;
+            }
+            return;
+        }
+
+        let shape: u8 = self.registers[0x0D];
+        let continuing: bool = (shape >> 3) & 1 == 1;
+        let alternating: bool = (shape >> 1) & 1 == 1;
+        let holding: bool = shape & 1 == 1;
+        if !continuing {
+            self.envelope_step = 0;
+            self.envelope_holding = true;
+        } else if holding {
+            if alternating {
+                self.envelope_step = 31 - self.envelope_step;
+            }
+            self.envelope_holding = true;
+        } else if alternating {
+            self.envelope_attack = !self.envelope_attack;
+        } else {
+            self.envelope_step = if self.envelope_attack { 0 } else { 31 };
+        }
+    }
+
+    /// Returns the 5-bit level of a channel.
+    fn get_channel_level(&self, channel: usize) -> u8 {
+        let mixer: u8 = self.registers[7];
+        let tone: bool = self.tone_outputs[channel] || (mixer >> channel) & 1 == 1;
+        let noise: bool = self.noise_shift & 1 == 1 || (mixer >> (channel + 3)) & 1 == 1;
+        if !(tone && noise) {
+            return 0;
+        }
+        let volume: u8 = self.registers[8 + channel];
+        if (volume >> 4) & 1 == 1 {
+            self.envelope_step
+        } else if volume & 0x0F == 0 {
+            0
+        } else {
+            (volume & 0x0F) * 2 + 1
+        }
+    }
+
+    pub fn get_output(&self) -> f32 {
+        (0..3)
+            .map(|channel| self.levels[self.get_channel_level(channel) as usize])
+            .sum::<f32>()
+            * CHANNEL_LEVEL
+    }
+}
+
+impl Serialize for Sunsoft5bAudio {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.address);
+        s.value(&mut self.registers);
+        s.value(&mut self.divider);
+        s.value(&mut self.tone_counters);
+        s.value(&mut self.tone_outputs);
+        s.value(&mut self.noise_counter);
+        s.value(&mut self.noise_shift);
+        s.value(&mut self.envelope_counter);
+        s.value(&mut self.envelope_step);
+        s.value(&mut self.envelope_attack);
+        s.value(&mut self.envelope_holding);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(audio: &mut Sunsoft5bAudio, register: u8, value: u8) {
+        audio.write_address(register);
+        audio.write_data(value);
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut audio: Sunsoft5bAudio = Sunsoft5bAudio::new();
+        // channel A only, tone without noise, at full volume, with a period of 2
+        write(&mut audio, 7, 0b111_110);
+        write(&mut audio, 8, 0x0F);
+        write(&mut audio, 0, 2);
+        let mut outputs: Vec<bool> = Vec::new();
+        for _ in 0..8 {
+            for _ in 0..CLOCK_DIVIDER {
+                audio.clock();
+            }
+            outputs.push(audio.get_output() > 0.0);
+        }
+        assert_eq!(
+            outputs,
+            vec![false, true, true, false, false, true, true, false]
+        );
+        assert!((audio.levels[31] - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_volume() {
+        let mut audio: Sunsoft5bAudio = Sunsoft5bAudio::new();
+        // tone and noise disabled leave a constant output
+        write(&mut audio, 7, 0xFF);
+        write(&mut audio, 8, 0x0F);
+        let full: f32 = audio.get_output();
+        write(&mut audio, 8, 0x0D);
+        // 6 dB quieter
+        assert!((audio.get_output() / full - 0.5).abs() < 0.01);
+        write(&mut audio, 8, 0);
+        assert_eq!(audio.get_output(), 0.0);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut audio: Sunsoft5bAudio = Sunsoft5bAudio::new();
+        write(&mut audio, 7, 0xFF);
+        write(&mut audio, 8, 0x10);
+        write(&mut audio, 0x0B, 1);
+        // attack once, then hold
+        write(&mut audio, 0x0D, 0b1101);
+        let mut steps: Vec<u8> = Vec::new();
+        for _ in 0..40 {
+            for _ in 0..CLOCK_DIVIDER {
+                audio.clock();
+            }
+            steps.push(audio.get_channel_level(0));
+        }
+        assert_eq!(&steps[..3], &[1, 2, 3]);
+        assert!(steps[30..].iter().all(|step| *step == 31));
+
+        // decay once, then go silent
+        write(&mut audio, 0x0D, 0b0000);
+        for _ in 0..40 * CLOCK_DIVIDER as u32 {
+            audio.clock();
+        }
+        assert_eq!(audio.get_channel_level(0), 0);
+    }
+
+    #[test]
+    fn test_noise() {
+        let mut audio: Sunsoft5bAudio = Sunsoft5bAudio::new();
+        // noise only
+        write(&mut audio, 7, 0b110_111);
+        write(&mut audio, 8, 0x0F);
+        let mut changes: u32 = 0;
+        let mut last: f32 = audio.get_output();
+        for _ in 0..10000 {
+            audio.clock();
+            if audio.get_output() != last {
+                changes += 1;
+            }
+            last = audio.get_output();
+        }
+        assert!(changes > 50);
+    }
+}
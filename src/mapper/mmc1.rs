@@ -0,0 +1,402 @@
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+/// Boards with 512 KiB of PRG-ROM switch between two 256 KiB halves.
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/// MMC1 reference: https://www.nesdev.org/wiki/MMC1
+///
+/// Mapper 1. Its registers are written one bit at a time through a serial shift register. Boards
+/// with CHR-RAM repurpose the upper bits of the CHR bank registers: SNROM uses them to disable the
+/// PRG-RAM, SOROM and SXROM to select the PRG-RAM bank, and SUROM and SXROM to select the 256 KiB
+/// half of the PRG-ROM.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    /// Submapper 5 boards (SEROM, SHROM, SH1ROM) have 32 KiB of PRG-ROM which can't be switched.
+    fixed_prg: bool,
+
+    shift_register: u8,
+    /// Number of bits written into the shift register so far.
+    shift_count: u8,
+    /// Bit layout: <pre>
+    /// 1 << 4      => CHR mode; switch two 4 KiB banks instead of a single 8 KiB one
+    /// 0b11 << 2   => PRG mode; 0 and 1 switch 32 KiB at $8000, 2 fixes the first bank at $8000,
+    ///                3 fixes the last bank at $C000
+    /// 0b11        => mirroring; single screen lower, single screen upper, vertical, horizontal
+    /// </pre>
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    /// Bit layout: <pre>
+    /// 1 << 4 => PRG-RAM disable
+    /// 0b1111 => PRG-ROM bank
+    /// </pre>
+    prg_bank: u8,
+
+    /// Number of CPU cycles elapsed, to detect writes on consecutive cycles.
+    cycle: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Mmc1 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            // plenty of iNES dumps don't specify their PRG-RAM
+            prg_ram = vec![0; 0x2000];
+        }
+        Mmc1 {
+            battery: rom.header.battery,
+            fixed_prg: rom.header.submapper == 5,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            shift_register: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    /// The 256 KiB half of the PRG-ROM selected by SUROM and SXROM boards.
+    fn get_prg_outer_bank(&self) -> usize {
+        if self.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            ((self.chr_bank_0 >> 4) & 1) as usize
+        } else {
+            0
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let offset: usize = (addr & 0x3FFF) as usize;
+        if self.fixed_prg {
+            return (addr - 0x8000) as usize % self.prg_rom.len();
+        }
+
+        let outer: usize = self.get_prg_outer_bank() * (PRG_OUTER_BANK_SIZE / PRG_BANK_SIZE);
+        let bank: usize = (self.prg_bank & 0x0F) as usize;
+        let high: bool = addr >= 0xC000;
+        let bank: usize = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) | high as usize,
+            2 if high => bank,
+            2 => 0,
+            _ if high => 0x0F,
+            _ => bank,
+        };
+        ((outer + bank) * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let offset: usize = (addr & 0x0FFF) as usize;
+        let bank: usize = if (self.control >> 4) & 1 == 0 {
+            (self.chr_bank_0 & !1) as usize | (addr >> 12) as usize
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn is_prg_ram_enabled(&self) -> bool {
+        // SNROM boards disable the PRG-RAM through the CHR bank register's upper bit
+        let snrom_disable: bool = self.chr_is_ram
+            && self.prg_rom.len() <= PRG_OUTER_BANK_SIZE
+            && self.prg_ram.len() <= 0x2000
+            && (self.chr_bank_0 >> 4) & 1 == 1;
+        (self.prg_bank >> 4) & 1 == 0 && !snrom_disable
+    }
+
+    fn get_prg_ram_addr(&self, addr: u16) -> usize {
+        let bank: usize = match self.prg_ram.len() {
+            // SOROM
+            0x4000 => ((self.chr_bank_0 >> 3) & 1) as usize,
+            // SXROM
+            0x8000 => ((self.chr_bank_0 >> 2) & 0b11) as usize,
+            _ => 0,
+        };
+        (bank * 0x2000 + (addr - 0x6000) as usize) % self.prg_ram.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.is_prg_ram_enabled() => {
+                self.prg_ram[self.get_prg_ram_addr(addr)]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.is_prg_ram_enabled() => {
+                let index: usize = self.get_prg_ram_addr(addr);
+                self.prg_ram[index] = value;
+            }
+            0x8000..=0xFFFF => {
+                // the second write of read-modify-write instructions gets ignored
+                let consecutive: bool = self
+                    .last_write_cycle
+                    .is_some_and(|last| self.cycle - last < 2);
+                self.last_write_cycle = Some(self.cycle);
+                if consecutive {
+                    return;
+                }
+
+                if (value >> 7) & 1 == 1 {
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+                self.shift_register |= (value & 1) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(addr, self.shift_register);
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Mmc1 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.shift_register);
+        s.value(&mut self.shift_count);
+        s.value(&mut self.control);
+        s.value(&mut self.chr_bank_0);
+        s.value(&mut self.chr_bank_1);
+        s.value(&mut self.prg_bank);
+        s.value(&mut self.cycle);
+        s.value(&mut self.last_write_cycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    /// Writes a value through the shift register, one bit at a time.
+    fn write_serial(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(addr, (value >> bit) & 1);
+            mmc1.clock_cpu();
+            mmc1.clock_cpu();
+        }
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut mmc1: Mmc1 = Mmc1::new(create_rom(1, 8, 2));
+        assert_eq!(mmc1.get_mirroring(), Mirroring::SingleScreenLower);
+        write_serial(&mut mmc1, 0x8000, 0b0_1110);
+        assert_eq!(mmc1.get_mirroring(), Mirroring::Vertical);
+
+        // writing with bit 7 set resets the shift register
+        mmc1.cpu_write(0x8000, 1);
+        mmc1.clock_cpu();
+        mmc1.clock_cpu();
+        mmc1.cpu_write(0x8000, 0x80);
+        mmc1.clock_cpu();
+        mmc1.clock_cpu();
+        write_serial(&mut mmc1, 0x8000, 0b0_0011);
+        assert_eq!(mmc1.get_mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_consecutive_writes() {
+        let mut mmc1: Mmc1 = Mmc1::new(create_rom(1, 8, 2));
+        mmc1.cpu_write(0x8000, 0);
+        mmc1.clock_cpu();
+        // ignored, as it happens on the following cycle
+        mmc1.cpu_write(0x8000, 1);
+        mmc1.clock_cpu();
+        mmc1.clock_cpu();
+        for _ in 0..4 {
+            mmc1.cpu_write(0x8000, 1);
+            mmc1.clock_cpu();
+            mmc1.clock_cpu();
+        }
+        assert_eq!(mmc1.control, 0b1_1110);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc1: Mmc1 = Mmc1::new(create_rom(1, 8, 2));
+        // power-on state fixes the last bank at $C000
+        assert_eq!(mmc1.cpu_read(0xC000), 7 * 16);
+        write_serial(&mut mmc1, 0xE000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), 3 * 16);
+        assert_eq!(mmc1.cpu_read(0xC000), 7 * 16);
+
+        // fix the first bank at $8000
+        write_serial(&mut mmc1, 0x8000, 0b0_1000);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 3 * 16);
+
+        // 32 KiB mode ignores the lowest bit
+        write_serial(&mut mmc1, 0x8000, 0b0_0000);
+        assert_eq!(mmc1.cpu_read(0x8000), 2 * 16);
+        assert_eq!(mmc1.cpu_read(0xC000), 3 * 16);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc1: Mmc1 = Mmc1::new(create_rom(1, 8, 4));
+        write_serial(&mut mmc1, 0xA000, 5);
+        write_serial(&mut mmc1, 0xC000, 2);
+        // 8 KiB mode ignores the lowest bit and the second register
+        assert_eq!(mmc1.ppu_read(0x0000), 4 * 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 5 * 4);
+
+        write_serial(&mut mmc1, 0x8000, 0b1_1100);
+        assert_eq!(mmc1.ppu_read(0x0000), 5 * 4);
+        assert_eq!(mmc1.ppu_read(0x1000), 2 * 4);
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut mmc1: Mmc1 = Mmc1::new(create_rom(1, 8, 2));
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+
+        write_serial(&mut mmc1, 0xE000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        write_serial(&mut mmc1, 0xE000, 0);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_snrom() {
+        let mut mmc1: Mmc1 = Mmc1::new(create_rom(1, 16, 0));
+        mmc1.cpu_write(0x6000, 0x42);
+        write_serial(&mut mmc1, 0xA000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        write_serial(&mut mmc1, 0xA000, 0);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+
+        mmc1.ppu_write(0x0000, 0x42);
+        assert_eq!(mmc1.ppu_read(0x0000), 0x42);
+    }
+
+    #[test]
+    fn test_surom() {
+        let mut rom: Rom = create_rom(1, 32, 0);
+        // the KiB index doesn't fit into a byte anymore, so mark the second half
+        rom.prg_rom[0x40000] = 0xAA;
+        rom.prg_rom[0x7C000] = 0xBB;
+        let mut mmc1: Mmc1 = Mmc1::new(rom);
+        // the last bank of the first 256 KiB half is fixed at $C000
+        assert_eq!(mmc1.cpu_read(0xC000), 15 * 16);
+        write_serial(&mut mmc1, 0xA000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x8000), 0xAA);
+        assert_eq!(mmc1.cpu_read(0xC000), 0xBB);
+        // SUROM boards keep their PRG-RAM enabled
+        mmc1.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_sxrom() {
+        let mut rom: Rom = create_rom(1, 32, 0);
+        rom.header.prg_ram_size = 0x8000;
+        let mut mmc1: Mmc1 = Mmc1::new(rom);
+        for bank in 0..4 {
+            write_serial(&mut mmc1, 0xA000, bank << 2);
+            mmc1.cpu_write(0x6000, bank);
+        }
+        for bank in 0..4 {
+            write_serial(&mut mmc1, 0xA000, bank << 2);
+            assert_eq!(mmc1.cpu_read(0x6000), bank);
+        }
+    }
+
+    #[test]
+    fn test_sorom() {
+        let mut rom: Rom = create_rom(1, 16, 0);
+        rom.header.prg_ram_size = 0x2000;
+        rom.header.prg_nvram_size = 0x2000;
+        let mut mmc1: Mmc1 = Mmc1::new(rom);
+        mmc1.cpu_write(0x6000, 1);
+        write_serial(&mut mmc1, 0xA000, 0b0_1000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        mmc1.cpu_write(0x6000, 2);
+        write_serial(&mut mmc1, 0xA000, 0);
+        assert_eq!(mmc1.cpu_read(0x6000), 1);
+    }
+
+    #[test]
+    fn test_fixed_prg() {
+        let mut rom: Rom = create_rom(1, 2, 2);
+        rom.header.submapper = 5;
+        let mut mmc1: Mmc1 = Mmc1::new(rom);
+        write_serial(&mut mmc1, 0xE000, 1);
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 16);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// MMC2 reference: https://www.nesdev.org/wiki/MMC2
+/// MMC4 reference: https://www.nesdev.org/wiki/MMC4
+///
+/// Mappers 9 (MMC2) and 10 (MMC4). Each pattern table has two 4 KiB CHR banks, and which one is
+/// active depends on a latch, which the PPU flips by fetching tile $FD or $FE. This lets games
+/// switch banks mid-frame without interrupts. The MMC2 switches 8 KiB of PRG, the MMC4 16 KiB
+/// and adds PRG-RAM.
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    mmc4: bool,
+
+    prg_bank: u8,
+    /// The banks used with the latch on $FD and on $FE, for both pattern tables.
+    chr_banks: [[u8; 2]; 2],
+    /// Whether the latch of each pattern table is on $FE.
+    latches: [Cell<bool>; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(rom: Rom) -> Mmc2 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mmc4: bool = rom.header.mapper == 10;
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if mmc4 && prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Mmc2 {
+            battery: rom.header.battery,
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            mmc4,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [Cell::new(false), Cell::new(false)],
+        }
+    }
+
+    /// The MMC2 switches 8 KiB at $8000 and fixes the last three banks, the MMC4 switches 16 KiB
+    /// at $8000 and fixes the last bank.
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let len: usize = self.prg_rom.len();
+        let offset: usize = match (self.mmc4, addr) {
+            (false, 0x8000..=0x9FFF) => self.prg_bank as usize * 0x2000 + (addr & 0x1FFF) as usize,
+            (false, _) => len - 0x8000 + (addr - 0x8000) as usize,
+            (true, 0x8000..=0xBFFF) => self.prg_bank as usize * 0x4000 + (addr & 0x3FFF) as usize,
+            (true, _) => len - 0x4000 + (addr & 0x3FFF) as usize,
+        };
+        offset % len
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let table: usize = (addr >> 12) as usize & 1;
+        let bank: u8 = self.chr_banks[table][self.latches[table].get() as usize];
+        (bank as usize * 0x1000 + (addr & 0x0FFF) as usize) % self.chr.len()
+    }
+
+    /// Flips the latches after fetching tile $FD or $FE. In the first pattern table, the MMC2 only
+    /// reacts to the first byte of the tiles' upper bit plane, $0FD8 and $0FE8.
+    fn update_latches(&self, addr: u16) {
+        let table: usize = (addr >> 12) as usize & 1;
+        let tile_byte: u16 = addr & 0x0FF8;
+        let exact: bool =
+            table == 1 || self.mmc4 || addr & 0x0FFF == 0x0FD8 || addr & 0x0FFF == 0x0FE8;
+        if !exact {
+            return;
+        }
+        match tile_byte {
+            0x0FD8 => self.latches[table].set(false),
+            0x0FE8 => self.latches[table].set(true),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// The latch flips after the byte got fetched, so the tile itself still uses the old bank.
+    fn ppu_read(&self, addr: u16) -> u8 {
+        let value: u8 = self.chr[self.get_chr_addr(addr)];
+        self.update_latches(addr);
+        value
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Mmc2 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_bank);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.latches);
+        s.value(&mut self.mirroring);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mmc2: Mmc2 = Mmc2::new(create_rom(9, 8, 16));
+        mmc2.cpu_write(0xA000, 5);
+        assert_eq!(mmc2.cpu_read(0x8000), 5 * 8);
+        assert_eq!(mmc2.cpu_read(0xA000), 13 * 8);
+        assert_eq!(mmc2.cpu_read(0xE000), 15 * 8);
+
+        let mut mmc4: Mmc2 = Mmc2::new(create_rom(10, 8, 16));
+        mmc4.cpu_write(0xA000, 5);
+        assert_eq!(mmc4.cpu_read(0xA000), 5 * 16 + 8);
+        assert_eq!(mmc4.cpu_read(0xC000), 7 * 16);
+        mmc4.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc4.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_latches() {
+        let mut mmc2: Mmc2 = Mmc2::new(create_rom(9, 8, 16));
+        mmc2.cpu_write(0xB000, 1);
+        mmc2.cpu_write(0xC000, 2);
+        mmc2.cpu_write(0xD000, 3);
+        mmc2.cpu_write(0xE000, 4);
+        assert_eq!(mmc2.ppu_read(0x0000), 4);
+        assert_eq!(mmc2.ppu_read(0x1000), 12);
+
+        // fetching tile $FE of the first pattern table switches it to the $FE bank, afterwards
+        assert_eq!(mmc2.ppu_read(0x0FE8), 4 + 3);
+        assert_eq!(mmc2.ppu_read(0x0000), 8);
+        assert_eq!(mmc2.ppu_read(0x1000), 12);
+
+        // the second pattern table reacts to any byte of the tile
+        mmc2.ppu_read(0x1FEA);
+        assert_eq!(mmc2.ppu_read(0x1000), 16);
+        mmc2.ppu_read(0x1FDF);
+        assert_eq!(mmc2.ppu_read(0x1000), 12);
+
+        // the first one only to $0FD8 and $0FE8 on the MMC2
+        mmc2.ppu_read(0x0FD9);
+        assert_eq!(mmc2.ppu_read(0x0000), 8);
+        mmc2.ppu_read(0x0FD8);
+        assert_eq!(mmc2.ppu_read(0x0000), 4);
+    }
+}
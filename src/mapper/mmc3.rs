@@ -0,0 +1,323 @@
+use crate::mapper::{Mapper, Scanline, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// MMC3 reference: https://www.nesdev.org/wiki/MMC3
+///
+/// Mapper 4. Switches 8 KiB PRG banks and 1/2 KiB CHR banks, and counts scanlines to raise an
+/// interrupt. The real chip clocks its counter on the rising edges of the PPU's A12 address line,
+/// filtering out the short pulses between tile fetches. Without a PPU to watch, the counter gets
+/// clocked once per rendered scanline instead, which is where the filtered edges land with the
+/// usual setup of background tiles in the lower and sprites in the upper pattern table.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    four_screen: bool,
+    /// The older NEC made chips (submapper 4) only raise an interrupt when the counter becomes 0,
+    /// while the newer Sharp made ones raise one whenever the counter is 0 after clocking it.
+    rev_a: bool,
+
+    /// Bit layout: <pre>
+    /// 1 << 7 => CHR inversion; swaps the 2 KiB and 1 KiB banks
+    /// 1 << 6 => PRG mode; swaps $8000 and $C000
+    /// 0b111  => register to update on the next write to $8001
+    /// </pre>
+    bank_select: u8,
+    /// R0 and R1 switch 2 KiB CHR banks, R2-R5 1 KiB CHR banks and R6 and R7 8 KiB PRG banks.
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_flag: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Mmc3 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Mmc3 {
+            battery: rom.header.battery,
+            four_screen: rom.header.mirroring == Mirroring::FourScreen,
+            rev_a: rom.header.submapper == 4,
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_flag: false,
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let bank_count: usize = self.prg_rom.len() / 0x2000;
+        let second_last: usize = bank_count.saturating_sub(2);
+        let swapped: bool = (self.bank_select >> 6) & 1 == 1;
+        let bank: usize = match (addr >> 13) & 0b11 {
+            0 if swapped => second_last,
+            0 => self.registers[6] as usize,
+            1 => self.registers[7] as usize,
+            2 if swapped => self.registers[6] as usize,
+            2 => second_last,
+            _ => bank_count - 1,
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let inverted: bool = (self.bank_select >> 7) & 1 == 1;
+        // with the inversion, the 2 KiB banks live in the upper pattern table
+        let slot: u16 = if inverted { addr ^ 0x1000 } else { addr } >> 10;
+        let bank: usize = match slot {
+            0 | 1 => (self.registers[0] & !1) as usize | slot as usize,
+            2 | 3 => (self.registers[1] & !1) as usize | (slot - 2) as usize,
+            _ => self.registers[slot as usize - 2] as usize,
+        };
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+
+    /// Clocks the scanline counter, as a filtered rising edge of A12 would.
+    fn clock_irq_counter(&mut self) {
+        let old: u8 = self.irq_counter;
+        let reloaded: bool = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled && (!self.rev_a || old != 0 || reloaded) {
+            self.irq_flag = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        let even: bool = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protected => {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = value,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0b111) as usize] = value,
+            0xA000..=0xBFFF if even && !self.four_screen => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0xA000..=0xBFFF if even => {}
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = (value >> 7) & 1 == 1;
+                self.prg_ram_write_protected = (value >> 6) & 1 == 1;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_flag = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// Stands in for the A12 rising edges, see [Mmc3].
+    fn notify_scanline(&mut self, scanline: Scanline) {
+        if scanline != Scanline::PostRender {
+            self.clock_irq_counter();
+        }
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Mmc3 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.bank_select);
+        s.value(&mut self.registers);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.prg_ram_enabled);
+        s.value(&mut self.prg_ram_write_protected);
+        s.value(&mut self.irq_latch);
+        s.value(&mut self.irq_counter);
+        s.value(&mut self.irq_reload);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_flag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mmc3: Mmc3 = Mmc3::new(create_rom(4, 8, 8));
+        mmc3.cpu_write(0x8000, 6);
+        mmc3.cpu_write(0x8001, 3);
+        mmc3.cpu_write(0x8000, 7);
+        mmc3.cpu_write(0x8001, 5);
+        assert_eq!(mmc3.cpu_read(0x8000), 3 * 8);
+        assert_eq!(mmc3.cpu_read(0xA000), 5 * 8);
+        assert_eq!(mmc3.cpu_read(0xC000), 14 * 8);
+        assert_eq!(mmc3.cpu_read(0xE000), 15 * 8);
+
+        // PRG mode 1 swaps $8000 and $C000
+        mmc3.cpu_write(0x8000, 0x46);
+        assert_eq!(mmc3.cpu_read(0x8000), 14 * 8);
+        assert_eq!(mmc3.cpu_read(0xA000), 5 * 8);
+        assert_eq!(mmc3.cpu_read(0xC000), 3 * 8);
+        assert_eq!(mmc3.cpu_read(0xE000), 15 * 8);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mmc3: Mmc3 = Mmc3::new(create_rom(4, 8, 8));
+        for (register, bank) in [(0, 9), (1, 20), (2, 33), (3, 34), (4, 35), (5, 36)] {
+            mmc3.cpu_write(0x8000, register);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        let banks = |mmc3: &Mmc3| {
+            (0..8)
+                .map(|i| mmc3.ppu_read(i * 0x400))
+                .collect::<Vec<u8>>()
+        };
+        // the lowest bit of the 2 KiB banks is ignored
+        assert_eq!(banks(&mmc3), vec![8, 9, 20, 21, 33, 34, 35, 36]);
+
+        mmc3.cpu_write(0x8000, 0x80);
+        assert_eq!(banks(&mmc3), vec![33, 34, 35, 36, 8, 9, 20, 21]);
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram() {
+        let mut mmc3: Mmc3 = Mmc3::new(create_rom(4, 8, 8));
+        mmc3.cpu_write(0xA000, 1);
+        assert_eq!(mmc3.get_mirroring(), Mirroring::Horizontal);
+        mmc3.cpu_write(0xA000, 0);
+        assert_eq!(mmc3.get_mirroring(), Mirroring::Vertical);
+
+        mmc3.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+        // write protection
+        mmc3.cpu_write(0xA001, 0xC0);
+        mmc3.cpu_write(0x6000, 0x43);
+        assert_eq!(mmc3.cpu_read(0x6000), 0x42);
+        // disabled
+        mmc3.cpu_write(0xA001, 0x00);
+        assert_eq!(mmc3.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mmc3: Mmc3 = Mmc3::new(create_rom(4, 8, 8));
+        mmc3.cpu_write(0xC000, 3);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+
+        // the first clock reloads the counter, the next three count it down
+        for _ in 0..3 {
+            mmc3.notify_scanline(Scanline::Visible(0));
+            assert!(!mmc3.get_irq());
+        }
+        mmc3.notify_scanline(Scanline::Visible(0));
+        assert!(mmc3.get_irq());
+
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.get_irq());
+        // disabled interrupts don't get raised
+        for _ in 0..4 {
+            mmc3.notify_scanline(Scanline::Visible(0));
+        }
+        assert!(!mmc3.get_irq());
+    }
+
+    #[test]
+    fn test_revisions() {
+        // with a latch of 0, the newer chips raise an interrupt on every scanline
+        let mut rom: Rom = create_rom(4, 8, 8);
+        let mut mmc3: Mmc3 = Mmc3::new(rom);
+        mmc3.cpu_write(0xE001, 0);
+        mmc3.notify_scanline(Scanline::Visible(0));
+        mmc3.notify_scanline(Scanline::Visible(0));
+        assert!(mmc3.get_irq());
+
+        // while the older ones only raise one after writing $C001
+        rom = create_rom(4, 8, 8);
+        rom.header.submapper = 4;
+        mmc3 = Mmc3::new(rom);
+        mmc3.cpu_write(0xE001, 0);
+        mmc3.notify_scanline(Scanline::Visible(0));
+        mmc3.notify_scanline(Scanline::Visible(0));
+        assert!(!mmc3.get_irq());
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.notify_scanline(Scanline::Visible(0));
+        assert!(mmc3.get_irq());
+    }
+}
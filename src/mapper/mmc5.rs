@@ -0,0 +1,681 @@
+pub mod audio;
+
+use crate::mapper::mmc5::audio::Mmc5Audio;
+use crate::mapper::{Mapper, Scanline, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// MMC5 reference: https://www.nesdev.org/wiki/MMC5
+///
+/// Mapper 5, the most capable of Nintendo's mappers. Besides flexible PRG and CHR banking, it
+/// comes with 1 KiB of extra RAM (ExRAM) usable as a third nametable or for per-tile attributes
+/// and CHR banks, a fill mode nametable, a vertical split screen, a scanline interrupt, a
+/// multiplier and expansion audio.
+///
+/// Many of these work by watching the PPU's fetches, so the PPU has to report its nametable reads
+/// through [Mapper::read_nametable] and its sprite fetches through [Mapper::notify_sprite_fetch].
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    exram: [u8; 0x400],
+
+    /// $5100: 0 switches 32 KiB, 1 two 16 KiB banks, 2 one 16 KiB and two 8 KiB banks and 3 four
+    /// 8 KiB banks.
+    prg_mode: u8,
+    /// $5101: 0 switches 8 KiB, 1 4 KiB, 2 2 KiB and 3 1 KiB banks.
+    chr_mode: u8,
+    /// $5102 and $5103, which have to be set to 2 and 1 to allow writing PRG-RAM.
+    prg_ram_protect: [u8; 2],
+    /// $5104: 0 uses ExRAM as nametable, 1 for extended attributes, 2 as plain RAM and 3 as
+    /// read-only RAM.
+    exram_mode: u8,
+    /// $5105: two bits per nametable; 0 selects CIRAM page 0, 1 CIRAM page 1, 2 ExRAM and 3 the
+    /// fill mode nametable.
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    /// $5113-$5117. For $5114-$5116, bit 7 selects ROM rather than RAM.
+    prg_banks: [u8; 5],
+    /// $5120-$5127 (set A, for sprites) and $5128-$512B (set B, for the background in 8x16 sprite
+    /// mode), including the upper bits from $5130.
+    chr_banks: [u16; 12],
+    chr_upper: u8,
+    /// Whether set B was written last, which then is used outside of rendering.
+    chr_set_b_last: bool,
+
+    /// $5200. Bit layout: <pre>
+    /// 1 << 7 => enabled
+    /// 1 << 6 => split region is right of the threshold, rather than left of it
+    /// 0..=4  => threshold, in tiles
+    /// </pre>
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_target: u8,
+    irq_enabled: bool,
+    /// Reading $5204 acknowledges the interrupt.
+    irq_pending: Cell<bool>,
+    in_frame: bool,
+    scanline_counter: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    /// Snooped from PPUCTRL.
+    sprites_8x16: bool,
+    /// Snooped from PPUMASK.
+    rendering_enabled: bool,
+    fetching_sprites: bool,
+    /// The scanline the background tiles are currently fetched for. The first two tiles of a
+    /// scanline are fetched at the end of the one before.
+    fetch_line: u8,
+    current_line: Option<u8>,
+    /// Background tile fetches on the current scanline, tracked on nametable reads.
+    tile_index: Cell<u8>,
+    /// Whether the tile being fetched lies within the split region.
+    in_split: Cell<bool>,
+    /// ExRAM byte of the tile being fetched, in extended attribute mode.
+    extended_attribute: Cell<u8>,
+
+    pub audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Mmc5 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if !rom.header.nes2 || prg_ram.is_empty() {
+            // iNES headers can't tell the size, so cover every board
+            prg_ram = vec![0; 0x10000];
+        }
+        Mmc5 {
+            battery: rom.header.battery,
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_is_ram,
+            exram: [0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            chr_set_b_last: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            sprites_8x16: false,
+            rendering_enabled: false,
+            fetching_sprites: false,
+            fetch_line: 0,
+            current_line: None,
+            tile_index: Cell::new(0),
+            in_split: Cell::new(false),
+            extended_attribute: Cell::new(0),
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    /// Returns the index into `prg_banks` of the register and the bank's size in 8 KiB units for
+    /// an address in $8000-$FFFF.
+    fn get_prg_bank(&self, addr: u16) -> (usize, usize) {
+        let slot: usize = ((addr - 0x8000) >> 13) as usize;
+        match (self.prg_mode, slot) {
+            (0, _) => (4, 4),
+            (1, 0 | 1) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0 | 1) => (2, 2),
+            (2, 2) => (3, 1),
+            (2, _) => (4, 1),
+            (_, slot) => (slot + 1, 1),
+        }
+    }
+
+    /// Maps an address in $6000-$FFFF to either PRG-ROM (true) or PRG-RAM (false).
+    fn get_prg_addr(&self, addr: u16) -> (bool, usize) {
+        if addr < 0x8000 {
+            let bank: usize = (self.prg_banks[0] & 0x07) as usize;
+            return (false, bank * 0x2000 + (addr & 0x1FFF) as usize);
+        }
+
+        let (index, size) = self.get_prg_bank(addr);
+        let register: u8 = self.prg_banks[index];
+        // $5117 always selects ROM
+        let rom: bool = (register >> 7) & 1 == 1 || index == 4;
+        let bank: usize = (register & 0x7F) as usize & !(size - 1);
+        let offset: usize = (addr as usize - 0x8000) % (size * 0x2000);
+        (rom, bank * 0x2000 + offset)
+    }
+
+    fn is_prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let background: bool = !self.fetching_sprites;
+        if self.in_frame && background {
+            if self.in_split.get() {
+                let fine_y: usize = self.get_split_y() & 7;
+                let offset: usize = (addr & 0x0FF8) as usize | fine_y;
+                return (self.split_bank as usize * 0x1000 + offset) % self.chr.len();
+            }
+            if self.exram_mode == 1 {
+                let bank: usize = (self.chr_upper as usize) << 6
+                    | (self.extended_attribute.get() & 0x3F) as usize;
+                return (bank * 0x1000 + (addr & 0x0FFF) as usize) % self.chr.len();
+            }
+        }
+
+        let use_set_b: bool = if self.in_frame {
+            self.sprites_8x16 && background
+        } else {
+            self.chr_set_b_last
+        };
+        let (size, register): (usize, usize) = match self.chr_mode {
+            0 => (0x2000, 7),
+            1 => (0x1000, (addr as usize >> 12) * 4 + 3),
+            2 => (0x800, (addr as usize >> 11) * 2 + 1),
+            _ => (0x400, addr as usize >> 10),
+        };
+        let bank: u16 = if use_set_b {
+            // set B only covers the lower 4 KiB, which get repeated for the upper ones
+            self.chr_banks[8 + (register & 0b11)]
+        } else {
+            self.chr_banks[register]
+        };
+        (bank as usize * size + addr as usize % size) % self.chr.len()
+    }
+
+    /// The split region's scanline, which scrolls independently of the rest of the background.
+    fn get_split_y(&self) -> usize {
+        (self.split_scroll as usize + self.fetch_line as usize) % 240
+    }
+
+    fn is_tile_in_split(&self, tile: u8) -> bool {
+        if (self.split_control >> 7) & 1 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let threshold: u8 = self.split_control & 0x1F;
+        if (self.split_control >> 6) & 1 == 1 {
+            tile >= threshold
+        } else {
+            tile < threshold
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x5010 | 0x5015 => self.audio.read_register(addr),
+            0x5204 => {
+                let out: u8 = (self.irq_pending.get() as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending.set(false);
+                out
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+            0x6000..=0xFFFF => {
+                let (rom, index) = self.get_prg_addr(addr);
+                let out: u8 = if rom {
+                    self.prg_rom[index % self.prg_rom.len()]
+                } else {
+                    self.prg_ram[index % self.prg_ram.len()]
+                };
+                if (0x8000..0xC000).contains(&addr) {
+                    self.audio.notify_read(out);
+                }
+                out
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5015 => self.audio.write_register(addr, value),
+            0x5100 => self.prg_mode = value & 0b11,
+            0x5101 => self.chr_mode = value & 0b11,
+            0x5102 => self.prg_ram_protect[0] = value & 0b11,
+            0x5103 => self.prg_ram_protect[1] = value & 0b11,
+            0x5104 => self.exram_mode = value & 0b11,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = value,
+            0x5120..=0x512B => {
+                let register: usize = (addr - 0x5120) as usize;
+                self.chr_banks[register] = (self.chr_upper as u16) << 8 | value as u16;
+                self.chr_set_b_last = register >= 8;
+            }
+            0x5130 => self.chr_upper = value & 0b11,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_target = value,
+            0x5204 => self.irq_enabled = (value >> 7) & 1 == 1,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let index: usize = (addr - 0x5C00) as usize;
+                match self.exram_mode {
+                    // only writable while rendering, otherwise 0 gets written
+                    0 | 1 => self.exram[index] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[index] = value,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF if self.is_prg_ram_writable() => {
+                let (rom, index) = self.get_prg_addr(addr);
+                if !rom {
+                    let len: usize = self.prg_ram.len();
+                    self.prg_ram[index % len] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    /// Only meaningful for nametables mapped to CIRAM; see [Mmc5::read_nametable] for the rest.
+    fn get_mirroring(&self) -> Mirroring {
+        match self.nametable_mapping {
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            0x55 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::SingleScreenLower,
+        }
+    }
+
+    fn read_nametable(&self, addr: u16, ciram: &[u8]) -> u8 {
+        let table: u16 = (addr >> 10) & 0b11;
+        let offset: usize = (addr & 0x3FF) as usize;
+        let attribute: bool = offset >= 0x3C0;
+
+        if self.in_frame && !self.fetching_sprites {
+            if !attribute {
+                let tile: u8 = self.tile_index.get();
+                self.tile_index.set(tile.saturating_add(1));
+                self.in_split.set(self.is_tile_in_split(tile));
+                self.extended_attribute.set(self.exram[offset]);
+                if self.in_split.get() {
+                    let row: usize = self.get_split_y() / 8;
+                    return self.exram[row * 32 + (tile & 0x1F) as usize];
+                }
+            } else if self.in_split.get() {
+                let y: usize = self.get_split_y();
+                let tile: usize = (self.tile_index.get().wrapping_sub(1) & 0x1F) as usize;
+                let shift: usize = ((y >> 4) & 1) * 4 + ((tile >> 1) & 1) * 2;
+                let palette: u8 = (self.exram[0x3C0 + (y / 32) * 8 + tile / 4] >> shift) & 0b11;
+                return palette * 0x55;
+            } else if self.exram_mode == 1 {
+                return (self.extended_attribute.get() >> 6) * 0x55;
+            }
+        }
+
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            page @ (0 | 1) => ciram[page as usize * 0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if attribute => self.fill_attribute * 0x55,
+            _ => self.fill_tile,
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8]) {
+        let table: u16 = (addr >> 10) & 0b11;
+        let offset: usize = (addr & 0x3FF) as usize;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            page @ (0 | 1) => ciram[page as usize * 0x400 + offset] = value,
+            2 if self.exram_mode <= 1 => self.exram[offset] = value,
+            _ => {}
+        }
+    }
+
+    fn get_irq(&self) -> bool {
+        (self.irq_enabled && self.irq_pending.get()) || self.audio.get_irq()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.audio.clock();
+    }
+
+    fn notify_scanline(&mut self, scanline: Scanline) {
+        self.current_line = match scanline {
+            Scanline::Visible(line) => Some(line),
+            _ => None,
+        };
+        match scanline {
+            Scanline::Visible(_) if !self.rendering_enabled => {}
+            Scanline::Visible(0) => {
+                self.in_frame = true;
+                self.scanline_counter = 0;
+                self.irq_pending.set(false);
+            }
+            Scanline::Visible(_) => {
+                self.scanline_counter = self.scanline_counter.wrapping_add(1);
+                if self.scanline_counter == self.irq_target && self.irq_target != 0 {
+                    self.irq_pending.set(true);
+                }
+            }
+            Scanline::PostRender => self.in_frame = false,
+            Scanline::PreRender => {}
+        }
+        self.tile_index.set(2);
+    }
+
+    fn notify_sprite_fetch(&mut self, active: bool) {
+        self.fetching_sprites = active;
+        if !active {
+            // the following fetches are for the first two tiles of the next scanline
+            self.tile_index.set(0);
+            self.fetch_line = self.current_line.map_or(0, |line| line.wrapping_add(1));
+        }
+    }
+
+    fn notify_ppu_register_write(&mut self, addr: u16, value: u8) {
+        match addr & 0x2007 {
+            0x2000 => self.sprites_8x16 = (value >> 5) & 1 == 1,
+            0x2001 => {
+                self.rendering_enabled = value & 0x18 != 0;
+                if !self.rendering_enabled {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.audio.get_output()
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Mmc5 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.bytes(&mut self.exram);
+        s.value(&mut self.prg_mode);
+        s.value(&mut self.chr_mode);
+        s.value(&mut self.prg_ram_protect);
+        s.value(&mut self.exram_mode);
+        s.value(&mut self.nametable_mapping);
+        s.value(&mut self.fill_tile);
+        s.value(&mut self.fill_attribute);
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.chr_upper);
+        s.value(&mut self.chr_set_b_last);
+        s.value(&mut self.split_control);
+        s.value(&mut self.split_scroll);
+        s.value(&mut self.split_bank);
+        s.value(&mut self.irq_target);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_pending);
+        s.value(&mut self.in_frame);
+        s.value(&mut self.scanline_counter);
+        s.value(&mut self.multiplicand);
+        s.value(&mut self.multiplier);
+        s.value(&mut self.sprites_8x16);
+        s.value(&mut self.rendering_enabled);
+        s.value(&mut self.fetching_sprites);
+        s.value(&mut self.fetch_line);
+        s.value(&mut self.current_line);
+        s.value(&mut self.tile_index);
+        s.value(&mut self.in_split);
+        s.value(&mut self.extended_attribute);
+        s.value(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    fn create() -> Mmc5 {
+        let mut mmc5: Mmc5 = Mmc5::new(create_rom(5, 16, 32));
+        mmc5.notify_ppu_register_write(0x2001, 0x18);
+        mmc5
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc5: Mmc5 = create();
+        // power-on state maps the last bank everywhere in mode 3
+        assert_eq!(mmc5.cpu_read(0xE000), 31 * 8);
+
+        for (i, bank) in [0x81, 0x82, 0x85].iter().enumerate() {
+            mmc5.cpu_write(0x5114 + i as u16, *bank);
+        }
+        mmc5.cpu_write(0x5117, 0x07);
+        let banks = |mmc5: &Mmc5| {
+            [0x8000, 0xA000, 0xC000, 0xE000]
+                .iter()
+                .map(|addr| mmc5.cpu_read(*addr) / 8)
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(banks(&mmc5), vec![1, 2, 5, 7]);
+
+        mmc5.cpu_write(0x5100, 2);
+        assert_eq!(banks(&mmc5), vec![2, 3, 5, 7]);
+        mmc5.cpu_write(0x5100, 1);
+        assert_eq!(banks(&mmc5), vec![2, 3, 6, 7]);
+        mmc5.cpu_write(0x5100, 0);
+        assert_eq!(banks(&mmc5), vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mmc5: Mmc5 = create();
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+
+        mmc5.cpu_write(0x5102, 2);
+        mmc5.cpu_write(0x5103, 1);
+        mmc5.cpu_write(0x5113, 3);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0x42);
+
+        // RAM banks can be mapped into $8000-$DFFF as well
+        mmc5.cpu_write(0x5114, 0x03);
+        assert_eq!(mmc5.cpu_read(0x8000), 0x42);
+        mmc5.cpu_write(0x8001, 0x43);
+        mmc5.cpu_write(0x5113, 0);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+        mmc5.cpu_write(0x5113, 3);
+        assert_eq!(mmc5.cpu_read(0x6001), 0x43);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc5: Mmc5 = create();
+        for i in 0..8 {
+            mmc5.cpu_write(0x5120 + i, 16 + i as u8);
+        }
+        let banks = |mmc5: &Mmc5| {
+            (0..8)
+                .map(|i| mmc5.ppu_read(i * 0x400))
+                .collect::<Vec<u8>>()
+        };
+
+        mmc5.cpu_write(0x5101, 3);
+        assert_eq!(banks(&mmc5), vec![16, 17, 18, 19, 20, 21, 22, 23]);
+        mmc5.cpu_write(0x5101, 2);
+        assert_eq!(banks(&mmc5), vec![34, 35, 38, 39, 42, 43, 46, 47]);
+        mmc5.cpu_write(0x5101, 1);
+        assert_eq!(banks(&mmc5), vec![76, 77, 78, 79, 92, 93, 94, 95]);
+        mmc5.cpu_write(0x5101, 0);
+        assert_eq!(banks(&mmc5), (184..192).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_chr_set_b() {
+        let mut mmc5: Mmc5 = create();
+        mmc5.cpu_write(0x5101, 3);
+        mmc5.cpu_write(0x5120, 1);
+        mmc5.cpu_write(0x5128, 2);
+        mmc5.notify_ppu_register_write(0x2000, 0x20);
+        mmc5.notify_scanline(Scanline::Visible(0));
+
+        // sprites use set A, the background set B while rendering 8x16 sprites
+        assert_eq!(mmc5.ppu_read(0x0000), 2);
+        assert_eq!(mmc5.ppu_read(0x1000), 2);
+        mmc5.notify_sprite_fetch(true);
+        assert_eq!(mmc5.ppu_read(0x0000), 1);
+
+        // outside of rendering, the last written set is used
+        mmc5.notify_scanline(Scanline::PostRender);
+        assert_eq!(mmc5.ppu_read(0x0000), 2);
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut mmc5: Mmc5 = create();
+        let mut ciram: Vec<u8> = vec![0; 0x800];
+        // CIRAM page 0, CIRAM page 1, ExRAM and fill mode
+        mmc5.cpu_write(0x5105, 0b11_10_01_00);
+        mmc5.cpu_write(0x5106, 0x42);
+        mmc5.cpu_write(0x5107, 2);
+
+        mmc5.write_nametable(0x2000, 1, &mut ciram);
+        mmc5.write_nametable(0x2400, 2, &mut ciram);
+        mmc5.write_nametable(0x2800, 3, &mut ciram);
+        assert_eq!(ciram[0x000], 1);
+        assert_eq!(ciram[0x400], 2);
+        assert_eq!(mmc5.read_nametable(0x2800, &ciram), 3);
+        assert_eq!(mmc5.read_nametable(0x2C00, &ciram), 0x42);
+        assert_eq!(mmc5.read_nametable(0x2FC0, &ciram), 0xAA);
+    }
+
+    #[test]
+    fn test_exram_modes() {
+        let mut mmc5: Mmc5 = create();
+        // not writable outside of rendering in nametable mode
+        mmc5.cpu_write(0x5C00, 0x42);
+        mmc5.cpu_write(0x5104, 2);
+        assert_eq!(mmc5.cpu_read(0x5C00), 0);
+        mmc5.cpu_write(0x5C00, 0x42);
+        assert_eq!(mmc5.cpu_read(0x5C00), 0x42);
+
+        // read-only mode
+        mmc5.cpu_write(0x5104, 3);
+        mmc5.cpu_write(0x5C00, 0x43);
+        assert_eq!(mmc5.cpu_read(0x5C00), 0x42);
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let mut mmc5: Mmc5 = create();
+        let ciram: Vec<u8> = vec![0; 0x800];
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C05, 0b10_000011);
+        mmc5.cpu_write(0x5104, 1);
+        mmc5.notify_scanline(Scanline::Visible(0));
+
+        mmc5.read_nametable(0x2005, &ciram);
+        assert_eq!(mmc5.read_nametable(0x23C1, &ciram), 0xAA);
+        // 4 KiB bank 3 is made of the 1 KiB banks 12 to 15
+        assert_eq!(mmc5.ppu_read(0x0000), 12);
+        assert_eq!(mmc5.ppu_read(0x1C00), 15);
+    }
+
+    #[test]
+    fn test_split_screen() {
+        let mut mmc5: Mmc5 = create();
+        let ciram: Vec<u8> = vec![7; 0x800];
+        mmc5.cpu_write(0x5104, 2);
+        // tile row 1 of the split region
+        mmc5.cpu_write(0x5C00 + 32 + 1, 0x42);
+        mmc5.cpu_write(0x5104, 0);
+        // split the two left-most tiles, scrolled down by 8 pixels
+        mmc5.cpu_write(0x5200, 0x82);
+        mmc5.cpu_write(0x5201, 8);
+        mmc5.cpu_write(0x5202, 1);
+
+        mmc5.notify_scanline(Scanline::Visible(0));
+        mmc5.notify_sprite_fetch(true);
+        mmc5.notify_sprite_fetch(false);
+        assert_eq!(mmc5.read_nametable(0x2000, &ciram), 0);
+        assert_eq!(mmc5.read_nametable(0x2001, &ciram), 0x42);
+        // fine Y comes from the split scroll, and the bank from $5202
+        assert_eq!(mmc5.ppu_read(0x0000), 4);
+        assert_eq!(mmc5.read_nametable(0x2002, &ciram), 7);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mmc5: Mmc5 = create();
+        mmc5.cpu_write(0x5203, 3);
+        mmc5.cpu_write(0x5204, 0x80);
+        mmc5.notify_scanline(Scanline::PreRender);
+        assert_eq!(mmc5.cpu_read(0x5204), 0);
+
+        for line in 0..3 {
+            mmc5.notify_scanline(Scanline::Visible(line));
+            assert!(!mmc5.get_irq());
+        }
+        mmc5.notify_scanline(Scanline::Visible(3));
+        assert!(mmc5.get_irq());
+        assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+        assert!(!mmc5.get_irq());
+
+        mmc5.notify_scanline(Scanline::PostRender);
+        assert_eq!(mmc5.cpu_read(0x5204), 0);
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mmc5: Mmc5 = create();
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_read(0x5205), (20000u16 & 0xFF) as u8);
+        assert_eq!(mmc5.cpu_read(0x5206), (20000u16 >> 8) as u8);
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut mmc5: Mmc5 = create();
+        mmc5.cpu_write(0x5011, 0xFF);
+        assert!(mmc5.get_audio_output() > 0.0);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::apu::pulse::Pulse;
+use crate::state::{Serialize, Serializer, StateError};
+use std::cell::Cell;
+
+/// Number of CPU cycles between clocks of the envelopes and length counters, which happen at
+/// about 240 Hz.
+const FRAME_PERIOD: u16 = 7457;
+
+/// MMC5 audio reference: https://www.nesdev.org/wiki/MMC5_audio
+///
+/// Two pulse channels, which work like the APU's ones without the sweep units, and an 8-bit PCM
+/// channel.
+pub struct Mmc5Audio {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    /// In read mode, the PCM channel plays the values the CPU reads from $8000-$BFFF, rather than
+    /// the ones written to $5011.
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    /// Set when the PCM channel reads a 0 in read mode. Reading $5010 acknowledges it.
+    pcm_irq_flag: Cell<bool>,
+    pcm_output: Cell<u8>,
+    /// The pulse timers are clocked on every other CPU cycle.
+    odd_cycle: bool,
+    /// Number of CPU cycles left until the envelopes and length counters get clocked.
+    frame_timer: u16,
+}
+
+impl Default for Mmc5Audio {
+    fn default() -> Mmc5Audio {
+        Mmc5Audio::new()
+    }
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Mmc5Audio {
+        Mmc5Audio {
+            pulse1: Pulse::new_without_sweep(),
+            pulse2: Pulse::new_without_sweep(),
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_flag: Cell::new(false),
+            pcm_output: Cell::new(0),
+            odd_cycle: false,
+            frame_timer: FRAME_PERIOD,
+        }
+    }
+
+    /// Handles writes to $5000-$5015.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5003 => self.pulse1.write_register(addr - 0x5000, value),
+            0x5004..=0x5007 => self.pulse2.write_register(addr - 0x5004, value),
+            0x5010 => {
+                self.pcm_read_mode = value & 1 == 1;
+                self.pcm_irq_enabled = (value >> 7) & 1 == 1;
+            }
+            // writing 0 has no effect in write mode
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm_output.set(value),
+            0x5015 => {
+                self.pulse1.length_counter.set_enabled(value & 1 == 1);
+                self.pulse2
+                    .length_counter
+                    .set_enabled((value >> 1) & 1 == 1);
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles reads of $5010 and $5015. Bit layout of $5010: <pre>
+    /// 1 << 7 => PCM interrupt
+    /// </pre>
+    /// Bit layout of $5015: <pre>
+    /// 1 << 1 => pulse 2 length counter is active
+    /// 1      => pulse 1 length counter is active
+    /// </pre>
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x5010 => {
+                let out: u8 = (self.get_irq() as u8) << 7;
+                self.pcm_irq_flag.set(false);
+                out
+            }
+            0x5015 => {
+                self.pulse1.length_counter.is_active() as u8
+                    | (self.pulse2.length_counter.is_active() as u8) << 1
+            }
+            _ => 0,
+        }
+    }
+
+    /// Observes the CPU's reads of $8000-$BFFF, which the PCM channel plays in read mode.
+    pub fn notify_read(&self, value: u8) {
+        if !self.pcm_read_mode {
+            return;
+        }
+        if value == 0 {
+            self.pcm_irq_flag.set(true);
+        } else {
+            self.pcm_output.set(value);
+        }
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.pcm_irq_enabled && self.pcm_irq_flag.get()
+    }
+
+    /// Advances the channels by a single CPU cycle.
+    pub fn clock(&mut self) {
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+        self.frame_timer -= 1;
+        if self.frame_timer == 0 {
+            self.frame_timer = FRAME_PERIOD;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+    }
+
+    /// Mixes the channels. The pulses use the same nonlinear curve as the APU's, while the PCM
+    /// channel at full volume is about as loud as the DMC.
+    pub fn get_output(&self) -> f32 {
+        let pulse: f32 = (self.pulse1.get_output() + self.pulse2.get_output()) as f32;
+        let pulse_output: f32 = if pulse == 0.0 {
+            0.0
+        } else {
+            95.52 / (8128.0 / pulse + 100.0)
+        };
+        pulse_output + self.pcm_output.get() as f32 / 255.0 * 0.4
+    }
+}
+
+impl Serialize for Mmc5Audio {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.pulse1);
+        s.value(&mut self.pulse2);
+        s.value(&mut self.pcm_read_mode);
+        s.value(&mut self.pcm_irq_enabled);
+        s.value(&mut self.pcm_irq_flag);
+        s.value(&mut self.pcm_output);
+        s.value(&mut self.odd_cycle);
+        s.value(&mut self.frame_timer);
+        if self.frame_timer == 0 || self.frame_timer > FRAME_PERIOD {
+            s.fail(StateError::Mismatch("MMC5 frame timer"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulses() {
+        let mut audio: Mmc5Audio = Mmc5Audio::new();
+        audio.write_register(0x5015, 0b11);
+        audio.write_register(0x5000, 0b1001_1111);
+        audio.write_register(0x5002, 0x02);
+        // length index 3 loads a length of 2
+        audio.write_register(0x5003, 0b0001_1000);
+        assert_eq!(audio.read_register(0x5015), 0b01);
+
+        let mut high: bool = false;
+        for _ in 0..16 {
+            audio.clock();
+            high |= audio.get_output() > 0.0;
+        }
+        assert!(high);
+
+        // two length counter clocks silence the channel
+        for _ in 0..FRAME_PERIOD * 2 {
+            audio.clock();
+        }
+        assert_eq!(audio.read_register(0x5015), 0);
+    }
+
+    #[test]
+    fn test_pcm() {
+        let mut audio: Mmc5Audio = Mmc5Audio::new();
+        audio.write_register(0x5011, 0xFF);
+        assert!((audio.get_output() - 0.4).abs() < 0.001);
+        audio.write_register(0x5011, 0);
+        assert!((audio.get_output() - 0.4).abs() < 0.001);
+
+        // read mode with interrupts enabled
+        audio.write_register(0x5010, 0x81);
+        audio.notify_read(0x80);
+        assert!((audio.get_output() - 0.2).abs() < 0.01);
+        audio.notify_read(0);
+        assert!(audio.get_irq());
+        assert_eq!(audio.read_register(0x5010), 0x80);
+        assert!(!audio.get_irq());
+    }
+}
@@ -0,0 +1,301 @@
+use crate::mapper::{Mapper, create_chr};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Multicart boards, which pack many small games into one cartridge and switch between them with
+/// NROM or UxROM like banking.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MulticartBoard {
+    /// K-1029, mapper 15: 100-in-1 Contra Function 16.
+    K1029,
+    /// Mapper 58: various "Study & Game" carts.
+    Bmc58,
+    /// Mapper 225: 52 Games and 64-in-1.
+    Bmc225,
+    /// Mapper 228: Action 52 and Cheetahmen II.
+    Action52,
+}
+
+/// Multicart reference: https://www.nesdev.org/wiki/Category:Multicart_mappers
+///
+/// Mappers 15, 58, 225 and 228. Except for the K-1029, these latch the bank numbers from the
+/// address of writes to $8000-$FFFF rather than the written value. Some also come with four 4-bit
+/// registers of RAM, which their menus use to remember the selected game across resets.
+pub struct Multicart {
+    board: MulticartBoard,
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// 8 KiB PRG banks at $8000, $A000, $C000 and $E000.
+    prg_banks: [usize; 4],
+    chr_bank: usize,
+    chr_write_protected: bool,
+    mirroring: Mirroring,
+    nibble_ram: [u8; 4],
+}
+
+impl Multicart {
+    pub fn new(rom: Rom, board: MulticartBoard) -> Multicart {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut out: Multicart = Multicart {
+            board,
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_banks: [0; 4],
+            chr_bank: 0,
+            chr_write_protected: false,
+            nibble_ram: [0; 4],
+        };
+        out.cpu_write(0x8000, 0);
+        out
+    }
+
+    /// Maps the same 16 KiB bank to $8000 and $C000.
+    fn set_prg_16k(&mut self, bank: usize) {
+        self.prg_banks = [bank * 2, bank * 2 + 1, bank * 2, bank * 2 + 1];
+    }
+
+    /// Maps a 32 KiB bank to $8000-$FFFF.
+    fn set_prg_32k(&mut self, bank: usize) {
+        self.prg_banks = [bank * 4, bank * 4 + 1, bank * 4 + 2, bank * 4 + 3];
+    }
+
+    fn set_mirroring(&mut self, horizontal: bool) {
+        self.mirroring = if horizontal {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+    }
+
+    /// The K-1029 latches the written value. Bit layout: <pre>
+    /// 1 << 7    => lowest bit of the 8 KiB bank in NROM-64 mode
+    /// 1 << 6    => horizontal mirroring
+    /// 0b11_1111 => 16 KiB bank
+    /// </pre>
+    /// The mode is selected by the lowest two bits of the address.
+    fn write_k1029(&mut self, addr: u16, value: u8) {
+        let bank: usize = (value & 0x3F) as usize;
+        match addr & 0b11 {
+            // NROM-256
+            0 => self.set_prg_32k(bank >> 1),
+            // UNROM, with the last bank of the 128 KiB block fixed
+            1 => {
+                self.prg_banks = [bank * 2, bank * 2 + 1, (bank | 7) * 2, (bank | 7) * 2 + 1];
+            }
+            // NROM-64
+            2 => self.prg_banks = [bank << 1 | (value >> 7) as usize; 4],
+            // NROM-128
+            _ => self.set_prg_16k(bank),
+        }
+        self.chr_write_protected = addr & 0b11 == 0 || addr & 0b11 == 3;
+        self.set_mirroring((value >> 6) & 1 == 1);
+    }
+
+    /// Address layout: <pre>
+    /// 1 << 7    => horizontal mirroring
+    /// 1 << 6    => 16 KiB PRG mode, rather than 32 KiB
+    /// 0b111 << 3 => CHR bank
+    /// 0b111     => PRG bank
+    /// </pre>
+    fn write_bmc58(&mut self, addr: u16) {
+        let bank: usize = (addr & 0b111) as usize;
+        if (addr >> 6) & 1 == 1 {
+            self.set_prg_16k(bank);
+        } else {
+            self.set_prg_32k(bank >> 1);
+        }
+        self.chr_bank = ((addr >> 3) & 0b111) as usize;
+        self.set_mirroring((addr >> 7) & 1 == 1);
+    }
+
+    /// Address layout: <pre>
+    /// 1 << 14       => upper bit of the PRG and CHR banks
+    /// 1 << 13       => horizontal mirroring
+    /// 1 << 12       => 16 KiB PRG mode, rather than 32 KiB
+    /// 0b11_1111 << 6 => PRG bank
+    /// 0b11_1111     => CHR bank
+    /// </pre>
+    fn write_bmc225(&mut self, addr: u16) {
+        let high: usize = ((addr >> 14) & 1) as usize;
+        let bank: usize = high << 6 | ((addr >> 6) & 0x3F) as usize;
+        if (addr >> 12) & 1 == 1 {
+            self.set_prg_16k(bank);
+        } else {
+            self.set_prg_32k(bank >> 1);
+        }
+        self.chr_bank = high << 6 | (addr & 0x3F) as usize;
+        self.set_mirroring((addr >> 13) & 1 == 1);
+    }
+
+    /// Address layout: <pre>
+    /// 1 << 13        => horizontal mirroring
+    /// 0b11 << 11     => PRG chip; there is no chip 2, chip 3 is the third one
+    /// 0b1_1111 << 6  => 16 KiB PRG bank within the chip
+    /// 1 << 5         => 16 KiB PRG mode, rather than 32 KiB
+    /// 0b1111         => upper bits of the CHR bank
+    /// </pre>
+    /// The lower two bits of the CHR bank come from the written value.
+    fn write_action52(&mut self, addr: u16, value: u8) {
+        let chip: usize = match (addr >> 11) & 0b11 {
+            3 => 2,
+            chip => chip as usize,
+        };
+        let bank: usize = chip * 32 + ((addr >> 6) & 0x1F) as usize;
+        if (addr >> 5) & 1 == 1 {
+            self.set_prg_16k(bank);
+        } else {
+            self.set_prg_32k(bank >> 1);
+        }
+        self.chr_bank = ((addr & 0x0F) << 2) as usize | (value & 0b11) as usize;
+        self.set_mirroring((addr >> 13) & 1 == 1);
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        (self.chr_bank * 0x2000 + addr as usize) % self.chr.len()
+    }
+
+    /// The address range of the 4-bit RAM registers, if the board has them.
+    fn get_nibble_ram_range(&self) -> Option<std::ops::RangeInclusive<u16>> {
+        match self.board {
+            MulticartBoard::Bmc225 => Some(0x5800..=0x5FFF),
+            MulticartBoard::Action52 => Some(0x4020..=0x5FFF),
+            _ => None,
+        }
+    }
+}
+
+impl Mapper for Multicart {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            _ if self
+                .get_nibble_ram_range()
+                .is_some_and(|range| range.contains(&addr)) =>
+            {
+                self.nibble_ram[(addr & 0b11) as usize]
+            }
+            0x8000..=0xFFFF => {
+                let bank: usize = self.prg_banks[((addr - 0x8000) >> 13) as usize];
+                self.prg_rom[(bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if self
+            .get_nibble_ram_range()
+            .is_some_and(|range| range.contains(&addr))
+        {
+            self.nibble_ram[(addr & 0b11) as usize] = value & 0x0F;
+            return;
+        }
+        if addr < 0x8000 {
+            return;
+        }
+        match self.board {
+            MulticartBoard::K1029 => self.write_k1029(addr, value),
+            MulticartBoard::Bmc58 => self.write_bmc58(addr),
+            MulticartBoard::Bmc225 => self.write_bmc225(addr),
+            MulticartBoard::Action52 => self.write_action52(addr, value),
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram && !self.chr_write_protected {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Serialize for Multicart {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.chr_bank);
+        s.value(&mut self.chr_write_protected);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.nibble_ram);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    fn get_banks(multicart: &Multicart) -> Vec<u8> {
+        (0..4)
+            .map(|i| multicart.cpu_read(0x8000 + i * 0x2000) / 8)
+            .collect()
+    }
+
+    #[test]
+    fn test_k1029() {
+        let mut multicart: Multicart = Multicart::new(create_rom(15, 16, 0), MulticartBoard::K1029);
+        multicart.cpu_write(0x8000, 0x03);
+        assert_eq!(get_banks(&multicart), vec![4, 5, 6, 7]);
+        multicart.ppu_write(0, 0x42);
+        assert_eq!(multicart.ppu_read(0), 0);
+
+        multicart.cpu_write(0x8001, 0x42);
+        assert_eq!(get_banks(&multicart), vec![4, 5, 14, 15]);
+        assert_eq!(multicart.get_mirroring(), Mirroring::Horizontal);
+        multicart.ppu_write(0, 0x42);
+        assert_eq!(multicart.ppu_read(0), 0x42);
+
+        multicart.cpu_write(0x8002, 0x83);
+        assert_eq!(get_banks(&multicart), vec![7, 7, 7, 7]);
+        multicart.cpu_write(0x8003, 0x03);
+        assert_eq!(get_banks(&multicart), vec![6, 7, 6, 7]);
+    }
+
+    #[test]
+    fn test_bmc58() {
+        let mut multicart: Multicart = Multicart::new(create_rom(58, 8, 8), MulticartBoard::Bmc58);
+        multicart.cpu_write(0x80D3, 0);
+        assert_eq!(get_banks(&multicart), vec![6, 7, 6, 7]);
+        assert_eq!(multicart.ppu_read(0), 2 * 8);
+        assert_eq!(multicart.get_mirroring(), Mirroring::Horizontal);
+        multicart.cpu_write(0x8003, 0);
+        assert_eq!(get_banks(&multicart), vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_bmc225() {
+        let mut multicart: Multicart =
+            Multicart::new(create_rom(225, 8, 8), MulticartBoard::Bmc225);
+        multicart.cpu_write(0x8000 | 1 << 12 | 3 << 6 | 5, 0);
+        assert_eq!(get_banks(&multicart), vec![6, 7, 6, 7]);
+        assert_eq!(multicart.ppu_read(0), 5 * 8);
+        multicart.cpu_write(0x5800, 0xFF);
+        assert_eq!(multicart.cpu_read(0x5800), 0x0F);
+    }
+
+    #[test]
+    fn test_action52() {
+        let mut multicart: Multicart =
+            Multicart::new(create_rom(228, 8, 8), MulticartBoard::Action52);
+        multicart.cpu_write(0x8000 | 1 << 5 | 3 << 6 | 1, 2);
+        assert_eq!(get_banks(&multicart), vec![6, 7, 6, 7]);
+        assert_eq!(multicart.ppu_read(0), 6 * 8);
+        multicart.cpu_write(0xA000, 0);
+        assert_eq!(multicart.get_mirroring(), Mirroring::Horizontal);
+        assert_eq!(get_banks(&multicart), vec![0, 1, 2, 3]);
+        multicart.cpu_write(0x4020, 0x15);
+        assert_eq!(multicart.cpu_read(0x4020), 5);
+    }
+}
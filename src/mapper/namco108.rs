@@ -0,0 +1,132 @@
+use crate::mapper::{Mapper, create_chr};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Namco 108 reference: https://www.nesdev.org/wiki/INES_Mapper_206
+///
+/// Mapper 206, also known as DxROM, the predecessor of the MMC3. It has the MMC3's bank registers,
+/// but neither its PRG or CHR modes, nor mirroring control, PRG-RAM or an interrupt counter.
+pub struct Namco108 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    bank_select: u8,
+    /// R0 and R1 switch 2 KiB CHR banks, R2-R5 1 KiB CHR banks and R6 and R7 8 KiB PRG banks.
+    registers: [u8; 8],
+}
+
+impl Namco108 {
+    pub fn new(rom: Rom) -> Namco108 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        Namco108 {
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let bank_count: usize = self.prg_rom.len() / 0x2000;
+        let bank: usize = match (addr >> 13) & 0b11 {
+            0 => self.registers[6] as usize,
+            1 => self.registers[7] as usize,
+            2 => bank_count.saturating_sub(2),
+            _ => bank_count - 1,
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let slot: u16 = addr >> 10;
+        let bank: usize = match slot {
+            0 | 1 => (self.registers[0] & !1) as usize | slot as usize,
+            2 | 3 => (self.registers[1] & !1) as usize | (slot - 2) as usize,
+            _ => self.registers[slot as usize - 2] as usize,
+        };
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Namco108 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF if addr & 1 == 0 => self.bank_select = value & 0b111,
+            0x8000..=0x9FFF => {
+                let mask: u8 = if self.bank_select >= 6 { 0x0F } else { 0x3F };
+                self.registers[self.bank_select as usize] = value & mask;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Serialize for Namco108 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.value(&mut self.bank_select);
+        s.value(&mut self.registers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_banks() {
+        let mut namco108: Namco108 = Namco108::new(create_rom(206, 8, 8));
+        for (register, bank) in [
+            (0, 9),
+            (1, 20),
+            (2, 33),
+            (3, 34),
+            (4, 35),
+            (5, 36),
+            (6, 3),
+            (7, 5),
+        ] {
+            namco108.cpu_write(0x8000, register);
+            namco108.cpu_write(0x8001, bank);
+        }
+        let banks: Vec<u8> = (0..8).map(|i| namco108.ppu_read(i * 0x400)).collect();
+        assert_eq!(banks, vec![8, 9, 20, 21, 33, 34, 35, 36]);
+        assert_eq!(namco108.cpu_read(0x8000), 3 * 8);
+        assert_eq!(namco108.cpu_read(0xA000), 5 * 8);
+        assert_eq!(namco108.cpu_read(0xC000), 14 * 8);
+        assert_eq!(namco108.cpu_read(0xE000), 15 * 8);
+
+        // the modes of the MMC3 don't exist
+        namco108.cpu_write(0x8000, 0xC0);
+        assert_eq!(namco108.cpu_read(0x8000), 3 * 8);
+        assert_eq!(namco108.ppu_read(0), 8);
+    }
+}
@@ -0,0 +1,292 @@
+pub mod audio;
+
+use crate::mapper::namco163::audio::Namco163Audio;
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Namco 163 reference: https://www.nesdev.org/wiki/Namco_163
+///
+/// Mapper 19. Switches 8 KiB PRG banks and 1 KiB CHR banks, maps either CHR-ROM or the console's
+/// nametable memory into each nametable, and comes with a cycle based interrupt counter and
+/// wavetable audio.
+///
+/// CHR banks $E0-$FF are supposed to map the console's nametable memory into the pattern tables
+/// too, which isn't supported as no game relies on it; they select CHR-ROM banks like the others.
+/// Likewise, only the PRG-RAM gets saved on cartridges with a battery, not the audio chip's
+/// internal RAM.
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    /// Banks $E0-$FF select a page of the console's nametable memory, by the lowest bit, the
+    /// others a CHR-ROM bank.
+    nametable_banks: [u8; 4],
+    mirroring: Mirroring,
+    /// $F800. Bit layout: <pre>
+    /// 0b1111 << 4 => has to be 0b0100 to allow any writes
+    /// 0b1111      => protects a 2 KiB part of PRG-RAM each
+    /// </pre>
+    prg_ram_protect: u8,
+
+    /// 15-bit counter, which counts up to $7FFF.
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_flag: bool,
+    pub audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(rom: Rom) -> Namco163 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Namco163 {
+            battery: rom.header.battery,
+            nametable_banks: match rom.header.mirroring {
+                Mirroring::Horizontal => [0xE0, 0xE0, 0xE1, 0xE1],
+                _ => [0xE0, 0xE1, 0xE0, 0xE1],
+            },
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            prg_ram_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_flag: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let slot: usize = ((addr >> 13) & 0b11) as usize;
+        let bank: usize = if slot == 3 {
+            self.prg_rom.len() / 0x2000 - 1
+        } else {
+            self.prg_banks[slot] as usize
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, bank: u8, addr: u16) -> usize {
+        (bank as usize * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+
+    fn is_prg_ram_writable(&self, addr: u16) -> bool {
+        let part: u16 = (addr - 0x6000) / 0x800;
+        self.prg_ram_protect >> 4 == 0b0100 && (self.prg_ram_protect >> part) & 1 == 0
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.audio.read_data(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()],
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(value),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_flag = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((value & 0x7F) as u16) << 8;
+                self.irq_enabled = (value >> 7) & 1 == 1;
+                self.irq_flag = false;
+            }
+            0x6000..=0x7FFF if self.is_prg_ram_writable(addr) => {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = value,
+            0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) >> 11) as usize] = value,
+            // bit 6 silences the audio
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                self.audio.set_disabled((value >> 6) & 1 == 1);
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = value & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            0xF800..=0xFFFF => {
+                self.prg_ram_protect = value;
+                self.audio.write_address(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(self.chr_banks[(addr >> 10) as usize & 0b111], addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize =
+                self.get_chr_addr(self.chr_banks[(addr >> 10) as usize & 0b111], addr);
+            self.chr[index] = value;
+        }
+    }
+
+    /// Only the header's mirroring, as the nametables get mapped by [Mapper::read_nametable].
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn read_nametable(&self, addr: u16, ciram: &[u8]) -> u8 {
+        let bank: u8 = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        if bank >= 0xE0 {
+            ciram[(bank & 1) as usize * 0x400 + (addr & 0x3FF) as usize]
+        } else {
+            self.chr[self.get_chr_addr(bank, addr)]
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, ciram: &mut [u8]) {
+        let bank: u8 = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        if bank >= 0xE0 {
+            ciram[(bank & 1) as usize * 0x400 + (addr & 0x3FF) as usize] = value;
+        } else if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(bank, addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn clock_cpu(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_flag = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.audio.get_output()
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Namco163 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.nametable_banks);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.prg_ram_protect);
+        s.value(&mut self.irq_counter);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_flag);
+        s.value(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_banks() {
+        let mut n163: Namco163 = Namco163::new(create_rom(19, 8, 16));
+        n163.cpu_write(0xE000, 3);
+        n163.cpu_write(0xE800, 4);
+        n163.cpu_write(0xF000, 5);
+        assert_eq!(n163.cpu_read(0x8000), 3 * 8);
+        assert_eq!(n163.cpu_read(0xA000), 4 * 8);
+        assert_eq!(n163.cpu_read(0xC000), 5 * 8);
+        assert_eq!(n163.cpu_read(0xE000), 15 * 8);
+
+        for i in 0..8 {
+            n163.cpu_write(0x8000 + i * 0x800, 40 + i as u8);
+        }
+        let banks: Vec<u8> = (0..8).map(|i| n163.ppu_read(i * 0x400)).collect();
+        assert_eq!(banks, vec![40, 41, 42, 43, 44, 45, 46, 47]);
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut n163: Namco163 = Namco163::new(create_rom(19, 8, 16));
+        let mut ciram: Vec<u8> = vec![0; 0x800];
+        n163.cpu_write(0xC000, 0xE1);
+        n163.cpu_write(0xC800, 100);
+        n163.write_nametable(0x2005, 0x42, &mut ciram);
+        assert_eq!(ciram[0x405], 0x42);
+        assert_eq!(n163.read_nametable(0x2005, &ciram), 0x42);
+        // CHR-ROM as nametable
+        assert_eq!(n163.read_nametable(0x2405, &ciram), 100);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut n163: Namco163 = Namco163::new(create_rom(19, 8, 16));
+        n163.cpu_write(0x6000, 0x42);
+        assert_eq!(n163.cpu_read(0x6000), 0);
+        n163.cpu_write(0xF800, 0x41);
+        n163.cpu_write(0x6000, 0x42);
+        n163.cpu_write(0x6800, 0x43);
+        assert_eq!(n163.cpu_read(0x6000), 0);
+        assert_eq!(n163.cpu_read(0x6800), 0x43);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut n163: Namco163 = Namco163::new(create_rom(19, 8, 16));
+        n163.cpu_write(0x5000, 0xFD);
+        n163.cpu_write(0x5800, 0xFF);
+        assert_eq!(n163.cpu_read(0x5800), 0xFF);
+        n163.clock_cpu();
+        assert!(!n163.get_irq());
+        n163.clock_cpu();
+        assert!(n163.get_irq());
+        // the counter stops at $7FFF
+        n163.clock_cpu();
+        assert_eq!(n163.cpu_read(0x5000), 0xFF);
+        n163.cpu_write(0x5000, 0);
+        assert!(!n163.get_irq());
+    }
+
+    #[test]
+    fn test_audio_ports() {
+        let mut n163: Namco163 = Namco163::new(create_rom(19, 8, 16));
+        n163.cpu_write(0xF800, 0x80);
+        n163.cpu_write(0x4800, 1);
+        n163.cpu_write(0x4800, 2);
+        n163.cpu_write(0xF800, 0x81);
+        assert_eq!(n163.cpu_read(0x4800), 2);
+    }
+}
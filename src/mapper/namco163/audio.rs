@@ -0,0 +1,226 @@
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+/// Number of CPU cycles it takes to update a single channel.
+const CYCLES_PER_CHANNEL: u8 = 15;
+/// Output level of a single step of a channel's sample times its volume. A single channel at full
+/// volume is about as loud as one of the APU's pulses.
+const STEP_LEVEL: f32 = 0.00125;
+
+/// Namco 163 audio reference: https://www.nesdev.org/wiki/Namco_163_audio
+///
+/// Up to 8 wavetable channels, playing 4-bit samples from 128 bytes of internal RAM, which also
+/// holds the channels' registers. The chip updates one channel at a time and outputs it until the
+/// next one is done, so the more channels are enabled, the lower their update rate. The channels
+/// get averaged here, rather than emulating the audible multiplexing.
+///
+/// Registers of channel n, at $40 + 8 * n: <pre>
+/// +0 => bits 0-7 of the frequency
+/// +1 => bits 0-7 of the phase
+/// +2 => bits 8-15 of the frequency
+/// +3 => bits 8-15 of the phase
+/// +4 => 0b11_1111 << 2: 64 - wave length / 4, 0b11: bits 16-17 of the frequency
+/// +5 => bits 16-23 of the phase
+/// +6 => address of the wave, in 4-bit samples
+/// +7 => 0b1111: volume; for channel 7, 0b111 << 4: number of enabled channels - 1
+/// </pre>
+pub struct Namco163Audio {
+    ram: [u8; 0x80],
+    /// Bit layout: <pre>
+    /// 1 << 7  => increment after every access
+    /// 0b1111111 => address
+    /// </pre>
+    address: Cell<u8>,
+    /// The channel being updated; counts down from 7, as the enabled channels are the highest
+    /// ones.
+    channel: u8,
+    cycle: u8,
+    /// Latest output of every channel, between -8 and 7 times the volume.
+    outputs: [i8; 8],
+    disabled: bool,
+}
+
+impl Default for Namco163Audio {
+    fn default() -> Namco163Audio {
+        Namco163Audio::new()
+    }
+}
+
+impl Namco163Audio {
+    pub fn new() -> Namco163Audio {
+        Namco163Audio {
+            ram: [0; 0x80],
+            address: Cell::new(0),
+            channel: 7,
+            cycle: 0,
+            outputs: [0; 8],
+            disabled: false,
+        }
+    }
+
+    /// Handles writes to the address port at $F800-$FFFF.
+    pub fn write_address(&mut self, value: u8) {
+        self.address.set(value);
+    }
+
+    /// Handles reads of the data port at $4800-$4FFF.
+    pub fn read_data(&self) -> u8 {
+        let value: u8 = self.ram[(self.address.get() & 0x7F) as usize];
+        self.increment_address();
+        value
+    }
+
+    /// Handles writes to the data port at $4800-$4FFF.
+    pub fn write_data(&mut self, value: u8) {
+        self.ram[(self.address.get() & 0x7F) as usize] = value;
+        self.increment_address();
+    }
+
+    fn increment_address(&self) {
+        let address: u8 = self.address.get();
+        if (address >> 7) & 1 == 1 {
+            self.address.set(0x80 | (address.wrapping_add(1) & 0x7F));
+        }
+    }
+
+    /// Silences the chip, as done through bit 6 of $E000.
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    fn get_enabled_channels(&self) -> u8 {
+        ((self.ram[0x7F] >> 4) & 0b111) + 1
+    }
+
+    /// Advances the chip by a single CPU cycle.
+    pub fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.cycle += 1;
+        if self.cycle < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.cycle = 0;
+        self.update_channel(self.channel);
+        self.channel = if self.channel <= 8 - self.get_enabled_channels() {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let base: usize = 0x40 + channel as usize * 8;
+        let registers: &mut [u8] = &mut self.ram[base..base + 8];
+        let frequency: u32 =
+            registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 0b11) as u32) << 16;
+        let length: u32 = 256 - (registers[4] & 0xFC) as u32;
+        let mut phase: u32 =
+            registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        phase = (phase + frequency) % (length << 16);
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let sample_address: u8 = registers[6].wrapping_add((phase >> 16) as u8);
+        let volume: i8 = (registers[7] & 0x0F) as i8;
+        let byte: u8 = self.ram[(sample_address >> 1) as usize];
+        let sample: u8 = if sample_address & 1 == 0 {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        self.outputs[channel as usize] = (sample as i8 - 8) * volume;
+    }
+
+    pub fn get_output(&self) -> f32 {
+        if self.disabled {
+            return 0.0;
+        }
+        let enabled: u8 = self.get_enabled_channels();
+        let sum: i32 = self.outputs[(8 - enabled) as usize..]
+            .iter()
+            .map(|output| *output as i32)
+            .sum();
+        sum as f32 / enabled as f32 * STEP_LEVEL
+    }
+}
+
+impl Serialize for Namco163Audio {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.bytes(&mut self.ram);
+        s.value(&mut self.address);
+        s.value(&mut self.channel);
+        s.value(&mut self.cycle);
+        s.value(&mut self.outputs);
+        s.value(&mut self.disabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_port() {
+        let mut audio: Namco163Audio = Namco163Audio::new();
+        audio.write_address(0xFE);
+        audio.write_data(1);
+        audio.write_data(2);
+        audio.write_data(3);
+        assert_eq!(audio.ram[0x7E], 1);
+        assert_eq!(audio.ram[0x7F], 2);
+        assert_eq!(audio.ram[0x00], 3);
+
+        // without auto increment
+        audio.write_address(0x7E);
+        assert_eq!(audio.read_data(), 1);
+        assert_eq!(audio.read_data(), 1);
+    }
+
+    #[test]
+    fn test_channel() {
+        let mut audio: Namco163Audio = Namco163Audio::new();
+        // a wave of 4 samples, 0, 15, 15, 0, at address 0
+        audio.write_address(0x80);
+        audio.write_data(0xF0);
+        audio.write_data(0x0F);
+        // channel 7 alone at full volume
+        audio.write_address(0xF8);
+        for value in [0x00, 0x00, 0x00, 0x00, 0xFC, 0x00, 0x00, 0x0F] {
+            audio.write_data(value);
+        }
+        // stepping one sample per update
+        audio.ram[0x7C] = 0xFC | 1;
+
+        let mut outputs: Vec<i8> = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..CYCLES_PER_CHANNEL {
+                audio.clock();
+            }
+            outputs.push(audio.outputs[7]);
+        }
+        // the phase advances before the sample gets read
+        assert_eq!(outputs, vec![7 * 15, 7 * 15, -8 * 15, -8 * 15]);
+        assert!(audio.get_output() < 0.0);
+
+        audio.set_disabled(true);
+        assert_eq!(audio.get_output(), 0.0);
+    }
+
+    #[test]
+    fn test_channel_count() {
+        let mut audio: Namco163Audio = Namco163Audio::new();
+        // channels 4-7
+        audio.ram[0x7F] = 0x30;
+        let mut updated: Vec<u8> = Vec::new();
+        for _ in 0..6 {
+            updated.push(audio.channel);
+            for _ in 0..CYCLES_PER_CHANNEL {
+                audio.clock();
+            }
+        }
+        assert_eq!(updated, vec![7, 6, 5, 4, 7, 6]);
+    }
+}
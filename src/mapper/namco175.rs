@@ -0,0 +1,188 @@
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Namco 175 and 340 reference: https://www.nesdev.org/wiki/INES_Mapper_210
+///
+/// Mapper 210, the Namco 163's siblings without audio or interrupt counter. Switches 8 KiB PRG
+/// and 1 KiB CHR banks through the same registers. The 175 (submapper 1) has hardwired mirroring
+/// and PRG-RAM, while the 340 (submapper 2) has mirroring control instead. Without a submapper,
+/// boards with a battery are taken to be a 175.
+pub struct Namco175 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    namco340: bool,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+}
+
+impl Namco175 {
+    pub fn new(rom: Rom) -> Namco175 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let namco340: bool = match rom.header.submapper {
+            1 => false,
+            2 => true,
+            _ => !rom.header.battery,
+        };
+        Namco175 {
+            battery: rom.header.battery,
+            prg_ram: if namco340 {
+                Vec::new()
+            } else {
+                create_prg_ram(&rom)
+            },
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            namco340,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let slot: usize = ((addr >> 13) & 0b11) as usize;
+        let bank: usize = if slot == 3 {
+            self.prg_rom.len() / 0x2000 - 1
+        } else {
+            self.prg_banks[slot] as usize
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = self.chr_banks[(addr >> 10) as usize & 0b111] as usize;
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Namco175 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = value,
+            0xC000..=0xC7FF if !self.namco340 => self.prg_ram_enabled = value & 1 == 1,
+            // bits 6-7 control the mirroring on the 340
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                if self.namco340 {
+                    self.mirroring = match value >> 6 {
+                        0 => Mirroring::SingleScreenLower,
+                        1 => Mirroring::Vertical,
+                        2 => Mirroring::Horizontal,
+                        _ => Mirroring::SingleScreenUpper,
+                    };
+                }
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = value & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Namco175 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.prg_ram_enabled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    fn create(submapper: u8) -> Namco175 {
+        let mut rom: Rom = create_rom(210, 8, 16);
+        rom.header.submapper = submapper;
+        Namco175::new(rom)
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut namco: Namco175 = create(1);
+        namco.cpu_write(0xE000, 3);
+        namco.cpu_write(0xE800, 4);
+        namco.cpu_write(0xF000, 5);
+        assert_eq!(namco.cpu_read(0x8000), 3 * 8);
+        assert_eq!(namco.cpu_read(0xA000), 4 * 8);
+        assert_eq!(namco.cpu_read(0xC000), 5 * 8);
+        assert_eq!(namco.cpu_read(0xE000), 15 * 8);
+        namco.cpu_write(0xB800, 42);
+        assert_eq!(namco.ppu_read(0x1C00), 42);
+    }
+
+    #[test]
+    fn test_namco175() {
+        let mut namco: Namco175 = create(1);
+        namco.cpu_write(0xE000, 0xC0);
+        assert_eq!(namco.get_mirroring(), Mirroring::Horizontal);
+        namco.cpu_write(0x6000, 0x42);
+        assert_eq!(namco.cpu_read(0x6000), 0);
+        namco.cpu_write(0xC000, 1);
+        namco.cpu_write(0x6000, 0x42);
+        assert_eq!(namco.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_namco340() {
+        let mut namco: Namco175 = create(2);
+        namco.cpu_write(0xE000, 0x40);
+        assert_eq!(namco.get_mirroring(), Mirroring::Vertical);
+        namco.cpu_write(0xE000, 0xC0);
+        assert_eq!(namco.get_mirroring(), Mirroring::SingleScreenUpper);
+        namco.cpu_write(0xC000, 1);
+        namco.cpu_write(0x6000, 0x42);
+        assert_eq!(namco.cpu_read(0x6000), 0);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// NINA-001 reference: https://www.nesdev.org/wiki/NINA-001
+///
+/// Mapper 34, submapper 1, used by Impossible Mission II. Its registers are at the end of the
+/// PRG-RAM at $6000-$7FFF, which still gets written too: $7FFD switches 32 KiB of PRG, $7FFE and
+/// $7FFF 4 KiB of CHR at $0000 and $1000.
+pub struct Nina001 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+    prg_bank: u8,
+    chr_banks: [u8; 2],
+}
+
+impl Nina001 {
+    pub fn new(rom: Rom) -> Nina001 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Nina001 {
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = self.chr_banks[(addr >> 12) as usize & 1] as usize;
+        (bank * 0x1000 + (addr & 0x0FFF) as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Nina001 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()],
+            0x8000..=0xFFFF => {
+                let offset: usize = self.prg_bank as usize * 0x8000 + (addr - 0x8000) as usize;
+                self.prg_rom[offset % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if !(0x6000..=0x7FFF).contains(&addr) {
+            return;
+        }
+        let len: usize = self.prg_ram.len();
+        self.prg_ram[(addr - 0x6000) as usize % len] = value;
+        match addr {
+            0x7FFD => self.prg_bank = value & 1,
+            0x7FFE => self.chr_banks[0] = value & 0x0F,
+            0x7FFF => self.chr_banks[1] = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Serialize for Nina001 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_bank);
+        s.value(&mut self.chr_banks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_banks() {
+        let mut nina: Nina001 = Nina001::new(create_rom(34, 4, 8));
+        assert_eq!(nina.ppu_read(0x1000), 4);
+        nina.cpu_write(0x7FFD, 1);
+        nina.cpu_write(0x7FFE, 5);
+        nina.cpu_write(0x7FFF, 2);
+        assert_eq!(nina.cpu_read(0x8000), 32);
+        assert_eq!(nina.ppu_read(0x0000), 20);
+        assert_eq!(nina.ppu_read(0x1000), 8);
+        // the registers are backed by RAM
+        assert_eq!(nina.cpu_read(0x7FFE), 5);
+    }
+}
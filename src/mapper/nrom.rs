@@ -0,0 +1,130 @@
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// NROM reference: https://www.nesdev.org/wiki/NROM
+///
+/// Mapper 0, without any bank switching. NROM-128 boards come with 16 KiB of PRG-ROM, which is
+/// mirrored into both halves of $8000-$FFFF, NROM-256 boards with 32 KiB.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    /// Only present on Family BASIC carts, at $6000-$7FFF.
+    prg_ram: Vec<u8>,
+    battery: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Nrom {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        Nrom {
+            battery: rom.header.battery,
+            prg_ram: create_prg_ram(&rom),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            mirroring: rom.header.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr
+            && !self.prg_ram.is_empty()
+        {
+            let len: usize = self.prg_ram.len();
+            self.prg_ram[(addr - 0x6000) as usize % len] = value;
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let len: usize = self.chr.len();
+            self.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Nrom {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_nrom_128() {
+        let nrom: Nrom = Nrom::new(create_rom(0, 1, 1));
+        assert_eq!(nrom.cpu_read(0x8000), 0);
+        assert_eq!(nrom.cpu_read(0xBFFF), 15);
+        // the 16 KiB get mirrored into $C000-$FFFF
+        assert_eq!(nrom.cpu_read(0xC000), 0);
+        assert_eq!(nrom.cpu_read(0xFFFF), 15);
+    }
+
+    #[test]
+    fn test_nrom_256() {
+        let nrom: Nrom = Nrom::new(create_rom(0, 2, 1));
+        assert_eq!(nrom.cpu_read(0xC000), 16);
+        assert_eq!(nrom.cpu_read(0xFFFF), 31);
+        assert_eq!(nrom.get_mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_chr() {
+        let mut nrom: Nrom = Nrom::new(create_rom(0, 1, 1));
+        assert_eq!(nrom.ppu_read(0x1C00), 7);
+        nrom.ppu_write(0x1C00, 0x42);
+        assert_eq!(nrom.ppu_read(0x1C00), 7);
+
+        let mut nrom: Nrom = Nrom::new(create_rom(0, 1, 0));
+        nrom.ppu_write(0x1C00, 0x42);
+        assert_eq!(nrom.ppu_read(0x1C00), 0x42);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut nrom: Nrom = Nrom::new(create_rom(0, 1, 1));
+        nrom.cpu_write(0x6123, 0x42);
+        assert_eq!(nrom.cpu_read(0x6123), 0x42);
+        // writes to ROM get ignored
+        nrom.cpu_write(0x8000, 0x42);
+        assert_eq!(nrom.cpu_read(0x8000), 0);
+    }
+}
@@ -0,0 +1,428 @@
+use crate::mapper::Mapper;
+use crate::mapper::fds::audio::FdsAudio;
+use crate::mapper::fme7::audio::Sunsoft5bAudio;
+use crate::mapper::mmc5::audio::Mmc5Audio;
+use crate::mapper::namco163::audio::Namco163Audio;
+use crate::mapper::vrc6::audio::Vrc6Audio;
+use crate::mapper::vrc7::opll::Opll;
+use crate::nsf::Nsf;
+use crate::region::Region;
+use crate::rom::Mirroring;
+use crate::state::{Serialize, Serializer};
+use std::cell::Cell;
+
+const DRIVER_ADDR: u16 = 0x4100;
+/// The player's driver program, which calls the init and play routines through trampolines
+/// jumping to the vectors at $4182 and $4186: <pre>
+/// $4100 LDX #$FF      ; reset the stack
+/// $4102 TXS
+/// $4103 LDA $4180     ; track
+/// $4106 LDX $4181     ; 0 on NTSC, 1 on PAL
+/// $4109 JSR $4118     ; init routine
+/// $410C LDA $4184     ; whether the play routine is due
+/// $410F BEQ $410C
+/// $4111 JSR $411B     ; play routine
+/// $4114 JMP $410C
+/// $4117 NOP
+/// $4118 JMP ($4182)
+/// $411B JMP ($4186)
+/// </pre>
+const DRIVER: [u8; 32] = [
+    0xA2, 0xFF, 0x9A, 0xAD, 0x80, 0x41, 0xAE, 0x81, 0x41, 0x20, 0x18, 0x41, 0xAD, 0x84, 0x41, 0xF0,
+    0xFB, 0x20, 0x1B, 0x41, 0x4C, 0x0C, 0x41, 0xEA, 0x6C, 0x82, 0x41, 0x6C, 0x86, 0x41, 0xEA, 0xEA,
+];
+
+/// The hardware an NSF plays on: 4 KiB PRG banks switched through $5FF8-$5FFF, 8 KiB of RAM at
+/// $6000-$7FFF, whichever expansion audio chips the file asks for, and the player's driver
+/// program along with a timer telling it when to call the play routine.
+///
+/// FDS rips get RAM at $6000-$FFFF instead, with bank switches copying the ROM into it, which
+/// includes $6000-$7FFF through $5FF6-$5FF7.
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    /// The banks mapped to $6000-$FFFF, the first two only being used by FDS rips.
+    banks: [u8; 10],
+    ram: Vec<u8>,
+    fds_ram: bool,
+
+    track: u8,
+    pal: bool,
+    init_addr: u16,
+    play_addr: u16,
+    /// CPU cycles between play routine calls.
+    play_period: u32,
+    play_timer: u32,
+    /// Set when the play routine is due. The driver acknowledges it by reading $4184.
+    play_pending: Cell<bool>,
+
+    multiplicand: u8,
+    multiplier: u8,
+    exram: Vec<u8>,
+    pub vrc6: Option<Vrc6Audio>,
+    pub vrc7: Option<Opll>,
+    pub fds: Option<FdsAudio>,
+    pub mmc5: Option<Mmc5Audio>,
+    pub namco163: Option<Namco163Audio>,
+    pub sunsoft5b: Option<Sunsoft5bAudio>,
+}
+
+impl NsfMapper {
+    /// Sets up the hardware for playing `track`, counting from 0.
+    pub fn new(nsf: &Nsf, track: u8, region: Region) -> NsfMapper {
+        let fds: bool = nsf.chips.fds;
+        // without bankswitching, the data gets loaded right at its address, otherwise at its
+        // offset within the first bank
+        let padding: usize = match nsf.banks {
+            Some(_) => (nsf.load_addr & 0x0FFF) as usize,
+            None if fds => (nsf.load_addr - 0x6000) as usize,
+            None => (nsf.load_addr - 0x8000) as usize,
+        };
+        let mut prg: Vec<u8> = vec![0; padding];
+        prg.extend_from_slice(&nsf.data);
+        prg.resize(prg.len().div_ceil(0x1000).max(1) * 0x1000, 0);
+
+        let banks: [u8; 10] = match nsf.banks {
+            Some(banks) => std::array::from_fn(|i| if i < 2 { banks[i + 6] } else { banks[i - 2] }),
+            None if fds => std::array::from_fn(|i| i as u8),
+            None => std::array::from_fn(|i| i.saturating_sub(2) as u8),
+        };
+        let speed: f64 = nsf.get_play_speed(region) as f64;
+
+        let mut out: NsfMapper = NsfMapper {
+            prg,
+            banks,
+            ram: vec![0; if fds { 0xA000 } else { 0x2000 }],
+            fds_ram: fds,
+            track,
+            pal: region != Region::Ntsc,
+            init_addr: nsf.init_addr,
+            play_addr: nsf.play_addr,
+            play_period: (speed * region.get_cpu_clock_rate() / 1_000_000.0).round() as u32,
+            play_timer: 0,
+            play_pending: Cell::new(false),
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            exram: vec![0; 0x400],
+            vrc6: nsf.chips.vrc6.then(Vrc6Audio::new),
+            vrc7: nsf.chips.vrc7.then(Opll::new),
+            fds: fds.then(FdsAudio::new),
+            mmc5: nsf.chips.mmc5.then(Mmc5Audio::new),
+            namco163: nsf.chips.namco163.then(Namco163Audio::new),
+            sunsoft5b: nsf.chips.sunsoft5b.then(Sunsoft5bAudio::new),
+        };
+        if fds {
+            for slot in 0..out.banks.len() {
+                out.load_ram_bank(slot);
+            }
+        }
+        out
+    }
+
+    fn get_prg_addr(&self, slot: usize, addr: u16) -> usize {
+        let bank: usize = self.banks[slot] as usize % (self.prg.len() / 0x1000);
+        bank * 0x1000 + (addr & 0x0FFF) as usize
+    }
+
+    /// Copies the bank selected for a slot into the RAM of FDS rips.
+    fn load_ram_bank(&mut self, slot: usize) {
+        let start: usize = self.get_prg_addr(slot, 0);
+        self.ram[slot * 0x1000..(slot + 1) * 0x1000]
+            .copy_from_slice(&self.prg[start..start + 0x1000]);
+    }
+
+    fn read_driver(&self, addr: u16) -> u8 {
+        match addr {
+            0x4180 => self.track,
+            0x4181 => self.pal as u8,
+            0x4182 => self.init_addr as u8,
+            0x4183 => (self.init_addr >> 8) as u8,
+            0x4184 => self.play_pending.replace(false) as u8,
+            0x4186 => self.play_addr as u8,
+            0x4187 => (self.play_addr >> 8) as u8,
+            _ => DRIVER
+                .get((addr - DRIVER_ADDR) as usize)
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Forwards writes to the audio chips' ports, which use the addresses of the boards they
+    /// come from.
+    fn write_audio(&mut self, addr: u16, value: u8) {
+        if let Some(audio) = &mut self.vrc6
+            && matches!(addr, 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002)
+        {
+            audio.write_register(addr, value);
+        }
+        if let Some(audio) = &mut self.vrc7 {
+            match addr {
+                0x9010 => audio.write_address(value),
+                0x9030 => audio.write_data(value),
+                _ => {}
+            }
+        }
+        if let Some(audio) = &mut self.fds
+            && (0x4040..=0x408A).contains(&addr)
+        {
+            audio.write_register(addr, value);
+        }
+        if let Some(audio) = &mut self.mmc5
+            && (0x5000..=0x5015).contains(&addr)
+        {
+            audio.write_register(addr, value);
+        }
+        if let Some(audio) = &mut self.namco163 {
+            match addr {
+                0x4800 => audio.write_data(value),
+                0xF800 => audio.write_address(value),
+                _ => {}
+            }
+        }
+        if let Some(audio) = &mut self.sunsoft5b {
+            match addr {
+                0xC000 => audio.write_address(value),
+                0xE000 => audio.write_data(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x4097 if self.fds.is_some() => self.fds.as_ref().unwrap().read_register(addr),
+            0x4100..=0x41FF => self.read_driver(addr),
+            0x4800 if self.namco163.is_some() => self.namco163.as_ref().unwrap().read_data(),
+            0x5010 | 0x5015 if self.mmc5.is_some() => {
+                self.mmc5.as_ref().unwrap().read_register(addr)
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FF5 => self.exram[addr as usize - 0x5C00],
+            // the reset vector points to the driver
+            0xFFFC => DRIVER_ADDR as u8,
+            0xFFFD => (DRIVER_ADDR >> 8) as u8,
+            0x6000..=0xFFFF if self.fds_ram => self.ram[addr as usize - 0x6000],
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => {
+                let slot: usize = (addr >> 12) as usize - 6;
+                self.prg[self.get_prg_addr(slot, addr)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        self.write_audio(addr, value);
+        match addr {
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FF5 => self.exram[addr as usize - 0x5C00] = value,
+            0x5FF6..=0x5FF7 if !self.fds_ram => {}
+            0x5FF6..=0x5FFF => {
+                let slot: usize = (addr - 0x5FF6) as usize;
+                self.banks[slot] = value;
+                if self.fds_ram {
+                    self.load_ram_bank(slot);
+                }
+            }
+            0x6000..=0xFFFF if self.fds_ram => self.ram[addr as usize - 0x6000] = value,
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _value: u8) {}
+
+    fn get_mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn clock_cpu(&mut self) {
+        self.play_timer += 1;
+        if self.play_timer >= self.play_period {
+            self.play_timer = 0;
+            self.play_pending.set(true);
+        }
+
+        if let Some(audio) = &mut self.vrc6 {
+            audio.clock();
+        }
+        if let Some(audio) = &mut self.vrc7 {
+            audio.clock();
+        }
+        if let Some(audio) = &mut self.fds {
+            audio.clock();
+        }
+        if let Some(audio) = &mut self.mmc5 {
+            audio.clock();
+        }
+        if let Some(audio) = &mut self.namco163 {
+            audio.clock();
+        }
+        if let Some(audio) = &mut self.sunsoft5b {
+            audio.clock();
+        }
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |audio| audio.get_output())
+            + self.vrc7.as_ref().map_or(0.0, |audio| audio.get_output())
+            + self.fds.as_ref().map_or(0.0, |audio| audio.get_output())
+            + self.mmc5.as_ref().map_or(0.0, |audio| audio.get_output())
+            + self
+                .namco163
+                .as_ref()
+                .map_or(0.0, |audio| audio.get_output())
+            + self
+                .sunsoft5b
+                .as_ref()
+                .map_or(0.0, |audio| audio.get_output())
+    }
+}
+
+impl Serialize for NsfMapper {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.banks);
+        s.bytes(&mut self.ram);
+        s.value(&mut self.play_timer);
+        s.value(&mut self.play_pending);
+        s.value(&mut self.multiplicand);
+        s.value(&mut self.multiplier);
+        s.bytes(&mut self.exram);
+        if let Some(chip) = &mut self.vrc6 {
+            s.value(chip);
+        }
+        if let Some(chip) = &mut self.vrc7 {
+            s.value(chip);
+        }
+        if let Some(chip) = &mut self.fds {
+            s.value(chip);
+        }
+        if let Some(chip) = &mut self.mmc5 {
+            s.value(chip);
+        }
+        if let Some(chip) = &mut self.namco163 {
+            s.value(chip);
+        }
+        if let Some(chip) = &mut self.sunsoft5b {
+            s.value(chip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsf::tests::create_nsf;
+
+    fn create_mapper(banks: [u8; 8], chips: u8) -> NsfMapper {
+        let mut bytes: Vec<u8> = create_nsf(banks, chips);
+        // 3 more banks, each filled with its number
+        bytes.resize(0x80 + 0x1000, 0);
+        for bank in 1..4 {
+            bytes.extend([bank; 0x1000]);
+        }
+        NsfMapper::new(&Nsf::from_bytes(&bytes).unwrap(), 2, Region::Ntsc)
+    }
+
+    #[test]
+    fn test_driver() {
+        let mapper: NsfMapper = create_mapper([0; 8], 0);
+        assert_eq!(mapper.cpu_read(0xFFFC), 0x00);
+        assert_eq!(mapper.cpu_read(0xFFFD), 0x41);
+        assert_eq!(mapper.cpu_read(0x4100), 0xA2);
+        assert_eq!(mapper.cpu_read(0x4180), 2);
+        assert_eq!(mapper.cpu_read(0x4181), 0);
+        assert_eq!(mapper.cpu_read(0x4182), 0x00);
+        assert_eq!(mapper.cpu_read(0x4183), 0x80);
+        assert_eq!(mapper.cpu_read(0x4186), 0x05);
+        assert_eq!(mapper.cpu_read(0x4187), 0x80);
+    }
+
+    #[test]
+    fn test_play_timer() {
+        let mut mapper: NsfMapper = create_mapper([0; 8], 0);
+        // 16666 microseconds at 1.789773 MHz
+        assert_eq!(mapper.play_period, 29828);
+        for _ in 0..29827 {
+            mapper.clock_cpu();
+        }
+        assert_eq!(mapper.cpu_read(0x4184), 0);
+        mapper.clock_cpu();
+        assert_eq!(mapper.cpu_read(0x4184), 1);
+        assert_eq!(mapper.cpu_read(0x4184), 0);
+    }
+
+    #[test]
+    fn test_banks() {
+        // not bankswitched, so loaded in order
+        let mut mapper: NsfMapper = create_mapper([0; 8], 0);
+        assert_eq!(mapper.cpu_read(0x8000), 0x85);
+        assert_eq!(mapper.cpu_read(0x9000), 1);
+        assert_eq!(mapper.cpu_read(0xB000), 3);
+        mapper.cpu_write(0x6000, 0x42);
+        assert_eq!(mapper.cpu_read(0x6000), 0x42);
+
+        let mut mapper: NsfMapper = create_mapper([3, 2, 1, 0, 0, 0, 0, 0], 0);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 1);
+        assert_eq!(mapper.cpu_read(0xB000), 0x85);
+        mapper.cpu_write(0x5FF8, 2);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        // no RAM there without the FDS
+        mapper.cpu_write(0x8000, 0x42);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+    }
+
+    #[test]
+    fn test_fds() {
+        let mut mapper: NsfMapper = create_mapper([3, 2, 1, 0, 0, 0, 1, 2], 0b100);
+        assert_eq!(mapper.cpu_read(0x6000), 1);
+        assert_eq!(mapper.cpu_read(0x7000), 2);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        mapper.cpu_write(0x8000, 0x42);
+        assert_eq!(mapper.cpu_read(0x8000), 0x42);
+        // switching banks reloads them
+        mapper.cpu_write(0x5FF6, 3);
+        assert_eq!(mapper.cpu_read(0x6000), 3);
+        mapper.cpu_write(0x5FF8, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+
+        mapper.cpu_write(0x4089, 0x80);
+        mapper.cpu_write(0x4040, 0x3F);
+        assert_eq!(mapper.cpu_read(0x4040), 0x7F);
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let mut mapper: NsfMapper = create_mapper([0; 8], 0b11_1111);
+        assert!(mapper.vrc6.is_some() && mapper.vrc7.is_some() && mapper.fds.is_some());
+        assert!(mapper.mmc5.is_some() && mapper.namco163.is_some());
+        // Sunsoft 5B channel A at full volume
+        mapper.cpu_write(0xC000, 7);
+        mapper.cpu_write(0xE000, 0xFF);
+        mapper.cpu_write(0xC000, 8);
+        mapper.cpu_write(0xE000, 0x0F);
+        assert!(mapper.get_audio_output() > 0.1);
+
+        mapper.cpu_write(0xF800, 0x80);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0xF800, 0);
+        assert_eq!(mapper.cpu_read(0x4800), 0x12);
+
+        mapper.cpu_write(0x5205, 3);
+        mapper.cpu_write(0x5206, 100);
+        assert_eq!(mapper.cpu_read(0x5205), 44);
+        assert_eq!(mapper.cpu_read(0x5206), 1);
+
+        let mapper: NsfMapper = create_mapper([0; 8], 0);
+        assert!(mapper.vrc6.is_none() && mapper.sunsoft5b.is_none());
+        assert_eq!(mapper.get_audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::mapper::{Mapper, Scanline, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// Number of CPU cycles per clock of the IRQ counter in cycle mode.
+const CYCLE_MODE_DIVIDER: u8 = 4;
+
+/// RAMBO-1 reference: https://www.nesdev.org/wiki/RAMBO-1
+///
+/// Mapper 64, Tengen's take on the MMC3. It adds a third switchable PRG bank, a mode with eight
+/// 1 KiB CHR banks, and an interrupt counter which can count CPU cycles rather than scanlines.
+/// Scanlines are counted through [Mapper::notify_scanline].
+pub struct Rambo1 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+
+    /// Bit layout: <pre>
+    /// 1 << 7 => CHR inversion; swaps $0000-$0FFF and $1000-$1FFF
+    /// 1 << 6 => PRG mode; rotates the switchable banks
+    /// 1 << 5 => 1 KiB CHR mode; R0 and R1 get split up, with R8 and R9
+    /// 0b1111 => register to update on the next write to $8001
+    /// </pre>
+    bank_select: u8,
+    /// R0-R9 and RF, at index 10.
+    registers: [u8; 11],
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_flag: bool,
+    cycle_mode: bool,
+    prescaler: u8,
+}
+
+impl Rambo1 {
+    pub fn new(rom: Rom) -> Rambo1 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        Rambo1 {
+            battery: rom.header.battery,
+            prg_ram: create_prg_ram(&rom),
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1, 1, 3, 2],
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_flag: false,
+            cycle_mode: false,
+            prescaler: 0,
+        }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let bank_count: usize = self.prg_rom.len() / 0x2000;
+        let rotated: bool = (self.bank_select >> 6) & 1 == 1;
+        let register: usize = match ((addr >> 13) & 0b11, rotated) {
+            (0, false) | (1, true) => 6,
+            (1, false) | (2, true) => 7,
+            (2, false) | (0, true) => 10,
+            _ => {
+                return ((bank_count - 1) * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len();
+            }
+        };
+        (self.registers[register] as usize * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let inverted: bool = (self.bank_select >> 7) & 1 == 1;
+        let split: bool = (self.bank_select >> 5) & 1 == 1;
+        let slot: u16 = if inverted { addr ^ 0x1000 } else { addr } >> 10;
+        let bank: usize = match slot {
+            0 | 2 if split => self.registers[(slot / 2) as usize] as usize,
+            1 | 3 if split => self.registers[8 + (slot / 2) as usize] as usize,
+            0 | 1 => (self.registers[0] & !1) as usize | slot as usize,
+            2 | 3 => (self.registers[1] & !1) as usize | (slot - 2) as usize,
+            _ => self.registers[slot as usize - 2] as usize,
+        };
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+
+    /// Unlike on the MMC3, a reload through $C001 sets the counter to one more than the latch.
+    fn clock_irq_counter(&mut self) {
+        if self.irq_reload {
+            self.irq_counter = self.irq_latch.wrapping_add(1);
+            self.irq_reload = false;
+        } else if self.irq_counter == 0 {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_flag = true;
+        }
+    }
+}
+
+impl Mapper for Rambo1 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        let even: bool = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = value,
+            0x8000..=0x9FFF => match self.bank_select & 0x0F {
+                register @ 0..=9 => self.registers[register as usize] = value,
+                0x0F => self.registers[10] = value,
+                _ => {}
+            },
+            0xA000..=0xBFFF if even => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.cycle_mode = value & 1 == 1;
+                self.irq_reload = true;
+                self.prescaler = 0;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_flag = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn clock_cpu(&mut self) {
+        if !self.cycle_mode {
+            return;
+        }
+        self.prescaler += 1;
+        if self.prescaler == CYCLE_MODE_DIVIDER {
+            self.prescaler = 0;
+            self.clock_irq_counter();
+        }
+    }
+
+    fn notify_scanline(&mut self, scanline: Scanline) {
+        if !self.cycle_mode && scanline != Scanline::PostRender {
+            self.clock_irq_counter();
+        }
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Rambo1 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.bank_select);
+        s.value(&mut self.registers);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.irq_latch);
+        s.value(&mut self.irq_counter);
+        s.value(&mut self.irq_reload);
+        s.value(&mut self.irq_enabled);
+        s.value(&mut self.irq_flag);
+        s.value(&mut self.cycle_mode);
+        s.value(&mut self.prescaler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    fn write_register(rambo1: &mut Rambo1, bank_select: u8, register: u8, value: u8) {
+        rambo1.cpu_write(0x8000, bank_select | register);
+        rambo1.cpu_write(0x8001, value);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut rambo1: Rambo1 = Rambo1::new(create_rom(64, 8, 16));
+        write_register(&mut rambo1, 0, 6, 3);
+        write_register(&mut rambo1, 0, 7, 4);
+        write_register(&mut rambo1, 0, 0x0F, 5);
+        assert_eq!(rambo1.cpu_read(0x8000), 3 * 8);
+        assert_eq!(rambo1.cpu_read(0xA000), 4 * 8);
+        assert_eq!(rambo1.cpu_read(0xC000), 5 * 8);
+        assert_eq!(rambo1.cpu_read(0xE000), 15 * 8);
+
+        rambo1.cpu_write(0x8000, 0x40);
+        assert_eq!(rambo1.cpu_read(0x8000), 5 * 8);
+        assert_eq!(rambo1.cpu_read(0xA000), 3 * 8);
+        assert_eq!(rambo1.cpu_read(0xC000), 4 * 8);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut rambo1: Rambo1 = Rambo1::new(create_rom(64, 8, 16));
+        for (register, bank) in [
+            (0, 10),
+            (1, 20),
+            (2, 30),
+            (3, 31),
+            (4, 32),
+            (5, 33),
+            (8, 40),
+            (9, 50),
+        ] {
+            write_register(&mut rambo1, 0, register, bank);
+        }
+        let banks = |rambo1: &Rambo1| {
+            (0..8)
+                .map(|i| rambo1.ppu_read(i * 0x400))
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(banks(&rambo1), vec![10, 11, 20, 21, 30, 31, 32, 33]);
+
+        rambo1.cpu_write(0x8000, 0x20);
+        assert_eq!(banks(&rambo1), vec![10, 40, 20, 50, 30, 31, 32, 33]);
+        rambo1.cpu_write(0x8000, 0xA0);
+        assert_eq!(banks(&rambo1), vec![30, 31, 32, 33, 10, 40, 20, 50]);
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut rambo1: Rambo1 = Rambo1::new(create_rom(64, 8, 16));
+        rambo1.cpu_write(0xC000, 2);
+        rambo1.cpu_write(0xC001, 0);
+        rambo1.cpu_write(0xE001, 0);
+        // reloading to 3, then counting down
+        for _ in 0..3 {
+            rambo1.notify_scanline(Scanline::Visible(0));
+            assert!(!rambo1.get_irq());
+        }
+        rambo1.notify_scanline(Scanline::Visible(0));
+        assert!(rambo1.get_irq());
+        rambo1.cpu_write(0xE000, 0);
+        assert!(!rambo1.get_irq());
+    }
+
+    #[test]
+    fn test_cycle_irq() {
+        let mut rambo1: Rambo1 = Rambo1::new(create_rom(64, 8, 16));
+        rambo1.cpu_write(0xC000, 1);
+        rambo1.cpu_write(0xC001, 1);
+        rambo1.cpu_write(0xE001, 0);
+        for _ in 0..CYCLE_MODE_DIVIDER * 3 - 1 {
+            rambo1.clock_cpu();
+        }
+        assert!(!rambo1.get_irq());
+        rambo1.clock_cpu();
+        assert!(rambo1.get_irq());
+        // scanlines don't count in cycle mode
+        rambo1.cpu_write(0xE000, 0);
+        rambo1.cpu_write(0xE001, 0);
+        for _ in 0..10 {
+            rambo1.notify_scanline(Scanline::Visible(0));
+        }
+        assert!(!rambo1.get_irq());
+    }
+}
@@ -0,0 +1,347 @@
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// VRC2 and VRC4 reference: https://www.nesdev.org/wiki/VRC2_and_VRC4
+///
+/// Mappers 21, 22, 23 and 25. Switches 8 KiB PRG banks and 1 KiB CHR banks. The boards connect
+/// different CPU address lines to the chip's two register select inputs, which is what the
+/// submappers tell apart. Without a submapper, both candidate lines get ORed together, which works
+/// because games only ever toggle the lines their board uses.
+///
+/// The VRC2 is the VRC4's predecessor, lacking the interrupt counter, the PRG swap mode and the
+/// one-screen mirroring modes.
+pub struct Vrc4 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    vrc2: bool,
+    /// CPU address lines connected to the register select inputs.
+    a0_lines: u16,
+    a1_lines: u16,
+    /// The VRC2a ignores the lowest bit of the CHR bank numbers.
+    chr_shift: u8,
+
+    prg_banks: [u8; 2],
+    /// Swaps $8000 and $C000.
+    prg_swapped: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    /// The VRC2 has a single bit latch at $6000-$7FFF on boards without PRG-RAM, which some games
+    /// use to detect the board.
+    latch: u8,
+    irq: VrcIrq,
+}
+
+impl Vrc4 {
+    pub fn new(rom: Rom) -> Vrc4 {
+        const A0: u16 = 1;
+        const A1: u16 = 1 << 1;
+        const A2: u16 = 1 << 2;
+        const A3: u16 = 1 << 3;
+        const A6: u16 = 1 << 6;
+        const A7: u16 = 1 << 7;
+        let (vrc2, a0_lines, a1_lines): (bool, u16, u16) =
+            match (rom.header.mapper, rom.header.submapper) {
+                // VRC4a, VRC4c
+                (21, 1) => (false, A1, A2),
+                (21, 2) => (false, A6, A7),
+                (21, _) => (false, A1 | A6, A2 | A7),
+                // VRC2a
+                (22, _) => (true, A1, A0),
+                // VRC4f, VRC4e, VRC2b
+                (23, 1) => (false, A0, A1),
+                (23, 2) => (false, A2, A3),
+                (23, 3) => (true, A0, A1),
+                (23, _) => (false, A0 | A2, A1 | A3),
+                // VRC4b, VRC4d, VRC2c
+                (25, 1) => (false, A1, A0),
+                (25, 2) => (false, A3, A2),
+                (25, 3) => (true, A1, A0),
+                (25, _) => (false, A1 | A3, A0 | A2),
+                (mapper, _) => panic!("Mapper {} is not a VRC2 or VRC4", mapper),
+            };
+
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() && !vrc2 {
+            prg_ram = vec![0; 0x2000];
+        }
+        Vrc4 {
+            battery: rom.header.battery,
+            chr_shift: if rom.header.mapper == 22 { 1 } else { 0 },
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            vrc2,
+            a0_lines,
+            a1_lines,
+            prg_banks: [0, 1],
+            prg_swapped: false,
+            chr_banks: [0; 8],
+            latch: 0,
+            irq: VrcIrq::new(),
+        }
+    }
+
+    /// Maps a register write to $x000-$x003, undoing the board's address line permutation.
+    fn get_register(&self, addr: u16) -> u16 {
+        let a0: u16 = (addr & self.a0_lines != 0) as u16;
+        let a1: u16 = (addr & self.a1_lines != 0) as u16;
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let bank_count: usize = self.prg_rom.len() / 0x2000;
+        let second_last: usize = bank_count.saturating_sub(2);
+        let bank: usize = match (addr >> 13) & 0b11 {
+            0 if self.prg_swapped => second_last,
+            0 => self.prg_banks[0] as usize,
+            1 => self.prg_banks[1] as usize,
+            2 if self.prg_swapped => self.prg_banks[0] as usize,
+            2 => second_last,
+            _ => bank_count - 1,
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize =
+            (self.chr_banks[(addr >> 10) as usize & 0b111] >> self.chr_shift) as usize;
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+
+    /// Updates the lower or upper part of a CHR bank number. `index` counts the registers at
+    /// $B000-$E003, two per bank.
+    fn write_chr_bank(&mut self, index: usize, value: u8) {
+        let bank: &mut u16 = &mut self.chr_banks[index / 2];
+        if index.is_multiple_of(2) {
+            *bank = (*bank & 0x1F0) | (value & 0x0F) as u16;
+        } else {
+            *bank = (*bank & 0x00F) | ((value & 0x1F) as u16) << 4;
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram.is_empty() => self.latch,
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()],
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr < 0x8000 {
+            if !(0x6000..=0x7FFF).contains(&addr) {
+                return;
+            }
+            if self.prg_ram.is_empty() {
+                self.latch = value & 1;
+            } else {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            return;
+        }
+
+        match self.get_register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9001 if self.vrc2 => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0x9000..=0x9001 => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            // bit 0 enables PRG-RAM, but some games never set it, so RAM stays enabled
+            0x9002..=0x9003 if !self.vrc2 => self.prg_swapped = (value >> 1) & 1 == 1,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            register @ 0xB000..=0xEFFF => {
+                let index: usize =
+                    ((register - 0xB000) >> 12) as usize * 4 + (register & 3) as usize;
+                self.write_chr_bank(index, value);
+            }
+            0xF000 if !self.vrc2 => self.irq.set_latch_nibble(value, false),
+            0xF001 if !self.vrc2 => self.irq.set_latch_nibble(value, true),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq.get_irq()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock_cpu();
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Vrc4 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.prg_swapped);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.latch);
+        s.value(&mut self.irq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    fn create(mapper: u8, submapper: u8) -> Vrc4 {
+        let mut rom: Rom = create_rom(mapper, 8, 16);
+        rom.header.submapper = submapper;
+        Vrc4::new(rom)
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut vrc4: Vrc4 = create(21, 1);
+        vrc4.cpu_write(0x8000, 3);
+        vrc4.cpu_write(0xA000, 5);
+        assert_eq!(vrc4.cpu_read(0x8000), 3 * 8);
+        assert_eq!(vrc4.cpu_read(0xA000), 5 * 8);
+        assert_eq!(vrc4.cpu_read(0xC000), 14 * 8);
+        assert_eq!(vrc4.cpu_read(0xE000), 15 * 8);
+
+        // VRC4a selects $9002 through A2
+        vrc4.cpu_write(0x9004, 0b10);
+        assert_eq!(vrc4.cpu_read(0x8000), 14 * 8);
+        assert_eq!(vrc4.cpu_read(0xC000), 3 * 8);
+    }
+
+    #[test]
+    fn test_address_lines() {
+        // the register for the upper half of CHR bank 1, $B003, for every variant
+        for (mapper, submapper, addr) in [
+            (21, 1, 0xB006),
+            (21, 2, 0xB0C0),
+            (21, 0, 0xB0C0),
+            (23, 1, 0xB003),
+            (23, 2, 0xB00C),
+            (23, 0, 0xB00C),
+            (25, 1, 0xB003),
+            (25, 2, 0xB00C),
+            (25, 0, 0xB00C),
+        ] {
+            let mut vrc4: Vrc4 = create(mapper, submapper);
+            vrc4.cpu_write(addr, 1);
+            assert_eq!(vrc4.ppu_read(0x400), 16, "mapper {} {}", mapper, submapper);
+        }
+
+        // A0 and A1 swapped
+        let mut vrc4: Vrc4 = create(25, 1);
+        vrc4.cpu_write(0xB001, 2);
+        vrc4.cpu_write(0xB002, 1);
+        assert_eq!(vrc4.ppu_read(0x000), 16);
+        assert_eq!(vrc4.ppu_read(0x400), 2);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut vrc4: Vrc4 = create(23, 1);
+        for (i, addr) in [
+            0xB000, 0xB002, 0xC000, 0xC002, 0xD000, 0xD002, 0xE000, 0xE002,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            vrc4.cpu_write(addr, 100 + i as u8);
+            vrc4.cpu_write(addr + 1, (100 + i as u8) >> 4);
+        }
+        let banks: Vec<u8> = (0..8).map(|i| vrc4.ppu_read(i * 0x400)).collect();
+        assert_eq!(banks, vec![100, 101, 102, 103, 104, 105, 106, 107]);
+
+        // the VRC2a ignores the lowest bit
+        let mut vrc2: Vrc4 = create(22, 0);
+        vrc2.cpu_write(0xB000, 7);
+        assert_eq!(vrc2.ppu_read(0), 3);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut vrc4: Vrc4 = create(25, 1);
+        vrc4.cpu_write(0x9000, 3);
+        assert_eq!(vrc4.get_mirroring(), Mirroring::SingleScreenUpper);
+        vrc4.cpu_write(0x9000, 1);
+        assert_eq!(vrc4.get_mirroring(), Mirroring::Horizontal);
+
+        let mut vrc2: Vrc4 = create(25, 3);
+        vrc2.cpu_write(0x9000, 2);
+        assert_eq!(vrc2.get_mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_vrc2_latch() {
+        let mut rom: Rom = create_rom(22, 8, 16);
+        rom.header.prg_ram_size = 0;
+        let mut vrc2: Vrc4 = Vrc4::new(rom);
+        vrc2.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc2.cpu_read(0x6000), 1);
+        vrc2.cpu_write(0x6000, 0xFE);
+        assert_eq!(vrc2.cpu_read(0x7000), 0);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut vrc4: Vrc4 = create(23, 1);
+        vrc4.cpu_write(0xF000, 0xE);
+        vrc4.cpu_write(0xF001, 0xF);
+        vrc4.cpu_write(0xF002, 0b110);
+        vrc4.clock_cpu();
+        assert!(!vrc4.get_irq());
+        vrc4.clock_cpu();
+        assert!(vrc4.get_irq());
+        vrc4.cpu_write(0xF003, 0);
+        assert!(!vrc4.get_irq());
+    }
+}
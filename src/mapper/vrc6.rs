@@ -0,0 +1,248 @@
+pub mod audio;
+
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::vrc6::audio::Vrc6Audio;
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// VRC6 reference: https://www.nesdev.org/wiki/VRC6
+///
+/// Mappers 24 (VRC6a) and 26 (VRC6b), which differ in having the register select lines A0 and A1
+/// swapped. Switches a 16 KiB and an 8 KiB PRG bank and 1 KiB CHR banks, and comes with a cycle
+/// based interrupt counter and expansion audio.
+///
+/// Only the 1 KiB CHR banking mode, which all games use, is supported.
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    swapped_lines: bool,
+
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+    pub audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(rom: Rom) -> Vrc6 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Vrc6 {
+            battery: rom.header.battery,
+            swapped_lines: rom.header.mapper == 26,
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    /// Maps a register write to $x000-$x003, undoing the VRC6b's address line swap.
+    fn get_register(&self, addr: u16) -> u16 {
+        let lines: u16 = addr & 0b11;
+        let register: u16 = if self.swapped_lines {
+            (lines >> 1) | (lines & 1) << 1
+        } else {
+            lines
+        };
+        (addr & 0xF000) | register
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let offset: usize = match addr {
+            0x8000..=0xBFFF => self.prg_bank_16k as usize * 0x4000 + (addr & 0x3FFF) as usize,
+            0xC000..=0xDFFF => self.prg_bank_8k as usize * 0x2000 + (addr & 0x1FFF) as usize,
+            _ => self.prg_rom.len() - 0x2000 + (addr & 0x1FFF) as usize,
+        };
+        offset % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = self.chr_banks[(addr >> 10) as usize & 0b111] as usize;
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            if self.prg_ram_enabled {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            return;
+        }
+
+        match self.get_register(addr) {
+            0x8000..=0x8003 => self.prg_bank_16k = value & 0x0F,
+            register @ (0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) => {
+                self.audio.write_register(register, value);
+            }
+            // Bit layout: <pre>
+            // 1 << 7     => PRG-RAM enabled
+            // 0b11 << 2  => mirroring
+            // 0b11       => CHR banking mode
+            // </pre>
+            0xB003 => {
+                self.prg_ram_enabled = (value >> 7) & 1 == 1;
+                self.mirroring = match (value >> 2) & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xC000..=0xC003 => self.prg_bank_8k = value & 0x1F,
+            register @ 0xD000..=0xE003 => {
+                let index: usize =
+                    ((register - 0xD000) >> 12) as usize * 4 + (register & 3) as usize;
+                self.chr_banks[index] = value;
+            }
+            0xF000 => self.irq.set_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq.get_irq()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock_cpu();
+        self.audio.clock();
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.audio.get_output()
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Vrc6 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_bank_16k);
+        s.value(&mut self.prg_bank_8k);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.prg_ram_enabled);
+        s.value(&mut self.irq);
+        s.value(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+
+    #[test]
+    fn test_prg_banks() {
+        let mut vrc6: Vrc6 = Vrc6::new(create_rom(24, 8, 16));
+        vrc6.cpu_write(0x8000, 2);
+        vrc6.cpu_write(0xC000, 9);
+        assert_eq!(vrc6.cpu_read(0x8000), 2 * 16);
+        assert_eq!(vrc6.cpu_read(0xA000), 2 * 16 + 8);
+        assert_eq!(vrc6.cpu_read(0xC000), 9 * 8);
+        assert_eq!(vrc6.cpu_read(0xE000), 15 * 8);
+    }
+
+    #[test]
+    fn test_chr_banks_and_swapped_lines() {
+        for mapper in [24, 26] {
+            let mut vrc6: Vrc6 = Vrc6::new(create_rom(mapper, 8, 16));
+            vrc6.cpu_write(0xD000, 10);
+            vrc6.cpu_write(0xD001, 11);
+            vrc6.cpu_write(0xE003, 12);
+            let banks: Vec<u8> = (0..8).map(|i| vrc6.ppu_read(i * 0x400)).collect();
+            if mapper == 24 {
+                assert_eq!(banks, vec![10, 11, 0, 0, 0, 0, 0, 12]);
+            } else {
+                // $D001 selects bank 2 with A0 and A1 swapped
+                assert_eq!(banks, vec![10, 0, 11, 0, 0, 0, 0, 12]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_control() {
+        let mut vrc6: Vrc6 = Vrc6::new(create_rom(24, 8, 16));
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_read(0x6000), 0);
+        vrc6.cpu_write(0xB003, 0x84);
+        assert_eq!(vrc6.get_mirroring(), Mirroring::Horizontal);
+        vrc6.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc6.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_irq_and_audio() {
+        let mut vrc6: Vrc6 = Vrc6::new(create_rom(26, 8, 16));
+        vrc6.cpu_write(0xF000, 0xFF);
+        // $F001 is at $F002 and vice versa on the VRC6b
+        vrc6.cpu_write(0xF002, 0b110);
+        vrc6.clock_cpu();
+        assert!(vrc6.get_irq());
+        vrc6.cpu_write(0xF001, 0);
+        assert!(!vrc6.get_irq());
+
+        vrc6.cpu_write(0x9000, 0x8F);
+        vrc6.cpu_write(0x9001, 0x80);
+        vrc6.clock_cpu();
+        assert!(vrc6.get_audio_output() > 0.1);
+    }
+}
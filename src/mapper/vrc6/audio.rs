@@ -0,0 +1,330 @@
+use crate::state::{Serialize, Serializer};
+
+/// Output level of a single step of the channels. A pulse at full volume is about as loud as one
+/// of the APU's, and unlike the APU, the channels get mixed linearly.
+const STEP_LEVEL: f32 = 0.0099;
+
+/// VRC6 audio reference: https://www.nesdev.org/wiki/VRC6_audio
+///
+/// Two pulse channels with eight duty cycles and a sawtooth channel. There are no envelopes or
+/// length counters; the game updates the volumes itself.
+pub struct Vrc6Audio {
+    pub pulse1: Vrc6Pulse,
+    pub pulse2: Vrc6Pulse,
+    pub sawtooth: Sawtooth,
+    /// Stops all channels' timers.
+    halted: bool,
+    /// Number of bits to shift the channels' periods to the right, 0, 4 or 8.
+    period_shift: u8,
+}
+
+impl Default for Vrc6Audio {
+    fn default() -> Vrc6Audio {
+        Vrc6Audio::new()
+    }
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Vrc6Audio {
+        Vrc6Audio {
+            pulse1: Vrc6Pulse::new(),
+            pulse2: Vrc6Pulse::new(),
+            sawtooth: Sawtooth::new(),
+            halted: false,
+            period_shift: 0,
+        }
+    }
+
+    /// Handles writes to $9000-$9003, $A000-$A002 and $B000-$B002, with the board's address line
+    /// permutation already undone. Bit layout of $9003: <pre>
+    /// 1 << 2 => shift the periods by 8 bits
+    /// 1 << 1 => shift the periods by 4 bits, unless shifting by 8
+    /// 1      => halt
+    /// </pre>
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x9000..=0x9002 => self.pulse1.write_register(addr - 0x9000, value),
+            0x9003 => {
+                self.halted = value & 1 == 1;
+                self.period_shift = if (value >> 2) & 1 == 1 {
+                    8
+                } else if (value >> 1) & 1 == 1 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0xA000..=0xA002 => self.pulse2.write_register(addr - 0xA000, value),
+            0xB000..=0xB002 => self.sawtooth.write_register(addr - 0xB000, value),
+            _ => {}
+        }
+    }
+
+    /// Advances the channels by a single CPU cycle.
+    pub fn clock(&mut self) {
+        if self.halted {
+            return;
+        }
+        self.pulse1.clock(self.period_shift);
+        self.pulse2.clock(self.period_shift);
+        self.sawtooth.clock(self.period_shift);
+    }
+
+    pub fn get_output(&self) -> f32 {
+        let sum: u8 =
+            self.pulse1.get_output() + self.pulse2.get_output() + self.sawtooth.get_output();
+        sum as f32 * STEP_LEVEL
+    }
+}
+
+pub struct Vrc6Pulse {
+    volume: u8,
+    /// Number of the 16 steps the output is high for, minus 1.
+    duty: u8,
+    /// Ignores the duty cycle and outputs the volume constantly.
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Default for Vrc6Pulse {
+    fn default() -> Vrc6Pulse {
+        Vrc6Pulse::new()
+    }
+}
+
+impl Vrc6Pulse {
+    pub fn new() -> Vrc6Pulse {
+        Vrc6Pulse {
+            volume: 0,
+            duty: 0,
+            constant: false,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+        }
+    }
+
+    /// Bit layout of register 0: <pre>
+    /// 1 << 7      => constant output
+    /// 0b111 << 4  => duty
+    /// 0b1111      => volume
+    /// </pre>
+    /// Register 1 holds the lower 8 bits of the period. Bit layout of register 2: <pre>
+    /// 1 << 7      => enabled
+    /// 0b1111      => upper 4 bits of the period
+    /// </pre>
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.constant = (value >> 7) & 1 == 1;
+                self.duty = (value >> 4) & 0b111;
+                self.volume = value & 0x0F;
+            }
+            1 => self.period = (self.period & 0xF00) | value as u16,
+            2 => {
+                self.period = (self.period & 0x0FF) | ((value & 0x0F) as u16) << 8;
+                self.enabled = (value >> 7) & 1 == 1;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, period_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> period_shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn get_output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+pub struct Sawtooth {
+    /// Added to the accumulator every other step.
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    /// The accumulator gets reset after 14 steps.
+    step: u8,
+    accumulator: u8,
+}
+
+impl Default for Sawtooth {
+    fn default() -> Sawtooth {
+        Sawtooth::new()
+    }
+}
+
+impl Sawtooth {
+    pub fn new() -> Sawtooth {
+        Sawtooth {
+            rate: 0,
+            enabled: false,
+            period: 0,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    /// Register 0 holds the 6-bit accumulator rate, registers 1 and 2 the period and enable flag
+    /// like the pulse channels' registers.
+    pub fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0xF00) | value as u16,
+            2 => {
+                self.period = (self.period & 0x0FF) | ((value & 0x0F) as u16) << 8;
+                self.enabled = (value >> 7) & 1 == 1;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, period_shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> period_shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// The upper 5 bits of the accumulator.
+    pub fn get_output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl Serialize for Vrc6Pulse {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.volume);
+        s.value(&mut self.duty);
+        s.value(&mut self.constant);
+        s.value(&mut self.enabled);
+        s.value(&mut self.period);
+        s.value(&mut self.timer);
+        s.value(&mut self.step);
+    }
+}
+
+impl Serialize for Sawtooth {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.rate);
+        s.value(&mut self.enabled);
+        s.value(&mut self.period);
+        s.value(&mut self.timer);
+        s.value(&mut self.step);
+        s.value(&mut self.accumulator);
+    }
+}
+
+impl Serialize for Vrc6Audio {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.pulse1);
+        s.value(&mut self.pulse2);
+        s.value(&mut self.sawtooth);
+        s.value(&mut self.halted);
+        s.value(&mut self.period_shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut audio: Vrc6Audio = Vrc6Audio::new();
+        // duty 4 of 16 at volume 15, with a period of 1 cycle
+        audio.write_register(0x9000, 0x3F);
+        audio.write_register(0x9001, 0);
+        audio.write_register(0x9002, 0x80);
+        let mut high: usize = 0;
+        for _ in 0..32 {
+            audio.clock();
+            if audio.pulse1.get_output() == 15 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 8);
+
+        // constant output
+        audio.write_register(0x9000, 0x8F);
+        assert!((0..32).all(|_| {
+            audio.clock();
+            audio.pulse1.get_output() == 15
+        }));
+
+        audio.write_register(0x9002, 0);
+        assert_eq!(audio.get_output(), 0.0);
+    }
+
+    #[test]
+    fn test_sawtooth() {
+        let mut audio: Vrc6Audio = Vrc6Audio::new();
+        audio.write_register(0xB000, 42);
+        audio.write_register(0xB002, 0x80);
+        let mut outputs: Vec<u8> = Vec::new();
+        for _ in 0..14 {
+            audio.clock();
+            outputs.push(audio.sawtooth.get_output());
+        }
+        assert_eq!(
+            outputs,
+            vec![0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]
+        );
+    }
+
+    #[test]
+    fn test_halt_and_shift() {
+        let mut audio: Vrc6Audio = Vrc6Audio::new();
+        audio.write_register(0xB000, 42);
+        audio.write_register(0xB001, 0x10);
+        audio.write_register(0xB002, 0x80);
+        audio.write_register(0x9003, 1);
+        for _ in 0..100 {
+            audio.clock();
+        }
+        assert_eq!(audio.sawtooth.get_output(), 0);
+
+        // shifting the period of 16 by 4 bits makes it 1
+        audio.write_register(0x9003, 0b10);
+        for _ in 0..4 {
+            audio.clock();
+        }
+        assert_eq!(audio.sawtooth.get_output(), 5);
+    }
+}
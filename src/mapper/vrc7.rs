@@ -0,0 +1,311 @@
+pub mod opll;
+
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::vrc7::opll::Opll;
+use crate::mapper::{Mapper, create_chr, create_prg_ram};
+use crate::rom::{Mirroring, Rom};
+use crate::state::{Serialize, Serializer};
+
+/// VRC7 reference: https://www.nesdev.org/wiki/VRC7
+///
+/// Mapper 85. Switches 8 KiB PRG banks and 1 KiB CHR banks, and comes with a cycle based interrupt
+/// counter and FM synthesis audio. The VRC7a (submapper 2) selects the second register of each
+/// pair through A4, the VRC7b (submapper 1) through A3. Without a submapper, either line works.
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    battery: bool,
+    /// CPU address lines selecting the second register of each pair.
+    select_lines: u16,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+    pub audio: Opll,
+}
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Vrc7 {
+        let (chr, chr_is_ram) = create_chr(&rom);
+        let mut prg_ram: Vec<u8> = create_prg_ram(&rom);
+        if prg_ram.is_empty() {
+            prg_ram = vec![0; 0x2000];
+        }
+        Vrc7 {
+            battery: rom.header.battery,
+            select_lines: match rom.header.submapper {
+                1 => 1 << 3,
+                2 => 1 << 4,
+                _ => 1 << 3 | 1 << 4,
+            },
+            mirroring: rom.header.mirroring,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_is_ram,
+            prg_ram,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            prg_ram_enabled: false,
+            irq: VrcIrq::new(),
+            audio: Opll::new(),
+        }
+    }
+
+    /// Maps a register write to $x000 or $x010, undoing the board's address line choice.
+    fn get_register(&self, addr: u16) -> u16 {
+        (addr & 0xF000)
+            | if addr & self.select_lines != 0 {
+                0x10
+            } else {
+                0
+            }
+    }
+
+    fn get_prg_addr(&self, addr: u16) -> usize {
+        let slot: usize = ((addr >> 13) & 0b11) as usize;
+        let bank: usize = if slot == 3 {
+            self.prg_rom.len() / 0x2000 - 1
+        } else {
+            self.prg_banks[slot] as usize
+        };
+        (bank * 0x2000 + (addr & 0x1FFF) as usize) % self.prg_rom.len()
+    }
+
+    fn get_chr_addr(&self, addr: u16) -> usize {
+        let bank: usize = self.chr_banks[(addr >> 10) as usize & 0b111] as usize;
+        (bank * 0x400 + (addr & 0x3FF) as usize) % self.chr.len()
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.get_prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            if self.prg_ram_enabled {
+                let len: usize = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = value;
+            }
+            return;
+        }
+
+        // the audio ports are decoded the same way on both boards
+        match addr & 0xF030 {
+            0x9010 => {
+                self.audio.write_address(value);
+                return;
+            }
+            0x9030 => {
+                self.audio.write_data(value);
+                return;
+            }
+            _ => {}
+        }
+
+        match self.get_register(addr) {
+            0x8000 => self.prg_banks[0] = value & 0x3F,
+            0x8010 => self.prg_banks[1] = value & 0x3F,
+            0x9000 => self.prg_banks[2] = value & 0x3F,
+            register @ 0xA000..=0xD010 => {
+                let index: usize =
+                    ((register - 0xA000) >> 12) as usize * 2 + (register >> 4) as usize % 2;
+                self.chr_banks[index] = value;
+            }
+            // Bit layout: <pre>
+            // 1 << 7 => PRG-RAM enabled
+            // 1 << 6 => audio held in reset
+            // 0b11   => mirroring
+            // </pre>
+            0xE000 => {
+                self.prg_ram_enabled = (value >> 7) & 1 == 1;
+                self.audio.set_reset((value >> 6) & 1 == 1);
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            0xE010 => self.irq.set_latch(value),
+            0xF000 => self.irq.write_control(value),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[self.get_chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let index: usize = self.get_chr_addr(addr);
+            self.chr[index] = value;
+        }
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn get_irq(&self) -> bool {
+        self.irq.get_irq()
+    }
+
+    fn clock_cpu(&mut self) {
+        self.irq.clock_cpu();
+        self.audio.clock();
+    }
+
+    fn get_audio_output(&self) -> f32 {
+        self.audio.get_output()
+    }
+
+    fn get_save_data(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    fn get_save_data_mut(&mut self) -> Option<&mut [u8]> {
+        self.battery.then_some(self.prg_ram.as_mut_slice())
+    }
+}
+
+impl Serialize for Vrc7 {
+    fn serialize(&mut self, s: &mut Serializer) {
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_ram);
+        s.value(&mut self.prg_banks);
+        s.value(&mut self.chr_banks);
+        s.value(&mut self.mirroring);
+        s.value(&mut self.prg_ram_enabled);
+        s.value(&mut self.irq);
+        s.value(&mut self.audio);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::tests::create_rom;
+    use crate::state::{StateReader, StateWriter};
+
+    fn create(submapper: u8) -> Vrc7 {
+        let mut rom: Rom = create_rom(85, 8, 16);
+        rom.header.submapper = submapper;
+        Vrc7::new(rom)
+    }
+
+    #[test]
+    fn test_banks() {
+        for (submapper, select) in [(1, 0x08), (2, 0x10), (0, 0x08), (0, 0x10)] {
+            let mut vrc7: Vrc7 = create(submapper);
+            vrc7.cpu_write(0x8000, 4);
+            vrc7.cpu_write(0x8000 | select, 5);
+            vrc7.cpu_write(0x9000, 6);
+            assert_eq!(vrc7.cpu_read(0x8000), 4 * 8);
+            assert_eq!(vrc7.cpu_read(0xA000), 5 * 8);
+            assert_eq!(vrc7.cpu_read(0xC000), 6 * 8);
+            assert_eq!(vrc7.cpu_read(0xE000), 15 * 8);
+
+            for i in 0..8 {
+                let addr: u16 = 0xA000 + (i / 2) * 0x1000 + if i % 2 == 1 { select } else { 0 };
+                vrc7.cpu_write(addr, 20 + i as u8);
+            }
+            let banks: Vec<u8> = (0..8).map(|i| vrc7.ppu_read(i * 0x400)).collect();
+            assert_eq!(banks, vec![20, 21, 22, 23, 24, 25, 26, 27]);
+        }
+    }
+
+    #[test]
+    fn test_control() {
+        let mut vrc7: Vrc7 = create(2);
+        vrc7.cpu_write(0xE000, 0x83);
+        assert_eq!(vrc7.get_mirroring(), Mirroring::SingleScreenUpper);
+        vrc7.cpu_write(0x6000, 0x42);
+        assert_eq!(vrc7.cpu_read(0x6000), 0x42);
+        vrc7.cpu_write(0xE000, 0x00);
+        assert_eq!(vrc7.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut vrc7: Vrc7 = create(2);
+        vrc7.cpu_write(0xE010, 0xFE);
+        vrc7.cpu_write(0xF000, 0b110);
+        vrc7.clock_cpu();
+        assert!(!vrc7.get_irq());
+        vrc7.clock_cpu();
+        assert!(vrc7.get_irq());
+        vrc7.cpu_write(0xF010, 0);
+        assert!(!vrc7.get_irq());
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut vrc7: Vrc7 = create(2);
+        for (register, value) in [(0x30, 0x40), (0x10, 0x20), (0x20, 0x19)] {
+            vrc7.cpu_write(0x9010, register);
+            vrc7.cpu_write(0x9030, value);
+        }
+        let mut peak: f32 = 0.0;
+        for _ in 0..100_000 {
+            vrc7.clock_cpu();
+            peak = peak.max(vrc7.get_audio_output().abs());
+        }
+        assert!(peak > 0.01);
+
+        // holding the chip in reset silences it
+        vrc7.cpu_write(0xE000, 0x40);
+        assert_eq!(vrc7.get_audio_output(), 0.0);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut vrc7: Vrc7 = create(2);
+        vrc7.cpu_write(0x8000, 4);
+        vrc7.cpu_write(0xE000, 0x83);
+        vrc7.cpu_write(0x6000, 0x42);
+        vrc7.cpu_write(0xE010, 0x80);
+        vrc7.cpu_write(0xF000, 0b110);
+        for (register, value) in [(0x30, 0x40), (0x10, 0x20), (0x20, 0x19)] {
+            vrc7.cpu_write(0x9010, register);
+            vrc7.cpu_write(0x9030, value);
+        }
+        for _ in 0..10_000 {
+            vrc7.clock_cpu();
+        }
+
+        let mut writer: StateWriter = StateWriter::new();
+        writer.add_section(*b"MAPR", |s| vrc7.serialize(s));
+        let bytes: Vec<u8> = writer.into_bytes();
+        let mut loaded: Vrc7 = create(2);
+        let reader: StateReader = StateReader::new(&bytes).unwrap();
+        reader
+            .load_section(*b"MAPR", |s| loaded.serialize(s))
+            .unwrap();
+
+        assert_eq!(loaded.cpu_read(0x8000), 4 * 8);
+        assert_eq!(loaded.cpu_read(0x6000), 0x42);
+        assert_eq!(loaded.get_mirroring(), Mirroring::SingleScreenUpper);
+        for _ in 0..10_000 {
+            vrc7.clock_cpu();
+            loaded.clock_cpu();
+            assert_eq!(loaded.get_audio_output(), vrc7.get_audio_output());
+            assert_eq!(loaded.get_irq(), vrc7.get_irq());
+        }
+    }
+}
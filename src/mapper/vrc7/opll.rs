@@ -0,0 +1,571 @@
+use crate::state::{Serialize, Serializer, StateError};
+use std::f32::consts::TAU;
+
+/// The OPLL runs at the same 3.58 MHz as the NTSC console and generates a sample every 72 of its
+/// cycles, so every 36 CPU cycles.
+const CPU_CYCLES_PER_SAMPLE: u32 = 36;
+const SAMPLE_RATE: f32 = 49716.0;
+/// Attenuation in dB at which an operator is inaudible.
+const MAX_ATTENUATION: f32 = 48.0;
+/// Phase shift of the carrier, in cycles, by a modulator at full amplitude.
+const MODULATION_DEPTH: f32 = 2.0;
+/// Output level of a channel at full amplitude.
+const CHANNEL_LEVEL: f32 = 0.08;
+/// Tremolo runs at 3.7 Hz with a depth of 4.8 dB, vibrato at 6.4 Hz with a depth of about 14
+/// cents.
+const TREMOLO_RATE: f32 = 3.7;
+const TREMOLO_DEPTH: f32 = 4.8;
+const VIBRATO_RATE: f32 = 6.4;
+const VIBRATO_DEPTH: f32 = 0.008;
+
+/// Frequency multipliers, doubled to keep the 1/2 an integer.
+const MULTIPLIERS: [u8; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+/// Attenuation in dB by the upper 4 bits of the F-number, for the highest octave and the
+/// steepest key scale level of 6 dB per octave.
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+/// The VRC7's built-in instruments 1-15, as dumped from the chip. Instrument 0 is the one defined
+/// by registers $00-$07.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// VRC7 audio reference: https://www.nesdev.org/wiki/VRC7_audio
+///
+/// A cut down Yamaha YM2413 (OPLL), with six two-operator FM channels. In each channel, the
+/// modulator, which can feed back into itself, shifts the phase of the carrier's sine wave. The
+/// channels play one of 15 built-in instruments, or a single custom one.
+pub struct Opll {
+    address: u8,
+    custom_patch: [u8; 8],
+    channels: [Channel; 6],
+    /// While set, the chip is held in reset and silent.
+    reset: bool,
+    cycle: u32,
+    /// Phases of the tremolo and vibrato oscillators, in cycles.
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+    output: f32,
+}
+
+impl Default for Opll {
+    fn default() -> Opll {
+        Opll::new()
+    }
+}
+
+impl Opll {
+    pub fn new() -> Opll {
+        Opll {
+            address: 0,
+            custom_patch: [0; 8],
+            channels: std::array::from_fn(|_| Channel::new()),
+            reset: false,
+            cycle: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    /// Selects the register the next write to the data port goes to.
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    /// Writes the register selected through [Opll::write_address]. Registers: <pre>
+    /// $00-$07 => custom instrument
+    /// $10-$15 => lower 8 bits of the F-number
+    /// $20-$25 => 1 << 5: sustain, 1 << 4: key on, 0b111 << 1: octave, 1: F-number bit 8
+    /// $30-$35 => 0b1111 << 4: instrument, 0b1111: attenuation in steps of 3 dB
+    /// </pre>
+    pub fn write_data(&mut self, value: u8) {
+        if self.reset {
+            return;
+        }
+        let index: usize = (self.address & 0x0F) as usize;
+        match self.address {
+            0x00..=0x07 => self.custom_patch[index] = value,
+            0x10..=0x15 => {
+                let channel: &mut Channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0x100) | value as u16;
+            }
+            0x20..=0x25 => {
+                let channel: &mut Channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0xFF) | ((value & 1) as u16) << 8;
+                channel.octave = (value >> 1) & 0b111;
+                channel.sustain = (value >> 5) & 1 == 1;
+                channel.set_key_on((value >> 4) & 1 == 1);
+            }
+            0x30..=0x35 => {
+                let channel: &mut Channel = &mut self.channels[index];
+                channel.instrument = value >> 4;
+                channel.volume = value & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    /// Holds the chip in reset, which clears its registers and silences it.
+    pub fn set_reset(&mut self, reset: bool) {
+        if reset {
+            *self = Opll::new();
+        }
+        self.reset = reset;
+    }
+
+    /// Advances the chip by a single CPU cycle.
+    pub fn clock(&mut self) {
+        if self.reset {
+            return;
+        }
+        self.cycle += 1;
+        if self.cycle == CPU_CYCLES_PER_SAMPLE {
+            self.cycle = 0;
+            self.generate_sample();
+        }
+    }
+
+    fn generate_sample(&mut self) {
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_RATE / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE / SAMPLE_RATE).fract();
+        let tremolo: f32 = (1.0 - (self.tremolo_phase * TAU).cos()) / 2.0 * TREMOLO_DEPTH;
+        let vibrato: f32 = (self.vibrato_phase * TAU).sin() * VIBRATO_DEPTH;
+
+        let mut sum: f32 = 0.0;
+        for channel in self.channels.iter_mut() {
+            let patch: Patch = Patch::new(if channel.instrument == 0 {
+                &self.custom_patch
+            } else {
+                &PATCHES[channel.instrument as usize - 1]
+            });
+            sum += channel.generate_sample(&patch, tremolo, vibrato);
+        }
+        self.output = sum * CHANNEL_LEVEL;
+    }
+
+    pub fn get_output(&self) -> f32 {
+        self.output
+    }
+}
+
+/// The decoded settings of one of the operators of an instrument.
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    /// Sustained tones hold the sustain level while the key is on, percussive ones keep decaying.
+    sustained: bool,
+    /// Scales the envelope rates by the whole key, rather than just the octave.
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    /// Cuts off the negative half of the sine wave.
+    rectified: bool,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+}
+
+/// Instrument reference: https://www.nesdev.org/wiki/VRC7_audio#Custom_Patch
+///
+/// Bit layout of the 8 bytes: <pre>
+/// 0, 1 => modulator and carrier: 1 << 7: tremolo, 1 << 6: vibrato, 1 << 5: sustained,
+///         1 << 4: key scale rate, 0b1111: multiplier
+/// 2    => 0b11 << 6: modulator key scale level, 0b11_1111: modulator attenuation
+/// 3    => 0b11 << 6: carrier key scale level, 1 << 4: carrier rectified,
+///         1 << 3: modulator rectified, 0b111: feedback
+/// 4, 5 => modulator and carrier: 0b1111 << 4: attack rate, 0b1111: decay rate
+/// 6, 7 => modulator and carrier: 0b1111 << 4: sustain level, 0b1111: release rate
+/// </pre>
+struct Patch {
+    modulator: OperatorPatch,
+    carrier: OperatorPatch,
+    /// Modulator attenuation in steps of 0.75 dB.
+    modulator_level: u8,
+    feedback: u8,
+}
+
+impl Patch {
+    fn new(bytes: &[u8; 8]) -> Patch {
+        let operator = |i: usize, key_scale_level: u8, rectified: bool| OperatorPatch {
+            tremolo: (bytes[i] >> 7) & 1 == 1,
+            vibrato: (bytes[i] >> 6) & 1 == 1,
+            sustained: (bytes[i] >> 5) & 1 == 1,
+            key_scale_rate: (bytes[i] >> 4) & 1 == 1,
+            multiplier: bytes[i] & 0x0F,
+            key_scale_level,
+            rectified,
+            attack_rate: bytes[4 + i] >> 4,
+            decay_rate: bytes[4 + i] & 0x0F,
+            sustain_level: bytes[6 + i] >> 4,
+            release_rate: bytes[6 + i] & 0x0F,
+        };
+        Patch {
+            modulator: operator(0, bytes[2] >> 6, (bytes[3] >> 3) & 1 == 1),
+            carrier: operator(1, bytes[3] >> 6, (bytes[3] >> 4) & 1 == 1),
+            modulator_level: bytes[2] & 0x3F,
+            feedback: bytes[3] & 0b111,
+        }
+    }
+}
+
+struct Channel {
+    f_number: u16,
+    octave: u8,
+    /// Makes notes release slowly after the key is let go.
+    sustain: bool,
+    key_on: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            f_number: 0,
+            octave: 0,
+            sustain: false,
+            key_on: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+        }
+    }
+
+    fn set_key_on(&mut self, key_on: bool) {
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            self.modulator.stage = Stage::Release;
+            self.carrier.stage = Stage::Release;
+        }
+        self.key_on = key_on;
+    }
+
+    /// Attenuation in dB from the key scale level, which makes higher notes quieter.
+    fn get_key_scale_attenuation(&self, key_scale_level: u8) -> f32 {
+        if key_scale_level == 0 {
+            return 0.0;
+        }
+        let base: f32 =
+            KEY_SCALE_LEVELS[(self.f_number >> 5) as usize] - 6.0 * (7 - self.octave) as f32;
+        base.max(0.0) / (1 << (3 - key_scale_level)) as f32
+    }
+
+    fn generate_sample(&mut self, patch: &Patch, tremolo: f32, vibrato: f32) -> f32 {
+        // the envelope rates are offset by the key, to make higher notes decay faster
+        let key: u8 = self.octave << 1 | (self.f_number >> 8) as u8;
+        let frequency: f32 = self.f_number as f32 * (1 << self.octave) as f32 / (1 << 19) as f32;
+
+        let modulator: &OperatorPatch = &patch.modulator;
+        self.modulator.clock_envelope(modulator, key, self.sustain);
+        self.modulator.clock_phase(modulator, frequency, vibrato);
+        let attenuation: f32 = self.modulator.envelope
+            + patch.modulator_level as f32 * 0.75
+            + self.get_key_scale_attenuation(modulator.key_scale_level)
+            + if modulator.tremolo { tremolo } else { 0.0 };
+        let feedback: f32 = if patch.feedback == 0 {
+            0.0
+        } else {
+            let previous: f32 = (self.modulator.outputs[0] + self.modulator.outputs[1]) / 2.0;
+            previous * (1 << (patch.feedback - 1)) as f32 / 32.0
+        };
+        let modulation: f32 = self.modulator.get_output(modulator, attenuation, feedback);
+
+        let carrier: &OperatorPatch = &patch.carrier;
+        self.carrier.clock_envelope(carrier, key, self.sustain);
+        self.carrier.clock_phase(carrier, frequency, vibrato);
+        let attenuation: f32 = self.carrier.envelope
+            + self.volume as f32 * 3.0
+            + self.get_key_scale_attenuation(carrier.key_scale_level)
+            + if carrier.tremolo { tremolo } else { 0.0 };
+        self.carrier
+            .get_output(carrier, attenuation, modulation * MODULATION_DEPTH)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+struct Operator {
+    /// Phase of the sine wave, in cycles.
+    phase: f32,
+    /// Attenuation of the envelope in dB.
+    envelope: f32,
+    stage: Stage,
+    /// The last two outputs, for the modulator's feedback.
+    outputs: [f32; 2],
+}
+
+impl Operator {
+    fn new() -> Operator {
+        Operator {
+            phase: 0.0,
+            envelope: MAX_ATTENUATION,
+            stage: Stage::Release,
+            outputs: [0.0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = Stage::Attack;
+    }
+
+    fn clock_phase(&mut self, patch: &OperatorPatch, frequency: f32, vibrato: f32) {
+        let vibrato: f32 = if patch.vibrato { 1.0 + vibrato } else { 1.0 };
+        let increment: f32 = frequency * MULTIPLIERS[patch.multiplier as usize] as f32 / 2.0;
+        self.phase = (self.phase + increment * vibrato).fract();
+    }
+
+    /// Moves the envelope through its stages. Rates of 0 stand still, while every increase by 4
+    /// doubles the speed.
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key: u8, sustain: bool) {
+        let rate_offset: u8 = if patch.key_scale_rate { key } else { key >> 2 };
+        let get_rate = |rate: u8| -> u8 {
+            if rate == 0 {
+                0
+            } else {
+                (rate * 4 + rate_offset).min(63)
+            }
+        };
+        let sustain_level: f32 = (patch.sustain_level as f32 * 3.0).min(MAX_ATTENUATION);
+
+        match self.stage {
+            Stage::Attack => {
+                let rate: u8 = get_rate(patch.attack_rate);
+                if rate >= 60 {
+                    self.envelope = 0.0;
+                } else if rate > 0 {
+                    // the attack is exponential, fast at first and slowing down near the peak
+                    let speed: f32 = 3.8 / (1.7 / get_rate_scale(rate) * SAMPLE_RATE);
+                    self.envelope -= (self.envelope + 1.0) * speed;
+                }
+                if self.envelope <= 0.1 {
+                    self.envelope = 0.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.envelope += get_decay_step(get_rate(patch.decay_rate));
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain if patch.sustained => {}
+            Stage::Sustain => self.envelope += get_decay_step(get_rate(patch.release_rate)),
+            Stage::Release => {
+                let rate: u8 = if sustain {
+                    5
+                } else if patch.sustained {
+                    patch.release_rate
+                } else {
+                    7
+                };
+                self.envelope += get_decay_step(get_rate(rate));
+            }
+        }
+        self.envelope = self.envelope.min(MAX_ATTENUATION);
+    }
+
+    /// Returns the output between -1 and 1, with the phase shifted by `modulation` cycles.
+    fn get_output(&mut self, patch: &OperatorPatch, attenuation: f32, modulation: f32) -> f32 {
+        let mut wave: f32 = ((self.phase + modulation) * TAU).sin();
+        if patch.rectified && wave < 0.0 {
+            wave = 0.0;
+        }
+        let output: f32 = if attenuation >= MAX_ATTENUATION {
+            0.0
+        } else {
+            wave * 10f32.powf(-attenuation / 20.0)
+        };
+        self.outputs = [self.outputs[1], output];
+        output
+    }
+}
+
+/// Speed factor of an envelope rate, relative to a rate of 0.
+fn get_rate_scale(rate: u8) -> f32 {
+    2f32.powf(rate as f32 / 4.0)
+}
+
+/// Increase of the attenuation per sample while decaying or releasing. At a rate of 4, decaying
+/// all the way takes about 6 seconds.
+fn get_decay_step(rate: u8) -> f32 {
+    if rate == 0 {
+        0.0
+    } else {
+        MAX_ATTENUATION / (12.2 / get_rate_scale(rate) * SAMPLE_RATE)
+    }
+}
+
+impl Serialize for Stage {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut index: u8 = *self as u8;
+        s.value(&mut index);
+        *self = match index {
+            0 => Stage::Attack,
+            1 => Stage::Decay,
+            2 => Stage::Sustain,
+            3 => Stage::Release,
+            _ => {
+                s.fail(StateError::Mismatch("envelope stage"));
+                *self
+            }
+        };
+    }
+}
+
+impl Serialize for Operator {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.phase);
+        s.value(&mut self.envelope);
+        s.value(&mut self.stage);
+        s.value(&mut self.outputs);
+    }
+}
+
+impl Serialize for Channel {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.f_number);
+        s.value(&mut self.octave);
+        s.value(&mut self.sustain);
+        s.value(&mut self.key_on);
+        s.value(&mut self.instrument);
+        s.value(&mut self.volume);
+        s.value(&mut self.modulator);
+        s.value(&mut self.carrier);
+    }
+}
+
+impl Serialize for Opll {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.address);
+        s.value(&mut self.custom_patch);
+        s.value(&mut self.channels);
+        s.value(&mut self.reset);
+        s.value(&mut self.cycle);
+        s.value(&mut self.tremolo_phase);
+        s.value(&mut self.vibrato_phase);
+        s.value(&mut self.output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(opll: &mut Opll, register: u8, value: u8) {
+        opll.write_address(register);
+        opll.write_data(value);
+    }
+
+    /// Runs the chip for a number of samples, returning the peak output.
+    fn run(opll: &mut Opll, samples: u32) -> f32 {
+        let mut peak: f32 = 0.0;
+        for _ in 0..samples * CPU_CYCLES_PER_SAMPLE {
+            opll.clock();
+            peak = peak.max(opll.get_output().abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn test_patch() {
+        let patch: Patch = Patch::new(&PATCHES[0]);
+        assert!(!patch.modulator.sustained);
+        assert!(patch.carrier.sustained);
+        assert_eq!(patch.modulator.multiplier, 3);
+        assert_eq!(patch.carrier.multiplier, 1);
+        assert_eq!(patch.modulator_level, 5);
+        assert_eq!(patch.feedback, 6);
+        assert_eq!(patch.carrier.attack_rate, 8);
+        assert_eq!(patch.modulator.sustain_level, 4);
+        assert_eq!(patch.carrier.release_rate, 7);
+    }
+
+    #[test]
+    fn test_note() {
+        let mut opll: Opll = Opll::new();
+        assert_eq!(run(&mut opll, 100), 0.0);
+
+        // a flute at full volume, playing an A4
+        write(&mut opll, 0x30, 0x40);
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x19);
+        let peak: f32 = run(&mut opll, 2000);
+        assert!(peak > CHANNEL_LEVEL * 0.5 && peak <= CHANNEL_LEVEL);
+
+        // the output swings around 0, about 440 times a second
+        let mut crossings: u32 = 0;
+        let mut last: f32 = opll.get_output();
+        for _ in 0..SAMPLE_RATE as u32 / 10 {
+            run(&mut opll, 1);
+            if (last < 0.0) != (opll.get_output() < 0.0) {
+                crossings += 1;
+            }
+            last = opll.get_output();
+        }
+        assert!((80..=100).contains(&crossings), "{}", crossings);
+
+        // releasing the key fades the note out
+        write(&mut opll, 0x20, 0x09);
+        run(&mut opll, SAMPLE_RATE as u32 * 2);
+        assert!(run(&mut opll, 100) < 0.001);
+    }
+
+    #[test]
+    fn test_volume() {
+        let play = |volume: u8| {
+            let mut opll: Opll = Opll::new();
+            write(&mut opll, 0x31, 0x30 | volume);
+            write(&mut opll, 0x11, 0x20);
+            write(&mut opll, 0x21, 0x19);
+            run(&mut opll, 2000)
+        };
+        // every step is 3 dB quieter
+        let ratio: f32 = play(2) / play(0);
+        assert!((ratio - 0.5).abs() < 0.05, "{}", ratio);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut opll: Opll = Opll::new();
+        write(&mut opll, 0x30, 0x40);
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x1B);
+        run(&mut opll, 100);
+        opll.set_reset(true);
+        assert_eq!(opll.get_output(), 0.0);
+        write(&mut opll, 0x20, 0x1B);
+        assert_eq!(run(&mut opll, 100), 0.0);
+    }
+}
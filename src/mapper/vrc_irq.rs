@@ -0,0 +1,160 @@
+use crate::state::{Serialize, Serializer};
+
+/// VRC IRQ reference: https://www.nesdev.org/wiki/VRC_IRQ
+///
+/// The interrupt counter shared by the VRC4, VRC6 and VRC7. It counts up from a latched value,
+/// either every CPU cycle or, through a prescaler, every scanline, and raises an interrupt once it
+/// overflows.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    /// Counts down by 3 per CPU cycle from 341, which makes the counter tick once per 113.67 CPU
+    /// cycles, the length of a scanline.
+    prescaler: i16,
+    /// Whether to enable the interrupt again once it got acknowledged.
+    enable_after_ack: bool,
+    enabled: bool,
+    cycle_mode: bool,
+    flag: bool,
+}
+
+impl Default for VrcIrq {
+    fn default() -> VrcIrq {
+        VrcIrq::new()
+    }
+}
+
+impl VrcIrq {
+    pub fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            enable_after_ack: false,
+            enabled: false,
+            cycle_mode: false,
+            flag: false,
+        }
+    }
+
+    pub fn set_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /// Sets the lower (`high == false`) or upper nibble of the latch, as done by the VRC4.
+    pub fn set_latch_nibble(&mut self, value: u8, high: bool) {
+        self.latch = if high {
+            (self.latch & 0x0F) | (value << 4)
+        } else {
+            (self.latch & 0xF0) | (value & 0x0F)
+        };
+    }
+
+    /// Bit layout of the written value: <pre>
+    /// 1 << 2 => cycle mode, rather than scanline mode
+    /// 1 << 1 => enabled
+    /// 1      => enable again after acknowledging
+    /// </pre>
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 1 == 1;
+        self.enabled = (value >> 1) & 1 == 1;
+        self.cycle_mode = (value >> 2) & 1 == 1;
+        self.flag = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.flag = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.flag
+    }
+
+    pub fn clock_cpu(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += 341;
+            self.clock_counter();
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.flag = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Serialize for VrcIrq {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.latch);
+        s.value(&mut self.counter);
+        s.value(&mut self.prescaler);
+        s.value(&mut self.enable_after_ack);
+        s.value(&mut self.enabled);
+        s.value(&mut self.cycle_mode);
+        s.value(&mut self.flag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_mode() {
+        let mut irq: VrcIrq = VrcIrq::new();
+        irq.set_latch(0xFD);
+        irq.write_control(0b110);
+        for _ in 0..2 {
+            irq.clock_cpu();
+        }
+        assert!(!irq.get_irq());
+        irq.clock_cpu();
+        assert!(irq.get_irq());
+
+        // acknowledging without the A bit disables the counter
+        irq.acknowledge();
+        for _ in 0..10 {
+            irq.clock_cpu();
+        }
+        assert!(!irq.get_irq());
+    }
+
+    #[test]
+    fn test_scanline_mode() {
+        let mut irq: VrcIrq = VrcIrq::new();
+        irq.set_latch_nibble(0xF, true);
+        irq.set_latch_nibble(0xE, false);
+        irq.write_control(0b011);
+        // two scanlines of 113.67 cycles
+        for _ in 0..227 {
+            irq.clock_cpu();
+        }
+        assert!(!irq.get_irq());
+        irq.clock_cpu();
+        assert!(irq.get_irq());
+
+        // with the A bit, the counter keeps running after acknowledging
+        irq.acknowledge();
+        for _ in 0..114 * 2 {
+            irq.clock_cpu();
+        }
+        assert!(irq.get_irq());
+    }
+}
@@ -0,0 +1,378 @@
+pub mod bk2;
+pub mod fm2;
+
+use crate::cpu::Cpu;
+use crate::input::InputDevice;
+use crate::input::controller::Controller;
+use crate::input::multitap::FourScore;
+use crate::region::Region;
+use crate::state::StateError;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Resets the console before the frame.
+pub const COMMAND_RESET: u8 = 1;
+/// Power cycles the console before the frame. There is no power-on state to return to, so this
+/// resets it like [COMMAND_RESET].
+pub const COMMAND_POWER: u8 = 2;
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// A line which doesn't follow the format, counting from 1.
+    InvalidLine(usize),
+    /// The movie asks for an input device which isn't supported.
+    UnsupportedDevice(String),
+    /// The machine's state differs from the recording's after a frame.
+    Desync {
+        frame: usize,
+        expected: u64,
+        actual: u64,
+    },
+    State(StateError),
+    Io(std::io::Error),
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MovieError::InvalidLine(line) => write!(f, "invalid movie line {}", line),
+            MovieError::UnsupportedDevice(device) => {
+                write!(f, "unsupported movie input device: {}", device)
+            }
+            MovieError::Desync {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "movie desynced on frame {}: expected hash {:016x}, got {:016x}",
+                frame, expected, actual
+            ),
+            MovieError::State(err) => write!(f, "failed to load the movie's save state: {}", err),
+            MovieError::Io(err) => write!(f, "failed to access movie: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<std::io::Error> for MovieError {
+    fn from(err: std::io::Error) -> MovieError {
+        MovieError::Io(err)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> MovieError {
+        MovieError::State(err)
+    }
+}
+
+/// The input of a single frame.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct FrameInput {
+    /// [COMMAND_RESET] and [COMMAND_POWER] flags.
+    pub commands: u8,
+    /// The buttons of players 1-4, as passed to [Controller::set_buttons].
+    pub buttons: [u8; 4],
+}
+
+/// A recording of the input given to the console on every frame, starting either at power-on
+/// or from a save state. Replaying it on the same cartridge gets the machine through the exact
+/// same states, which the hashes recorded for every frame make sure of.
+#[derive(PartialEq, Debug)]
+pub struct Movie {
+    pub region: Region,
+    pub rom_filename: String,
+    /// The cartridge's checksum as written by the recording emulator, kept as is.
+    pub rom_checksum: String,
+    pub guid: String,
+    pub rerecord_count: u32,
+    /// Whether standard controllers are plugged into the two ports.
+    pub controllers: [bool; 2],
+    /// Whether a Four Score is plugged in, for 4 players.
+    pub four_score: bool,
+    pub comments: Vec<String>,
+    /// The state made by [Cpu::save_state] to start from, or `None` to start at power-on.
+    pub save_state: Option<Vec<u8>>,
+    pub frames: Vec<FrameInput>,
+    /// The hash of the machine's state after every frame, see [hash_state]. Movies made by
+    /// other emulators have none.
+    pub frame_hashes: Vec<u64>,
+}
+
+impl Movie {
+    pub fn new(region: Region) -> Movie {
+        Movie {
+            region,
+            rom_filename: String::new(),
+            rom_checksum: String::new(),
+            guid: String::new(),
+            rerecord_count: 0,
+            controllers: [true, true],
+            four_score: false,
+            comments: Vec::new(),
+            save_state: None,
+            frames: Vec::new(),
+            frame_hashes: Vec::new(),
+        }
+    }
+
+    /// Reads an FCEUX movie (.fm2), or else a BizHawk input log, the "Input Log.txt" inside of a
+    /// .bk2 archive.
+    pub fn from_file(path: &Path) -> Result<Movie, MovieError> {
+        let text: String = std::fs::read_to_string(path)?;
+        if text.trim_start().starts_with("[Input]") {
+            Movie::from_bk2_input_log(&text)
+        } else {
+            Movie::from_fm2(&text)
+        }
+    }
+
+    /// Plugs in the input devices the movie was recorded with.
+    fn plug_in(&self, cpu: &mut Cpu) {
+        for port in 0..2 {
+            let device: Option<Box<dyn InputDevice>> = if self.four_score {
+                Some(Box::new(FourScore::new(port)))
+            } else if self.controllers[port] {
+                Some(Box::new(Controller::new()))
+            } else {
+                None
+            };
+            cpu.set_port(port, device);
+        }
+    }
+
+    /// Gives the frame's input to the devices and runs it.
+    fn run_frame(&self, cpu: &mut Cpu, input: &FrameInput) {
+        if input.commands & (COMMAND_RESET | COMMAND_POWER) != 0 {
+            cpu.reset();
+        }
+        for (player, buttons) in input.buttons.iter().enumerate() {
+            // with a Four Score, players 3 and 4 are the second controllers of each port
+            if let Some(device) = cpu.get_port_mut(player % 2) {
+                device.set_controller_buttons(player / 2, *buttons);
+            }
+        }
+        cpu.run_frame();
+    }
+}
+
+/// FNV-1a reference: http://www.isthe.com/chongo/tech/comp/fnv/
+///
+/// Hashes the whole state of the machine, as made by [Cpu::save_state].
+pub fn hash_state(cpu: &mut Cpu) -> u64 {
+    cpu.save_state()
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+/// Records the input given on every frame into a [Movie].
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Starts recording, on a machine which was just powered on or, with `from_save_state`, in
+    /// whatever state the machine is in. The movie's input devices get plugged in.
+    pub fn start(cpu: &mut Cpu, mut movie: Movie, from_save_state: bool) -> MovieRecorder {
+        movie.region = cpu.get_region();
+        movie.plug_in(cpu);
+        movie.save_state = from_save_state.then(|| cpu.save_state());
+        movie.frames.clear();
+        movie.frame_hashes.clear();
+        MovieRecorder { movie }
+    }
+
+    pub fn get_frame_count(&self) -> usize {
+        self.movie.frames.len()
+    }
+
+    /// Runs a frame with the given input, recording it.
+    pub fn record_frame(&mut self, cpu: &mut Cpu, input: FrameInput) {
+        self.movie.run_frame(cpu, &input);
+        self.movie.frames.push(input);
+        self.movie.frame_hashes.push(hash_state(cpu));
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Replays a [Movie], checking the machine's state against the recorded hashes.
+pub struct MoviePlayer {
+    movie: Movie,
+    position: usize,
+}
+
+impl MoviePlayer {
+    /// Starts playback, plugging in the movie's input devices and loading its save state. Movies
+    /// without one need a machine which was just powered on.
+    pub fn start(cpu: &mut Cpu, movie: Movie) -> Result<MoviePlayer, MovieError> {
+        cpu.set_region(movie.region);
+        movie.plug_in(cpu);
+        if let Some(state) = &movie.save_state {
+            cpu.load_state(state)?;
+        }
+        Ok(MoviePlayer { movie, position: 0 })
+    }
+
+    pub fn get_movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Number of frames played so far.
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.movie.frames.len()
+    }
+
+    /// Runs the next frame of the movie, returning false once there are none left.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<bool, MovieError> {
+        let Some(input) = self.movie.frames.get(self.position) else {
+            return Ok(false);
+        };
+        self.movie.run_frame(cpu, input);
+        if let Some(expected) = self.movie.frame_hashes.get(self.position) {
+            let actual: u64 = hash_state(cpu);
+            if actual != *expected {
+                return Err(MovieError::Desync {
+                    frame: self.position,
+                    expected: *expected,
+                    actual,
+                });
+            }
+        }
+        self.position += 1;
+        Ok(true)
+    }
+
+    /// Plays the rest of the movie.
+    pub fn play_to_end(&mut self, cpu: &mut Cpu) -> Result<(), MovieError> {
+        while self.step(cpu)? {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::input::controller::Button;
+
+    /// A machine endlessly reading the first controller, storing its A and B buttons at $10 and
+    /// $11.
+    pub fn create_cpu() -> Cpu {
+        let mut cpu: Cpu = Cpu::new();
+        cpu.write(0xFFFC, 0x00);
+        cpu.write(0xFFFD, 0x02);
+        // LDA #$01, STA $4016, LDA #$00, STA $4016, LDA $4016, STA $10, LDA $4016, STA $11,
+        // LDA #$01, BNE back to the start
+        let program: [u8; 24] = [
+            0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0x85,
+            0x10, 0xAD, 0x16, 0x40, 0x85, 0x11, 0xA9, 0x01, 0xD0, 0xE8,
+        ];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.write(0x0200 + i as u16, *byte);
+        }
+        cpu.reset();
+        cpu
+    }
+
+    /// Some input pressing A and B in turns.
+    pub fn create_inputs(count: usize) -> Vec<FrameInput> {
+        (0..count)
+            .map(|i| FrameInput {
+                commands: 0,
+                buttons: [
+                    if i % 3 == 0 { Button::A.get_mask() } else { 0 },
+                    if i % 5 == 0 { Button::B.get_mask() } else { 0 },
+                    0,
+                    0,
+                ],
+            })
+            .collect()
+    }
+
+    fn record(cpu: &mut Cpu, from_save_state: bool) -> Movie {
+        let mut recorder: MovieRecorder =
+            MovieRecorder::start(cpu, Movie::new(Region::Ntsc), from_save_state);
+        for input in create_inputs(20) {
+            recorder.record_frame(cpu, input);
+        }
+        recorder.finish()
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut cpu: Cpu = create_cpu();
+        let movie: Movie = record(&mut cpu, false);
+        assert_eq!(movie.frames.len(), 20);
+        assert_eq!(movie.frame_hashes.len(), 20);
+        // the input made a difference
+        assert_ne!(movie.frame_hashes[0], movie.frame_hashes[1]);
+        let end: u64 = hash_state(&mut cpu);
+
+        let mut cpu: Cpu = create_cpu();
+        let mut player: MoviePlayer = MoviePlayer::start(&mut cpu, movie).unwrap();
+        player.play_to_end(&mut cpu).unwrap();
+        assert!(player.is_finished());
+        assert_eq!(player.get_position(), 20);
+        assert_eq!(hash_state(&mut cpu), end);
+        assert!(!player.step(&mut cpu).unwrap());
+    }
+
+    #[test]
+    fn test_replay_from_save_state() {
+        let mut cpu: Cpu = create_cpu();
+        for _ in 0..3 {
+            cpu.run_frame();
+        }
+        let movie: Movie = record(&mut cpu, true);
+        assert!(movie.save_state.is_some());
+
+        let mut cpu: Cpu = create_cpu();
+        let mut player: MoviePlayer = MoviePlayer::start(&mut cpu, movie).unwrap();
+        player.play_to_end(&mut cpu).unwrap();
+        assert_eq!(cpu.get_frame(), 23);
+    }
+
+    #[test]
+    fn test_desync() {
+        let mut cpu: Cpu = create_cpu();
+        let mut movie: Movie = record(&mut cpu, false);
+        movie.frames[5].buttons[0] ^= Button::Start.get_mask();
+
+        let mut cpu: Cpu = create_cpu();
+        let mut player: MoviePlayer = MoviePlayer::start(&mut cpu, movie).unwrap();
+        assert!(matches!(
+            player.play_to_end(&mut cpu),
+            Err(MovieError::Desync { frame: 5, .. })
+        ));
+        assert_eq!(player.get_position(), 5);
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut cpu: Cpu = create_cpu();
+        let mut movie: Movie = Movie::new(Region::Ntsc);
+        movie.four_score = true;
+        let mut recorder: MovieRecorder = MovieRecorder::start(&mut cpu, movie, false);
+        let input: FrameInput = FrameInput {
+            commands: 0,
+            buttons: [0, 0, Button::A.get_mask(), 0],
+        };
+        recorder.record_frame(&mut cpu, input);
+
+        // player 3 comes after player 1 on $4016
+        cpu.write(0x4016, 1);
+        cpu.write(0x4016, 0);
+        let bits: Vec<u8> = (0..10).map(|_| cpu.read(0x4016) & 1).collect();
+        assert_eq!(bits, vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::movie::{COMMAND_POWER, COMMAND_RESET, FrameInput, Movie, MovieError};
+use crate::region::Region;
+
+/// The gamepad buttons in the order BizHawk lists them, with their masks and mnemonics.
+const BUTTONS: [(&str, u8, char); 8] = [
+    ("Up", 0x10, 'U'),
+    ("Down", 0x20, 'D'),
+    ("Left", 0x40, 'L'),
+    ("Right", 0x80, 'R'),
+    ("Start", 0x08, 'S'),
+    ("Select", 0x04, 's'),
+    ("B", 0x02, 'B'),
+    ("A", 0x01, 'A'),
+];
+const COMMANDS: [(&str, u8, char); 2] =
+    [("Reset", COMMAND_RESET, 'r'), ("Power", COMMAND_POWER, 'P')];
+
+/// What a column of the input log stands for.
+#[derive(Clone, Copy)]
+enum Column {
+    Command(u8),
+    Button { player: usize, mask: u8 },
+}
+
+fn parse_column(key: &str) -> Result<Column, MovieError> {
+    if let Some((_, mask, _)) = COMMANDS.iter().find(|(name, _, _)| *name == key) {
+        return Ok(Column::Command(*mask));
+    }
+    let unsupported = || MovieError::UnsupportedDevice(key.to_string());
+    let (player, button): (&str, &str) = key.split_once(' ').ok_or_else(unsupported)?;
+    let player: usize = match player {
+        "P1" => 0,
+        "P2" => 1,
+        "P3" => 2,
+        "P4" => 3,
+        _ => return Err(unsupported()),
+    };
+    let (_, mask, _) = BUTTONS
+        .iter()
+        .find(|(name, _, _)| *name == button)
+        .ok_or_else(unsupported)?;
+    Ok(Column::Button {
+        player,
+        mask: *mask,
+    })
+}
+
+impl Movie {
+    /// BK2 reference: https://tasvideos.org/Bizhawk/BK2Format
+    ///
+    /// Parses a BizHawk input log, the "Input Log.txt" inside of a .bk2 archive. The `LogKey`
+    /// line names the columns, in groups starting with `#`, and every frame lists them between
+    /// the same `|` separators, with `.` for the released buttons: <pre>
+    /// LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|
+    /// |..|U......A|
+    /// </pre>
+    /// The archive itself needs to be extracted first. Its other files, like the header and
+    /// save state, aren't read.
+    pub fn from_bk2_input_log(text: &str) -> Result<Movie, MovieError> {
+        let mut out: Movie = Movie::new(Region::Ntsc);
+        out.controllers = [false, false];
+        let mut columns: Option<Vec<Column>> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line: &str = line.trim_end_matches('\r');
+            if let Some(keys) = line.strip_prefix("LogKey:") {
+                let parsed: Vec<Column> = keys
+                    .split('|')
+                    .map(|key| key.trim_start_matches('#'))
+                    .filter(|key| !key.is_empty())
+                    .map(parse_column)
+                    .collect::<Result<_, _>>()?;
+                for column in &parsed {
+                    if let Column::Button { player, .. } = column {
+                        match player {
+                            0 | 1 => out.controllers[*player] = true,
+                            _ => out.four_score = true,
+                        }
+                    }
+                }
+                columns = Some(parsed);
+            } else if line.starts_with('|') {
+                let invalid = MovieError::InvalidLine(index + 1);
+                let columns: &Vec<Column> = columns.as_ref().ok_or(invalid)?;
+                let states: Vec<char> = line.chars().filter(|c| *c != '|').collect();
+                if states.len() != columns.len() {
+                    return Err(MovieError::InvalidLine(index + 1));
+                }
+                let mut frame: FrameInput = FrameInput::default();
+                for (column, state) in columns.iter().zip(states) {
+                    if state == '.' || state == ' ' {
+                        continue;
+                    }
+                    match column {
+                        Column::Command(mask) => frame.commands |= mask,
+                        Column::Button { player, mask } => frame.buttons[*player] |= mask,
+                    }
+                }
+                out.frames.push(frame);
+            }
+        }
+        if out.four_score {
+            out.controllers = [true, true];
+        }
+        Ok(out)
+    }
+
+    /// Writes the movie's input as a BizHawk input log, see [Movie::from_bk2_input_log].
+    pub fn to_bk2_input_log(&self) -> String {
+        let players: usize = if self.four_score { 4 } else { 2 };
+        let players: Vec<usize> = (0..players)
+            .filter(|player| self.four_score || self.controllers[*player])
+            .collect();
+
+        let mut out: String = String::from("[Input]\nLogKey:#");
+        for (name, _, _) in COMMANDS {
+            out.push_str(&format!("{}|", name));
+        }
+        for player in &players {
+            out.push('#');
+            for (name, _, _) in BUTTONS {
+                out.push_str(&format!("P{} {}|", player + 1, name));
+            }
+        }
+        out.push('\n');
+
+        for frame in &self.frames {
+            out.push('|');
+            for (_, mask, mnemonic) in COMMANDS {
+                out.push(if frame.commands & mask != 0 {
+                    mnemonic
+                } else {
+                    '.'
+                });
+            }
+            out.push('|');
+            for player in &players {
+                for (_, mask, mnemonic) in BUTTONS {
+                    let pressed: bool = frame.buttons[*player] & mask != 0;
+                    out.push(if pressed { mnemonic } else { '.' });
+                }
+                out.push('|');
+            }
+            out.push('\n');
+        }
+        out.push_str("[/Input]\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movie::tests::create_inputs;
+
+    #[test]
+    fn test_parse() {
+        let text: &str = "[Input]\n\
+            LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|\
+            #P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|\n\
+            |.P|........|........|\n\
+            |..|U......A|.......A|\n\
+            |r.|...RS...|........|\n\
+            [/Input]\n";
+        let movie: Movie = Movie::from_bk2_input_log(text).unwrap();
+        assert_eq!(movie.controllers, [true, true]);
+        assert!(!movie.four_score);
+        let frames: Vec<(u8, u8, u8)> = movie
+            .frames
+            .iter()
+            .map(|frame| (frame.commands, frame.buttons[0], frame.buttons[1]))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                (COMMAND_POWER, 0, 0),
+                (0, 0x11, 0x01),
+                (COMMAND_RESET, 0x88, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            Movie::from_bk2_input_log("LogKey:#P1 Zapper X|\n"),
+            Err(MovieError::UnsupportedDevice(_))
+        ));
+        assert!(matches!(
+            Movie::from_bk2_input_log("LogKey:#P1 A|P1 B|\n|A|\n"),
+            Err(MovieError::InvalidLine(2))
+        ));
+        assert!(matches!(
+            Movie::from_bk2_input_log("|A|\n"),
+            Err(MovieError::InvalidLine(1))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie: Movie = Movie::new(Region::Ntsc);
+        movie.frames = create_inputs(10);
+        movie.frames[4].commands = COMMAND_RESET;
+        let text: String = movie.to_bk2_input_log();
+        assert!(text.contains("\n|r.|........|........|\n"));
+        assert_eq!(Movie::from_bk2_input_log(&text).unwrap(), movie);
+
+        movie.four_score = true;
+        movie.frames[2].buttons = [0x80, 0x40, 0x20, 0x10];
+        let text: String = movie.to_bk2_input_log();
+        assert!(text.contains("\n|..|...R....|..L.....|.D......|U.......|\n"));
+        assert_eq!(Movie::from_bk2_input_log(&text).unwrap(), movie);
+    }
+}
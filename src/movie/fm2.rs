@@ -0,0 +1,334 @@
+use crate::movie::{FrameInput, Movie, MovieError};
+use crate::region::Region;
+
+/// The gamepad buttons in the order FM2 lists them, from bit 7 to bit 0.
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+/// FM2 port types.
+const PORT_NONE: &str = "0";
+const PORT_GAMEPAD: &str = "1";
+const PORT_ZAPPER: &str = "2";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl Movie {
+    /// FM2 reference: https://fceux.com/web/help/fm2.html
+    ///
+    /// Parses an FCEUX movie. The header is made of `key value` lines, followed by one line per
+    /// frame: <pre>
+    /// |commands|port 0|port 1|port 2|
+    /// |0|R..U...A|........||
+    /// </pre>
+    /// where gamepads list their buttons as `RLDUTSBA`, with `.` or a space for the released
+    /// ones. With a Four Score, 4 gamepads take the place of ports 0 and 1. Movies starting
+    /// from a save state hold it base64 encoded, which has to be one of ours. Besides FCEUX's
+    /// keys, `frameHashes` holds the hashes to check the replay against, and `dendy` marks Dendy
+    /// movies, which `palFlag` can't tell apart from NTSC ones.
+    pub fn from_fm2(text: &str) -> Result<Movie, MovieError> {
+        let mut out: Movie = Movie::new(Region::Ntsc);
+        let mut dendy: bool = false;
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || MovieError::InvalidLine(index + 1);
+            let line: &str = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                out.frames.push(out.parse_frame(line).ok_or_else(invalid)?);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (key, value): (&str, &str) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "palFlag" => {
+                    out.region = if value == "1" {
+                        Region::Pal
+                    } else {
+                        Region::Ntsc
+                    };
+                }
+                "dendy" => dendy = value == "1",
+                "romFilename" => out.rom_filename = value.to_string(),
+                "romChecksum" => out.rom_checksum = value.to_string(),
+                "guid" => out.guid = value.to_string(),
+                "rerecordCount" => out.rerecord_count = value.parse().map_err(|_| invalid())?,
+                "fourscore" => out.four_score = value == "1",
+                "port0" | "port1" => {
+                    let port: usize = (key == "port1") as usize;
+                    out.controllers[port] = match value {
+                        PORT_NONE => false,
+                        PORT_GAMEPAD => true,
+                        PORT_ZAPPER => return Err(MovieError::UnsupportedDevice("Zapper".into())),
+                        _ => return Err(MovieError::UnsupportedDevice(value.to_string())),
+                    };
+                }
+                "port2" if value != PORT_NONE => {
+                    return Err(MovieError::UnsupportedDevice(format!(
+                        "expansion {}",
+                        value
+                    )));
+                }
+                "binary" if value == "1" => return Err(invalid()),
+                "comment" => out.comments.push(value.to_string()),
+                "savestate" => {
+                    let encoded: &str = value.strip_prefix("base64:").unwrap_or(value);
+                    out.save_state = Some(decode_base64(encoded).ok_or_else(invalid)?);
+                }
+                "frameHashes" => {
+                    out.frame_hashes = value
+                        .split_whitespace()
+                        .map(|hash| u64::from_str_radix(hash, 16))
+                        .collect::<Result<Vec<u64>, _>>()
+                        .map_err(|_| invalid())?;
+                }
+                _ => {}
+            }
+        }
+        if dendy {
+            out.region = Region::Dendy;
+        }
+        Ok(out)
+    }
+
+    fn parse_frame(&self, line: &str) -> Option<FrameInput> {
+        let fields: Vec<&str> = line.split('|').collect();
+        let mut out: FrameInput = FrameInput {
+            commands: fields.get(1)?.trim().parse().ok()?,
+            buttons: [0; 4],
+        };
+        let gamepads: Vec<(usize, &str)> = if self.four_score {
+            (0..4)
+                .map(|player| Some((player, *fields.get(2 + player)?)))
+                .collect::<Option<_>>()?
+        } else {
+            (0..2)
+                .filter(|port| self.controllers[*port])
+                .map(|port| Some((port, *fields.get(2 + port)?)))
+                .collect::<Option<_>>()?
+        };
+        for (player, field) in gamepads {
+            out.buttons[player] = parse_gamepad(field)?;
+        }
+        Some(out)
+    }
+
+    /// Writes the movie as FM2, see [Movie::from_fm2].
+    pub fn to_fm2(&self) -> String {
+        let mut out: String = String::new();
+        let mut header = |key: &str, value: &str| {
+            out.push_str(key);
+            out.push(' ');
+            out.push_str(value);
+            out.push('\n');
+        };
+        header("version", "3");
+        header("emuVersion", "22020");
+        header("rerecordCount", &self.rerecord_count.to_string());
+        header(
+            "palFlag",
+            if self.region == Region::Pal { "1" } else { "0" },
+        );
+        if self.region == Region::Dendy {
+            header("dendy", "1");
+        }
+        header("romFilename", &self.rom_filename);
+        header("romChecksum", &self.rom_checksum);
+        header("guid", &self.guid);
+        header("fourscore", if self.four_score { "1" } else { "0" });
+        header("microphone", "0");
+        for (port, key) in ["port0", "port1"].iter().enumerate() {
+            header(
+                key,
+                if self.controllers[port] {
+                    PORT_GAMEPAD
+                } else {
+                    PORT_NONE
+                },
+            );
+        }
+        header("port2", PORT_NONE);
+        header("FDS", "0");
+        header("NewPPU", "0");
+        for comment in &self.comments {
+            header("comment", comment);
+        }
+        if let Some(state) = &self.save_state {
+            header("savestate", &format!("base64:{}", encode_base64(state)));
+        }
+        if !self.frame_hashes.is_empty() {
+            let hashes: Vec<String> = self
+                .frame_hashes
+                .iter()
+                .map(|hash| format!("{:016x}", hash))
+                .collect();
+            header("frameHashes", &hashes.join(" "));
+        }
+
+        for frame in &self.frames {
+            out.push_str(&format!("|{}|", frame.commands));
+            if self.four_score {
+                for buttons in frame.buttons {
+                    out.push_str(&format_gamepad(buttons));
+                    out.push('|');
+                }
+            } else {
+                for port in 0..2 {
+                    if self.controllers[port] {
+                        out.push_str(&format_gamepad(frame.buttons[port]));
+                    }
+                    out.push('|');
+                }
+            }
+            out.push_str("|\n");
+        }
+        out
+    }
+}
+
+fn parse_gamepad(field: &str) -> Option<u8> {
+    if field.len() != BUTTONS.len() {
+        return None;
+    }
+    let pressed = |(i, c): (usize, u8)| ((c != b'.' && c != b' ') as u8) << (7 - i);
+    Some(field.bytes().enumerate().map(pressed).sum())
+}
+
+fn format_gamepad(buttons: u8) -> String {
+    BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            if (buttons >> (7 - i)) & 1 == 1 {
+                *c as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut out: String = String::new();
+    for chunk in bytes.chunks(3) {
+        let group: u32 = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |group, (i, byte)| group | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(group >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = text
+        .trim_end_matches('=')
+        .bytes()
+        .map(|c| BASE64.iter().position(|digit| *digit == c).map(|i| i as u8))
+        .collect::<Option<_>>()?;
+    let mut out: Vec<u8> = Vec::new();
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let group: u32 = chunk.iter().enumerate().fold(0, |group, (i, digit)| {
+            group | (*digit as u32) << (18 - 6 * i)
+        });
+        out.extend((0..chunk.len() - 1).map(|i| (group >> (16 - 8 * i)) as u8));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movie::COMMAND_RESET;
+    use crate::movie::tests::create_inputs;
+
+    #[test]
+    fn test_parse() {
+        let text: &str = "version 3\n\
+            emuVersion 22020\n\
+            rerecordCount 42\n\
+            palFlag 0\n\
+            romFilename Super Mario Bros.\n\
+            romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+            guid 36DAA5C2-8F3C-2A51-2C5B-5B2DBF1B5A8C\n\
+            fourscore 0\n\
+            port0 1\n\
+            port1 0\n\
+            port2 0\n\
+            comment author somebody\n\
+            |1|........|||\n\
+            |0|R......A|||\n\
+            |0|.L.U S B|||\n";
+        let movie: Movie = Movie::from_fm2(text).unwrap();
+        assert_eq!(movie.rom_filename, "Super Mario Bros.");
+        assert_eq!(movie.rerecord_count, 42);
+        assert_eq!(movie.controllers, [true, false]);
+        assert_eq!(movie.comments, vec!["author somebody"]);
+        let buttons: Vec<u8> = movie.frames.iter().map(|frame| frame.buttons[0]).collect();
+        assert_eq!(buttons, vec![0, 0x81, 0x55]);
+        assert_eq!(movie.frames[0].commands, COMMAND_RESET);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            Movie::from_fm2("port0 1\n|0|R...|||\n"),
+            Err(MovieError::InvalidLine(2))
+        ));
+        assert!(matches!(
+            Movie::from_fm2("port1 2\n"),
+            Err(MovieError::UnsupportedDevice(_))
+        ));
+        assert!(matches!(
+            Movie::from_fm2("savestate base64:A\n"),
+            Err(MovieError::InvalidLine(1))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie: Movie = Movie::new(Region::Pal);
+        movie.rom_filename = "game".to_string();
+        movie.comments.push("author somebody".to_string());
+        movie.save_state = Some(vec![1, 2, 3, 4, 250]);
+        movie.frames = create_inputs(10);
+        movie.frame_hashes = (0..10).map(|i| i * 0x1234_5678_9ABC).collect();
+        assert_eq!(Movie::from_fm2(&movie.to_fm2()).unwrap(), movie);
+
+        movie.four_score = true;
+        movie.frames[3].buttons = [1, 2, 4, 8];
+        assert_eq!(Movie::from_fm2(&movie.to_fm2()).unwrap(), movie);
+        assert!(
+            movie
+                .to_fm2()
+                .contains("\n|0|.......A|......B.|.....S..|....T...||\n")
+        );
+
+        movie.region = Region::Dendy;
+        assert!(movie.to_fm2().contains("\npalFlag 0\ndendy 1\n"));
+        assert_eq!(Movie::from_fm2(&movie.to_fm2()).unwrap(), movie);
+
+        movie.four_score = false;
+        movie.controllers = [false, true];
+        movie.frames = vec![FrameInput {
+            commands: 0,
+            buttons: [0, 0x80, 0, 0],
+        }];
+        assert!(movie.to_fm2().ends_with("\n|0||R.......||\n"));
+    }
+
+    #[test]
+    fn test_base64() {
+        for bytes in [vec![], vec![0xFF], vec![1, 2], vec![1, 2, 3], vec![9; 100]] {
+            assert_eq!(decode_base64(&encode_base64(&bytes)).unwrap(), bytes);
+        }
+        assert_eq!(encode_base64(b"Man"), "TWFu");
+        assert_eq!(encode_base64(b"Ma"), "TWE=");
+        assert_eq!(decode_base64("TWE=").unwrap(), b"Ma");
+    }
+}
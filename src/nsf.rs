@@ -0,0 +1,618 @@
+use crate::cpu::Cpu;
+use crate::mapper::nsf::NsfMapper;
+use crate::region::Region;
+use crate::rom::TimingMode;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+const HEADER_SIZE: usize = 0x80;
+const MAGIC: [u8; 5] = [b'N', b'E', b'S', b'M', 0x1A];
+const NSFE_MAGIC: [u8; 4] = [b'N', b'S', b'F', b'E'];
+/// Play routine periods used when a file doesn't specify one, in microseconds.
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+#[derive(Debug)]
+pub enum NsfError {
+    /// The file starts with neither `NESM<EOF>` nor `NSFE`.
+    InvalidMagic,
+    /// The file, or one of its NSFe chunks, is shorter than it claims.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// A mandatory NSFe chunk is missing.
+    MissingChunk(&'static str),
+    /// The NSFe file contains a mandatory chunk, one whose ID starts with an uppercase letter,
+    /// which isn't understood.
+    UnknownChunk(String),
+    /// The data can't be loaded at the given address.
+    InvalidLoadAddress(u16),
+    /// The file has no track with the given index.
+    InvalidTrack(u8),
+    Io(std::io::Error),
+}
+
+impl Display for NsfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NsfError::InvalidMagic => write!(f, "not an NSF or NSFe file"),
+            NsfError::Truncated { expected, actual } => write!(
+                f,
+                "NSF is truncated: expected {} bytes, got {}",
+                expected, actual
+            ),
+            NsfError::MissingChunk(id) => write!(f, "NSFe file lacks a {} chunk", id),
+            NsfError::UnknownChunk(id) => write!(f, "unsupported NSFe chunk: {}", id),
+            NsfError::InvalidLoadAddress(addr) => write!(f, "invalid load address: ${:04X}", addr),
+            NsfError::InvalidTrack(track) => write!(f, "no such track: {}", track),
+            NsfError::Io(err) => write!(f, "failed to read NSF: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+impl From<std::io::Error> for NsfError {
+    fn from(err: std::io::Error) -> NsfError {
+        NsfError::Io(err)
+    }
+}
+
+/// The expansion audio chips a file uses, stored as flags in byte $7B of NSF headers: <pre>
+/// 1 << 5 => Sunsoft 5B
+/// 1 << 4 => Namco 163
+/// 1 << 3 => MMC5
+/// 1 << 2 => FDS
+/// 1 << 1 => VRC7
+/// 1      => VRC6
+/// </pre>
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ExpansionChips {
+    pub vrc6: bool,
+    pub vrc7: bool,
+    pub fds: bool,
+    pub mmc5: bool,
+    pub namco163: bool,
+    pub sunsoft5b: bool,
+}
+
+impl ExpansionChips {
+    pub fn from_bits(bits: u8) -> ExpansionChips {
+        ExpansionChips {
+            vrc6: bits & 1 == 1,
+            vrc7: (bits >> 1) & 1 == 1,
+            fds: (bits >> 2) & 1 == 1,
+            mmc5: (bits >> 3) & 1 == 1,
+            namco163: (bits >> 4) & 1 == 1,
+            sunsoft5b: (bits >> 5) & 1 == 1,
+        }
+    }
+}
+
+/// NSF reference: https://www.nesdev.org/wiki/NSF
+///
+/// A music rip: the sound code and data of a game, along with the addresses of a routine which
+/// sets up a track and one which gets called at a fixed rate to play it. Holds files in both
+/// the NSF and NSFe formats, the latter adding per-track metadata.
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Who ripped the music, only stored by NSFe files.
+    pub ripper: String,
+    pub track_count: u8,
+    /// Index of the track to play first, starting at 0.
+    pub starting_track: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    /// The 4 KiB banks initially mapped to $8000-$FFFF, for files which use bankswitching.
+    pub banks: Option<[u8; 8]>,
+    /// Time between play routine calls, in microseconds.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub timing: TimingMode,
+    pub chips: ExpansionChips,
+    /// Names of the tracks, only stored by NSFe files.
+    pub track_labels: Vec<String>,
+    /// Lengths of the tracks in milliseconds, if known.
+    pub track_times: Vec<Option<u32>>,
+    /// Lengths of the tracks' fade-outs in milliseconds, if known.
+    pub track_fades: Vec<Option<u32>>,
+    pub data: Vec<u8>,
+}
+
+/// Decodes a NUL terminated or padded string.
+fn parse_string(bytes: &[u8]) -> String {
+    let end: usize = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn parse_timing(flags: u8) -> TimingMode {
+    if flags & 0b10 == 0b10 {
+        TimingMode::MultiRegion
+    } else if flags & 1 == 1 {
+        TimingMode::Pal
+    } else {
+        TimingMode::Ntsc
+    }
+}
+
+/// Parses a list of NUL terminated strings.
+fn parse_strings(bytes: &[u8]) -> Vec<String> {
+    let mut out: Vec<String> = bytes.split(|&b| b == 0).map(parse_string).collect();
+    // the last string is terminated as well
+    if bytes.last() == Some(&0) {
+        out.pop();
+    }
+    out
+}
+
+/// Parses a list of 32-bit lengths, where negative ones are unknown.
+fn parse_times(bytes: &[u8]) -> Vec<Option<u32>> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| {
+            let time: i32 = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            u32::try_from(time).ok()
+        })
+        .collect()
+}
+
+impl Nsf {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Nsf, NsfError> {
+        let out: Nsf = if bytes.starts_with(&MAGIC) {
+            Nsf::parse_nsf(bytes)?
+        } else if bytes.starts_with(&NSFE_MAGIC) {
+            Nsf::parse_nsfe(bytes)?
+        } else {
+            return Err(NsfError::InvalidMagic);
+        };
+
+        // FDS rips without bankswitching may start in the RAM at $6000
+        let lowest: u16 = if out.chips.fds && out.banks.is_none() {
+            0x6000
+        } else {
+            0x8000
+        };
+        if out.load_addr < lowest {
+            return Err(NsfError::InvalidLoadAddress(out.load_addr));
+        }
+        Ok(out)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Nsf, NsfError> {
+        Nsf::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// NSF header layout: <pre>
+    /// $05     => version
+    /// $06     => track count
+    /// $07     => starting track, from 1
+    /// $08-$0D => load, init and play addresses
+    /// $0E-$6D => title, artist and copyright, 32 bytes each
+    /// $6E-$6F => NTSC play period
+    /// $70-$77 => initial banks
+    /// $78-$79 => PAL play period
+    /// $7A     => 1 << 1: both regions, 1: PAL
+    /// $7B     => expansion chips
+    /// </pre>
+    fn parse_nsf(bytes: &[u8]) -> Result<Nsf, NsfError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(NsfError::Truncated {
+                expected: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let banks: [u8; 8] = bytes[0x70..0x78].try_into().unwrap();
+        Ok(Nsf {
+            title: parse_string(&bytes[0x0E..0x2E]),
+            artist: parse_string(&bytes[0x2E..0x4E]),
+            copyright: parse_string(&bytes[0x4E..0x6E]),
+            ripper: String::new(),
+            track_count: bytes[6],
+            starting_track: bytes[7].saturating_sub(1),
+            load_addr: word(0x08),
+            init_addr: word(0x0A),
+            play_addr: word(0x0C),
+            banks: if banks == [0; 8] { None } else { Some(banks) },
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            timing: parse_timing(bytes[0x7A]),
+            chips: ExpansionChips::from_bits(bytes[0x7B]),
+            track_labels: Vec::new(),
+            track_times: Vec::new(),
+            track_fades: Vec::new(),
+            data: bytes[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    /// NSFe reference: https://www.nesdev.org/wiki/NSFe
+    ///
+    /// A sequence of chunks, each made of a 32-bit length, a 4 character ID and the data.
+    fn parse_nsfe(bytes: &[u8]) -> Result<Nsf, NsfError> {
+        let mut out: Nsf = Nsf {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            track_count: 1,
+            starting_track: 0,
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            banks: None,
+            ntsc_speed: DEFAULT_NTSC_SPEED,
+            pal_speed: DEFAULT_PAL_SPEED,
+            timing: TimingMode::Ntsc,
+            chips: ExpansionChips::default(),
+            track_labels: Vec::new(),
+            track_times: Vec::new(),
+            track_fades: Vec::new(),
+            data: Vec::new(),
+        };
+        let mut has_info: bool = false;
+        let mut has_data: bool = false;
+
+        let mut offset: usize = NSFE_MAGIC.len();
+        loop {
+            if bytes.len() < offset + 8 {
+                return Err(NsfError::Truncated {
+                    expected: offset + 8,
+                    actual: bytes.len(),
+                });
+            }
+            let size: usize =
+                u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            let id: &[u8] = &bytes[offset + 4..offset + 8];
+            offset += 8;
+            if bytes.len() < offset + size {
+                return Err(NsfError::Truncated {
+                    expected: offset + size,
+                    actual: bytes.len(),
+                });
+            }
+            let chunk: &[u8] = &bytes[offset..offset + size];
+            offset += size;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NsfError::Truncated {
+                            expected: 8,
+                            actual: chunk.len(),
+                        });
+                    }
+                    out.load_addr = u16::from_le_bytes([chunk[0], chunk[1]]);
+                    out.init_addr = u16::from_le_bytes([chunk[2], chunk[3]]);
+                    out.play_addr = u16::from_le_bytes([chunk[4], chunk[5]]);
+                    out.timing = parse_timing(chunk[6]);
+                    out.chips = ExpansionChips::from_bits(chunk[7]);
+                    out.track_count = chunk.get(8).copied().unwrap_or(1);
+                    out.starting_track = chunk.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => {
+                    out.data = chunk.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => {
+                    let mut banks: [u8; 8] = [0; 8];
+                    let len: usize = chunk.len().min(8);
+                    banks[..len].copy_from_slice(&chunk[..len]);
+                    out.banks = Some(banks);
+                }
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        out.ntsc_speed = u16::from_le_bytes([chunk[0], chunk[1]]);
+                    }
+                    if chunk.len() >= 4 {
+                        out.pal_speed = u16::from_le_bytes([chunk[2], chunk[3]]);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = parse_strings(chunk).into_iter();
+                    out.title = strings.next().unwrap_or_default();
+                    out.artist = strings.next().unwrap_or_default();
+                    out.copyright = strings.next().unwrap_or_default();
+                    out.ripper = strings.next().unwrap_or_default();
+                }
+                b"tlbl" => out.track_labels = parse_strings(chunk),
+                b"time" => out.track_times = parse_times(chunk),
+                b"fade" => out.track_fades = parse_times(chunk),
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(NsfError::UnknownChunk(
+                        String::from_utf8_lossy(id).into_owned(),
+                    ));
+                }
+                // optional chunks may be skipped
+                _ => {}
+            }
+        }
+
+        if !has_info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if !has_data {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+        Ok(out)
+    }
+
+    pub fn get_region(&self) -> Region {
+        match self.timing {
+            TimingMode::Ntsc | TimingMode::MultiRegion => Region::Ntsc,
+            TimingMode::Pal => Region::Pal,
+            TimingMode::Dendy => Region::Dendy,
+        }
+    }
+
+    /// Time between play routine calls in the given region, in microseconds.
+    pub fn get_play_speed(&self, region: Region) -> u16 {
+        match region {
+            Region::Ntsc if self.ntsc_speed != 0 => self.ntsc_speed,
+            Region::Ntsc => DEFAULT_NTSC_SPEED,
+            Region::Pal | Region::Dendy if self.pal_speed != 0 => self.pal_speed,
+            Region::Pal | Region::Dendy => DEFAULT_PAL_SPEED,
+        }
+    }
+}
+
+/// Plays an NSF on a console without a PPU. A driver program in the cartridge space calls the
+/// init routine for the selected track, then idles, calling the play routine whenever the
+/// cartridge's timer says so.
+pub struct NsfPlayer {
+    nsf: Nsf,
+    cpu: Cpu,
+    track: u8,
+}
+
+impl NsfPlayer {
+    /// Creates the player and starts the file's starting track.
+    pub fn new(nsf: Nsf, region: Region) -> NsfPlayer {
+        let mut cpu: Cpu = Cpu::new();
+        cpu.set_region(region);
+        let track: u8 = if nsf.starting_track < nsf.track_count {
+            nsf.starting_track
+        } else {
+            0
+        };
+        let mut out: NsfPlayer = NsfPlayer { nsf, cpu, track };
+        out.start_track();
+        out
+    }
+
+    pub fn get_nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn get_track(&self) -> u8 {
+        self.track
+    }
+
+    /// Restarts playback at the given track, counting from 0.
+    pub fn select_track(&mut self, track: u8) -> Result<(), NsfError> {
+        if track >= self.nsf.track_count {
+            return Err(NsfError::InvalidTrack(track));
+        }
+        self.track = track;
+        self.start_track();
+        Ok(())
+    }
+
+    /// Puts the console into the state the init routine expects: fresh cartridge banks and
+    /// RAM, cleared internal RAM, and silenced APU channels with the frame interrupt disabled.
+    fn start_track(&mut self) {
+        let region: Region = self.cpu.get_region();
+        let mapper: NsfMapper = NsfMapper::new(&self.nsf, self.track, region);
+        self.cpu.insert_cartridge(Box::new(mapper));
+        for addr in 0x0000..0x0800 {
+            self.cpu.write(addr, 0);
+        }
+        for addr in 0x4000..=0x4013 {
+            self.cpu.write(addr, 0);
+        }
+        self.cpu.write(0x4015, 0x00);
+        self.cpu.write(0x4015, 0x0F);
+        self.cpu.write(0x4017, 0x40);
+        self.cpu.reset();
+    }
+
+    pub fn run_frame(&mut self) {
+        self.cpu.run_frame();
+    }
+
+    pub fn get_cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn get_cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Gives up track selection, keeping the console playing the current track.
+    pub fn into_cpu(self) -> Cpu {
+        self.cpu
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Creates an NSF whose init routine stores the track and region at $00-$01, and whose play
+    /// routine counts its calls at $02. Both get loaded at $8000.
+    pub fn create_nsf(banks: [u8; 8], chips: u8) -> Vec<u8> {
+        let mut out: Vec<u8> = MAGIC.to_vec();
+        out.extend([1, 3, 2]);
+        out.extend([0x00, 0x80, 0x00, 0x80, 0x05, 0x80]);
+        for name in ["Title", "Artist", "Copyright"] {
+            let mut field: Vec<u8> = name.as_bytes().to_vec();
+            field.resize(32, 0);
+            out.extend(field);
+        }
+        out.extend(16666u16.to_le_bytes());
+        out.extend(banks);
+        out.extend(20000u16.to_le_bytes());
+        out.extend([0b10, chips, 0, 0, 0, 0]);
+        // STA $00; STX $01; RTS; INC $02; RTS
+        out.extend([0x85, 0x00, 0x86, 0x01, 0x60, 0xE6, 0x02, 0x60]);
+        out
+    }
+
+    fn create_chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = (data.len() as u32).to_le_bytes().to_vec();
+        out.extend(id);
+        out.extend(data);
+        out
+    }
+
+    #[test]
+    fn test_nsf() {
+        let nsf: Nsf = Nsf::from_bytes(&create_nsf([0; 8], 0b10_0001)).unwrap();
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.copyright, "Copyright");
+        assert_eq!(nsf.track_count, 3);
+        assert_eq!(nsf.starting_track, 1);
+        assert_eq!(nsf.load_addr, 0x8000);
+        assert_eq!(nsf.play_addr, 0x8005);
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.timing, TimingMode::MultiRegion);
+        assert_eq!(nsf.get_region(), Region::Ntsc);
+        assert_eq!(nsf.get_play_speed(Region::Ntsc), 16666);
+        assert_eq!(nsf.get_play_speed(Region::Pal), 20000);
+        assert!(nsf.chips.vrc6 && nsf.chips.sunsoft5b && !nsf.chips.fds);
+        assert_eq!(nsf.data.len(), 8);
+
+        let nsf: Nsf = Nsf::from_bytes(&create_nsf([0, 1, 2, 3, 4, 5, 6, 7], 0)).unwrap();
+        assert_eq!(nsf.banks, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+
+        let mut bytes: Vec<u8> = create_nsf([0; 8], 0);
+        bytes[0x09] = 0x70;
+        assert!(matches!(
+            Nsf::from_bytes(&bytes),
+            Err(NsfError::InvalidLoadAddress(0x7000))
+        ));
+        // FDS rips may load into RAM
+        bytes[0x7B] = 0b100;
+        assert!(Nsf::from_bytes(&bytes).is_ok());
+
+        assert!(matches!(
+            Nsf::from_bytes(&bytes[..0x40]),
+            Err(NsfError::Truncated { .. })
+        ));
+        assert!(matches!(
+            Nsf::from_bytes(b"NES\x1A"),
+            Err(NsfError::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn test_nsfe() {
+        let mut bytes: Vec<u8> = NSFE_MAGIC.to_vec();
+        bytes.extend(create_chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x05, 0x80, 1, 0b100, 4, 2],
+        ));
+        bytes.extend(create_chunk(b"DATA", &[0x60; 16]));
+        bytes.extend(create_chunk(b"BANK", &[1, 2]));
+        bytes.extend(create_chunk(b"RATE", &10000u16.to_le_bytes()));
+        bytes.extend(create_chunk(b"auth", b"Game\0Artist\0Copyright\0Ripper\0"));
+        bytes.extend(create_chunk(b"tlbl", b"Intro\0Stage\0"));
+        let mut times: Vec<u8> = 90000i32.to_le_bytes().to_vec();
+        times.extend((-1i32).to_le_bytes());
+        bytes.extend(create_chunk(b"time", &times));
+        bytes.extend(create_chunk(b"xtra", &[1, 2, 3]));
+        bytes.extend(create_chunk(b"NEND", &[]));
+
+        let nsf: Nsf = Nsf::from_bytes(&bytes).unwrap();
+        assert_eq!(nsf.play_addr, 0x8005);
+        assert_eq!(nsf.timing, TimingMode::Pal);
+        assert_eq!(nsf.get_region(), Region::Pal);
+        assert!(nsf.chips.fds);
+        assert_eq!(nsf.track_count, 4);
+        assert_eq!(nsf.starting_track, 2);
+        assert_eq!(nsf.data, vec![0x60; 16]);
+        assert_eq!(nsf.banks, Some([1, 2, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.get_play_speed(Region::Ntsc), 10000);
+        assert_eq!(nsf.get_play_speed(Region::Pal), DEFAULT_PAL_SPEED);
+        assert_eq!(nsf.title, "Game");
+        assert_eq!(nsf.ripper, "Ripper");
+        assert_eq!(nsf.track_labels, vec!["Intro", "Stage"]);
+        assert_eq!(nsf.track_times, vec![Some(90000), None]);
+
+        // unknown mandatory chunks
+        let mut broken: Vec<u8> = bytes[..bytes.len() - 8].to_vec();
+        broken.extend(create_chunk(b"XTRA", &[]));
+        assert!(matches!(
+            Nsf::from_bytes(&broken),
+            Err(NsfError::UnknownChunk(id)) if id == "XTRA"
+        ));
+        // missing data
+        let mut broken: Vec<u8> = NSFE_MAGIC.to_vec();
+        broken.extend(create_chunk(b"INFO", &[0x00, 0x80, 0, 0x80, 0, 0x80, 0, 0]));
+        broken.extend(create_chunk(b"NEND", &[]));
+        assert!(matches!(
+            Nsf::from_bytes(&broken),
+            Err(NsfError::MissingChunk("DATA"))
+        ));
+        // missing end
+        assert!(matches!(
+            Nsf::from_bytes(&bytes[..bytes.len() - 8]),
+            Err(NsfError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_player() {
+        let nsf: Nsf = Nsf::from_bytes(&create_nsf([0; 8], 0)).unwrap();
+        let mut player: NsfPlayer = NsfPlayer::new(nsf, Region::Ntsc);
+        assert_eq!(player.get_track(), 1);
+        assert_eq!(player.get_nsf().track_count, 3);
+        for _ in 0..10 {
+            player.run_frame();
+        }
+        assert_eq!(player.get_cpu().read(0x00), 1);
+        assert_eq!(player.get_cpu().read(0x01), 0);
+        // the play routine runs about once per frame
+        let calls: u8 = player.get_cpu().read(0x02);
+        assert!((9..=11).contains(&calls), "{} calls", calls);
+
+        player.select_track(2).unwrap();
+        assert_eq!(player.get_cpu().read(0x02), 0);
+        player.run_frame();
+        assert_eq!(player.get_cpu().read(0x00), 2);
+        assert!(matches!(
+            player.select_track(3),
+            Err(NsfError::InvalidTrack(3))
+        ));
+
+        // the region is passed in X
+        let nsf: Nsf = Nsf::from_bytes(&create_nsf([0; 8], 0)).unwrap();
+        let mut player: NsfPlayer = NsfPlayer::new(nsf, Region::Pal);
+        player.run_frame();
+        assert_eq!(player.get_cpu().read(0x01), 1);
+    }
+
+    #[test]
+    fn test_subroutines() {
+        let mut bytes: Vec<u8> = create_nsf([0; 8], 0);
+        bytes.truncate(bytes.len() - 8);
+        bytes[0x0C] = 0x06;
+        // JSR $800A; STA $00; RTS; INC $02; RTS; NOP; LDA #$42; RTS
+        bytes.extend([
+            0x20, 0x0A, 0x80, 0x85, 0x00, 0x60, 0xE6, 0x02, 0x60, 0xEA, 0xA9, 0x42, 0x60,
+        ]);
+        let nsf: Nsf = Nsf::from_bytes(&bytes).unwrap();
+        let mut player: NsfPlayer = NsfPlayer::new(nsf, Region::Ntsc);
+        for _ in 0..3 {
+            player.run_frame();
+        }
+        assert_eq!(player.get_cpu().read(0x00), 0x42);
+        assert!(player.get_cpu().read(0x02) >= 2);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::palette::{FRAME_HEIGHT, FRAME_WIDTH, Palette};
+use std::f32::consts::PI;
+
+/// Number of signal samples the PPU generates for every pixel.
+const SAMPLES_PER_PIXEL: usize = 8;
+/// Length of one color subcarrier cycle, in signal samples.
+const SAMPLES_PER_CYCLE: usize = 12;
+/// Number of PPU dots in a full NTSC scanline.
+const DOTS_PER_SCANLINE: usize = 341;
+/// Number of PPU dots in a full NTSC frame. Odd frames are one dot shorter while rendering.
+const DOTS_PER_FRAME: usize = DOTS_PER_SCANLINE * 262;
+
+/// Number of output pixels the filter produces per scanline.
+pub const NTSC_OUTPUT_WIDTH: usize = FRAME_WIDTH * 2;
+
+/// Signal levels reference: https://www.nesdev.org/wiki/NTSC_video
+///
+/// Voltages of the low and high parts of the square wave for each of the four luma levels.
+const LEVELS_LOW: [f32; 4] = [0.228, 0.312, 0.552, 0.880];
+const LEVELS_HIGH: [f32; 4] = [0.616, 0.840, 1.100, 1.100];
+const LEVEL_BLACK: f32 = LEVELS_LOW[1];
+const LEVEL_WHITE: f32 = LEVELS_HIGH[3];
+/// Factor by which the emphasis bits attenuate the signal during their part of the color cycle.
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+/// Phase shift between the generated square waves and the decoder's I/Q axes, in radians.
+const HUE_OFFSET: f32 = 2.0 * PI / 3.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NtscPreset {
+    /// Luma and chroma share a single signal, producing color artifacts and dot crawl.
+    Composite,
+    /// Luma and chroma are transmitted separately; colors bleed but there are no artifacts.
+    SVideo,
+    /// The palette colors are output as-is.
+    Rgb,
+}
+
+/// Converts the PPU's palette-index frames into RGB images by simulating the NTSC signal
+/// generated by the 2C02 and decoding it the way a television would.
+pub struct NtscFilter {
+    preset: NtscPreset,
+    palette: Palette,
+    pub hue: f32,
+    pub saturation: f32,
+    pub brightness: f32,
+    /// Signal phase of the first dot of the next frame, in samples. Advances every frame, which
+    /// makes the artifact patterns crawl.
+    frame_phase: usize,
+    /// Whether the next frame is an odd one, which skips a dot.
+    odd_frame: bool,
+    /// Normalized voltage of every pixel value at every phase of the color cycle.
+    signal: Vec<[f32; SAMPLES_PER_CYCLE]>,
+}
+
+impl NtscFilter {
+    pub fn new(preset: NtscPreset) -> NtscFilter {
+        let mut signal: Vec<[f32; SAMPLES_PER_CYCLE]> = vec![[0.0; SAMPLES_PER_CYCLE]; 512];
+        for (pixel, levels) in signal.iter_mut().enumerate() {
+            for (phase, level) in levels.iter_mut().enumerate() {
+                *level = (generate_signal(pixel as u16, phase) - LEVEL_BLACK)
+                    / (LEVEL_WHITE - LEVEL_BLACK);
+            }
+        }
+
+        NtscFilter {
+            preset,
+            palette: Palette::new(),
+            hue: 0.0,
+            saturation: 1.0,
+            brightness: 1.0,
+            frame_phase: 0,
+            odd_frame: false,
+            signal,
+        }
+    }
+
+    pub fn get_preset(&self) -> NtscPreset {
+        self.preset
+    }
+
+    pub fn set_preset(&mut self, preset: NtscPreset) {
+        self.preset = preset
+    }
+
+    /// Sets the palette used by the [NtscPreset::Rgb] preset.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette
+    }
+
+    /// Filters a full frame of pixels (see [crate::palette::apply_mask]) into packed 24-bit RGB.
+    /// The output is [NTSC_OUTPUT_WIDTH] pixels wide and [FRAME_HEIGHT] pixels high. Panics if
+    /// the frame isn't [FRAME_WIDTH] by [FRAME_HEIGHT] pixels.
+    pub fn filter_frame(&mut self, frame: &[u16]) -> Vec<u8> {
+        assert_eq!(
+            frame.len(),
+            FRAME_WIDTH * FRAME_HEIGHT,
+            "frames have to be {}x{} pixels",
+            FRAME_WIDTH,
+            FRAME_HEIGHT
+        );
+        let mut out: Vec<u8> = vec![0; NTSC_OUTPUT_WIDTH * FRAME_HEIGHT * 3];
+        let mut line_signal: Vec<f32> = vec![0.0; FRAME_WIDTH * SAMPLES_PER_PIXEL];
+        let mut line_luma: Vec<f32> = vec![0.0; FRAME_WIDTH * SAMPLES_PER_PIXEL];
+
+        for y in 0..FRAME_HEIGHT {
+            let line: &[u16] = &frame[y * FRAME_WIDTH..(y + 1) * FRAME_WIDTH];
+            let out_line: &mut [u8] =
+                &mut out[y * NTSC_OUTPUT_WIDTH * 3..(y + 1) * NTSC_OUTPUT_WIDTH * 3];
+
+            if self.preset == NtscPreset::Rgb {
+                for (x, rgb) in out_line.chunks_exact_mut(3).enumerate() {
+                    rgb.copy_from_slice(&self.palette.get_color(line[x / 2]));
+                }
+                continue;
+            }
+
+            let phase: usize =
+                (self.frame_phase + y * DOTS_PER_SCANLINE * SAMPLES_PER_PIXEL) % SAMPLES_PER_CYCLE;
+            for (x, pixel) in line.iter().enumerate() {
+                let levels: &[f32; SAMPLES_PER_CYCLE] = &self.signal[(*pixel & 0x1FF) as usize];
+                let luma: f32 = levels.iter().sum::<f32>() / SAMPLES_PER_CYCLE as f32;
+                for p in 0..SAMPLES_PER_PIXEL {
+                    let index: usize = x * SAMPLES_PER_PIXEL + p;
+                    line_signal[index] = levels[(phase + index) % SAMPLES_PER_CYCLE];
+                    line_luma[index] = luma;
+                }
+            }
+
+            self.decode_line(&line_signal, &line_luma, phase, out_line);
+        }
+
+        // A frame is 89342 dots long, odd frames skip one. Both aren't a multiple of the color
+        // cycle, so every frame starts at a different phase.
+        let dots: usize = if self.odd_frame {
+            DOTS_PER_FRAME - 1
+        } else {
+            DOTS_PER_FRAME
+        };
+        self.frame_phase = (self.frame_phase + dots * SAMPLES_PER_PIXEL) % SAMPLES_PER_CYCLE;
+        self.odd_frame = !self.odd_frame;
+        out
+    }
+
+    /// Demodulates a scanline into RGB. Composite video has to separate luma and chroma out of
+    /// the same signal, while S-Video gets the luma passed in separately.
+    fn decode_line(&self, signal: &[f32], luma: &[f32], phase: usize, out: &mut [u8]) {
+        let samples_per_output: usize = signal.len() / NTSC_OUTPUT_WIDTH;
+
+        for (x, rgb) in out.chunks_exact_mut(3).enumerate() {
+            let center: usize = x * samples_per_output + samples_per_output / 2;
+            let begin: usize = center.saturating_sub(SAMPLES_PER_CYCLE / 2);
+            let end: usize = (center + SAMPLES_PER_CYCLE / 2).min(signal.len());
+
+            let mut y: f32 = 0.0;
+            let mut i: f32 = 0.0;
+            let mut q: f32 = 0.0;
+            for p in begin..end {
+                let level: f32 = signal[p] / SAMPLES_PER_CYCLE as f32;
+                let angle: f32 =
+                    PI * ((phase + p) % SAMPLES_PER_CYCLE) as f32 / 6.0 + HUE_OFFSET + self.hue;
+                match self.preset {
+                    NtscPreset::SVideo => {
+                        // the chroma signal carries no luma, so remove the pixel's average level
+                        let chroma: f32 = signal[p] - luma[p];
+                        y += luma[p] / SAMPLES_PER_CYCLE as f32;
+                        i += chroma / SAMPLES_PER_CYCLE as f32 * angle.cos();
+                        q += chroma / SAMPLES_PER_CYCLE as f32 * angle.sin();
+                    }
+                    _ => {
+                        y += level;
+                        i += level * angle.cos();
+                        q += level * angle.sin();
+                    }
+                }
+            }
+
+            // compensate for the samples cut off at the edges of the scanline
+            let scale: f32 = SAMPLES_PER_CYCLE as f32 / (end - begin) as f32;
+            y *= scale * self.brightness;
+            i *= scale * self.saturation;
+            q *= scale * self.saturation;
+
+            rgb[0] = to_channel(y + 0.946882 * i + 0.623557 * q);
+            rgb[1] = to_channel(y - 0.274788 * i - 0.635691 * q);
+            rgb[2] = to_channel(y - 1.108545 * i + 1.709007 * q);
+        }
+    }
+}
+
+fn to_channel(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Returns the voltage of the square wave a pixel produces at the given phase of the color cycle.
+fn generate_signal(pixel: u16, phase: usize) -> f32 {
+    let color: usize = (pixel & 0x0F) as usize;
+    let emphasis: u16 = pixel >> 6;
+    let level: usize = if color > 13 {
+        1
+    } else {
+        ((pixel >> 4) & 3) as usize
+    };
+
+    let mut low: f32 = LEVELS_LOW[level];
+    let mut high: f32 = LEVELS_HIGH[level];
+    if color == 0 {
+        low = high;
+    } else if color > 12 {
+        high = low;
+    }
+
+    let in_color_phase = |color: usize| -> bool { (color + phase) % SAMPLES_PER_CYCLE < 6 };
+    let mut signal: f32 = if in_color_phase(color) { high } else { low };
+
+    if (emphasis & 1 == 1 && in_color_phase(0))
+        || (emphasis & 2 == 2 && in_color_phase(4))
+        || (emphasis & 4 == 4 && in_color_phase(8))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    signal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_pixel(out: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let index: usize = (y * NTSC_OUTPUT_WIDTH + x) * 3;
+        [out[index], out[index + 1], out[index + 2]]
+    }
+
+    #[test]
+    fn test_greys() {
+        let mut filter: NtscFilter = NtscFilter::new(NtscPreset::Composite);
+        let frame: Vec<u16> = vec![0x30; FRAME_WIDTH * FRAME_HEIGHT];
+        let out: Vec<u8> = filter.filter_frame(&frame);
+        assert_eq!(get_pixel(&out, 100, 100), [255, 255, 255]);
+
+        let frame: Vec<u16> = vec![0x0F; FRAME_WIDTH * FRAME_HEIGHT];
+        let out: Vec<u8> = filter.filter_frame(&frame);
+        assert_eq!(get_pixel(&out, 100, 100), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_hues() {
+        let mut filter: NtscFilter = NtscFilter::new(NtscPreset::SVideo);
+        for (color, channel) in [(0x16, 0), (0x1A, 1), (0x12, 2)] {
+            let frame: Vec<u16> = vec![color; FRAME_WIDTH * FRAME_HEIGHT];
+            let out: Vec<u8> = filter.filter_frame(&frame);
+            let rgb: [u8; 3] = get_pixel(&out, 200, 50);
+            for other in 0..3 {
+                if other != channel {
+                    assert!(rgb[channel] > rgb[other], "{:02X}: {:?}", color, rgb);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dot_crawl() {
+        // vertical stripes of alternating colors produce artifacts which move between frames
+        let mut frame: Vec<u16> = vec![0x0F; FRAME_WIDTH * FRAME_HEIGHT];
+        for (index, pixel) in frame.iter_mut().enumerate() {
+            if index % 2 == 0 {
+                *pixel = 0x30;
+            }
+        }
+
+        let mut composite: NtscFilter = NtscFilter::new(NtscPreset::Composite);
+        let first: Vec<u8> = composite.filter_frame(&frame);
+        let second: Vec<u8> = composite.filter_frame(&frame);
+        assert_ne!(first, second);
+
+        let mut svideo: NtscFilter = NtscFilter::new(NtscPreset::SVideo);
+        let first: Vec<u8> = svideo.filter_frame(&frame);
+        let second: Vec<u8> = svideo.filter_frame(&frame);
+        let pixel: [u8; 3] = get_pixel(&first, 101, 20);
+        assert_eq!(pixel, get_pixel(&second, 101, 20));
+        // without crosstalk, black and white only blend into grey
+        assert_eq!(pixel[0], pixel[1]);
+        assert_eq!(pixel[1], pixel[2]);
+    }
+
+    #[test]
+    fn test_rgb() {
+        let mut filter: NtscFilter = NtscFilter::new(NtscPreset::Rgb);
+        let mut frame: Vec<u16> = vec![0x0F; FRAME_WIDTH * FRAME_HEIGHT];
+        frame[FRAME_WIDTH + 3] = 0x16;
+        let out: Vec<u8> = filter.filter_frame(&frame);
+        assert_eq!(get_pixel(&out, 6, 1), [0x98, 0x22, 0x20]);
+        assert_eq!(get_pixel(&out, 7, 1), [0x98, 0x22, 0x20]);
+        assert_eq!(get_pixel(&out, 8, 1), [0, 0, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "frames have to be 256x240 pixels")]
+    fn test_invalid_frame() {
+        NtscFilter::new(NtscPreset::Rgb).filter_frame(&[0x0F; FRAME_WIDTH * (FRAME_HEIGHT + 1)]);
+    }
+}
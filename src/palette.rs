@@ -0,0 +1,250 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Width of a frame produced by the PPU, in pixels.
+pub const FRAME_WIDTH: usize = 256;
+/// Height of a frame produced by the PPU, in pixels.
+pub const FRAME_HEIGHT: usize = 240;
+
+/// Number of distinct colors the PPU can index with its 6-bit palette values.
+pub const PALETTE_SIZE: usize = 64;
+
+/// Palette reference: https://www.nesdev.org/wiki/PPU_palettes
+///
+/// The default NTSC palette, as generated for a 2C02 PPU. Each entry is `[red, green, blue]`.
+#[rustfmt::skip]
+const DEFAULT_NTSC_PALETTE: [[u8; 3]; PALETTE_SIZE] = [
+    [0x54, 0x54, 0x54], [0x00, 0x1E, 0x74], [0x08, 0x10, 0x90], [0x30, 0x00, 0x88],
+    [0x44, 0x00, 0x64], [0x5C, 0x00, 0x30], [0x54, 0x04, 0x00], [0x3C, 0x18, 0x00],
+    [0x20, 0x2A, 0x00], [0x08, 0x3A, 0x00], [0x00, 0x40, 0x00], [0x00, 0x3C, 0x00],
+    [0x00, 0x32, 0x3C], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+
+    [0x98, 0x96, 0x98], [0x08, 0x4C, 0xC4], [0x30, 0x32, 0xEC], [0x5C, 0x1E, 0xE4],
+    [0x88, 0x14, 0xB0], [0xA0, 0x14, 0x64], [0x98, 0x22, 0x20], [0x78, 0x3C, 0x00],
+    [0x54, 0x5A, 0x00], [0x28, 0x72, 0x00], [0x08, 0x7C, 0x00], [0x00, 0x76, 0x28],
+    [0x00, 0x66, 0x78], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+
+    [0xEC, 0xEE, 0xEC], [0x4C, 0x9A, 0xEC], [0x78, 0x7C, 0xEC], [0xB0, 0x62, 0xEC],
+    [0xE4, 0x54, 0xEC], [0xEC, 0x58, 0xB4], [0xEC, 0x6A, 0x64], [0xD4, 0x88, 0x20],
+    [0xA0, 0xAA, 0x00], [0x74, 0xC4, 0x00], [0x4C, 0xD0, 0x20], [0x38, 0xCC, 0x6C],
+    [0x38, 0xB4, 0xCC], [0x3C, 0x3C, 0x3C], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+
+    [0xEC, 0xEE, 0xEC], [0xA8, 0xCC, 0xEC], [0xBC, 0xBC, 0xEC], [0xD4, 0xB2, 0xEC],
+    [0xEC, 0xAE, 0xEC], [0xEC, 0xAE, 0xD4], [0xEC, 0xB4, 0xB0], [0xE4, 0xC4, 0x90],
+    [0xCC, 0xD2, 0x78], [0xB4, 0xDE, 0x78], [0xA8, 0xE2, 0x90], [0x98, 0xE2, 0xB4],
+    [0xA0, 0xD6, 0xE4], [0xA0, 0xA2, 0xA0], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+];
+
+/// How much the two non-emphasized color channels are dimmed for every set emphasis bit.
+/// Only used for palettes which do not come with their own emphasis variants.
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+/// Converts a 6-bit palette index plus the emphasis and greyscale bits of PPUMASK ($2001) into
+/// the pixel format understood by [Palette::get_color].
+///
+/// Bit layout of the returned pixel: <pre>
+/// 0..=5 => palette index
+/// 6     => emphasize red
+/// 7     => emphasize green
+/// 8     => emphasize blue
+/// </pre>
+pub fn apply_mask(index: u8, mask: u8) -> u16 {
+    let index: u8 = if mask & 1 == 1 {
+        // greyscale mode only keeps the column of the grey entries
+        index & 0x30
+    } else {
+        index & 0x3F
+    };
+    ((mask as u16 >> 5) << 6) | index as u16
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    /// A `.pal` file has to contain either 64 or 512 (64 per emphasis combination) colors.
+    InvalidSize(usize),
+    Io(std::io::Error),
+}
+
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::InvalidSize(size) => write!(
+                f,
+                "invalid palette size of {} bytes, expected 192 or 1536 bytes",
+                size
+            ),
+            PaletteError::Io(err) => write!(f, "failed to read palette: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(err: std::io::Error) -> PaletteError {
+        PaletteError::Io(err)
+    }
+}
+
+/// Maps the PPU's output pixels (see [apply_mask]) to RGB colors.
+pub struct Palette {
+    /// One set of 64 colors for each of the 8 emphasis combinations.
+    colors: [[u8; 3]; PALETTE_SIZE * 8],
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new()
+    }
+}
+
+impl Palette {
+    /// Creates the built-in NTSC palette.
+    pub fn new() -> Palette {
+        Palette::from_base_colors(&DEFAULT_NTSC_PALETTE)
+    }
+
+    /// Parses the contents of a `.pal` file. 192 byte files only contain the 64 base colors, with
+    /// the emphasis variants being derived from them. 1536 byte files additionally contain all
+    /// emphasis combinations, ordered by the emphasis bits.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Palette, PaletteError> {
+        let mut colors: Vec<[u8; 3]> = Vec::with_capacity(bytes.len() / 3);
+        for rgb in bytes.chunks_exact(3) {
+            colors.push([rgb[0], rgb[1], rgb[2]]);
+        }
+
+        if bytes.len() == PALETTE_SIZE * 3 {
+            let mut base: [[u8; 3]; PALETTE_SIZE] = [[0; 3]; PALETTE_SIZE];
+            base.copy_from_slice(&colors);
+            Ok(Palette::from_base_colors(&base))
+        } else if bytes.len() == PALETTE_SIZE * 8 * 3 {
+            let mut out = Palette {
+                colors: [[0; 3]; PALETTE_SIZE * 8],
+            };
+            out.colors.copy_from_slice(&colors);
+            Ok(out)
+        } else {
+            Err(PaletteError::InvalidSize(bytes.len()))
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Palette, PaletteError> {
+        Palette::from_bytes(&std::fs::read(path)?)
+    }
+
+    fn from_base_colors(base: &[[u8; 3]; PALETTE_SIZE]) -> Palette {
+        let mut out = Palette {
+            colors: [[0; 3]; PALETTE_SIZE * 8],
+        };
+
+        for emphasis in 0..8 {
+            for (index, color) in base.iter().enumerate() {
+                out.colors[emphasis * PALETTE_SIZE + index] = tint(*color, emphasis as u8, index);
+            }
+        }
+        out
+    }
+
+    /// Returns the `[red, green, blue]` color of a single pixel.
+    pub fn get_color(&self, pixel: u16) -> [u8; 3] {
+        self.colors[(pixel & 0x1FF) as usize]
+    }
+
+    /// Converts a whole frame of pixels into packed 24-bit RGB. `out` has to be three times as
+    /// long as `frame`.
+    pub fn convert_frame(&self, frame: &[u16], out: &mut [u8]) {
+        for (pixel, rgb) in frame.iter().zip(out.chunks_exact_mut(3)) {
+            rgb.copy_from_slice(&self.get_color(*pixel));
+        }
+    }
+
+    /// Serializes the palette into the 1536 byte `.pal` format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+}
+
+/// Emphasis dims the channels which are not emphasized. The black entries in columns $xE and $xF
+/// do not get affected by emphasis.
+fn tint(color: [u8; 3], emphasis: u8, index: usize) -> [u8; 3] {
+    if emphasis == 0 || index & 0x0E == 0x0E {
+        return color;
+    }
+
+    let mut factors: [f32; 3] = [1.0; 3];
+    for channel in 0..3 {
+        if emphasis >> channel & 1 == 1 {
+            for (other, factor) in factors.iter_mut().enumerate() {
+                if other != channel {
+                    *factor *= EMPHASIS_ATTENUATION;
+                }
+            }
+        }
+    }
+
+    [
+        (color[0] as f32 * factors[0]).round() as u8,
+        (color[1] as f32 * factors[1]).round() as u8,
+        (color[2] as f32 * factors[2]).round() as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_palette() {
+        let palette: Palette = Palette::new();
+        assert_eq!(palette.get_color(0x0F), [0, 0, 0]);
+        assert_eq!(palette.get_color(0x30), [0xEC, 0xEE, 0xEC]);
+        assert_eq!(palette.get_color(0x16), [0x98, 0x22, 0x20]);
+    }
+
+    #[test]
+    fn test_apply_mask() {
+        // greyscale
+        assert_eq!(apply_mask(0x16, 0b0000_0001), 0x10);
+        assert_eq!(apply_mask(0x2C, 0b0000_0001), 0x20);
+        // emphasize red and blue
+        assert_eq!(apply_mask(0x16, 0b1010_0000), 0x16 | 0b101 << 6);
+        // the upper two bits of the index are ignored
+        assert_eq!(apply_mask(0xC1, 0), 0x01);
+    }
+
+    #[test]
+    fn test_emphasis() {
+        let palette: Palette = Palette::new();
+        let plain: [u8; 3] = palette.get_color(0x30);
+        let red: [u8; 3] = palette.get_color(apply_mask(0x30, 0b0010_0000));
+        assert_eq!(red[0], plain[0]);
+        assert!(red[1] < plain[1]);
+        assert!(red[2] < plain[2]);
+
+        // all emphasis bits darken every channel
+        let all: [u8; 3] = palette.get_color(apply_mask(0x30, 0b1110_0000));
+        assert!(all.iter().zip(plain.iter()).all(|(a, p)| a < p));
+
+        // black stays black
+        assert_eq!(palette.get_color(apply_mask(0x0F, 0b1110_0000)), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let mut bytes: Vec<u8> = vec![0; 192];
+        bytes[3..6].copy_from_slice(&[1, 2, 3]);
+        let palette: Palette = Palette::from_bytes(&bytes).unwrap();
+        assert_eq!(palette.get_color(0x01), [1, 2, 3]);
+
+        let mut bytes: Vec<u8> = vec![0; 1536];
+        bytes[(64 * 3 + 1) * 3..(64 * 3 + 2) * 3].copy_from_slice(&[4, 5, 6]);
+        let palette: Palette = Palette::from_bytes(&bytes).unwrap();
+        assert_eq!(palette.get_color(apply_mask(0x01, 0b0110_0000)), [4, 5, 6]);
+        assert_eq!(palette.to_bytes(), bytes);
+
+        assert!(matches!(
+            Palette::from_bytes(&[0; 100]),
+            Err(PaletteError::InvalidSize(100))
+        ));
+    }
+}
@@ -1,7 +1,10 @@
+#[cfg(test)]
 use crate::cpu::Instruction;
-use std::collections::{BTreeMap, LinkedList};
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::collections::LinkedList;
 
-struct Parser {
+pub struct Parser {
     instruction_length_map: BTreeMap<u8, u8>,
 }
 
@@ -221,6 +224,13 @@ impl Parser {
         return out;
     }
 
+    /// Returns the number of bytes the instruction with the given op code is long.
+    pub fn get_instruction_length(&self, op_code: u8) -> Option<u8> {
+        self.instruction_length_map.get(&op_code).copied()
+    }
+
+    /// Decodes a whole program, which the tests use to check the instruction lengths.
+    #[cfg(test)]
     pub fn parse_to_instructions(&self, bytes: &[u8], instructions: &mut LinkedList<Instruction>) {
         let mut index: usize = 0;
         while index < bytes.len() {
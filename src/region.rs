@@ -0,0 +1,187 @@
+use crate::rom::RomHeader;
+use crate::state::{Serialize, Serializer, StateError};
+
+/// Timing reference: https://www.nesdev.org/wiki/Cycle_reference_chart
+///
+/// The console variant to emulate. Every region runs its CPU and PPU off a different master clock
+/// divider and produces a different number of scanlines per frame.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    /// The Dendy and other famiclones combine PAL frame timing with NTSC-like CPU timing.
+    Dendy,
+}
+
+impl Region {
+    /// Picks the region a cartridge expects, unless the user explicitly asked for one.
+    pub fn detect(header: &RomHeader, user_override: Option<Region>) -> Region {
+        user_override.unwrap_or_else(|| header.get_region())
+    }
+
+    /// Frequency of the master clock crystal, in Hz.
+    pub fn get_master_clock_rate(&self) -> u32 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal | Region::Dendy => 26_601_712,
+        }
+    }
+
+    /// Number of master clock ticks per CPU cycle.
+    pub fn get_cpu_divider(&self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Number of master clock ticks per PPU dot.
+    pub fn get_ppu_divider(&self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// Frequency of the CPU, in Hz.
+    pub fn get_cpu_clock_rate(&self) -> f64 {
+        self.get_master_clock_rate() as f64 / self.get_cpu_divider() as f64
+    }
+
+    /// Number of PPU dots per CPU cycle; 3 on NTSC and Dendy, 3.2 on PAL.
+    pub fn get_ppu_dots_per_cpu_cycle(&self) -> f64 {
+        self.get_cpu_divider() as f64 / self.get_ppu_divider() as f64
+    }
+
+    pub fn get_scanlines_per_frame(&self) -> u32 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Number of scanlines between the NMI and the pre-render scanline.
+    pub fn get_vblank_scanlines(&self) -> u32 {
+        match self {
+            Region::Ntsc | Region::Dendy => 20,
+            Region::Pal => 70,
+        }
+    }
+
+    /// Number of idle scanlines between the last visible scanline and the start of vblank. The
+    /// Dendy puts its extra scanlines here instead of into vblank, so NTSC games keep working.
+    pub fn get_post_render_scanlines(&self) -> u32 {
+        match self {
+            Region::Ntsc | Region::Pal => 1,
+            Region::Dendy => 51,
+        }
+    }
+
+    /// Number of PPU dots in a frame. On NTSC, odd frames are one dot shorter while rendering.
+    pub fn get_dots_per_frame(&self, odd_frame: bool, rendering: bool) -> u32 {
+        let dots: u32 = self.get_scanlines_per_frame() * 341;
+        if *self == Region::Ntsc && odd_frame && rendering {
+            dots - 1
+        } else {
+            dots
+        }
+    }
+
+    /// Average number of CPU cycles per frame, with rendering enabled.
+    pub fn get_cpu_cycles_per_frame(&self) -> f64 {
+        let dots: f64 = (self.get_dots_per_frame(false, true) + self.get_dots_per_frame(true, true))
+            as f64
+            / 2.0;
+        dots / self.get_ppu_dots_per_cpu_cycle()
+    }
+
+    pub fn get_frame_rate(&self) -> f64 {
+        self.get_cpu_clock_rate() / self.get_cpu_cycles_per_frame()
+    }
+
+    /// APU frame counter reference: https://www.nesdev.org/wiki/APU_Frame_Counter
+    ///
+    /// CPU cycles after a reset or $4017 write at which the frame counter steps. The last entry
+    /// is where the 4-step sequence ends and the fifth one is where the 5-step sequence ends.
+    pub fn get_frame_counter_steps(&self) -> [u32; 5] {
+        match self {
+            Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+            Region::Pal => [8313, 16627, 24939, 33253, 41565],
+        }
+    }
+
+    /// Noise channel periods, in CPU cycles.
+    pub fn get_noise_periods(&self) -> [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => [
+                4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+            ],
+            Region::Pal => [
+                4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+            ],
+        }
+    }
+
+    /// DMC output rates, in CPU cycles.
+    pub fn get_dmc_periods(&self) -> [u16; 16] {
+        match self {
+            Region::Ntsc | Region::Dendy => [
+                428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+            ],
+            Region::Pal => [
+                398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+            ],
+        }
+    }
+}
+
+impl Serialize for Region {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut index: u8 = *self as u8;
+        s.value(&mut index);
+        *self = match index {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            _ => {
+                s.fail(StateError::Mismatch("region"));
+                *self
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_ratios() {
+        assert_eq!(Region::Ntsc.get_ppu_dots_per_cpu_cycle(), 3.0);
+        assert_eq!(Region::Pal.get_ppu_dots_per_cpu_cycle(), 3.2);
+        assert_eq!(Region::Dendy.get_ppu_dots_per_cpu_cycle(), 3.0);
+
+        assert_eq!(Region::Ntsc.get_cpu_cycles_per_frame(), 29780.5);
+        assert_eq!(Region::Pal.get_cpu_cycles_per_frame(), 33247.5);
+        assert_eq!(Region::Dendy.get_cpu_cycles_per_frame(), 35464.0);
+    }
+
+    #[test]
+    fn test_frame_rates() {
+        assert!((Region::Ntsc.get_frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.get_frame_rate() - 50.0070).abs() < 0.001);
+        assert!((Region::Dendy.get_frame_rate() - 50.0070).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_scanlines() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            // 240 visible scanlines plus the pre-render scanline
+            assert_eq!(
+                240 + region.get_post_render_scanlines() + region.get_vblank_scanlines() + 1,
+                region.get_scanlines_per_frame()
+            );
+        }
+    }
+}
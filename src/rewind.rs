@@ -0,0 +1,400 @@
+use crate::cpu::Cpu;
+use crate::state::StateError;
+use crate::wav::discard_apu_samples;
+use std::collections::VecDeque;
+
+/// Number of frames between snapshots, by default.
+pub const DEFAULT_INTERVAL: u64 = 4;
+/// Memory the snapshots may take up, by default.
+pub const DEFAULT_BUDGET: usize = 32 * 1024 * 1024;
+/// Every this many snapshots, one holds the whole state rather than the changes to the previous
+/// one. This bounds the number of deltas to apply to reconstruct a state.
+const KEYFRAME_INTERVAL: usize = 30;
+/// Runs of fewer zeros than this get stored as part of the surrounding literal bytes.
+const MIN_ZERO_RUN: usize = 4;
+
+/// A save state, compressed either on its own or as the changes to the previous snapshot.
+struct Snapshot {
+    frame: u64,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+/// Lets the machine step backward in time, by keeping snapshots of it taken every few frames.
+/// Most snapshots only hold the bytes which changed since the previous one, XORed with them and
+/// with the runs of zeros compressed, which takes a small fraction of a full save state. Once the
+/// snapshots exceed the memory budget, the oldest ones get dropped.
+///
+/// Rewinding loads the latest snapshot at or before the target frame, then runs the machine
+/// forward to land on it exactly. The frames get run with the input devices in their current
+/// state, so the frontend should restore its input as well to replay them faithfully.
+pub struct RewindBuffer {
+    interval: u64,
+    budget: usize,
+    snapshots: VecDeque<Snapshot>,
+    /// Total size of the snapshots' data.
+    size: usize,
+    /// The uncompressed state of the newest snapshot, which the next delta is based on.
+    newest_state: Vec<u8>,
+    /// Number of snapshots taken since the last keyframe.
+    deltas: usize,
+}
+
+impl RewindBuffer {
+    /// Creates a buffer taking a snapshot every `interval` frames, using up to `budget` bytes.
+    pub fn new(interval: u64, budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            interval: interval.max(1),
+            budget,
+            snapshots: VecDeque::new(),
+            size: 0,
+            newest_state: Vec::new(),
+            deltas: 0,
+        }
+    }
+
+    pub fn get_interval(&self) -> u64 {
+        self.interval
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.enforce_budget();
+    }
+
+    /// Memory taken up by the snapshots, in bytes.
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_snapshot_count(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// The earliest frame the machine can be rewound to.
+    pub fn get_oldest_frame(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.frame)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.size = 0;
+        self.newest_state.clear();
+        self.deltas = 0;
+    }
+
+    /// Called after every frame, taking a snapshot once `interval` frames passed since the last
+    /// one. A frame older than the newest snapshot means the machine was taken elsewhere, like by
+    /// loading a save state, so the snapshots of the abandoned timeline get cleared.
+    pub fn record(&mut self, cpu: &mut Cpu) {
+        let frame: u64 = cpu.get_frame();
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|newest| frame < newest.frame)
+        {
+            self.clear();
+        }
+        if let Some(newest) = self.snapshots.back()
+            && frame < newest.frame + self.interval
+        {
+            return;
+        }
+
+        let state: Vec<u8> = cpu.save_state();
+        let keyframe: bool =
+            self.deltas + 1 >= KEYFRAME_INTERVAL || state.len() != self.newest_state.len();
+        let data: Vec<u8> = if keyframe {
+            self.deltas = 0;
+            compress(&state)
+        } else {
+            self.deltas += 1;
+            compress(&xor(&state, &self.newest_state))
+        };
+        self.size += data.len();
+        self.snapshots.push_back(Snapshot {
+            frame,
+            keyframe,
+            data,
+        });
+        self.newest_state = state;
+        self.enforce_budget();
+    }
+
+    /// Drops the oldest snapshots until the rest fit the budget, always keeping the newest one.
+    /// The snapshot after a dropped keyframe becomes the new keyframe.
+    fn enforce_budget(&mut self) {
+        while self.size > self.budget && self.snapshots.len() > 1 {
+            let oldest: Snapshot = self.snapshots.pop_front().unwrap();
+            self.size -= oldest.data.len();
+            let next: &mut Snapshot = self.snapshots.front_mut().unwrap();
+            if !next.keyframe {
+                let state: Vec<u8> = xor(&decompress(&oldest.data), &decompress(&next.data));
+                let data: Vec<u8> = compress(&state);
+                self.size = self.size - next.data.len() + data.len();
+                next.data = data;
+                next.keyframe = true;
+            }
+        }
+    }
+
+    /// Reconstructs the state of a snapshot, from its last keyframe onward. Also returns the
+    /// number of deltas applied.
+    fn reconstruct_state(&self, index: usize) -> (Vec<u8>, usize) {
+        let start: usize = self
+            .snapshots
+            .range(..=index)
+            .rposition(|snapshot| snapshot.keyframe)
+            .unwrap();
+        let mut state: Vec<u8> = decompress(&self.snapshots[start].data);
+        for snapshot in self.snapshots.range(start + 1..=index) {
+            state = xor(&state, &decompress(&snapshot.data));
+        }
+        (state, index - start)
+    }
+
+    /// Takes the machine back to the given frame, forgetting the snapshots taken after it.
+    /// Returns false, leaving the machine untouched, if the frame is older than the oldest
+    /// snapshot or isn't in the past.
+    pub fn rewind_to(&mut self, cpu: &mut Cpu, frame: u64) -> Result<bool, StateError> {
+        if frame >= cpu.get_frame() || self.get_oldest_frame().is_none_or(|oldest| frame < oldest) {
+            return Ok(false);
+        }
+
+        let index: usize = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| snapshot.frame <= frame)
+            .unwrap();
+        if index + 1 != self.snapshots.len() {
+            // only forget the newer snapshots once the state made it into the machine
+            let (state, deltas): (Vec<u8>, usize) = self.reconstruct_state(index);
+            cpu.load_state(&state)?;
+            for newest in self.snapshots.drain(index + 1..) {
+                self.size -= newest.data.len();
+            }
+            self.newest_state = state;
+            self.deltas = deltas;
+        } else {
+            cpu.load_state(&self.newest_state)?;
+        }
+        while cpu.get_frame() < frame {
+            cpu.run_frame();
+        }
+        // the re-executed frames were already heard
+        discard_apu_samples(cpu);
+        Ok(true)
+    }
+
+    /// Takes the machine back by a single frame.
+    pub fn rewind_frame(&mut self, cpu: &mut Cpu) -> Result<bool, StateError> {
+        match cpu.get_frame().checked_sub(1) {
+            Some(frame) => self.rewind_to(cpu, frame),
+            None => Ok(false),
+        }
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut out: usize = 0;
+    let mut shift: u32 = 0;
+    loop {
+        let byte: u8 = bytes[*pos];
+        *pos += 1;
+        out |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return out;
+        }
+        shift += 7;
+    }
+}
+
+/// Compresses the runs of zeros, which make up most of a delta and much of a save state. The
+/// output is the length, followed by pairs of a run of zeros and of literal bytes: <pre>
+/// length, (zeros, literal length, literal bytes)*
+/// </pre>
+/// with the numbers stored 7 bits at a time, least significant first.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    write_varint(&mut out, data.len());
+    let mut pos: usize = 0;
+    while pos < data.len() {
+        let zeros: usize = data[pos..].iter().take_while(|byte| **byte == 0).count();
+        let start: usize = pos + zeros;
+        let mut end: usize = start;
+        while end < data.len() {
+            let run: usize = data[end..]
+                .iter()
+                .take(MIN_ZERO_RUN)
+                .take_while(|byte| **byte == 0)
+                .count();
+            if run == MIN_ZERO_RUN || end + run == data.len() {
+                break;
+            }
+            end += run.max(1);
+        }
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, end - start);
+        out.extend_from_slice(&data[start..end]);
+        pos = end;
+    }
+    out
+}
+
+fn decompress(bytes: &[u8]) -> Vec<u8> {
+    let mut pos: usize = 0;
+    let len: usize = read_varint(bytes, &mut pos);
+    let mut out: Vec<u8> = Vec::with_capacity(len);
+    while out.len() < len {
+        let zeros: usize = read_varint(bytes, &mut pos);
+        out.resize(out.len() + zeros, 0);
+        let literal: usize = read_varint(bytes, &mut pos);
+        out.extend_from_slice(&bytes[pos..pos + literal]);
+        pos += literal;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine playing a square wave, so that the APU's state changes every frame.
+    fn create_cpu() -> Cpu {
+        let mut cpu: Cpu = Cpu::new();
+        cpu.write(0xFFFC, 0x00);
+        cpu.write(0xFFFD, 0x02);
+        // LDA #$01, STA $4015, LDA #$BF, STA $4000, LDA #$FD, STA $4002, LDA #$08, STA $4003,
+        // followed by BNE to itself
+        let program: [u8; 22] = [
+            0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0xA9, 0xFD, 0x8D, 0x02,
+            0x40, 0xA9, 0x08, 0x8D, 0x03, 0x40, 0xD0, 0xFE,
+        ];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.write(0x0200 + i as u16, *byte);
+        }
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_compress() {
+        let mut data: Vec<u8> = vec![0; 1000];
+        data[10] = 1;
+        data[12] = 2;
+        data[500..510].fill(3);
+        data[999] = 4;
+        let compressed: Vec<u8> = compress(&data);
+        assert!(compressed.len() < 30);
+        assert_eq!(decompress(&compressed), data);
+
+        for data in [vec![], vec![0; 3], vec![5; 3], vec![1, 0, 0, 0, 0, 2, 0]] {
+            assert_eq!(decompress(&compress(&data)), data);
+        }
+    }
+
+    #[test]
+    fn test_rewind_frame() {
+        let mut cpu: Cpu = create_cpu();
+        let mut rewind: RewindBuffer = RewindBuffer::new(4, DEFAULT_BUDGET);
+        let mut states: Vec<Vec<u8>> = vec![cpu.save_state()];
+        rewind.record(&mut cpu);
+        for _ in 0..100 {
+            cpu.run_frame();
+            states.push(cpu.save_state());
+            rewind.record(&mut cpu);
+        }
+        assert_eq!(rewind.get_snapshot_count(), 26);
+        assert!(rewind.get_size() < 26 * states[0].len() / 4);
+
+        // lands on every frame, whether or not it has a snapshot
+        for frame in (90..100).rev() {
+            assert!(rewind.rewind_frame(&mut cpu).unwrap());
+            assert_eq!(cpu.get_frame(), frame);
+            assert_eq!(cpu.save_state(), states[frame as usize]);
+        }
+        assert!(rewind.rewind_to(&mut cpu, 33).unwrap());
+        assert_eq!(cpu.save_state(), states[33]);
+        assert_eq!(rewind.get_snapshot_count(), 9);
+
+        // running forward again takes new snapshots
+        for _ in 0..20 {
+            cpu.run_frame();
+            rewind.record(&mut cpu);
+        }
+        assert!(rewind.rewind_to(&mut cpu, 50).unwrap());
+        assert_eq!(cpu.save_state(), states[50]);
+
+        assert!(rewind.rewind_to(&mut cpu, 0).unwrap());
+        assert_eq!(cpu.save_state(), states[0]);
+        assert!(!rewind.rewind_frame(&mut cpu).unwrap());
+        assert!(!rewind.rewind_to(&mut cpu, 10).unwrap());
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut cpu: Cpu = create_cpu();
+        let mut rewind: RewindBuffer = RewindBuffer::new(1, 4096);
+        let mut states: Vec<Vec<u8>> = vec![cpu.save_state()];
+        rewind.record(&mut cpu);
+        for _ in 0..200 {
+            cpu.run_frame();
+            states.push(cpu.save_state());
+            rewind.record(&mut cpu);
+        }
+        assert!(rewind.get_size() <= 4096);
+        let oldest: u64 = rewind.get_oldest_frame().unwrap();
+        assert!(oldest > 0);
+        assert!(!rewind.rewind_to(&mut cpu, oldest - 1).unwrap());
+        assert!(rewind.rewind_to(&mut cpu, oldest).unwrap());
+        assert_eq!(cpu.save_state(), states[oldest as usize]);
+
+        // a budget too small for anything still keeps the newest snapshot
+        rewind.set_budget(0);
+        assert_eq!(rewind.get_snapshot_count(), 1);
+    }
+
+    #[test]
+    fn test_failed_rewind() {
+        let mut cpu: Cpu = create_cpu();
+        let mut rewind: RewindBuffer = RewindBuffer::new(4, DEFAULT_BUDGET);
+        for _ in 0..20 {
+            cpu.run_frame();
+            rewind.record(&mut cpu);
+        }
+        // the snapshots have no cartridge to load into this one
+        cpu.load_rom(crate::mapper::tests::create_rom(0, 1, 1))
+            .unwrap();
+        assert!(rewind.rewind_to(&mut cpu, 5).is_err());
+        assert_eq!(rewind.get_snapshot_count(), 5);
+        assert_eq!(cpu.get_frame(), 20);
+    }
+
+    #[test]
+    fn test_record_after_load() {
+        let mut cpu: Cpu = create_cpu();
+        let mut rewind: RewindBuffer = RewindBuffer::new(1, DEFAULT_BUDGET);
+        let state: Vec<u8> = cpu.save_state();
+        for _ in 0..10 {
+            cpu.run_frame();
+            rewind.record(&mut cpu);
+        }
+        cpu.load_state(&state).unwrap();
+        cpu.run_frame();
+        rewind.record(&mut cpu);
+        assert_eq!(rewind.get_snapshot_count(), 1);
+        assert_eq!(rewind.get_oldest_frame(), Some(1));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::region::Region;
+use crate::state::{Serialize, Serializer, StateError};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+
+/// Mirroring reference: https://www.nesdev.org/wiki/Mirroring#Nametable_Mirroring
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    /// All four nametables map to the first page of VRAM.
+    SingleScreenLower,
+    /// All four nametables map to the second page of VRAM.
+    SingleScreenUpper,
+}
+
+/// The CPU/PPU timing the cartridge was made for, stored in byte 12 of NES 2.0 headers.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimingMode {
+    Ntsc,
+    Pal,
+    /// Works on every console, such as games which detect the region at runtime.
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    /// The file does not start with `NES<EOF>`.
+    InvalidMagic,
+    /// The file is shorter than its header claims.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// The cartridge uses a mapper which isn't emulated.
+    UnsupportedMapper(u16),
+    /// The header declares a ROM which is empty, too big to address, or isn't made of whole
+    /// banks, naming which one.
+    InvalidSize(&'static str),
+    Io(std::io::Error),
+}
+
+impl Display for RomError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::InvalidMagic => write!(f, "not an iNES file"),
+            RomError::Truncated { expected, actual } => write!(
+                f,
+                "ROM is truncated: expected {} bytes, got {}",
+                expected, actual
+            ),
+            RomError::UnsupportedMapper(mapper) => write!(f, "unsupported mapper: {}", mapper),
+            RomError::InvalidSize(what) => write!(f, "invalid {} size", what),
+            RomError::Io(err) => write!(f, "failed to read ROM: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<std::io::Error> for RomError {
+    fn from(err: std::io::Error) -> RomError {
+        RomError::Io(err)
+    }
+}
+
+/// Header reference: https://www.nesdev.org/wiki/NES_2.0
+pub struct RomHeader {
+    /// Whether the header uses the NES 2.0 format instead of plain iNES.
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    /// Whether the cartridge contains battery-backed memory.
+    pub battery: bool,
+    pub trainer: bool,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: TimingMode,
+}
+
+impl RomHeader {
+    pub fn parse(bytes: &[u8]) -> Result<RomHeader, RomError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(RomError::Truncated {
+                expected: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(RomError::InvalidMagic);
+        }
+
+        let nes2: bool = bytes[7] & 0x0C == 0x08;
+        let mirroring: Mirroring = if bytes[6] & 0x08 == 0x08 {
+            Mirroring::FourScreen
+        } else if bytes[6] & 1 == 1 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut out = RomHeader {
+            nes2,
+            mapper: ((bytes[7] & 0xF0) | (bytes[6] >> 4)) as u16,
+            submapper: 0,
+            mirroring,
+            battery: bytes[6] & 0x02 == 0x02,
+            trainer: bytes[6] & 0x04 == 0x04,
+            prg_rom_size: bytes[4] as usize * 0x4000,
+            chr_rom_size: bytes[5] as usize * 0x2000,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            timing: TimingMode::Ntsc,
+        };
+
+        if nes2 {
+            out.mapper |= ((bytes[8] & 0x0F) as u16) << 8;
+            out.submapper = bytes[8] >> 4;
+            out.prg_rom_size = get_nes2_rom_size(bytes[4], bytes[9] & 0x0F, 0x4000)
+                .ok_or(RomError::InvalidSize("PRG-ROM"))?;
+            out.chr_rom_size = get_nes2_rom_size(bytes[5], bytes[9] >> 4, 0x2000)
+                .ok_or(RomError::InvalidSize("CHR-ROM"))?;
+            out.prg_ram_size = get_nes2_ram_size(bytes[10] & 0x0F);
+            out.prg_nvram_size = get_nes2_ram_size(bytes[10] >> 4);
+            out.chr_ram_size = get_nes2_ram_size(bytes[11] & 0x0F);
+            out.chr_nvram_size = get_nes2_ram_size(bytes[11] >> 4);
+            out.timing = match bytes[12] & 0x03 {
+                0 => TimingMode::Ntsc,
+                1 => TimingMode::Pal,
+                2 => TimingMode::MultiRegion,
+                _ => TimingMode::Dendy,
+            };
+        } else {
+            // iNES 1.0 assumes 8 KiB of PRG-RAM and CHR-RAM whenever there is no CHR-ROM
+            out.prg_ram_size = if out.battery { 0 } else { 0x2000 };
+            out.prg_nvram_size = if out.battery { 0x2000 } else { 0 };
+            out.chr_ram_size = if out.chr_rom_size == 0 { 0x2000 } else { 0 };
+            if bytes[9] & 1 == 1 {
+                out.timing = TimingMode::Pal;
+            }
+        }
+
+        // every mapper switches PRG-ROM in 8 KiB banks at the smallest
+        if out.prg_rom_size == 0 || !out.prg_rom_size.is_multiple_of(0x2000) {
+            return Err(RomError::InvalidSize("PRG-ROM"));
+        }
+        Ok(out)
+    }
+
+    pub fn get_region(&self) -> Region {
+        match self.timing {
+            TimingMode::Ntsc | TimingMode::MultiRegion => Region::Ntsc,
+            TimingMode::Pal => Region::Pal,
+            TimingMode::Dendy => Region::Dendy,
+        }
+    }
+}
+
+/// NES 2.0 ROM sizes are either stored as a multiple of the bank size, or, if the most
+/// significant nibble is $F, in exponent-multiplier notation.
+fn get_nes2_rom_size(lsb: u8, msb: u8, bank_size: usize) -> Option<usize> {
+    if msb == 0x0F {
+        2usize
+            .checked_pow((lsb >> 2) as u32)?
+            .checked_mul((lsb & 0x03) as usize * 2 + 1)
+    } else {
+        ((msb as usize) << 8 | lsb as usize).checked_mul(bank_size)
+    }
+}
+
+/// NES 2.0 RAM sizes are stored as a shift count, where 0 means no RAM at all.
+fn get_nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+/// An iNES/NES 2.0 ROM image.
+pub struct Rom {
+    pub header: RomHeader,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+}
+
+impl Rom {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Rom, RomError> {
+        let header: RomHeader = RomHeader::parse(bytes)?;
+        if crate::mapper::get_info(header.mapper).is_none() {
+            return Err(RomError::UnsupportedMapper(header.mapper));
+        }
+
+        let mut offset: usize = HEADER_SIZE;
+        let trainer_size: usize = if header.trainer { TRAINER_SIZE } else { 0 };
+        let expected: usize = (offset + trainer_size)
+            .checked_add(header.prg_rom_size)
+            .and_then(|size| size.checked_add(header.chr_rom_size))
+            .ok_or(RomError::InvalidSize("ROM"))?;
+        if bytes.len() < expected {
+            return Err(RomError::Truncated {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let trainer: Option<Vec<u8>> = if header.trainer {
+            offset += TRAINER_SIZE;
+            Some(bytes[HEADER_SIZE..offset].to_vec())
+        } else {
+            None
+        };
+        let prg_rom: Vec<u8> = bytes[offset..offset + header.prg_rom_size].to_vec();
+        offset += header.prg_rom_size;
+        let chr_rom: Vec<u8> = bytes[offset..offset + header.chr_rom_size].to_vec();
+
+        Ok(Rom {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Rom, RomError> {
+        Rom::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+impl Serialize for Mirroring {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut index: u8 = *self as u8;
+        s.value(&mut index);
+        *self = match index {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            _ => {
+                s.fail(StateError::Mismatch("mirroring"));
+                *self
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_header(flags: [u8; 12]) -> Vec<u8> {
+        let mut out: Vec<u8> = MAGIC.to_vec();
+        out.extend_from_slice(&flags);
+        out
+    }
+
+    #[test]
+    fn test_ines() {
+        let header: RomHeader =
+            RomHeader::parse(&create_header([2, 1, 0x13, 0x40, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert!(!header.nes2);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery);
+        assert!(!header.trainer);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.timing, TimingMode::Pal);
+        assert_eq!(header.get_region(), Region::Pal);
+    }
+
+    #[test]
+    fn test_nes2() {
+        let header: RomHeader = RomHeader::parse(&create_header([
+            0x02, 0x00, 0x18, 0x08, 0x31, 0x01, 0x07, 0x70, 0x03, 0, 0, 0,
+        ]))
+        .unwrap();
+        assert!(header.nes2);
+        assert_eq!(header.mapper, 0x101);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.mirroring, Mirroring::FourScreen);
+        assert_eq!(header.prg_rom_size, 0x102 * 0x4000);
+        assert_eq!(header.prg_ram_size, 0x2000);
+        assert_eq!(header.chr_nvram_size, 0x2000);
+        assert_eq!(header.timing, TimingMode::Dendy);
+        assert_eq!(header.get_region(), Region::Dendy);
+
+        // exponent-multiplier notation: 2^4 * 3
+        assert_eq!(get_nes2_rom_size(0b0001_0001, 0x0F, 0x4000), Some(48));
+        // 2^63 * 7 doesn't fit
+        assert_eq!(get_nes2_rom_size(0xFF, 0x0F, 0x4000), None);
+        assert!(matches!(
+            RomHeader::parse(&create_header([
+                0xFF, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0
+            ])),
+            Err(RomError::InvalidSize("PRG-ROM"))
+        ));
+        assert!(matches!(
+            RomHeader::parse(&create_header([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])),
+            Err(RomError::InvalidSize("PRG-ROM"))
+        ));
+        // 48 bytes isn't a whole 8 KiB bank
+        assert!(matches!(
+            RomHeader::parse(&create_header([
+                0b0001_0001,
+                0,
+                0,
+                0x08,
+                0,
+                0x0F,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0
+            ])),
+            Err(RomError::InvalidSize("PRG-ROM"))
+        ));
+    }
+
+    #[test]
+    fn test_rom() {
+        let mut bytes: Vec<u8> = create_header([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend(vec![1; TRAINER_SIZE]);
+        bytes.extend(vec![2; 0x4000]);
+        bytes.extend(vec![3; 0x2000]);
+
+        let rom: Rom = Rom::from_bytes(&bytes).unwrap();
+        assert_eq!(rom.trainer.unwrap(), vec![1; TRAINER_SIZE]);
+        assert_eq!(rom.prg_rom, vec![2; 0x4000]);
+        assert_eq!(rom.chr_rom, vec![3; 0x2000]);
+
+        assert!(matches!(
+            Rom::from_bytes(&bytes[..0x1000]),
+            Err(RomError::Truncated { .. })
+        ));
+        assert!(matches!(
+            Rom::from_bytes(&[0; 16]),
+            Err(RomError::InvalidMagic)
+        ));
+
+        bytes[7] = 0xF0;
+        assert!(matches!(
+            Rom::from_bytes(&bytes),
+            Err(RomError::UnsupportedMapper(0xF0))
+        ));
+        assert_eq!(
+            RomError::UnsupportedMapper(0xF0).to_string(),
+            "unsupported mapper: 240"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::cell::Cell;
+use std::fmt::{Display, Formatter};
+
+/// Version of the save state format. Bump it whenever a section's layout changes, and check
+/// [Serializer::get_version] when reading the changed fields, so older states keep loading.
+pub const VERSION: u16 = 1;
+const MAGIC: [u8; 4] = *b"NESS";
+const HEADER_SIZE: usize = 6;
+
+#[derive(Debug)]
+pub enum StateError {
+    InvalidMagic,
+    /// The state was made by a newer version of the format.
+    UnsupportedVersion(u16),
+    /// The state ends in the middle of a section.
+    Truncated,
+    /// A section the machine needs is missing.
+    MissingSection([u8; 4]),
+    /// The state doesn't fit the machine, like a cartridge's RAM of a different size.
+    Mismatch(&'static str),
+    Io(std::io::Error),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::InvalidMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is newer than the supported version {}",
+                version, VERSION
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::MissingSection(tag) => write!(
+                f,
+                "save state is missing the {} section",
+                String::from_utf8_lossy(tag).trim_end()
+            ),
+            StateError::Mismatch(what) => {
+                write!(f, "save state doesn't match the machine: {}", what)
+            }
+            StateError::Io(err) => write!(f, "failed to access save state: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<std::io::Error> for StateError {
+    fn from(err: std::io::Error) -> StateError {
+        StateError::Io(err)
+    }
+}
+
+/// Either saves values into a section or loads them back. Both directions go through the same
+/// [Serialize::serialize] method, so they can't get out of step.
+///
+/// Loading stops at the first error, leaving the remaining values untouched. The error gets
+/// reported once the section is done.
+pub struct Serializer {
+    loading: bool,
+    version: u16,
+    data: Vec<u8>,
+    position: usize,
+    error: Option<StateError>,
+}
+
+impl Serializer {
+    fn new_saving() -> Serializer {
+        Serializer {
+            loading: false,
+            version: VERSION,
+            data: Vec::new(),
+            position: 0,
+            error: None,
+        }
+    }
+
+    fn new_loading(data: &[u8], version: u16) -> Serializer {
+        Serializer {
+            loading: true,
+            version,
+            data: data.to_vec(),
+            position: 0,
+            error: None,
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    /// The format version of the state being loaded, or [VERSION] when saving.
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn value<T: Serialize>(&mut self, value: &mut T) {
+        value.serialize(self);
+    }
+
+    /// Stores a block of memory along with its size, which has to match when loading.
+    pub fn bytes(&mut self, bytes: &mut [u8]) {
+        let mut size: u32 = bytes.len() as u32;
+        self.value(&mut size);
+        if self.loading && self.error.is_none() && size as usize != bytes.len() {
+            self.fail(StateError::Mismatch("memory size"));
+        }
+        self.raw(bytes);
+    }
+
+    /// Reports that a loaded value is out of range.
+    pub fn fail(&mut self, error: StateError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Copies the bytes into the section, or out of it when loading.
+    fn raw(&mut self, bytes: &mut [u8]) {
+        if !self.loading {
+            self.data.extend_from_slice(bytes);
+            return;
+        }
+        if self.error.is_some() {
+            return;
+        }
+        match self.data.get(self.position..self.position + bytes.len()) {
+            Some(data) => {
+                bytes.copy_from_slice(data);
+                self.position += bytes.len();
+            }
+            None => self.fail(StateError::Truncated),
+        }
+    }
+
+    /// Ends loading a section, which has to be used up entirely.
+    fn finish(self) -> Result<(), StateError> {
+        match self.error {
+            Some(err) => Err(err),
+            None if self.position != self.data.len() => Err(StateError::Mismatch("section size")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// State which goes into save states.
+pub trait Serialize {
+    fn serialize(&mut self, s: &mut Serializer);
+}
+
+macro_rules! serialize_number {
+    ($($number:ty),*) => {$(
+        impl Serialize for $number {
+            fn serialize(&mut self, s: &mut Serializer) {
+                let mut bytes = self.to_le_bytes();
+                s.raw(&mut bytes);
+                *self = <$number>::from_le_bytes(bytes);
+            }
+        }
+    )*};
+}
+
+serialize_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl Serialize for usize {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut value: u64 = *self as u64;
+        s.value(&mut value);
+        *self = value as usize;
+    }
+}
+
+impl Serialize for bool {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut value: u8 = *self as u8;
+        s.value(&mut value);
+        *self = value != 0;
+    }
+}
+
+impl<T: Serialize, const N: usize> Serialize for [T; N] {
+    fn serialize(&mut self, s: &mut Serializer) {
+        for value in self.iter_mut() {
+            s.value(value);
+        }
+    }
+}
+
+/// Vectors get resized to the loaded length.
+impl<T: Serialize + Default> Serialize for Vec<T> {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut len: u32 = self.len() as u32;
+        s.value(&mut len);
+        if s.is_loading() {
+            if s.error.is_some() || len as usize > s.data.len() - s.position {
+                s.fail(StateError::Truncated);
+                return;
+            }
+            self.resize_with(len as usize, T::default);
+        }
+        for value in self.iter_mut() {
+            s.value(value);
+        }
+    }
+}
+
+impl<T: Serialize + Default> Serialize for Option<T> {
+    fn serialize(&mut self, s: &mut Serializer) {
+        let mut some: bool = self.is_some();
+        s.value(&mut some);
+        if !some {
+            *self = None;
+            return;
+        }
+        s.value(self.get_or_insert_with(T::default));
+    }
+}
+
+impl<T: Serialize + Copy> Serialize for Cell<T> {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(self.get_mut());
+    }
+}
+
+impl<A: Serialize, B: Serialize> Serialize for (A, B) {
+    fn serialize(&mut self, s: &mut Serializer) {
+        s.value(&mut self.0);
+        s.value(&mut self.1);
+    }
+}
+
+/// Builds a save state out of tagged sections: <pre>
+/// "NESS", format version as u16
+/// for each section: 4 byte tag, payload size as u32, payload
+/// </pre>
+/// All values are little endian.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut data: Vec<u8> = MAGIC.to_vec();
+        data.extend(VERSION.to_le_bytes());
+        StateWriter { data }
+    }
+
+    pub fn add_section(&mut self, tag: [u8; 4], save: impl FnOnce(&mut Serializer)) {
+        let mut s: Serializer = Serializer::new_saving();
+        save(&mut s);
+        self.data.extend(tag);
+        self.data.extend((s.data.len() as u32).to_le_bytes());
+        self.data.extend(s.data);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// The sections of a save state. Sections with unknown tags get ignored, so that states from
+/// later versions with added sections only fail on the sections that changed.
+pub struct StateReader<'a> {
+    version: u16,
+    sections: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+        let version: u16 = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut sections: Vec<([u8; 4], &[u8])> = Vec::new();
+        let mut rest: &[u8] = &bytes[HEADER_SIZE..];
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err(StateError::Truncated);
+            }
+            let tag: [u8; 4] = [rest[0], rest[1], rest[2], rest[3]];
+            let size: usize = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let Some(payload) = rest.get(8..8 + size) else {
+                return Err(StateError::Truncated);
+            };
+            sections.push((tag, payload));
+            rest = &rest[8 + size..];
+        }
+        Ok(StateReader { version, sections })
+    }
+
+    pub fn get_version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn has_section(&self, tag: [u8; 4]) -> bool {
+        self.sections.iter().any(|(other, _)| *other == tag)
+    }
+
+    pub fn load_section(
+        &self,
+        tag: [u8; 4],
+        load: impl FnOnce(&mut Serializer),
+    ) -> Result<(), StateError> {
+        let Some((_, payload)) = self.sections.iter().find(|(other, _)| *other == tag) else {
+            return Err(StateError::MissingSection(tag));
+        };
+        let mut s: Serializer = Serializer::new_loading(payload, self.version);
+        load(&mut s);
+        s.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, PartialEq, Debug)]
+    struct Example {
+        a: u8,
+        b: u16,
+        c: bool,
+        d: f32,
+        e: [i8; 3],
+        f: Vec<u32>,
+        g: Option<u64>,
+        h: Cell<usize>,
+        ram: [u8; 4],
+    }
+
+    impl Serialize for Example {
+        fn serialize(&mut self, s: &mut Serializer) {
+            s.value(&mut self.a);
+            s.value(&mut self.b);
+            s.value(&mut self.c);
+            s.value(&mut self.d);
+            s.value(&mut self.e);
+            s.value(&mut self.f);
+            s.value(&mut self.g);
+            s.value(&mut self.h);
+            s.bytes(&mut self.ram);
+        }
+    }
+
+    fn create_example() -> Example {
+        Example {
+            a: 1,
+            b: 0x1234,
+            c: true,
+            d: 0.5,
+            e: [-1, 2, -3],
+            f: vec![7, 8],
+            g: Some(9),
+            h: Cell::new(10),
+            ram: [1, 2, 3, 4],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer: StateWriter = StateWriter::new();
+        writer.add_section(*b"TEST", |s| create_example().serialize(s));
+        writer.add_section(*b"NEW ", |s| s.value(&mut 5u8));
+        let bytes: Vec<u8> = writer.into_bytes();
+        assert_eq!(&bytes[0..6], b"NESS\x01\x00");
+        assert_eq!(&bytes[6..10], b"TEST");
+
+        let reader: StateReader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.get_version(), VERSION);
+        assert!(reader.has_section(*b"NEW "));
+        let mut example: Example = Example::default();
+        reader
+            .load_section(*b"TEST", |s| example.serialize(s))
+            .unwrap();
+        assert_eq!(example, create_example());
+        assert!(matches!(
+            reader.load_section(*b"MAPR", |_| {}),
+            Err(StateError::MissingSection(tag)) if &tag == b"MAPR"
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            StateReader::new(b"NES\x1A\x01\x00"),
+            Err(StateError::InvalidMagic)
+        ));
+        assert!(matches!(
+            StateReader::new(b"NESS\xFF\x00"),
+            Err(StateError::UnsupportedVersion(0xFF))
+        ));
+
+        let mut writer: StateWriter = StateWriter::new();
+        writer.add_section(*b"TEST", |s| create_example().serialize(s));
+        let bytes: Vec<u8> = writer.into_bytes();
+        assert!(matches!(
+            StateReader::new(&bytes[..bytes.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+
+        // leftover or missing bytes mean the section doesn't fit
+        let reader: StateReader = StateReader::new(&bytes).unwrap();
+        let result = reader.load_section(*b"TEST", |s| s.value(&mut 0u8));
+        assert!(matches!(result, Err(StateError::Mismatch(_))));
+        let result = reader.load_section(*b"TEST", |s| {
+            create_example().serialize(s);
+            s.value(&mut 0u8);
+        });
+        assert!(matches!(result, Err(StateError::Truncated)));
+
+        // memory blocks have to keep their size
+        let mut writer: StateWriter = StateWriter::new();
+        writer.add_section(*b"RAM ", |s| s.bytes(&mut [0; 8]));
+        let bytes: Vec<u8> = writer.into_bytes();
+        let reader: StateReader = StateReader::new(&bytes).unwrap();
+        let result = reader.load_section(*b"RAM ", |s| s.bytes(&mut [0; 4]));
+        assert!(matches!(result, Err(StateError::Mismatch("memory size"))));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::cpu::Cpu;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Size of the RIFF header plus the "fmt " and "data" chunk headers.
+const HEADER_SIZE: u32 = 44;
+
+/// The RIFF chunk size is a u32 that also covers the rest of the header, which caps the samples
+/// a file can hold at a bit under 4 GiB worth.
+const MAX_SAMPLES: u32 = (u32::MAX - (HEADER_SIZE - 8)) / 2;
+
+/// WAV reference: http://soundfile.sapp.org/doc/WaveFormat/
+///
+/// Writes mono 16-bit PCM samples into a WAV file. The chunk sizes in the header are only known
+/// once all samples are written, so they get filled in by [WavWriter::finish]. Samples past the
+/// format's size limit get dropped.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    samples_written: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> std::io::Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(writer: W, sample_rate: u32) -> std::io::Result<WavWriter<W>> {
+        let mut out: WavWriter<W> = WavWriter {
+            writer,
+            sample_rate,
+            samples_written: 0,
+        };
+        out.write_header()?;
+        Ok(out)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let data_size: u32 = self.samples_written * 2;
+        let mut header: Vec<u8> = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM format, 1 channel
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        // byte rate, block align and bits per sample
+        header.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+        self.writer.write_all(&header)
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        let room: usize = (MAX_SAMPLES - self.samples_written) as usize;
+        let samples: &[i16] = &samples[..samples.len().min(room)];
+        let mut bytes: Vec<u8> = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)?;
+        self.samples_written += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn get_samples_written(&self) -> u32 {
+        self.samples_written
+    }
+
+    /// Fills in the header's chunk sizes and returns the underlying writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Drops the samples the APU generated so far.
+pub fn discard_apu_samples(cpu: &mut Cpu) {
+    let mut buffer: Vec<i16> = vec![0; 4096];
+    while cpu.get_apu_mut().read_samples(&mut buffer) > 0 {}
+}
+
+/// Moves the samples the APU generated so far into the recording.
+pub fn write_apu_samples<W: Write + Seek>(
+    cpu: &mut Cpu,
+    wav: &mut WavWriter<W>,
+) -> std::io::Result<()> {
+    let mut buffer: Vec<i16> = vec![0; 4096];
+    loop {
+        let count: usize = cpu.get_apu_mut().read_samples(&mut buffer);
+        if count == 0 {
+            return Ok(());
+        }
+        wav.write_samples(&buffer[..count])?;
+    }
+}
+
+/// Runs the CPU for a number of frames, recording the APU's output. Samples generated before the
+/// first frame get discarded, so that a recording always starts at a frame boundary and identical
+/// runs produce identical files.
+pub fn record_frames<W: Write + Seek>(
+    cpu: &mut Cpu,
+    wav: &mut WavWriter<W>,
+    frames: u64,
+) -> std::io::Result<()> {
+    discard_apu_samples(cpu);
+    for _ in 0..frames {
+        cpu.run_frame();
+        write_apu_samples(cpu, wav)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header() {
+        let mut wav: WavWriter<Cursor<Vec<u8>>> =
+            WavWriter::new(Cursor::new(Vec::new()), 48000).unwrap();
+        wav.write_samples(&[0, 1, -1]).unwrap();
+        assert_eq!(wav.get_samples_written(), 3);
+        let bytes: Vec<u8> = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 48000);
+        assert_eq!(u16::from_le_bytes(bytes[34..36].try_into().unwrap()), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 6);
+        assert_eq!(&bytes[44..], &[0, 0, 1, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut wav: WavWriter<Cursor<Vec<u8>>> =
+            WavWriter::new(Cursor::new(Vec::new()), 48000).unwrap();
+        wav.samples_written = MAX_SAMPLES - 1;
+        wav.write_samples(&[1, 2, 3]).unwrap();
+        wav.write_samples(&[4]).unwrap();
+        assert_eq!(wav.get_samples_written(), MAX_SAMPLES);
+        let bytes: Vec<u8> = wav.finish().unwrap().into_inner();
+
+        assert_eq!(&bytes[44..], &[1, 0]);
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            u32::MAX - 1
+        );
+        assert_eq!(
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+            MAX_SAMPLES * 2
+        );
+    }
+
+    /// Records a pulse wave, starting after a few frames.
+    fn record() -> Vec<u8> {
+        let mut cpu: Cpu = Cpu::new();
+        cpu.write(0xFFFC, 0x00);
+        cpu.write(0xFFFD, 0x02);
+        // LDA #$01, STA $4015, LDA #$BF, STA $4000, LDA #$FD, STA $4002, LDA #$08, STA $4003,
+        // followed by BNE to itself
+        let program: [u8; 22] = [
+            0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0xA9, 0xFD, 0x8D, 0x02,
+            0x40, 0xA9, 0x08, 0x8D, 0x03, 0x40, 0xD0, 0xFE,
+        ];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.write(0x0200 + i as u16, *byte);
+        }
+        cpu.reset();
+        cpu.run_frame();
+
+        let mut wav: WavWriter<Cursor<Vec<u8>>> =
+            WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        record_frames(&mut cpu, &mut wav, 2).unwrap();
+        wav.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_record_frames() {
+        let bytes: Vec<u8> = record();
+        // two NTSC frames at 44.1 kHz
+        let samples: usize = (bytes.len() - 44) / 2;
+        assert!((1460..=1470).contains(&samples));
+        assert!(bytes[44..].iter().any(|byte| *byte != 0));
+        assert_eq!(bytes, record());
+    }
+}